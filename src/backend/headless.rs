@@ -0,0 +1,236 @@
+// SPDX-License-Identifier: GPL-3.0-only
+
+use crate::{
+    backend::{
+        offscreen::{self, OffscreenSurface},
+        render,
+    },
+    config::OutputConfig,
+    ipc,
+    shell::Shell,
+    state::{BackendData, Data},
+    utils::prelude::*,
+};
+use anyhow::{Context, Result};
+use smithay::{
+    backend::{
+        egl::EGLDisplay,
+        renderer::{gles2::Gles2Renderer, ImportDma, ImportEgl},
+    },
+    reexports::{
+        calloop::{EventLoop, LoopHandle, RegistrationToken},
+        wayland_server::DisplayHandle,
+    },
+    wayland::output::{Mode, Output},
+};
+use std::cell::RefCell;
+
+/// Comma-separated list of virtual outputs, e.g. `1920x1080@60,1280x720`
+const OUTPUTS_ENV: &str = "COSMIC_HEADLESS_OUTPUTS";
+/// Set to `none` to skip rendering completely
+const RENDERER_ENV: &str = "COSMIC_HEADLESS_RENDERER";
+const DEFAULT_OUTPUT: &str = "1920x1080@60";
+
+pub struct HeadlessState {
+    _egl: Option<EGLDisplay>,
+    /// Software renderer, `None` if outputs are not rendered at all
+    pub renderer: Option<Gles2Renderer>,
+    surfaces: Vec<Surface>,
+}
+
+struct Surface {
+    offscreen: OffscreenSurface,
+    /// Frame clock of the output, `None` while it is disabled
+    timer_token: Option<RegistrationToken>,
+}
+
+impl HeadlessState {
+    pub fn add_output(
+        &mut self,
+        handle: LoopHandle<'_, Data>,
+        mode: Mode,
+        position: (i32, i32),
+    ) -> Result<Output> {
+        let name = format!("HEADLESS-{}", self.surfaces.len());
+        let offscreen = OffscreenSurface::new(name, mode, 1.0, position);
+        let output = offscreen.output.clone();
+        let timer_token = insert_frame_clock(&handle, output.clone())?;
+        self.surfaces.push(Surface {
+            offscreen,
+            timer_token: Some(timer_token),
+        });
+
+        Ok(output)
+    }
+
+    pub fn schedule_render(&mut self, output: &Output) {
+        if let Some(surface) = self
+            .surfaces
+            .iter_mut()
+            .find(|s| s.offscreen.output == *output)
+        {
+            surface.offscreen.dirty = true;
+        }
+    }
+
+    pub fn apply_config_for_output(
+        &mut self,
+        output: &Output,
+        shell: &mut Shell,
+        test_only: bool,
+        loop_handle: &LoopHandle<'_, Data>,
+    ) -> Result<(), anyhow::Error> {
+        let surface = match self
+            .surfaces
+            .iter_mut()
+            .find(|s| s.offscreen.output == *output)
+        {
+            Some(surface) => surface,
+            None => return Ok(()),
+        };
+        if test_only {
+            return Ok(());
+        }
+
+        let enabled = output
+            .user_data()
+            .get::<RefCell<OutputConfig>>()
+            .unwrap()
+            .borrow()
+            .enabled;
+        match (enabled, surface.timer_token.take()) {
+            (true, None) => {
+                surface.timer_token = Some(insert_frame_clock(loop_handle, output.clone())?);
+                shell.add_output(output);
+                shell.refresh_outputs();
+            }
+            (false, Some(token)) => {
+                loop_handle.remove(token);
+                surface.offscreen.release_buffers();
+                shell.remove_output(output);
+                shell.refresh_outputs();
+            }
+            (_, token) => surface.timer_token = token,
+        }
+        surface.offscreen.dirty = true;
+        Ok(())
+    }
+}
+
+fn insert_frame_clock(handle: &LoopHandle<'_, Data>, output: Output) -> Result<RegistrationToken> {
+    offscreen::insert_frame_clock(handle, output, |output, data| {
+        let headless = data.state.backend.headless();
+        let surface = match headless
+            .surfaces
+            .iter_mut()
+            .find(|s| s.offscreen.output == *output)
+        {
+            Some(surface) if surface.offscreen.dirty => &mut surface.offscreen,
+            _ => return,
+        };
+        let result = match headless.renderer.as_mut() {
+            Some(renderer) => surface.render_output(None, renderer, None, &mut data.state.common),
+            None => {
+                surface.send_frames(&mut data.state.common);
+                Ok(())
+            }
+        };
+        if let Err(err) = result {
+            slog_scope::error!("Error rendering: {}", err);
+        }
+        surface.dirty = false;
+    })
+}
+
+pub fn init_backend(
+    dh: &DisplayHandle,
+    event_loop: &mut EventLoop<Data>,
+    state: &mut State,
+) -> Result<()> {
+    let modes = std::env::var(OUTPUTS_ENV)
+        .unwrap_or_else(|_| String::from(DEFAULT_OUTPUT))
+        .split(',')
+        .map(|desc| {
+            ipc::parse_mode(desc.trim()).map(|(size, refresh)| Mode {
+                size: size.into(),
+                refresh,
+            })
+        })
+        .collect::<Result<Vec<_>>>()
+        .with_context(|| format!("Failed to parse {}", OUTPUTS_ENV))?;
+    if modes.is_empty() {
+        anyhow::bail!("{} contains no outputs", OUTPUTS_ENV);
+    }
+
+    let (egl, mut renderer) = match std::env::var(RENDERER_ENV) {
+        Ok(x) if x == "none" => (None, None),
+        _ => match render::software_renderer() {
+            Ok((egl, renderer)) => (Some(egl), Some(renderer)),
+            Err(err) => {
+                slog_scope::warn!("Failed to initialize software renderer: {:?}", err);
+                slog_scope::info!("Running without a renderer.");
+                (None, None)
+            }
+        },
+    };
+    if let Some(renderer) = renderer.as_mut() {
+        init_egl_client_side(dh, state, renderer)?;
+    }
+
+    state.backend = BackendData::Headless(HeadlessState {
+        _egl: egl,
+        renderer,
+        surfaces: Vec::new(),
+    });
+
+    // virtual outputs are placed next to each other, left to right
+    let mut outputs = Vec::new();
+    let mut x = 0;
+    for mode in modes {
+        let output = state
+            .backend
+            .headless()
+            .add_output(event_loop.handle(), mode, (x, 0))
+            .with_context(|| "Failed to create wl_output")?;
+        x += mode.size.w;
+        outputs.push(output);
+    }
+
+    state
+        .common
+        .output_configuration_state
+        .add_heads(outputs.iter());
+    state.common.output_configuration_state.update();
+    for output in &outputs {
+        state.common.shell.add_output(output);
+    }
+    state.common.config.read_outputs(
+        outputs.iter(),
+        &mut state.backend,
+        &mut state.common.shell,
+        &state.common.event_loop_handle,
+    );
+    state.common.shell.refresh_outputs();
+    state.common.config.write_outputs(outputs.iter());
+
+    Ok(())
+}
+
+fn init_egl_client_side(
+    dh: &DisplayHandle,
+    state: &mut State,
+    renderer: &mut Gles2Renderer,
+) -> Result<()> {
+    let bind_result = renderer.bind_wl_display(dh);
+    match bind_result {
+        Ok(_) => slog_scope::info!("EGL client-side buffers enabled"),
+        Err(err) => slog_scope::warn!("Unable to initialize bind display to EGL: {}", err),
+    };
+    let dmabuf_formats = renderer.dmabuf_formats().cloned().collect::<Vec<_>>();
+    state
+        .common
+        .dmabuf_state
+        .create_global::<State, _>(dh, dmabuf_formats, None);
+
+    Ok(())
+}
@@ -151,6 +151,9 @@ pub fn interface_name(device: &impl ControlDevice, connector: connector::Handle)
 pub struct EdidInfo {
     pub model: String,
     pub manufacturer: String,
+    pub serial: Option<String>,
+    /// Size of the display in millimeters
+    pub physical_size: Option<(u32, u32)>,
 }
 
 pub fn edid_info(device: &impl ControlDevice, connector: connector::Handle) -> Result<EdidInfo> {
@@ -160,6 +163,8 @@ pub fn edid_info(device: &impl ControlDevice, connector: connector::Handle) -> R
     let edid_info = device.get_property(edid_prop)?;
     let mut manufacturer = "Unknown".into();
     let mut model = "Unknown".into();
+    let mut serial = None;
+    let mut physical_size = None;
     let props = device.get_properties(connector)?;
     let (ids, vals) = props.as_props_and_values();
     for (&id, &val) in ids.iter().zip(vals.iter()) {
@@ -183,6 +188,26 @@ pub fn edid_info(device: &impl ControlDevice, connector: connector::Handle) -> R
                     } else {
                         format!("{}", edid.product.product_code)
                     };
+                    // the serial string descriptor is optional, many displays only fill in the number
+                    serial = edid
+                        .descriptors
+                        .0
+                        .iter()
+                        .find_map(|x| match x {
+                            MonitorDescriptor::SerialNumber(serial) => Some(serial.clone()),
+                            _ => None,
+                        })
+                        .or_else(|| {
+                            Some(edid.product.serial_number)
+                                .filter(|number| *number != 0)
+                                .map(|number| number.to_string())
+                        });
+                    // stored in centimeters, zero for projectors and displays of variable size
+                    physical_size = edid
+                        .display
+                        .max_size
+                        .map(|size| ((size.width * 10.0) as u32, (size.height * 10.0) as u32))
+                        .filter(|(w, h)| *w > 0 && *h > 0);
                 }
             }
             break;
@@ -192,6 +217,8 @@ pub fn edid_info(device: &impl ControlDevice, connector: connector::Handle) -> R
     Ok(EdidInfo {
         model,
         manufacturer,
+        serial,
+        physical_size,
     })
 }
 
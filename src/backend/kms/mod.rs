@@ -4,8 +4,8 @@
 use crate::state::Fps;
 
 use crate::{
-    backend::render,
-    config::OutputConfig,
+    backend::render::{self, AsGles2Renderer},
+    config::{OutputConfig, OutputSerial},
     shell::Shell,
     state::{BackendData, ClientState, Common, Data},
     utils::prelude::*,
@@ -14,35 +14,41 @@ use crate::{
 use anyhow::{Context, Result};
 use smithay::{
     backend::{
-        allocator::{dmabuf::Dmabuf, gbm::GbmDevice, Format},
+        allocator::{dmabuf::Dmabuf, gbm::GbmDevice, Buffer as _, Format},
         drm::{DrmDevice, DrmEvent, DrmEventTime, DrmNode, GbmBufferedSurface, NodeType},
         egl::{EGLContext, EGLDevice, EGLDisplay},
         input::InputEvent,
         libinput::{LibinputInputBackend, LibinputSessionInterface},
         renderer::{
-            gles2::Gles2Renderbuffer,
+            gles2::{Gles2Renderbuffer, Gles2Renderer},
             multigpu::{egl::EglGlesBackend, GpuManager},
-            Bind,
+            utils::with_renderer_surface_state,
+            Bind, ExportDma, Offscreen,
         },
         session::{auto::AutoSession, Session, Signal},
         udev::{all_gpus, primary_gpu, UdevBackend, UdevEvent},
     },
     reexports::{
         calloop::{
+            generic::Generic,
             timer::{TimeoutAction, Timer},
-            Dispatcher, EventLoop, InsertError, LoopHandle, RegistrationToken,
+            Dispatcher, EventLoop, InsertError, Interest, LoopHandle, Mode, PostAction,
+            RegistrationToken,
         },
-        drm::control::{connector, crtc, Device as ControlDevice, ModeTypeFlags},
+        drm::control::{connector, crtc, framebuffer, Device as ControlDevice, ModeTypeFlags},
         input::Libinput,
         nix::{fcntl::OFlag, sys::stat::dev_t},
         wayland_server::{
-            protocol::{wl_output, wl_surface::WlSurface},
+            protocol::{wl_buffer::WlBuffer, wl_output, wl_surface::WlSurface},
             DisplayHandle, Resource,
         },
     },
-    utils::{Size, signaling::{Linkable, SignalToken, Signaler}},
+    utils::{
+        signaling::{Linkable, SignalToken, Signaler},
+        Buffer, IsAlive, Size,
+    },
     wayland::{
-        dmabuf::DmabufGlobal,
+        dmabuf::{get_dmabuf, DmabufGlobal},
         output::{Mode as OutputMode, Output, PhysicalProperties},
     },
 };
@@ -50,21 +56,37 @@ use smithay::{
 use std::{
     cell::RefCell,
     collections::{HashMap, HashSet},
-    path::PathBuf,
+    path::{Path, PathBuf},
     rc::Rc,
     time::{Duration, Instant},
 };
 
 mod drm_helpers;
+mod scanout;
 mod session_fd;
 mod socket;
+mod software;
+mod virtual_output;
+use scanout::*;
 use session_fd::*;
 use socket::*;
+use software::*;
+use virtual_output::*;
 
 pub struct KmsState {
     devices: HashMap<DrmNode, Device>,
     pub api: GpuManager<EglGlesBackend>,
-    pub primary: DrmNode,
+    /// Render node of the gpu used for rendering, `None` if no gpu offers hardware-acceleration
+    pub primary: Option<DrmNode>,
+    /// Only initialized, once a device without hardware-acceleration shows up
+    software: Option<SoftwareRenderer>,
+    virtual_outputs: Vec<VirtualSurface>,
+    /// Used to name virtual outputs, names are never reused
+    next_virtual_output: usize,
+    /// Surfaces committed a new buffer, while the current one was scanned out directly
+    deferred_buffers: Vec<WlSurface>,
+    /// Deferred buffers replaced while scanned out, released once they left the screen
+    pending_release: Vec<WlBuffer>,
     session: AutoSession,
     signaler: Signaler<Signal>,
     _restart_token: SignalToken,
@@ -74,7 +96,8 @@ pub struct KmsState {
 pub struct Device {
     render_node: DrmNode,
     surfaces: HashMap<crtc::Handle, Surface>,
-    allocator: Rc<RefCell<GbmDevice<SessionFd>>>,
+    /// `None`, if the device is driven by the software renderer
+    allocator: Option<Rc<RefCell<GbmDevice<SessionFd>>>>,
     drm: Dispatcher<'static, DrmDevice<SessionFd>, Data>,
     formats: HashSet<Format>,
     supports_atomic: bool,
@@ -82,16 +105,32 @@ pub struct Device {
     socket: Option<Socket>,
 }
 
+pub enum SurfaceTarget {
+    Gbm(GbmBufferedSurface<Rc<RefCell<GbmDevice<SessionFd>>>, SessionFd>),
+    Dumb(DumbSurface),
+}
+
 pub struct Surface {
-    surface: Option<GbmBufferedSurface<Rc<RefCell<GbmDevice<SessionFd>>>, SessionFd>>,
+    surface: Option<SurfaceTarget>,
     connector: connector::Handle,
     output: Output,
     last_render: Option<(Dmabuf, Instant)>,
     last_submit: Option<DrmEventTime>,
+    /// Refresh rate of the current mode in mHz
     refresh_rate: u32,
     vrr: bool,
     pending: bool,
     render_timer_token: Option<RegistrationToken>,
+    /// Estimate of how long rendering a frame takes until the gpu is done,
+    /// `None` until the first frame was rendered
+    render_time: Option<Duration>,
+    scanout: ScanoutCache<framebuffer::Handle>,
+    /// If the last frame was a client buffer scanned out directly
+    scanout_active: bool,
+    /// Copies of the last frames for mirroring outputs, as the swapchain reuses its buffers
+    mirror_buffers: Vec<(Size<i32, Buffer>, Gles2Renderbuffer)>,
+    /// Most recent copy, exported for the mirrors
+    mirror_frame: Option<Dmabuf>,
     #[cfg(feature = "debug")]
     fps: Fps,
 }
@@ -150,27 +189,28 @@ pub fn init_backend(
     let primary = if let Some(path) = std::env::var("COSMIC_RENDER_DEVICE")
         .ok()
         .and_then(|x| DrmNode::from_path(x).ok())
+        .and_then(|x| x.node_with_type(NodeType::Render).and_then(Result::ok))
     {
-        path
+        Some(path)
     } else {
         primary_gpu(session.seat())
             .ok()
             .flatten()
             .and_then(|x| DrmNode::from_path(x).ok())
             .and_then(|x| x.node_with_type(NodeType::Render).and_then(Result::ok))
-            .unwrap_or_else(|| {
-                for dev in all_gpus(session.seat()).expect("No GPU found") {
-                    if let Some(node) = DrmNode::from_path(dev)
-                        .ok()
-                        .and_then(|x| x.node_with_type(NodeType::Render).and_then(Result::ok))
-                    {
-                        return node;
-                    }
-                }
-                panic!("Failed to initialize any GPU");
+            .or_else(|| {
+                all_gpus(session.seat())
+                    .expect("No GPU found")
+                    .iter()
+                    .flat_map(|dev| DrmNode::from_path(dev).ok())
+                    .find_map(|x| x.node_with_type(NodeType::Render).and_then(Result::ok))
             })
     };
-    slog_scope::info!("Using {} as primary gpu for rendering", primary);
+    match primary {
+        Some(primary) => slog_scope::info!("Using {} as primary gpu for rendering", primary),
+        // e.g. only simple framebuffer devices, which have no render node
+        None => slog_scope::warn!("No gpu offers hardware-acceleration, rendering in software"),
+    }
 
     let udev_dispatcher = Dispatcher::new(udev_backend, move |event, _, data: &mut Data| {
         match match event {
@@ -291,6 +331,11 @@ pub fn init_backend(
             udev_event_source,
         ],
         primary,
+        software: None,
+        virtual_outputs: Vec::new(),
+        next_virtual_output: 1,
+        deferred_buffers: Vec::new(),
+        pending_release: Vec::new(),
         session,
         signaler,
         _restart_token,
@@ -331,32 +376,39 @@ impl State {
         let drm_node = DrmNode::from_dev_id(dev)?;
         let supports_atomic = drm.is_atomic();
 
-        let gbm = GbmDevice::new(fd)
-            .with_context(|| format!("Failed to initialize GBM device for {}", path.display()))?;
-        let egl_display = EGLDisplay::new(&gbm, None).with_context(|| {
-            format!("Failed to create EGLDisplay for device: {}", path.display())
-        })?;
-        let egl_device = EGLDevice::device_for_display(&egl_display).with_context(|| {
-            format!("Unable to find matching egl device for {}", path.display())
-        })?;
-        let render_node = egl_device
-            .try_get_render_node()
-            .ok()
-            .and_then(std::convert::identity)
-            .with_context(|| {
-                format!(
-                    "Failed to determine path of egl device for {}",
-                    path.display()
-                )
-            })?;
-        let egl_context = EGLContext::new(&egl_display, None).with_context(|| {
-            format!(
-                "Failed to create EGLContext for device {:?}:{}",
-                egl_device,
-                path.display()
-            )
-        })?;
-        let formats = egl_context.dmabuf_render_formats().clone();
+        let (allocator, render_node, formats) = match init_hardware_rendering(fd, &path) {
+            Ok((gbm, render_node, formats)) => {
+                (Some(Rc::new(RefCell::new(gbm))), render_node, formats)
+            }
+            Err(err) => {
+                slog_scope::warn!(
+                    "Failed to initialize hardware-acceleration for {}: {:?}",
+                    path.display(),
+                    err
+                );
+                // the software renderer runs on EGL as well, don't touch anything if that fails too
+                let kms = self.backend.kms();
+                if kms.software.is_none() {
+                    kms.software = Some(SoftwareRenderer::new().with_context(|| {
+                        format!(
+                            "Failed to initialize software renderer, {} is not usable",
+                            path.display()
+                        )
+                    })?);
+                }
+                slog_scope::info!("Falling back to software rendering for {}", path.display());
+                if kms.primary.is_some()
+                    && kms.primary
+                        == drm_node
+                            .node_with_type(NodeType::Render)
+                            .and_then(Result::ok)
+                {
+                    slog_scope::warn!("Primary gpu is not usable for rendering");
+                    kms.primary = None;
+                }
+                (None, drm_node, HashSet::new())
+            }
+        };
 
         drm.link(self.backend.kms().signaler.clone());
         let dispatcher =
@@ -364,7 +416,23 @@ impl State {
                 DrmEvent::VBlank(crtc) => {
                     if let Some(device) = data.state.backend.kms().devices.get_mut(&drm_node) {
                         if let Some(surface) = device.surfaces.get_mut(&crtc) {
-                            match surface.surface.as_mut().map(|x| x.frame_submitted()) {
+                            match surface.surface.as_mut().map(|x| match x {
+                                SurfaceTarget::Gbm(gbm) => {
+                                    let drm = gbm.surface();
+                                    // direct scanout flips bypass the swapchain
+                                    if surface.scanout.frame_submitted(|fb| {
+                                        let _ = drm.destroy_framebuffer(fb);
+                                    }) {
+                                        Ok(())
+                                    } else {
+                                        gbm.frame_submitted()
+                                    }
+                                }
+                                SurfaceTarget::Dumb(dumb) => {
+                                    dumb.frame_submitted();
+                                    Ok(())
+                                }
+                            }) {
                                 Some(Ok(_)) => {
                                     surface.last_submit = metadata.take().map(|data| data.time);
                                     surface.pending = false;
@@ -385,6 +453,14 @@ impl State {
                             };
                         }
                     }
+                    // buffers held back for direct scanout can be shown now
+                    for surface in data.state.backend.kms().retire_scanout_buffers() {
+                        for output in data.state.common.shell.outputs_for_surface(&surface) {
+                            data.state
+                                .backend
+                                .schedule_render(&data.state.common.event_loop_handle, &output);
+                        }
+                    }
                 }
                 DrmEvent::Error(err) => {
                     slog_scope::warn!("Failed to read events of device {:?}: {}", dev, err);
@@ -396,22 +472,26 @@ impl State {
             .register_dispatcher(dispatcher.clone())
             .with_context(|| format!("Failed to add drm device to event loop: {}", dev))?;
 
-        let socket = match self.create_socket(dh, render_node, formats.clone().into_iter()) {
-            Ok(socket) => Some(socket),
-            Err(err) => {
-                slog_scope::warn!(
-                    "Failed to initialize hardware-acceleration for clients on {}: {}",
-                    render_node,
-                    err
-                );
-                None
-            }
+        // without hardware-acceleration clients have to use shm buffers
+        let socket = match allocator.is_some() {
+            true => match self.create_socket(dh, render_node, formats.clone().into_iter()) {
+                Ok(socket) => Some(socket),
+                Err(err) => {
+                    slog_scope::warn!(
+                        "Failed to initialize hardware-acceleration for clients on {}: {}",
+                        render_node,
+                        err
+                    );
+                    None
+                }
+            },
+            false => None,
         };
 
         let mut device = Device {
             render_node,
             surfaces: HashMap::new(),
-            allocator: Rc::new(RefCell::new(gbm)),
+            allocator,
             drm: dispatcher,
             formats,
             supports_atomic,
@@ -578,6 +658,39 @@ impl State {
     }
 }
 
+/// Sets up gbm and EGL for a drm device, returning its render node and supported formats
+fn init_hardware_rendering(
+    fd: SessionFd,
+    path: &Path,
+) -> Result<(GbmDevice<SessionFd>, DrmNode, HashSet<Format>)> {
+    let gbm = GbmDevice::new(fd)
+        .with_context(|| format!("Failed to initialize GBM device for {}", path.display()))?;
+    let egl_display = EGLDisplay::new(&gbm, None)
+        .with_context(|| format!("Failed to create EGLDisplay for device: {}", path.display()))?;
+    let egl_device = EGLDevice::device_for_display(&egl_display)
+        .with_context(|| format!("Unable to find matching egl device for {}", path.display()))?;
+    let render_node = egl_device
+        .try_get_render_node()
+        .ok()
+        .and_then(std::convert::identity)
+        .with_context(|| {
+            format!(
+                "Failed to determine path of egl device for {}",
+                path.display()
+            )
+        })?;
+    let egl_context = EGLContext::new(&egl_display, None).with_context(|| {
+        format!(
+            "Failed to create EGLContext for device {:?}:{}",
+            egl_device,
+            path.display()
+        )
+    })?;
+    let formats = egl_context.dmabuf_render_formats().clone();
+
+    Ok((gbm, render_node, formats))
+}
+
 pub struct OutputChanges {
     pub added: Vec<(crtc::Handle, connector::Handle)>,
     pub removed: Vec<crtc::Handle>,
@@ -636,7 +749,10 @@ impl Device {
             size: (mode.size().0 as i32, mode.size().1 as i32).into(),
             refresh: refresh_rate as i32,
         };
-        let (phys_w, phys_h) = conn_info.size().unwrap_or((0, 0));
+        let (phys_w, phys_h) = edid_info
+            .physical_size
+            .or_else(|| conn_info.size())
+            .unwrap_or((0, 0));
         let output = Output::new(
             interface,
             PhysicalProperties {
@@ -648,6 +764,11 @@ impl Device {
             },
             None,
         );
+        if let Some(serial) = edid_info.serial {
+            output
+                .user_data()
+                .insert_if_missing(|| OutputSerial(serial));
+        }
         for mode in conn_info.modes() {
             let refresh_rate = drm_helpers::calculate_refresh_rate(*mode);
             let mode = OutputMode {
@@ -683,6 +804,11 @@ impl Device {
             last_render: None,
             pending: false,
             render_timer_token: None,
+            render_time: None,
+            scanout: ScanoutCache::default(),
+            scanout_active: false,
+            mirror_buffers: Vec::new(),
+            mirror_frame: None,
             #[cfg(feature = "debug")]
             fps: Fps::default(),
         };
@@ -693,6 +819,12 @@ impl Device {
 }
 
 const MAX_CPU_COPIES: usize = 3;
+/// Copies of the frames of mirrored outputs, one is written while mirrors read the other one
+const MIRROR_BUFFERS: usize = 2;
+/// Assumed render time for surfaces, that have not rendered a frame yet
+const DEFAULT_RENDER_TIME: Duration = Duration::from_millis(8);
+/// Extra time reserved for the gpu to finish and for scheduling jitter
+const RENDER_SLACK: Duration = Duration::from_millis(2);
 
 fn render_node_for_output(
     dh: &DisplayHandle,
@@ -732,26 +864,163 @@ fn render_node_for_output(
     }
 }
 
+/// Updates the render time estimate of a surface, once the gpu finished rendering into `buffer`.
+///
+/// Submitting a frame returns long before the gpu is done, but dmabufs only become readable
+/// once their implicit fences signaled.
+fn track_render_time(
+    loop_handle: &LoopHandle<'static, Data>,
+    buffer: Dmabuf,
+    device: DrmNode,
+    crtc: crtc::Handle,
+    start: Instant,
+) {
+    let fd = match buffer.handles().next() {
+        Some(fd) => fd,
+        None => return,
+    };
+    if let Err(err) = loop_handle.insert_source(
+        Generic::new(fd, Interest::READ, Mode::OneShot),
+        move |_, _, data: &mut Data| {
+            // the buffer keeps the fd open
+            let _ = &buffer;
+            if let Some(surface) = data
+                .state
+                .backend
+                .kms()
+                .devices
+                .get_mut(&device)
+                .and_then(|device| device.surfaces.get_mut(&crtc))
+            {
+                surface.update_render_time(start.elapsed());
+            }
+            Ok(PostAction::Remove)
+        },
+    ) {
+        slog_scope::warn!("Failed to wait for rendering to finish: {}", err);
+    }
+}
+
 impl Surface {
+    fn frame_duration(&self) -> Duration {
+        Duration::from_secs_f64(1_000.0 / self.refresh_rate.max(1) as f64)
+    }
+
+    /// Updates the render time estimate.
+    ///
+    /// Rises immediately on slow frames, but only decays slowly to avoid missing vblanks after spikes.
+    fn update_render_time(&mut self, elapsed: Duration) {
+        self.render_time = Some(match self.render_time {
+            Some(estimate) if estimate > elapsed => (estimate * 7 + elapsed) / 8,
+            _ => elapsed,
+        });
+    }
+
+    /// Returns the latest point in time to start rendering, that still makes the next vblank.
+    ///
+    /// `None` means we should render right away, e.g. because we don't know when the next
+    /// vblank happens or because the display adapts its refresh rate to us anyway (vrr).
+    fn render_deadline(&self) -> Option<Instant> {
+        if self.vrr || self.refresh_rate == 0 {
+            return None;
+        }
+        let last_vblank = match self.last_submit.as_ref()? {
+            DrmEventTime::Monotonic(instant) => *instant,
+            DrmEventTime::Realtime(_) => return None,
+        };
+
+        let frame = self.frame_duration();
+        let budget = self.render_time.unwrap_or(DEFAULT_RENDER_TIME) + RENDER_SLACK;
+        if budget >= frame {
+            return None;
+        }
+
+        let now = Instant::now();
+        let frames_since = now.saturating_duration_since(last_vblank).as_nanos() / frame.as_nanos();
+        let next_vblank = last_vblank + frame * (frames_since.min(u32::MAX as u128 - 1) as u32 + 1);
+        let deadline = next_vblank - budget;
+        if deadline > now {
+            Some(deadline)
+        } else {
+            None
+        }
+    }
+
     pub fn render_output(
         &mut self,
         dh: &DisplayHandle,
         api: &mut GpuManager<EglGlesBackend>,
+        allocator: Option<&Rc<RefCell<GbmDevice<SessionFd>>>>,
+        software: Option<&mut SoftwareRenderer>,
         target_node: &DrmNode,
+        mirror: Option<(Output, Option<Dmabuf>)>,
         state: &mut Common,
     ) -> Result<()> {
-        if self.surface.is_none() {
-            return Ok(());
+        let needs_reset = render::needs_buffer_reset(&self.output, state);
+        let surface = match self.surface.as_mut() {
+            Some(SurfaceTarget::Gbm(surface)) => surface,
+            Some(SurfaceTarget::Dumb(surface)) => {
+                if needs_reset {
+                    surface.reset_buffers();
+                }
+                let software = software.with_context(|| "Software renderer not initialized")?;
+                return surface.render_output(
+                    &mut software.renderer,
+                    state,
+                    &self.output,
+                    mirror
+                        .as_ref()
+                        .map(|(source, frame)| (source, frame.as_ref())),
+                    #[cfg(feature = "debug")]
+                    &mut self.fps,
+                );
+            }
+            None => return Ok(()),
+        };
+        if needs_reset {
+            surface.reset_buffers();
         }
 
-        if render::needs_buffer_reset(&self.output, state) {
-            self.surface.as_mut().unwrap().reset_buffers();
+        let mirrored = state.shell.mirrors_of(&self.output).next().is_some();
+        if !mirrored {
+            self.mirror_buffers.clear();
+            self.mirror_frame = None;
+        }
+
+        // fullscreen clients might not need any compositing at all, unless mirrors need a copy
+        if let (None, false, Some(dmabuf), Some(allocator)) = (
+            mirror.as_ref(),
+            mirrored,
+            scanout::scanout_candidate(&self.output, state),
+            allocator,
+        ) {
+            let gbm = allocator.borrow();
+            let mut plane = GbmPlane {
+                gbm: &*gbm,
+                surface: surface.surface(),
+            };
+            if let Some(fb) = self.scanout.framebuffer(&mut plane, &dmabuf) {
+                match plane.flip(fb) {
+                    Ok(()) => {
+                        // the cache holds the buffer, until it left the screen again
+                        self.scanout.queue(&dmabuf);
+                        self.scanout_active = true;
+                        self.last_render = Some((dmabuf, Instant::now()));
+                        return Ok(());
+                    }
+                    // the buffer passed the test commit, so this is likely temporary (e.g. EBUSY)
+                    Err(err) => slog_scope::debug!("{:?}", err),
+                }
+            }
+        }
+        // the buffer ages don't account for the frames we skipped
+        if std::mem::take(&mut self.scanout_active) {
+            surface.reset_buffers();
         }
 
         let render_node = render_node_for_output(dh, &self.output, *target_node, &state.shell);
         let mut renderer = api.renderer(&render_node, &target_node).unwrap();
 
-        let surface = self.surface.as_mut().unwrap();
         let (buffer, age) = surface
             .next_buffer()
             .with_context(|| "Failed to allocate buffer")?;
@@ -760,21 +1029,34 @@ impl Surface {
             .bind(buffer.clone())
             .with_context(|| "Failed to bind buffer")?;
 
-        match render::render_output(
-            Some(&render_node),
-            &mut renderer,
-            age,
-            state,
-            &self.output,
-            false,
-            #[cfg(feature = "debug")]
-            Some(&mut self.fps),
-        ) {
+        let result = match mirror.as_ref() {
+            Some((source, frame)) => {
+                render::render_mirror(&mut renderer, &self.output, source, frame.as_ref())
+            }
+            None => render::render_output(
+                Some(&render_node),
+                &mut renderer,
+                age,
+                state,
+                &self.output,
+                false,
+                #[cfg(feature = "debug")]
+                Some(&mut self.fps),
+            ),
+        };
+        match result {
             Ok(_) => {
-                self.last_render = Some((buffer, Instant::now()));
+                self.last_render = Some((buffer.clone(), Instant::now()));
                 surface
                     .queue_buffer()
                     .with_context(|| "Failed to submit buffer for display")?;
+                std::mem::drop(renderer);
+                if mirrored {
+                    if let Err(err) = self.copy_for_mirrors(api, target_node, &buffer) {
+                        slog_scope::warn!("Failed to copy frame for mirroring: {:?}", err);
+                        self.mirror_frame = None;
+                    }
+                }
             }
             Err(err) => {
                 surface.reset_buffers();
@@ -783,6 +1065,45 @@ impl Surface {
         };
         Ok(())
     }
+
+    /// Copies the frame into a buffer owned by us, which stays untouched while mirrors read it.
+    fn copy_for_mirrors(
+        &mut self,
+        api: &mut GpuManager<EglGlesBackend>,
+        node: &DrmNode,
+        frame: &Dmabuf,
+    ) -> Result<()> {
+        let mut renderer = api
+            .renderer::<Gles2Renderbuffer>(node, node)
+            .with_context(|| "Failed to create renderer")?;
+        let size = frame.size();
+        self.mirror_buffers
+            .retain(|(buffer_size, _)| *buffer_size == size);
+        if self.mirror_buffers.len() < MIRROR_BUFFERS {
+            let buffer = Offscreen::<Gles2Renderbuffer>::create_buffer(renderer.as_gles2(), size)
+                .with_context(|| "Failed to allocate buffer")?;
+            self.mirror_buffers.push((size, buffer));
+        } else {
+            // the oldest copy is not read anymore
+            self.mirror_buffers.rotate_left(1);
+        }
+        let (_, buffer) = self.mirror_buffers.last().unwrap();
+        renderer
+            .bind(buffer.clone())
+            .with_context(|| "Failed to bind buffer")?;
+        if let Err(err) =
+            render::render_mirror(&mut renderer, &self.output, &self.output, Some(frame))
+        {
+            anyhow::bail!("Rendering failed: {}", err);
+        }
+        self.mirror_frame = Some(
+            renderer
+                .as_gles2()
+                .export_framebuffer(size)
+                .with_context(|| "Failed to export frame")?,
+        );
+        Ok(())
+    }
 }
 
 impl KmsState {
@@ -797,6 +1118,30 @@ impl KmsState {
         test_only: bool,
         loop_handle: &LoopHandle<'_, Data>,
     ) -> Result<(), anyhow::Error> {
+        if let Some(surface) = self
+            .virtual_outputs
+            .iter_mut()
+            .find(|s| s.offscreen.output == *output)
+        {
+            let enabled = output
+                .user_data()
+                .get::<RefCell<OutputConfig>>()
+                .unwrap()
+                .borrow()
+                .enabled;
+            if !test_only && enabled != surface.enabled {
+                surface.enabled = enabled;
+                if enabled {
+                    shell.add_output(output);
+                } else {
+                    shell.remove_output(output);
+                }
+                shell.refresh_outputs();
+            }
+            surface.offscreen.dirty = true;
+            return Ok(());
+        }
+
         let recreated = if let Some(device) = self
             .devices
             .values_mut()
@@ -817,6 +1162,11 @@ impl KmsState {
                 if !test_only {
                     if surface.surface.take().is_some() {
                         // just drop it
+                        let drm = &*device.drm.as_source_ref();
+                        surface.scanout.clear(|fb| {
+                            let _ = drm.destroy_framebuffer(fb);
+                        });
+                        surface.scanout_active = false;
                         shell.remove_output(output);
                     }
                 }
@@ -841,7 +1191,7 @@ impl KmsState {
                     .ok_or(anyhow::anyhow!("Unknown mode"))?;
 
                 if !test_only {
-                    if let Some(gbm_surface) = surface.surface.as_mut() {
+                    if let Some(target) = surface.surface.as_mut() {
                         if output_config.vrr != surface.vrr {
                             surface.vrr = drm_helpers::set_vrr(
                                 drm,
@@ -850,28 +1200,50 @@ impl KmsState {
                                 output_config.vrr,
                             )?;
                         }
-                        gbm_surface.use_mode(*mode).unwrap();
+                        match target {
+                            SurfaceTarget::Gbm(gbm_surface) => gbm_surface.use_mode(*mode).unwrap(),
+                            SurfaceTarget::Dumb(dumb_surface) => dumb_surface.use_mode(*mode)?,
+                        }
+                        surface.refresh_rate = drm_helpers::calculate_refresh_rate(*mode);
+                        surface.render_time = None;
+                        // cached buffers don't match the new mode anymore
+                        surface.scanout.clear(|fb| {
+                            let _ = drm.destroy_framebuffer(fb);
+                        });
                         false
                     } else {
                         surface.vrr = drm_helpers::set_vrr(drm, *crtc, conn, output_config.vrr)
                             .unwrap_or(false);
                         surface.refresh_rate = drm_helpers::calculate_refresh_rate(*mode);
+                        surface.render_time = None;
                         let mut drm_surface = drm.create_surface(*crtc, *mode, &[conn])?;
                         drm_surface.link(self.signaler.clone());
 
-                        let target = GbmBufferedSurface::new(
-                            drm_surface,
-                            device.allocator.clone(),
-                            device.formats.clone(),
-                            None,
-                        )
-                        .with_context(|| {
-                            format!(
-                                "Failed to initialize Gbm surface for {}",
-                                drm_helpers::interface_name(drm, conn)
-                                    .unwrap_or_else(|_| String::from("Unknown"))
+                        let target = match device.allocator.as_ref() {
+                            Some(allocator) => GbmBufferedSurface::new(
+                                drm_surface,
+                                allocator.clone(),
+                                device.formats.clone(),
+                                None,
                             )
-                        })?;
+                            .map(SurfaceTarget::Gbm)
+                            .with_context(|| {
+                                format!(
+                                    "Failed to initialize Gbm surface for {}",
+                                    drm_helpers::interface_name(drm, conn)
+                                        .unwrap_or_else(|_| String::from("Unknown"))
+                                )
+                            })?,
+                            None => DumbSurface::new(drm_surface)
+                                .map(SurfaceTarget::Dumb)
+                                .with_context(|| {
+                                    format!(
+                                        "Failed to initialize dumb surface for {}",
+                                        drm_helpers::interface_name(drm, conn)
+                                            .unwrap_or_else(|_| String::from("Unknown"))
+                                    )
+                                })?,
+                        };
                         surface.surface = Some(target);
                         shell.add_output(output);
                         true
@@ -896,12 +1268,95 @@ impl KmsState {
         }
         Ok(())
     }
-    pub fn target_node_for_output(&self, output: &Output) -> Option<DrmNode> {
+    /// Renderer for outputs of devices without hardware-acceleration, if any showed up
+    pub fn software_renderer(&mut self) -> Option<&mut Gles2Renderer> {
+        self.software
+            .as_mut()
+            .map(|software| &mut software.renderer)
+    }
+
+    /// Holds the buffer of the commit back, if the display still reads from the current one.
+    ///
+    /// Returns false, if the commit can be handled as usual.
+    pub fn defer_buffer(&mut self, surface: &WlSurface) -> bool {
+        let current = with_renderer_surface_state(surface, |state| state.wl_buffer().cloned());
+        let scanned_out = current
+            .as_ref()
+            .map_or(false, |buffer| self.scans_out(buffer));
+        if !scanned_out && !self.deferred_buffers.contains(surface) {
+            return false;
+        }
+
+        if let Some(replaced) = scanout::defer_buffer(surface) {
+            // the current buffer is released by smithay, once the deferred buffer is applied
+            if Some(&replaced) != current.as_ref() {
+                if self.scans_out(&replaced) {
+                    self.pending_release.push(replaced);
+                } else {
+                    replaced.release();
+                }
+            }
+        }
+        if !self.deferred_buffers.contains(surface) {
+            self.deferred_buffers.push(surface.clone());
+        }
+        true
+    }
+
+    /// Applies deferred buffers and releases buffers, that are not scanned out anymore.
+    ///
+    /// Returns the surfaces, that got a new buffer.
+    fn retire_scanout_buffers(&mut self) -> Vec<WlSurface> {
+        for buffer in std::mem::take(&mut self.pending_release) {
+            if self.scans_out(&buffer) {
+                self.pending_release.push(buffer);
+            } else {
+                buffer.release();
+            }
+        }
+
+        let mut applied = Vec::new();
+        for surface in std::mem::take(&mut self.deferred_buffers) {
+            if !surface.alive() {
+                continue;
+            }
+            let current = with_renderer_surface_state(&surface, |state| state.wl_buffer().cloned());
+            if current.map_or(false, |buffer| self.scans_out(&buffer)) {
+                self.deferred_buffers.push(surface);
+            } else {
+                scanout::apply_deferred_buffer(&surface);
+                applied.push(surface);
+            }
+        }
+        applied
+    }
+
+    /// If any output shows the client buffer without compositing
+    fn scans_out(&self, buffer: &WlBuffer) -> bool {
+        let dmabuf = match get_dmabuf(buffer) {
+            Ok(dmabuf) => dmabuf,
+            Err(_) => return false,
+        };
         self.devices
+            .values()
+            .flat_map(|dev| dev.surfaces.values())
+            .any(|surface| surface.scanout.holds(&dmabuf))
+    }
+
+    /// Render node used for the given output, `None` if it is rendered in software.
+    pub fn target_node_for_output(&self, output: &Output) -> Option<DrmNode> {
+        match self
+            .devices
             .values()
             .find(|dev| dev.surfaces.values().any(|s| s.output == *output))
-            .map(|dev| &dev.render_node)
-            .copied()
+        {
+            Some(dev) => dev.allocator.is_some().then(|| dev.render_node),
+            None => self
+                .virtual_outputs
+                .iter()
+                .find(|s| s.offscreen.output == *output)
+                .and(self.primary),
+        }
     }
 
     pub fn try_early_import(
@@ -955,6 +1410,15 @@ impl KmsState {
         loop_handle: &LoopHandle<'_, Data>,
         output: &Output,
     ) -> Result<(), InsertError<Timer>> {
+        if let Some(surface) = self
+            .virtual_outputs
+            .iter_mut()
+            .find(|s| s.offscreen.output == *output)
+        {
+            surface.offscreen.dirty = true;
+            return Ok(());
+        }
+
         if let Some((device, crtc, surface)) = self
             .devices
             .iter_mut()
@@ -966,47 +1430,75 @@ impl KmsState {
             }
             if !surface.pending {
                 surface.pending = true;
-                /*
-                let instant = surface
-                    .last_submit
-                    .as_ref()
-                    .and_then(|x| match x {
-                        DrmEventTime::Monotonic(instant) => Some(instant),
-                        DrmEventTime::Realtime(_) => None,
-                    })
-                    .map(|i| {
-                        *i + Duration::from_secs_f64(1.0 / surface.refresh_rate as f64)
-                            - Duration::from_millis(20) // render budget
-                    });
-                */
+                // render as late as possible to include the most recent client updates and input
+                let timer = match surface.render_deadline() {
+                    Some(deadline) => Timer::from_deadline(deadline),
+                    None => Timer::immediate(),
+                };
 
                 let device = *device;
                 let crtc = *crtc;
-                surface.render_timer_token = Some(loop_handle.insert_source(
-                    //if surface.vrr || instant.is_none() {
-                    Timer::immediate(), /*} else {
-                                            Timer::from_deadline(instant.unwrap())
-                                        }*/
-                    move |_time, _, data| {
+                let output = output.clone();
+                surface.render_timer_token =
+                    Some(loop_handle.insert_source(timer, move |_time, _, data| {
                         let backend = data.state.backend.kms();
+                        let mirror =
+                            data.state
+                                .common
+                                .shell
+                                .mirrored_output(&output)
+                                .map(|source| {
+                                    let frame = backend.mirror_frame(&source);
+                                    (source, frame)
+                                });
+                        let device_node = device;
                         if let Some(device) = backend.devices.get_mut(&device) {
                             if let Some(surface) = device.surfaces.get_mut(&crtc) {
+                                let start = Instant::now();
                                 if let Err(err) = surface.render_output(
                                     &data.display.handle(),
                                     &mut backend.api,
+                                    device.allocator.as_ref(),
+                                    backend.software.as_mut(),
                                     &device.render_node,
+                                    mirror,
                                     &mut data.state.common,
                                 ) {
                                     slog_scope::error!("Error rendering: {}", err);
-                                    return TimeoutAction::ToDuration(Duration::from_secs_f64(
-                                        1.0 / surface.refresh_rate as f64,
-                                    ));
+                                    return TimeoutAction::ToDuration(surface.frame_duration());
                                 }
+                                match surface
+                                    .last_render
+                                    .as_ref()
+                                    .filter(|(_, time)| *time >= start)
+                                {
+                                    Some((buffer, _)) => track_render_time(
+                                        &data.state.common.event_loop_handle,
+                                        buffer.clone(),
+                                        device_node,
+                                        crtc,
+                                        start,
+                                    ),
+                                    // software rendering is done, once the frame was copied
+                                    None => surface.update_render_time(start.elapsed()),
+                                }
+                            }
+                        }
+
+                        // outputs mirroring this one need to show the new frame
+                        for mirror in data.state.common.shell.mirrors_of(&output) {
+                            if let Err(err) = backend
+                                .schedule_render(&data.state.common.event_loop_handle, mirror)
+                            {
+                                slog_scope::crit!(
+                                    "Error scheduling event loop for output {}: {:?}",
+                                    mirror.name(),
+                                    err
+                                );
                             }
                         }
                         TimeoutAction::Drop
-                    },
-                )?);
+                    })?);
             }
         }
         Ok(())
@@ -1020,5 +1512,173 @@ impl KmsState {
                     .map(|(buf, time)| (dev.render_node.clone(), buf, time))
                 )
             )
+            .or_else(|| {
+                self.virtual_outputs
+                    .iter()
+                    .find(|s| &s.offscreen.output == output)
+                    .and_then(|s| s.last_render.clone())
+                    .zip(self.primary)
+                    .map(|((buf, time), primary)| (primary, buf, time))
+            })
+    }
+
+    /// Latest frame of the given output for mirroring it.
+    pub fn mirror_frame(&self, output: &Output) -> Option<Dmabuf> {
+        self.devices
+            .values()
+            .flat_map(|dev| dev.surfaces.values())
+            .find(|s| &s.output == output)
+            .map(|s| s.mirror_frame.clone())
+            .unwrap_or_else(|| {
+                // virtual outputs render into buffers of their own anyway
+                self.virtual_outputs
+                    .iter()
+                    .find(|s| &s.offscreen.output == output)
+                    .and_then(|s| s.last_render.clone())
+                    .map(|(frame, _)| frame)
+            })
+    }
+
+    /// Whether the given output can be the source of a mirror, which needs its frames as dmabufs.
+    pub fn can_mirror(&self, output: &Output) -> bool {
+        self.devices
+            .values()
+            .find(|dev| dev.surfaces.values().any(|s| &s.output == output))
+            .map(|dev| dev.allocator.is_some())
+            .unwrap_or(true)
+    }
+
+    /// Creates a new virtual output, that is not yet part of the shell.
+    pub fn create_virtual_output(
+        &mut self,
+        mode: OutputMode,
+        scale: f64,
+        position: (i32, i32),
+        loop_handle: &LoopHandle<'_, Data>,
+    ) -> Result<Output> {
+        let primary = self
+            .primary
+            .with_context(|| "Virtual outputs require a gpu with hardware-acceleration")?;
+        self.api
+            .renderer::<Gles2Renderbuffer>(&primary, &primary)
+            .map(|_| ())
+            .with_context(|| "Virtual outputs require hardware-acceleration on the primary gpu")?;
+
+        let name = format!("VIRTUAL-{}", self.next_virtual_output);
+        let surface = VirtualSurface::new(name, mode, scale, position, loop_handle)?;
+        self.next_virtual_output += 1;
+        let output = surface.offscreen.output.clone();
+        self.virtual_outputs.push(surface);
+        Ok(output)
+    }
+
+    /// Removes the virtual output with the given name, returns `None` if there is no such output.
+    pub fn destroy_virtual_output(
+        &mut self,
+        name: &str,
+        shell: &mut Shell,
+        loop_handle: &LoopHandle<'_, Data>,
+    ) -> Option<Output> {
+        let idx = self
+            .virtual_outputs
+            .iter()
+            .position(|s| s.offscreen.output.name() == name)?;
+        let surface = self.virtual_outputs.remove(idx);
+        loop_handle.remove(surface.timer_token);
+        if surface.enabled {
+            shell.remove_output(&surface.offscreen.output);
+            shell.refresh_outputs();
+        }
+        Some(surface.offscreen.output)
+    }
+
+    fn render_virtual_output(&mut self, output: &Output, state: &mut Common) {
+        // the gpu is not ours while the session is inactive
+        if !self.session.is_active() {
+            return;
+        }
+        let mirror = state.shell.mirrored_output(output).map(|source| {
+            let frame = self.mirror_frame(&source);
+            (source, frame)
+        });
+        let surface = match self
+            .virtual_outputs
+            .iter_mut()
+            .find(|s| s.offscreen.output == *output)
+        {
+            Some(surface) if surface.enabled && surface.offscreen.dirty => surface,
+            _ => return,
+        };
+        surface.offscreen.dirty = false;
+        // virtual outputs are rendered on the primary gpu
+        let primary = match self.primary {
+            Some(primary) => primary,
+            None => return,
+        };
+        if let Err(err) = surface.render_output(&mut self.api, &primary, mirror, state) {
+            slog_scope::error!("Error rendering: {}", err);
+            return;
+        }
+
+        // outputs mirroring this one need to show the new frame
+        for mirror in state.shell.mirrors_of(output) {
+            if let Err(err) = self.schedule_render(&state.event_loop_handle, mirror) {
+                slog_scope::crit!(
+                    "Error scheduling event loop for output {}: {:?}",
+                    mirror.name(),
+                    err
+                );
+            }
+        }
+    }
+}
+
+impl State {
+    /// Creates a virtual output and places it right of all existing outputs.
+    pub fn create_virtual_output(&mut self, mode: OutputMode, scale: f64) -> Result<Output> {
+        let position = (self.common.shell.global_space().size.w, 0);
+        let output = match &mut self.backend {
+            BackendData::Kms(state) => state.create_virtual_output(
+                mode,
+                scale,
+                position,
+                &self.common.event_loop_handle,
+            )?,
+            _ => anyhow::bail!("Virtual outputs are only supported on the kms backend"),
+        };
+
+        self.common
+            .output_configuration_state
+            .add_heads(std::iter::once(&output));
+        self.common.output_configuration_state.update();
+        if let Err(err) = self.backend.apply_config_for_output(
+            &output,
+            false,
+            &mut self.common.shell,
+            &self.common.event_loop_handle,
+        ) {
+            let _ = self.destroy_virtual_output(&output.name());
+            return Err(err);
+        }
+        Ok(output)
+    }
+
+    pub fn destroy_virtual_output(&mut self, name: &str) -> Result<()> {
+        let output = match &mut self.backend {
+            BackendData::Kms(state) => state
+                .destroy_virtual_output(
+                    name,
+                    &mut self.common.shell,
+                    &self.common.event_loop_handle,
+                )
+                .with_context(|| format!("Unknown virtual output {}", name))?,
+            _ => anyhow::bail!("Virtual outputs are only supported on the kms backend"),
+        };
+
+        self.common
+            .output_configuration_state
+            .remove_heads(std::iter::once(&output));
+        self.common.output_configuration_state.update();
+        Ok(())
     }
 }
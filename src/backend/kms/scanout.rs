@@ -0,0 +1,557 @@
+// SPDX-License-Identifier: GPL-3.0-only
+
+use crate::{
+    input::TabletToolCursor, shell::grabs::SeatMoveGrabState, state::Common, utils::prelude::*,
+};
+
+use super::session_fd::SessionFd;
+use anyhow::{Context, Result};
+use smithay::{
+    backend::{
+        allocator::{
+            dmabuf::Dmabuf,
+            gbm::{GbmBufferFlags, GbmDevice},
+            Buffer, Modifier,
+        },
+        drm::DrmSurface,
+        renderer::utils::{on_commit_buffer_handler, with_renderer_surface_state},
+    },
+    desktop::{layer_map_for_output, PopupManager},
+    reexports::{
+        drm::control::{framebuffer, Device as ControlDevice},
+        wayland_server::protocol::{
+            wl_buffer::WlBuffer, wl_output::Transform, wl_surface::WlSurface,
+        },
+    },
+    wayland::{
+        compositor::{get_children, with_states, BufferAssignment, Damage, SurfaceAttributes},
+        dmabuf::get_dmabuf,
+        output::Output,
+        seat::CursorImageStatus,
+        shell::wlr_layer::Layer as WlrLayer,
+    },
+};
+use std::{cell::RefCell, collections::VecDeque, os::unix::io::RawFd};
+
+/// `DRM_MODE_FB_MODIFIERS`, tells the kernel to respect the passed modifiers
+const FB_MODIFIERS: u32 = 1 << 1;
+/// Clients usually cycle through two or three buffers
+const MAX_CACHED_BUFFERS: usize = 4;
+
+/// Returns the buffer of the fullscreen window on `output`, if it can be shown without compositing.
+///
+/// This is only the case, if nothing else is visible on the output and the buffer exactly covers the current mode.
+pub fn scanout_candidate(output: &Output, state: &Common) -> Option<Dmabuf> {
+    // the fps overlay is drawn on top of everything
+    if cfg!(feature = "debug") {
+        return None;
+    }
+
+    let window = state
+        .shell
+        .active_space(output)
+        .get_fullscreen(output)?
+        .clone();
+    let mode = output.current_mode()?;
+    if output.current_transform() != Transform::Normal {
+        return None;
+    }
+    if layer_map_for_output(output)
+        .layers_on(WlrLayer::Overlay)
+        .next()
+        .is_some()
+        || state.shell.input_method_state.popups().next().is_some()
+        || cursor_visible(output, state)
+    {
+        return None;
+    }
+
+    let surface = window.toplevel().wl_surface();
+    if !get_children(surface).is_empty()
+        || PopupManager::popups_for_surface(surface).next().is_some()
+    {
+        return None;
+    }
+    let buffer_transform: smithay::utils::Transform = with_states(surface, |states| {
+        states
+            .cached_state
+            .current::<SurfaceAttributes>()
+            .buffer_transform
+            .into()
+    });
+    if buffer_transform != smithay::utils::Transform::Normal {
+        return None;
+    }
+
+    let dmabuf = get_dmabuf(&next_buffer(surface)?).ok()?;
+    if dmabuf.size() != (mode.size.w, mode.size.h).into() {
+        return None;
+    }
+    Some(dmabuf)
+}
+
+/// Buffer the surface shows next, which might still be deferred
+fn next_buffer(surface: &WlSurface) -> Option<WlBuffer> {
+    let deferred = with_states(surface, |states| {
+        let deferred = states.data_map.get::<RefCell<DeferredBuffer>>()?.borrow();
+        match deferred.buffer.as_ref()? {
+            BufferAssignment::NewBuffer(buffer) => Some(Some(buffer.clone())),
+            BufferAssignment::Removed => Some(None),
+        }
+    });
+    match deferred {
+        Some(buffer) => buffer,
+        None => with_renderer_surface_state(surface, |state| state.wl_buffer().cloned()),
+    }
+}
+
+/// Buffer and damage of commits, that happened while the current buffer was scanned out.
+///
+/// Smithay releases the replaced buffer right away on commit, but the display might still read from it.
+#[derive(Default)]
+struct DeferredBuffer {
+    buffer: Option<BufferAssignment>,
+    damage: Vec<Damage>,
+}
+
+/// Holds the buffer of the last commit back from smithay, until [`apply_deferred_buffer`] is called.
+///
+/// Returns the deferred buffer replaced by this commit, which smithay never saw and is not released.
+pub fn defer_buffer(surface: &WlSurface) -> Option<WlBuffer> {
+    with_states(surface, |states| {
+        states
+            .data_map
+            .insert_if_missing(|| RefCell::new(DeferredBuffer::default()));
+        let mut deferred = states
+            .data_map
+            .get::<RefCell<DeferredBuffer>>()
+            .unwrap()
+            .borrow_mut();
+        let mut attrs = states.cached_state.current::<SurfaceAttributes>();
+        deferred.damage.append(&mut attrs.damage);
+        let buffer = attrs.buffer.take()?;
+        let replaced = match std::mem::replace(&mut deferred.buffer, Some(buffer)) {
+            Some(BufferAssignment::NewBuffer(old)) => Some(old),
+            _ => None,
+        };
+        let attached = match deferred.buffer.as_ref() {
+            Some(BufferAssignment::NewBuffer(new)) => Some(new),
+            _ => None,
+        };
+        replaced.filter(|old| Some(old) != attached)
+    })
+}
+
+/// Hands the deferred buffer to smithay, which releases the buffer it replaces.
+pub fn apply_deferred_buffer(surface: &WlSurface) {
+    with_states(surface, |states| {
+        if let Some(deferred) = states.data_map.get::<RefCell<DeferredBuffer>>() {
+            let mut deferred = deferred.borrow_mut();
+            let mut attrs = states.cached_state.current::<SurfaceAttributes>();
+            attrs.buffer = deferred.buffer.take();
+            attrs.damage = std::mem::take(&mut deferred.damage);
+        }
+    });
+    on_commit_buffer_handler(surface);
+}
+
+/// Any cursor or grab on the output needs to be composited
+fn cursor_visible(output: &Output, state: &Common) -> bool {
+    let output_geo = output.geometry();
+    state.seats.iter().any(|seat| {
+        let userdata = seat.user_data();
+        if userdata
+            .get::<TabletToolCursor>()
+            .and_then(TabletToolCursor::location)
+            .is_some()
+            || userdata
+                .get::<SeatMoveGrabState>()
+                .unwrap()
+                .borrow()
+                .is_some()
+        {
+            return true;
+        }
+
+        let hidden = userdata
+            .get::<RefCell<CursorImageStatus>>()
+            .map(|status| *status.borrow() == CursorImageStatus::Hidden)
+            .unwrap_or(false);
+        let on_output = seat
+            .get_pointer()
+            .map(|ptr| output_geo.contains(ptr.current_location().to_i32_round()))
+            .unwrap_or(false);
+        !hidden && on_output
+    })
+}
+
+/// Abstraction over the primary plane of an output
+pub trait PlaneTest {
+    type Framebuffer: Copy;
+
+    /// Creates a framebuffer for the given buffer
+    fn import(&mut self, dmabuf: &Dmabuf) -> Result<Self::Framebuffer>;
+    /// Tests, if the framebuffer can be shown on the plane without a modeset
+    fn test(&mut self, fb: Self::Framebuffer) -> bool;
+    fn destroy(&mut self, fb: Self::Framebuffer);
+}
+
+/// Imports and tests a buffer, `None` means it has to be composited.
+pub fn try_scanout<P: PlaneTest>(plane: &mut P, dmabuf: &Dmabuf) -> Option<P::Framebuffer> {
+    let fb = match plane.import(dmabuf) {
+        Ok(fb) => fb,
+        Err(err) => {
+            slog_scope::debug!("Unable to import buffer for direct scanout: {:?}", err);
+            return None;
+        }
+    };
+    if plane.test(fb) {
+        Some(fb)
+    } else {
+        plane.destroy(fb);
+        None
+    }
+}
+
+/// Results of recent scanout attempts, so buffers are only imported and tested once.
+///
+/// Also holds the client buffers the display still reads from, until a later flip completed.
+#[derive(Debug)]
+pub struct ScanoutCache<F> {
+    /// Most recently used first, `None` for buffers that failed
+    entries: VecDeque<(Dmabuf, Option<F>)>,
+    /// Client buffer of the pending flip, if it is a direct scanout
+    queued: Option<Dmabuf>,
+    /// Client buffer currently on screen
+    on_screen: Option<Dmabuf>,
+    /// Framebuffers dropped from the cache, while they were still in use
+    retired: Vec<(Dmabuf, F)>,
+}
+
+impl<F> Default for ScanoutCache<F> {
+    fn default() -> Self {
+        ScanoutCache {
+            entries: VecDeque::new(),
+            queued: None,
+            on_screen: None,
+            retired: Vec::new(),
+        }
+    }
+}
+
+impl<F: Copy> ScanoutCache<F> {
+    pub fn framebuffer<P: PlaneTest<Framebuffer = F>>(
+        &mut self,
+        plane: &mut P,
+        dmabuf: &Dmabuf,
+    ) -> Option<F> {
+        if let Some(idx) = self.entries.iter().position(|(buf, _)| buf == dmabuf) {
+            let entry = self.entries.remove(idx).unwrap();
+            let fb = entry.1;
+            self.entries.push_front(entry);
+            return fb;
+        }
+
+        let fb = try_scanout(plane, dmabuf);
+        if self.entries.len() >= MAX_CACHED_BUFFERS {
+            if let Some((old_buf, Some(old_fb))) = self.entries.pop_back() {
+                self.retire(old_buf, old_fb, |fb| plane.destroy(fb));
+            }
+        }
+        self.entries.push_front((dmabuf.clone(), fb));
+        fb
+    }
+
+    /// Never try to scan out this buffer again, because it failed the test commit
+    pub fn reject<P: PlaneTest<Framebuffer = F>>(&mut self, plane: &mut P, dmabuf: &Dmabuf) {
+        let fb = self
+            .entries
+            .iter_mut()
+            .find(|(buf, _)| buf == dmabuf)
+            .and_then(|(_, fb)| fb.take());
+        if let Some(fb) = fb {
+            self.retire(dmabuf.clone(), fb, |fb| plane.destroy(fb));
+        }
+    }
+
+    /// If the display reads from the client buffer, now or with the pending flip
+    pub fn holds(&self, dmabuf: &Dmabuf) -> bool {
+        self.queued.as_ref() == Some(dmabuf) || self.on_screen.as_ref() == Some(dmabuf)
+    }
+
+    /// The buffer was submitted for the next flip
+    pub fn queue(&mut self, dmabuf: &Dmabuf) {
+        self.queued = Some(dmabuf.clone());
+    }
+
+    /// The pending flip completed, returns if it was a direct scanout.
+    ///
+    /// Framebuffers of buffers, that left the screen, are destroyed.
+    pub fn frame_submitted(&mut self, mut destroy: impl FnMut(F)) -> bool {
+        self.on_screen = self.queued.take();
+        let on_screen = self.on_screen.as_ref();
+        self.retired.retain(|(buf, fb)| {
+            let in_use = Some(buf) == on_screen;
+            if !in_use {
+                destroy(*fb);
+            }
+            in_use
+        });
+        self.on_screen.is_some()
+    }
+
+    /// Drops all entries, must only be called once none of the framebuffers is on screen anymore
+    pub fn clear(&mut self, mut destroy: impl FnMut(F)) {
+        for (_, fb) in self.entries.drain(..) {
+            if let Some(fb) = fb {
+                destroy(fb);
+            }
+        }
+        for (_, fb) in self.retired.drain(..) {
+            destroy(fb);
+        }
+        self.queued = None;
+        self.on_screen = None;
+    }
+
+    /// Destroys the framebuffer, or keeps it until the display doesn't need it anymore
+    fn retire(&mut self, dmabuf: Dmabuf, fb: F, destroy: impl FnOnce(F)) {
+        if self.queued.as_ref() == Some(&dmabuf) || self.on_screen.as_ref() == Some(&dmabuf) {
+            self.retired.push((dmabuf, fb));
+        } else {
+            destroy(fb);
+        }
+    }
+}
+
+/// Primary plane of a gbm-backed surface
+pub struct GbmPlane<'a> {
+    pub gbm: &'a GbmDevice<SessionFd>,
+    pub surface: &'a DrmSurface<SessionFd>,
+}
+
+impl GbmPlane<'_> {
+    /// Puts the framebuffer on screen with the next vblank
+    pub fn flip(&mut self, fb: framebuffer::Handle) -> Result<()> {
+        if self.surface.commit_pending() {
+            self.surface.commit(fb, true)
+        } else {
+            self.surface.page_flip(fb, true)
+        }
+        .with_context(|| "Failed to submit buffer for direct scanout")
+    }
+}
+
+impl PlaneTest for GbmPlane<'_> {
+    type Framebuffer = framebuffer::Handle;
+
+    fn import(&mut self, dmabuf: &Dmabuf) -> Result<framebuffer::Handle> {
+        let num_planes = dmabuf.num_planes();
+        let mut fds: [RawFd; 4] = [0; 4];
+        let mut strides = [0i32; 4];
+        let mut offsets = [0i32; 4];
+        for (i, ((fd, stride), offset)) in dmabuf
+            .handles()
+            .zip(dmabuf.strides())
+            .zip(dmabuf.offsets())
+            .enumerate()
+        {
+            fds[i] = fd;
+            strides[i] = stride as i32;
+            offsets[i] = offset as i32;
+        }
+
+        let format = dmabuf.format();
+        let size = dmabuf.size();
+        let bo = self
+            .gbm
+            .import_buffer_object_from_dma_buf_with_modifiers::<()>(
+                num_planes as u32,
+                fds,
+                size.w as u32,
+                size.h as u32,
+                format.code,
+                GbmBufferFlags::SCANOUT,
+                strides,
+                offsets,
+                format.modifier,
+            )
+            .with_context(|| "Failed to import dmabuf into gbm")?;
+
+        let fb = if format.modifier != Modifier::Invalid {
+            let mut modifiers = [None; 4];
+            for modifier in modifiers.iter_mut().take(num_planes) {
+                *modifier = Some(format.modifier);
+            }
+            self.surface
+                .add_planar_framebuffer(&bo, &modifiers, FB_MODIFIERS)
+        } else {
+            self.surface
+                .add_planar_framebuffer(&bo, &[None, None, None, None], 0)
+        }
+        .with_context(|| "Failed to create framebuffer")?;
+        // the framebuffer keeps its own reference to the buffer
+        Ok(fb)
+    }
+
+    fn test(&mut self, fb: framebuffer::Handle) -> bool {
+        self.surface
+            .test_buffer(fb, &self.surface.pending_mode(), false)
+            .unwrap_or(false)
+    }
+
+    fn destroy(&mut self, fb: framebuffer::Handle) {
+        let _ = self.surface.destroy_framebuffer(fb);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use smithay::backend::allocator::{dmabuf::DmabufFlags, Fourcc};
+    use std::{fs::File, os::unix::io::IntoRawFd};
+
+    #[derive(Default)]
+    struct MockPlane {
+        /// Whether test commits succeed
+        accept: bool,
+        imported: usize,
+        tested: usize,
+        destroyed: Vec<u32>,
+    }
+
+    impl PlaneTest for MockPlane {
+        type Framebuffer = u32;
+
+        fn import(&mut self, _dmabuf: &Dmabuf) -> Result<u32> {
+            self.imported += 1;
+            Ok(self.imported as u32)
+        }
+
+        fn test(&mut self, _fb: u32) -> bool {
+            self.tested += 1;
+            self.accept
+        }
+
+        fn destroy(&mut self, fb: u32) {
+            self.destroyed.push(fb);
+        }
+    }
+
+    fn dmabuf() -> Dmabuf {
+        let fd = File::open("/dev/null").unwrap().into_raw_fd();
+        let mut builder = Dmabuf::builder((1920, 1080), Fourcc::Xrgb8888, DmabufFlags::empty());
+        builder.add_plane(fd, 0, 0, 1920 * 4, Modifier::Linear);
+        builder.build().unwrap()
+    }
+
+    fn plane(accept: bool) -> MockPlane {
+        MockPlane {
+            accept,
+            ..MockPlane::default()
+        }
+    }
+
+    #[test]
+    fn framebuffer() {
+        let mut plane = plane(true);
+        let mut cache = ScanoutCache::default();
+        let buffer = dmabuf();
+
+        assert_eq!(cache.framebuffer(&mut plane, &buffer), Some(1));
+        // cached buffers are neither imported nor tested again
+        assert_eq!(cache.framebuffer(&mut plane, &buffer), Some(1));
+        assert_eq!((plane.imported, plane.tested), (1, 1));
+        assert!(plane.destroyed.is_empty());
+    }
+
+    #[test]
+    fn failed_test() {
+        let mut plane = plane(false);
+        let mut cache = ScanoutCache::default();
+        let buffer = dmabuf();
+
+        assert_eq!(cache.framebuffer(&mut plane, &buffer), None);
+        assert_eq!(plane.destroyed, vec![1]);
+        assert_eq!(cache.framebuffer(&mut plane, &buffer), None);
+        assert_eq!((plane.imported, plane.tested), (1, 1));
+    }
+
+    #[test]
+    fn reject() {
+        let mut plane = plane(true);
+        let mut cache = ScanoutCache::default();
+        let buffer = dmabuf();
+
+        assert_eq!(cache.framebuffer(&mut plane, &buffer), Some(1));
+        cache.reject(&mut plane, &buffer);
+        assert_eq!(plane.destroyed, vec![1]);
+        assert_eq!(cache.framebuffer(&mut plane, &buffer), None);
+        assert_eq!(plane.imported, 1);
+    }
+
+    #[test]
+    fn evict_least_recently_used() {
+        let mut plane = plane(true);
+        let mut cache = ScanoutCache::default();
+        let buffers = (0..=MAX_CACHED_BUFFERS)
+            .map(|_| dmabuf())
+            .collect::<Vec<_>>();
+
+        for buffer in &buffers[..MAX_CACHED_BUFFERS] {
+            cache.framebuffer(&mut plane, buffer);
+        }
+        // the first buffer is used again, so the second one is the oldest
+        assert_eq!(cache.framebuffer(&mut plane, &buffers[0]), Some(1));
+        cache.framebuffer(&mut plane, &buffers[MAX_CACHED_BUFFERS]);
+        assert_eq!(plane.destroyed, vec![2]);
+        assert_eq!(cache.framebuffer(&mut plane, &buffers[0]), Some(1));
+    }
+
+    #[test]
+    fn hold_buffer_on_screen() {
+        let mut plane = plane(true);
+        let mut cache = ScanoutCache::default();
+        let buffer = dmabuf();
+        let mut destroyed = Vec::new();
+
+        let fb = cache.framebuffer(&mut plane, &buffer).unwrap();
+        cache.queue(&buffer);
+        cache.reject(&mut plane, &buffer);
+        assert!(plane.destroyed.is_empty());
+
+        // still on screen after its own flip
+        assert!(cache.frame_submitted(|fb| destroyed.push(fb)));
+        assert!(destroyed.is_empty());
+
+        // a composited frame replaced it
+        assert!(!cache.frame_submitted(|fb| destroyed.push(fb)));
+        assert_eq!(destroyed, vec![fb]);
+    }
+
+    #[test]
+    fn hold_client_buffer_until_retired() {
+        let mut plane = plane(true);
+        let mut cache = ScanoutCache::default();
+        let buffer = dmabuf();
+        let next = dmabuf();
+
+        cache.framebuffer(&mut plane, &buffer);
+        cache.queue(&buffer);
+        assert!(cache.holds(&buffer));
+        assert!(cache.frame_submitted(|_| {}));
+        assert!(cache.holds(&buffer));
+
+        // the client committed the next buffer, the old one is read until the flip completed
+        cache.framebuffer(&mut plane, &next);
+        cache.queue(&next);
+        assert!(cache.holds(&buffer));
+        assert!(cache.frame_submitted(|_| {}));
+        assert!(!cache.holds(&buffer));
+        assert!(cache.holds(&next));
+
+        // a composited frame replaced it
+        assert!(!cache.frame_submitted(|_| {}));
+        assert!(!cache.holds(&next));
+    }
+}
@@ -0,0 +1,196 @@
+// SPDX-License-Identifier: GPL-3.0-only
+
+#[cfg(feature = "debug")]
+use crate::state::Fps;
+use crate::{backend::render, state::Common};
+
+use super::session_fd::SessionFd;
+use anyhow::{Context, Result};
+use smithay::{
+    backend::{
+        allocator::dmabuf::Dmabuf,
+        drm::DrmSurface,
+        egl::EGLDisplay,
+        renderer::{
+            gles2::{Gles2Renderbuffer, Gles2Renderer},
+            Bind, ExportMem, Offscreen, TextureMapping,
+        },
+    },
+    reexports::drm::{
+        buffer::{Buffer as _, DrmFourcc},
+        control::{dumbbuffer::DumbBuffer, framebuffer, Device as ControlDevice, Mode},
+    },
+    utils::{Buffer, Rectangle, Size},
+    wayland::output::Output,
+};
+
+/// Renderer used for all devices, that failed to initialize hardware-acceleration.
+///
+/// It runs on the software rasterizer of mesa, so it still requires a working EGL implementation.
+pub struct SoftwareRenderer {
+    _egl: EGLDisplay,
+    pub renderer: Gles2Renderer,
+}
+
+impl SoftwareRenderer {
+    pub fn new() -> Result<SoftwareRenderer> {
+        let (egl, renderer) = render::software_renderer()?;
+        Ok(SoftwareRenderer {
+            _egl: egl,
+            renderer,
+        })
+    }
+}
+
+/// Output surface scanning out cpu-accessible dumb buffers.
+///
+/// Frames are rendered offscreen in software and copied into the next dumb buffer.
+pub struct DumbSurface {
+    surface: DrmSurface<SessionFd>,
+    buffers: Vec<(DumbBuffer, framebuffer::Handle)>,
+    /// Index of the buffer currently on screen
+    front: usize,
+    offscreen: Option<(Size<i32, Buffer>, Gles2Renderbuffer)>,
+}
+
+impl DumbSurface {
+    pub fn new(surface: DrmSurface<SessionFd>) -> Result<DumbSurface> {
+        let mut dumb = DumbSurface {
+            surface,
+            buffers: Vec::new(),
+            front: 0,
+            offscreen: None,
+        };
+        dumb.create_buffers(dumb.surface.pending_mode())?;
+        Ok(dumb)
+    }
+
+    fn create_buffers(&mut self, mode: Mode) -> Result<()> {
+        self.destroy_buffers();
+        let (w, h) = mode.size();
+        for _ in 0..2 {
+            let buffer = self
+                .surface
+                .create_dumb_buffer((w as u32, h as u32), DrmFourcc::Xrgb8888, 32)
+                .with_context(|| "Failed to allocate dumb buffer")?;
+            let fb = self
+                .surface
+                .add_framebuffer(&buffer, 24, 32)
+                .with_context(|| "Failed to create framebuffer for dumb buffer")?;
+            self.buffers.push((buffer, fb));
+        }
+        self.front = 0;
+        Ok(())
+    }
+
+    fn destroy_buffers(&mut self) {
+        for (buffer, fb) in self.buffers.drain(..) {
+            let _ = self.surface.destroy_framebuffer(fb);
+            let _ = self.surface.destroy_dumb_buffer(buffer);
+        }
+    }
+
+    pub fn use_mode(&mut self, mode: Mode) -> Result<()> {
+        self.surface.use_mode(mode)?;
+        self.create_buffers(mode)
+    }
+
+    /// The last queued buffer is on screen now
+    pub fn frame_submitted(&mut self) {
+        self.front = (self.front + 1) % self.buffers.len();
+    }
+
+    pub fn reset_buffers(&mut self) {
+        self.offscreen = None;
+    }
+
+    pub fn render_output(
+        &mut self,
+        renderer: &mut Gles2Renderer,
+        state: &mut Common,
+        output: &Output,
+        mirror: Option<(&Output, Option<&Dmabuf>)>,
+        #[cfg(feature = "debug")] fps: &mut Fps,
+    ) -> Result<()> {
+        let (w, h) = self.surface.pending_mode().size();
+        let size = Size::<i32, Buffer>::from((w as i32, h as i32));
+
+        // we keep rendering into the same offscreen buffer, so its age is always 1
+        let age = match self.offscreen.as_ref() {
+            Some((offscreen_size, _)) if *offscreen_size == size => 1,
+            _ => {
+                let buffer = Offscreen::<Gles2Renderbuffer>::create_buffer(renderer, size)
+                    .with_context(|| "Failed to allocate offscreen buffer")?;
+                self.offscreen = Some((size, buffer));
+                0
+            }
+        };
+        renderer
+            .bind(self.offscreen.as_ref().unwrap().1.clone())
+            .with_context(|| "Failed to bind buffer")?;
+
+        let result = match mirror {
+            Some((source, frame)) => render::render_mirror(renderer, output, source, frame),
+            None => render::render_output(
+                None,
+                renderer,
+                age,
+                state,
+                output,
+                false,
+                #[cfg(feature = "debug")]
+                Some(fps),
+            ),
+        };
+        if let Err(err) = result {
+            self.offscreen = None;
+            anyhow::bail!("Rendering failed: {}", err);
+        }
+
+        let mapping = renderer
+            .copy_framebuffer(Rectangle::from_loc_and_size((0, 0), size))
+            .with_context(|| "Failed to read back frame")?;
+        let flipped = mapping.flipped();
+        let pixels = renderer
+            .map_texture(&mapping)
+            .with_context(|| "Failed to read back frame")?;
+
+        let back = (self.front + 1) % self.buffers.len();
+        let (buffer, fb) = &mut self.buffers[back];
+        let pitch = buffer.pitch() as usize;
+        let mut map = self
+            .surface
+            .map_dumb_buffer(buffer)
+            .with_context(|| "Failed to map dumb buffer")?;
+        let (w, h) = (w as usize, h as usize);
+        for y in 0..h {
+            let src_row = if flipped { h - 1 - y } else { y };
+            let src = &pixels[src_row * w * 4..(src_row + 1) * w * 4];
+            let dst = &mut map[y * pitch..y * pitch + w * 4];
+            // gl gives us RGBA, Xrgb8888 is BGRX in memory
+            for (dst, src) in dst.chunks_exact_mut(4).zip(src.chunks_exact(4)) {
+                dst[0] = src[2];
+                dst[1] = src[1];
+                dst[2] = src[0];
+                dst[3] = 0xff;
+            }
+        }
+        std::mem::drop(map);
+
+        let fb = *fb;
+        if self.surface.commit_pending() {
+            self.surface.commit(fb, true)
+        } else {
+            self.surface.page_flip(fb, true)
+        }
+        .with_context(|| "Failed to submit buffer for display")?;
+
+        Ok(())
+    }
+}
+
+impl Drop for DumbSurface {
+    fn drop(&mut self) {
+        self.destroy_buffers();
+    }
+}
@@ -0,0 +1,82 @@
+// SPDX-License-Identifier: GPL-3.0-only
+
+use crate::{
+    backend::offscreen::{self, OffscreenSurface},
+    state::{Common, Data},
+};
+
+use anyhow::{Context, Result};
+use smithay::{
+    backend::{
+        allocator::dmabuf::Dmabuf,
+        drm::DrmNode,
+        renderer::{
+            gles2::Gles2Renderbuffer,
+            multigpu::{egl::EglGlesBackend, GpuManager},
+        },
+    },
+    reexports::calloop::{LoopHandle, RegistrationToken},
+    wayland::output::{Mode, Output},
+};
+use std::time::Instant;
+
+/// Output without a connector, rendered offscreen on the primary gpu.
+///
+/// Its frames are only accessible through screen capturing, e.g. for remote desktop sessions.
+pub struct VirtualSurface {
+    pub offscreen: OffscreenSurface,
+    pub last_render: Option<(Dmabuf, Instant)>,
+    /// If the output is currently part of the shell
+    pub enabled: bool,
+    pub timer_token: RegistrationToken,
+}
+
+impl VirtualSurface {
+    pub fn new(
+        name: String,
+        mode: Mode,
+        scale: f64,
+        position: (i32, i32),
+        loop_handle: &LoopHandle<'_, Data>,
+    ) -> Result<VirtualSurface> {
+        let offscreen = OffscreenSurface::new(name, mode, scale, position);
+        let timer_token = offscreen::insert_frame_clock(
+            loop_handle,
+            offscreen.output.clone(),
+            |output, data| {
+                let backend = data.state.backend.kms();
+                backend.render_virtual_output(output, &mut data.state.common);
+            },
+        )?;
+
+        Ok(VirtualSurface {
+            offscreen,
+            last_render: None,
+            enabled: false,
+            timer_token,
+        })
+    }
+
+    pub fn render_output(
+        &mut self,
+        api: &mut GpuManager<EglGlesBackend>,
+        primary: &DrmNode,
+        mirror: Option<(Output, Option<Dmabuf>)>,
+        state: &mut Common,
+    ) -> Result<()> {
+        let mut renderer = api
+            .renderer::<Gles2Renderbuffer>(primary, primary)
+            .with_context(|| "Failed to create renderer")?;
+        self.offscreen.render_output(
+            Some(primary),
+            &mut renderer,
+            mirror
+                .as_ref()
+                .map(|(source, frame)| (source, frame.as_ref())),
+            state,
+        )?;
+        let dmabuf = self.offscreen.export_frame(&mut renderer)?;
+        self.last_render = Some((dmabuf, Instant::now()));
+        Ok(())
+    }
+}
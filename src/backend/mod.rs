@@ -1,16 +1,17 @@
 // SPDX-License-Identifier: GPL-3.0-only
 
 use crate::state::{Data, State};
-use anyhow::{Context, Result};
+use anyhow::{anyhow, Context, Result};
 use smithay::reexports::{calloop::EventLoop, wayland_server::DisplayHandle};
 
+pub mod offscreen;
 pub mod render;
 
+pub mod headless;
 pub mod kms;
+pub mod wayland;
 pub mod winit;
 pub mod x11;
-// TODO
-// pub mod wayland; // tbd in smithay
 
 pub fn init_backend_auto(
     dh: &DisplayHandle,
@@ -19,13 +20,22 @@ pub fn init_backend_auto(
 ) -> Result<()> {
     let res = match std::env::var("COSMIC_BACKEND") {
         Ok(x) if x == "x11" => x11::init_backend(dh, event_loop, state),
+        Ok(x) if x == "wayland" => wayland::init_backend(dh, event_loop, state),
         Ok(x) if x == "winit" => winit::init_backend(dh, event_loop, state),
         Ok(x) if x == "kms" => kms::init_backend(dh, event_loop, state),
-        Ok(_) => unimplemented!("There is no backend with this identifier"),
+        Ok(x) if x == "headless" => headless::init_backend(dh, event_loop, state),
+        Ok(x) => Err(anyhow!("There is no backend with the identifier {}", x)),
         Err(_) => {
-            if std::env::var_os("DISPLAY").is_some()
-                || std::env::var_os("WAYLAND_DISPLAY").is_some()
-            {
+            if std::env::var_os("WAYLAND_DISPLAY").is_some() {
+                match wayland::init_backend(dh, event_loop, state) {
+                    Ok(_) => Ok(()),
+                    Err(err) => {
+                        slog_scope::warn!("Wayland Backend failed with error: {}", err);
+                        slog_scope::info!("Falling back to winit backend.");
+                        winit::init_backend(dh, event_loop, state)
+                    }
+                }
+            } else if std::env::var_os("DISPLAY").is_some() {
                 match x11::init_backend(dh, event_loop, state) {
                     Ok(_) => Ok(()),
                     Err(err) => {
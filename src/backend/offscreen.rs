@@ -0,0 +1,204 @@
+// SPDX-License-Identifier: GPL-3.0-only
+
+#[cfg(feature = "debug")]
+use crate::state::Fps;
+use crate::{
+    backend::render::{self, AsGles2Renderer, CustomElem},
+    config::OutputConfig,
+    state::{Common, Data},
+    utils::prelude::*,
+};
+
+use anyhow::{anyhow, Context, Result};
+use smithay::{
+    backend::{
+        allocator::dmabuf::Dmabuf,
+        drm::DrmNode,
+        renderer::{
+            gles2::Gles2Renderbuffer, Bind, ExportDma, ImportAll, ImportDma, Offscreen, Renderer,
+        },
+    },
+    desktop::space::RenderElement,
+    reexports::{
+        calloop::{
+            timer::{TimeoutAction, Timer},
+            LoopHandle, RegistrationToken,
+        },
+        wayland_server::protocol::wl_output::{Subpixel, Transform},
+    },
+    utils::{Buffer, Size},
+    wayland::output::{Mode, Output, PhysicalProperties, Scale},
+};
+use std::{cell::RefCell, time::Duration};
+
+/// Buffers rendered in turns, so the last frame stays untouched while the next one is rendered
+const BUFFERS: usize = 2;
+
+/// Output without a display, rendered into offscreen buffers.
+///
+/// Offscreen outputs can take any mode, the frame clock picks up a new refresh rate on its own.
+pub struct OffscreenSurface {
+    pub output: Output,
+    /// Oldest first
+    buffers: Vec<(Size<i32, Buffer>, Gles2Renderbuffer)>,
+    /// If the output needs to be rendered with the next tick of the frame clock
+    pub dirty: bool,
+    #[cfg(feature = "debug")]
+    fps: Fps,
+}
+
+impl OffscreenSurface {
+    /// Creates the output with the given name, its only mode and its config.
+    pub fn new(name: String, mode: Mode, scale: f64, position: (i32, i32)) -> OffscreenSurface {
+        let output = Output::new(
+            name.clone(),
+            PhysicalProperties {
+                size: (0, 0).into(),
+                subpixel: Subpixel::Unknown,
+                make: "COSMIC".to_string(),
+                model: name,
+            },
+            None,
+        );
+        output.add_mode(mode);
+        output.set_preferred(mode);
+        output.change_current_state(
+            Some(mode),
+            Some(Transform::Normal),
+            Some(Scale::Fractional(scale)),
+            Some(position.into()),
+        );
+        output.user_data().insert_if_missing(|| {
+            RefCell::new(OutputConfig {
+                mode: ((mode.size.w, mode.size.h), Some(mode.refresh as u32)),
+                scale,
+                position,
+                ..Default::default()
+            })
+        });
+
+        OffscreenSurface {
+            output,
+            buffers: Vec::new(),
+            dirty: true,
+            #[cfg(feature = "debug")]
+            fps: Fps::default(),
+        }
+    }
+
+    /// Renders the next frame into the buffer, that was not rendered into last.
+    pub fn render_output<R>(
+        &mut self,
+        gpu: Option<&DrmNode>,
+        renderer: &mut R,
+        mirror: Option<(&Output, Option<&Dmabuf>)>,
+        state: &mut Common,
+    ) -> Result<()>
+    where
+        R: Renderer + ImportAll + ImportDma + Bind<Gles2Renderbuffer> + AsGles2Renderer,
+        <R as Renderer>::TextureId: Clone + 'static,
+        CustomElem: RenderElement<R>,
+    {
+        if render::needs_buffer_reset(&self.output, state) {
+            self.buffers.clear();
+        }
+
+        let size = self.buffer_size();
+        self.buffers.retain(|(buffer_size, _)| *buffer_size == size);
+        let age = if self.buffers.len() < BUFFERS {
+            let buffer = Offscreen::<Gles2Renderbuffer>::create_buffer(renderer.as_gles2(), size)
+                .with_context(|| "Failed to allocate buffer")?;
+            self.buffers.push((size, buffer));
+            0
+        } else {
+            // every frame rotates the buffers, so the oldest one is as old as there are buffers
+            self.buffers.rotate_left(1);
+            BUFFERS as u8
+        };
+        renderer
+            .bind(self.buffers.last().unwrap().1.clone())
+            .with_context(|| "Failed to bind buffer")?;
+
+        let result = match mirror {
+            Some((source, frame)) => {
+                render::render_mirror(renderer, &self.output, source, frame).map(|_| ())
+            }
+            None => render::render_output(
+                gpu,
+                renderer,
+                age,
+                state,
+                &self.output,
+                false,
+                #[cfg(feature = "debug")]
+                Some(&mut self.fps),
+            )
+            .map(|_| ()),
+        };
+        if let Err(err) = result {
+            self.buffers.clear();
+            anyhow::bail!("Rendering failed: {}", err);
+        }
+
+        self.send_frames(state);
+        Ok(())
+    }
+
+    /// Drops the buffers, e.g. while the output is disabled.
+    pub fn release_buffers(&mut self) {
+        self.buffers.clear();
+    }
+
+    pub fn send_frames(&self, state: &mut Common) {
+        state
+            .shell
+            .active_space_mut(&self.output)
+            .space
+            .send_frames(state.start_time.elapsed().as_millis() as u32);
+    }
+
+    /// Exports the last rendered frame, which stays untouched until the next but one frame.
+    ///
+    /// Must be called with the renderer still bound to that frame.
+    pub fn export_frame<R: AsGles2Renderer>(&self, renderer: &mut R) -> Result<Dmabuf> {
+        renderer
+            .as_gles2()
+            .export_framebuffer(self.buffer_size())
+            .with_context(|| "Failed to export frame")
+    }
+
+    fn buffer_size(&self) -> Size<i32, Buffer> {
+        self.output
+            .geometry()
+            .size
+            .to_f64()
+            .to_buffer(
+                self.output.current_scale().fractional_scale(),
+                self.output.current_transform().into(),
+            )
+            .to_i32_round()
+    }
+}
+
+/// Inserts a timer as frame clock of the given output, as there is no display telling us when to render.
+pub fn insert_frame_clock(
+    loop_handle: &LoopHandle<'_, Data>,
+    output: Output,
+    mut render: impl FnMut(&Output, &mut Data) + 'static,
+) -> Result<RegistrationToken> {
+    loop_handle
+        .insert_source(Timer::immediate(), move |_, _, data| {
+            render(&output, data);
+            TimeoutAction::ToDuration(frame_interval(&output))
+        })
+        .map_err(|_| anyhow!("Failed to add output to event loop"))
+}
+
+fn frame_interval(output: &Output) -> Duration {
+    let refresh = output
+        .current_mode()
+        .map(|mode| mode.refresh)
+        .filter(|refresh| *refresh > 0)
+        .unwrap_or(60_000);
+    Duration::from_nanos(1_000_000_000_000 / refresh as u64)
+}
@@ -1,6 +1,8 @@
 // SPDX-License-Identifier: GPL-3.0-only
 
-use crate::{utils::prelude::*, wayland::handlers::data_device::get_dnd_icon};
+use crate::{
+    input::TabletToolCursor, utils::prelude::*, wayland::handlers::data_device::get_dnd_icon,
+};
 use smithay::{
     backend::renderer::{Frame, ImportAll, ImportMem, Renderer, Texture},
     desktop::space::{RenderElement, SpaceOutputTuple, SurfaceTree},
@@ -290,63 +292,99 @@ where
     }
 
     // draw the cursor as relevant
-    {
-        // reset the cursor if the surface is no longer alive
-        let cursor_status = seat
-            .user_data()
-            .get::<RefCell<CursorImageStatus>>()
-            .map(|cell| {
-                let mut cursor_status = cell.borrow_mut();
-                if let CursorImageStatus::Image(ref surface) = *cursor_status {
-                    if !surface.alive() {
-                        *cursor_status = CursorImageStatus::Default;
-                    }
-                }
-                cursor_status.clone()
-            })
-            .unwrap_or(CursorImageStatus::Default);
-
-        if let CursorImageStatus::Image(wl_surface) = cursor_status {
-            Some(draw_surface_cursor(wl_surface.clone(), location.to_i32_round()).into())
-        } else if draw_default {
-            let seat_userdata = seat.user_data();
-            seat_userdata.insert_if_missing(CursorState::default);
-            let state = seat_userdata.get::<CursorState>().unwrap();
-            let frame = state
-                .cursor
-                .get_image(1, start_time.elapsed().as_millis() as u32);
-            let new_frame = state.current_image.borrow().as_ref() != Some(&frame);
-
-            let mut cache = state.image_cache.borrow_mut();
-            let pointer_images = cache
-                .entry((TypeId::of::<<R as Renderer>::TextureId>(), renderer.id()))
-                .or_default();
-            let pointer_image = pointer_images
-                .iter()
-                .find_map(|(image, texture)| if image == &frame { Some(texture) } else { None })
-                .and_then(|texture| {
-                    texture
-                        .downcast_ref::<<R as Renderer>::TextureId>()
-                        .cloned()
-                })
-                .unwrap_or_else(|| {
-                    let texture = renderer
-                        .import_memory(
-                            &frame.pixels_rgba,
-                            (frame.width as i32, frame.height as i32).into(),
-                            false,
-                        )
-                        .expect("Failed to import cursor bitmap");
-                    pointer_images.push((frame.clone(), Box::new(texture.clone())));
-                    texture
-                });
-            let hotspot =
-                Point::<i32, Logical>::from((frame.xhot as i32, frame.yhot as i32)).to_f64();
-            *state.current_image.borrow_mut() = Some(frame);
-
-            Some(PointerElement::new(seat, pointer_image.clone(), location - hotspot, new_frame).into())
-        } else {
-            None
+    let cursor_status = seat
+        .user_data()
+        .get::<RefCell<CursorImageStatus>>()
+        .map(current_status)
+        .unwrap_or(CursorImageStatus::Default);
+    draw_cursor_status(renderer, seat, cursor_status, location, start_time, draw_default)
+}
+
+/// Draws the cursor of a tablet tool, that is in proximity of one of the seats tablets.
+pub fn draw_tablet_cursor<R, I>(
+    renderer: &mut R,
+    seat: &Seat<State>,
+    location: Point<f64, Logical>,
+    start_time: &std::time::Instant,
+    draw_default: bool,
+) -> Option<I>
+where
+    I: From<SurfaceTree> + From<PointerElement<<R as Renderer>::TextureId>>,
+    R: Renderer + ImportAll + ImportMem,
+    <R as Renderer>::TextureId: Clone + 'static,
+{
+    let cursor_status = seat
+        .user_data()
+        .get::<TabletToolCursor>()
+        .map(|tool| current_status(&tool.status))
+        .unwrap_or(CursorImageStatus::Default);
+    draw_cursor_status(renderer, seat, cursor_status, location, start_time, draw_default)
+}
+
+// reset the cursor if the surface is no longer alive
+fn current_status(cell: &RefCell<CursorImageStatus>) -> CursorImageStatus {
+    let mut cursor_status = cell.borrow_mut();
+    if let CursorImageStatus::Image(ref surface) = *cursor_status {
+        if !surface.alive() {
+            *cursor_status = CursorImageStatus::Default;
         }
     }
+    cursor_status.clone()
+}
+
+fn draw_cursor_status<R, I>(
+    renderer: &mut R,
+    seat: &Seat<State>,
+    cursor_status: CursorImageStatus,
+    location: Point<f64, Logical>,
+    start_time: &std::time::Instant,
+    draw_default: bool,
+) -> Option<I>
+where
+    I: From<SurfaceTree> + From<PointerElement<<R as Renderer>::TextureId>>,
+    R: Renderer + ImportAll + ImportMem,
+    <R as Renderer>::TextureId: Clone + 'static,
+{
+    if let CursorImageStatus::Image(wl_surface) = cursor_status {
+        Some(draw_surface_cursor(wl_surface.clone(), location.to_i32_round()).into())
+    } else if draw_default {
+        let seat_userdata = seat.user_data();
+        seat_userdata.insert_if_missing(CursorState::default);
+        let state = seat_userdata.get::<CursorState>().unwrap();
+        let frame = state
+            .cursor
+            .get_image(1, start_time.elapsed().as_millis() as u32);
+        let new_frame = state.current_image.borrow().as_ref() != Some(&frame);
+
+        let mut cache = state.image_cache.borrow_mut();
+        let pointer_images = cache
+            .entry((TypeId::of::<<R as Renderer>::TextureId>(), renderer.id()))
+            .or_default();
+        let pointer_image = pointer_images
+            .iter()
+            .find_map(|(image, texture)| if image == &frame { Some(texture) } else { None })
+            .and_then(|texture| {
+                texture
+                    .downcast_ref::<<R as Renderer>::TextureId>()
+                    .cloned()
+            })
+            .unwrap_or_else(|| {
+                let texture = renderer
+                    .import_memory(
+                        &frame.pixels_rgba,
+                        (frame.width as i32, frame.height as i32).into(),
+                        false,
+                    )
+                    .expect("Failed to import cursor bitmap");
+                pointer_images.push((frame.clone(), Box::new(texture.clone())));
+                texture
+            });
+        let hotspot =
+            Point::<i32, Logical>::from((frame.xhot as i32, frame.yhot as i32)).to_f64();
+        *state.current_image.borrow_mut() = Some(frame);
+
+        Some(PointerElement::new(seat, pointer_image.clone(), location - hotspot, new_frame).into())
+    } else {
+        None
+    }
 }
@@ -1,6 +1,7 @@
 // SPDX-License-Identifier: GPL-3.0-only
 
 use crate::{
+    input::TabletToolCursor,
     state::Common,
     shell::grabs::{
         SeatMoveGrabState,
@@ -14,14 +15,17 @@ use crate::{
     utils::prelude::*,
 };
 
+use anyhow::Context;
 use slog::Logger;
 use smithay::{
     backend::{
+        allocator::{dmabuf::Dmabuf, Buffer},
         drm::DrmNode,
+        egl::{EGLContext, EGLDevice, EGLDisplay},
         renderer::{
             gles2::{Gles2Renderbuffer, Gles2Renderer, Gles2Texture},
             multigpu::{egl::EglGlesBackend, Error as MultiError, MultiFrame, MultiRenderer},
-            Frame, ImportAll, Renderer,
+            Frame, ImportAll, ImportDma, Renderer,
         },
     },
     desktop::{
@@ -29,11 +33,12 @@ use smithay::{
         layer_map_for_output,
         space::{RenderElement, RenderError, SpaceOutputTuple, SurfaceTree},
         utils::damage_from_surface_tree,
-        Window,
+        LayerSurface, Window, WindowSurfaceType,
     },
-    utils::{Physical, Point, Rectangle, Scale, Transform},
+    utils::{Logical, Physical, Point, Rectangle, Scale, Size, Transform},
     wayland::{output::Output, shell::wlr_layer::Layer as WlrLayer},
 };
+use std::{cell::RefCell, collections::VecDeque, mem::Discriminant};
 
 pub mod cursor;
 use self::cursor::PointerElement;
@@ -43,6 +48,10 @@ pub type GlMultiRenderer<'a> =
 pub type GlMultiFrame = MultiFrame<EglGlesBackend, EglGlesBackend>;
 
 static CLEAR_COLOR: [f32; 4] = [0.153, 0.161, 0.165, 1.0];
+/// Input method popups are drawn above any other surface, but below the cursor
+const INPUT_POPUP_Z_INDEX: u8 = 90;
+/// Number of frames the fullscreen damage history reaches back
+const MAX_DAMAGE_AGE: usize = 4;
 
 smithay::custom_elements! {
     pub CustomElem<=Gles2Renderer>;
@@ -124,6 +133,28 @@ impl AsGles2Renderer for GlMultiRenderer<'_> {
     }
 }
 
+/// Creates a renderer on the software rasterizer of mesa, for systems without a usable gpu.
+pub fn software_renderer() -> anyhow::Result<(EGLDisplay, Gles2Renderer)> {
+    let device = EGLDevice::enumerate()
+        .with_context(|| "Failed to enumerate EGL devices")?
+        .find(|device| {
+            device
+                .extensions()
+                .iter()
+                .any(|ext| ext == "EGL_MESA_device_software")
+        })
+        .with_context(|| "No software EGL device available")?;
+    let egl = EGLDisplay::new(&device, None).with_context(|| "Failed to create EGL display")?;
+    let context = EGLContext::new(&egl, None).with_context(|| "Failed to create EGL context")?;
+    let renderer = unsafe { Gles2Renderer::new(context, None) }
+        .with_context(|| "Failed to initialize renderer")?;
+    Ok((egl, renderer))
+}
+
+/// Returns true, if the buffers of an output have to be reset before rendering.
+///
+/// The fullscreen path tracks damage on its own and picks up buffers rendered by the space,
+/// but the space does not know about frames rendered by the fullscreen path.
 pub fn needs_buffer_reset(output: &Output, state: &Common) -> bool {
     use std::sync::atomic::{AtomicBool, Ordering};
     struct DidCustomRendering(AtomicBool);
@@ -135,12 +166,146 @@ pub fn needs_buffer_reset(output: &Output, state: &Common) -> bool {
 
     let userdata = output.user_data();
     userdata.insert_if_missing(|| DidCustomRendering(AtomicBool::new(false)));
-    userdata
+    let did_render_custom = userdata
         .get::<DidCustomRendering>()
         .unwrap()
         .0
-        .swap(will_render_custom, Ordering::AcqRel)
-        != will_render_custom
+        .swap(will_render_custom, Ordering::AcqRel);
+    did_render_custom && !will_render_custom
+}
+
+#[derive(PartialEq)]
+enum ElementId {
+    Window(Window),
+    Layer(LayerSurface),
+    Custom(Discriminant<CustomElem>, usize),
+}
+
+/// Damage tracking state of the fullscreen render path, stored per output
+#[derive(Default)]
+struct FullscreenDamage {
+    /// Elements drawn in the last frame and their geometry
+    last_elements: Vec<(ElementId, Rectangle<i32, Physical>)>,
+    /// Damage of the previous frames, newest first
+    history: VecDeque<Vec<Rectangle<i32, Physical>>>,
+}
+
+impl FullscreenDamage {
+    /// Computes the damage of a new frame and records it
+    fn frame_damage(
+        &mut self,
+        elements: Vec<(
+            ElementId,
+            Rectangle<i32, Physical>,
+            Vec<Rectangle<i32, Physical>>,
+        )>,
+        output_geo: Rectangle<i32, Physical>,
+    ) -> Vec<Rectangle<i32, Physical>> {
+        let mut damage = Vec::new();
+        for (id, geo, elem_damage) in &elements {
+            match self.last_elements.iter().find(|(last_id, _)| last_id == id) {
+                Some((_, last_geo)) if last_geo == geo => {
+                    damage.extend(elem_damage.iter().copied())
+                }
+                Some((_, last_geo)) => damage.extend([*last_geo, *geo]),
+                None => damage.push(*geo),
+            }
+        }
+        for (last_id, last_geo) in &self.last_elements {
+            if !elements.iter().any(|(id, _, _)| id == last_id) {
+                damage.push(*last_geo);
+            }
+        }
+        let damage = damage
+            .into_iter()
+            .filter_map(|rect| rect.intersection(output_geo))
+            .collect::<Vec<_>>();
+
+        self.last_elements = elements.into_iter().map(|(id, geo, _)| (id, geo)).collect();
+        self.history.push_front(damage.clone());
+        self.history.truncate(MAX_DAMAGE_AGE);
+        damage
+    }
+
+    /// Damage a buffer of the given age needs to be repainted with, `None` if it is too old
+    fn damage_for_age(&self, age: u8) -> Option<Vec<Rectangle<i32, Physical>>> {
+        let age = age as usize;
+        // the newest entry is the current frame
+        if age == 0 || age > self.history.len() {
+            return None;
+        }
+        Some(self.history.iter().take(age).flatten().copied().collect())
+    }
+}
+
+/// Draws the last frame of the output mirrored by `output`, scaled to fit and centered.
+///
+/// Without a `frame` the output is just cleared.
+pub fn render_mirror<R>(
+    renderer: &mut R,
+    output: &Output,
+    source: &Output,
+    frame: Option<&Dmabuf>,
+) -> Result<Option<Vec<Rectangle<i32, Physical>>>, RenderError<R>>
+where
+    R: Renderer + ImportDma,
+{
+    let transform = Transform::from(output.current_transform());
+    let mode = output.current_mode().unwrap();
+    let area = transform.transform_size(mode.size);
+    let output_geo = Rectangle::from_loc_and_size((0, 0), area);
+
+    let texture = frame
+        .map(|frame| renderer.import_dmabuf(frame, None))
+        .transpose()
+        .map_err(RenderError::<R>::Rendering)?;
+    // the frame is rendered for the transform of the source
+    let src_transform = Transform::from(source.current_transform());
+    let src_size = frame
+        .map(|frame| src_transform.transform_size(frame.size()))
+        .unwrap_or_else(|| (1, 1).into());
+    let scale = f64::min(
+        area.w as f64 / src_size.w as f64,
+        area.h as f64 / src_size.h as f64,
+    );
+    let size = Size::<f64, Physical>::from((src_size.w as f64 * scale, src_size.h as f64 * scale));
+    let location = Point::<f64, Physical>::from((
+        (area.w as f64 - size.w) / 2.0,
+        (area.h as f64 - size.h) / 2.0,
+    ));
+
+    renderer
+        .render(mode.size, transform, |_, frame| {
+            frame.clear(CLEAR_COLOR, &[output_geo])?;
+            if let Some(texture) = texture.as_ref() {
+                frame.render_texture_at(
+                    texture,
+                    location.to_i32_round(),
+                    1,
+                    scale,
+                    src_transform,
+                    &[Rectangle::from_loc_and_size((0, 0), size.to_i32_round())],
+                    1.0,
+                )?;
+            }
+            Ok(Some(vec![output_geo]))
+        })
+        .and_then(std::convert::identity)
+        .map_err(RenderError::<R>::Rendering)
+}
+
+fn input_popups<'a>(
+    state: &'a Common,
+    parent_location: Point<i32, Logical>,
+) -> impl Iterator<Item = CustomElem> + 'a {
+    state.shell.input_method_state.popups().map(move |popup| {
+        SurfaceTree {
+            surface: popup.surface().clone(),
+            position: parent_location + popup.location(),
+            z_index: INPUT_POPUP_Z_INDEX,
+        }
+        .into()
+    })
 }
 
 pub fn render_output<R>(
@@ -196,13 +361,17 @@ where
     let res = if let Some(window) = maybe_fullscreen_window {
         #[cfg(not(feature = "debug"))]
         {
-            render_fullscreen(gpu, renderer, window, state, output, hardware_cursor)
+            render_fullscreen(gpu, renderer, age, window, state, space_idx, output, hardware_cursor)
         }
         #[cfg(feature = "debug")]
         {
-            render_fullscreen(gpu, renderer, window, state, output, hardware_cursor, fps.as_deref_mut())
+            render_fullscreen(gpu, renderer, age, window, state, space_idx, output, hardware_cursor, fps.as_deref_mut())
         }
     } else {
+        // the history is outdated once the space rendered a frame in between
+        if let Some(damage) = output.user_data().get::<RefCell<FullscreenDamage>>() {
+            *damage.borrow_mut() = FullscreenDamage::default();
+        }
         #[cfg(not(feature = "debug"))]
         {
             render_desktop(gpu, renderer, age, state, space_idx, output, hardware_cursor)
@@ -273,6 +442,27 @@ where
         }
     }
 
+    // input method popups are placed relative to the surface of the focused text-input
+    let workspace = &state.shell.spaces[space_idx];
+    if let Some(parent_location) = state.shell.text_input_state.focus().and_then(|surface| {
+        if let Some(window) = workspace
+            .space
+            .window_for_surface(surface, WindowSurfaceType::TOPLEVEL)
+        {
+            workspace
+                .space
+                .window_location(window)
+                .map(|location| location - window.geometry().loc)
+        } else {
+            let map = layer_map_for_output(output);
+            let layer_surface = map.layer_for_surface(surface, WindowSurfaceType::TOPLEVEL)?;
+            let output_location = workspace.space.output_geometry(output)?.loc;
+            Some(output_location + map.layer_geometry(layer_surface)?.loc)
+        }
+    }) {
+        custom_elements.extend(input_popups(state, parent_location));
+    }
+
     for seat in &state.seats {
         let pointer = match seat.get_pointer() {
             Some(ptr) => ptr,
@@ -288,13 +478,30 @@ where
             custom_elements.push(grab);
         }
 
-        if let Some(cursor) = cursor::draw_cursor(
-            renderer.as_gles2(),
-            seat,
-            location,
-            &state.start_time,
-            !hardware_cursor,
-        ) {
+        // a tablet tool in proximity takes over the cursor of its seat
+        let tool_location = seat
+            .user_data()
+            .get::<TabletToolCursor>()
+            .and_then(TabletToolCursor::location);
+        let cursor = match tool_location {
+            Some(tool_location) => cursor::draw_tablet_cursor(
+                renderer.as_gles2(),
+                seat,
+                state
+                    .shell
+                    .space_relative_output_geometry(tool_location, output),
+                &state.start_time,
+                !hardware_cursor,
+            ),
+            None => cursor::draw_cursor(
+                renderer.as_gles2(),
+                seat,
+                location,
+                &state.start_time,
+                !hardware_cursor,
+            ),
+        };
+        if let Some(cursor) = cursor {
             custom_elements.push(cursor)
         }
     }
@@ -311,8 +518,10 @@ where
 fn render_fullscreen<R>(
     _gpu: Option<&DrmNode>,
     renderer: &mut R,
+    age: u8,
     window: Window,
     state: &mut Common,
+    space_idx: usize,
     output: &Output,
     hardware_cursor: bool,
     #[cfg(feature = "debug")] fps: Option<&mut Fps>,
@@ -343,6 +552,19 @@ where
         custom_elements.push(fps_overlay.into());
     }
 
+    // input method popups are placed relative to the surface of the focused text-input
+    if let Some(parent_location) = state.shell.text_input_state.focus().and_then(|surface| {
+        if window.toplevel().wl_surface() == surface {
+            Some((0, 0).into())
+        } else {
+            let map = layer_map_for_output(output);
+            let layer_surface = map.layer_for_surface(surface, WindowSurfaceType::TOPLEVEL)?;
+            map.layer_geometry(layer_surface).map(|geo| geo.loc)
+        }
+    }) {
+        custom_elements.extend(input_popups(state, parent_location));
+    }
+
     for seat in &state.seats {
         let pointer = match seat.get_pointer() {
             Some(ptr) => ptr,
@@ -352,55 +574,164 @@ where
             .shell
             .space_relative_output_geometry(pointer.current_location().to_i32_round(), output);
 
-        if let Some(cursor) = cursor::draw_cursor(
-            renderer.as_gles2(),
-            seat,
-            location,
-            &state.start_time,
-            !hardware_cursor,
-        ) {
+        // a tablet tool in proximity takes over the cursor of its seat
+        let tool_location = seat
+            .user_data()
+            .get::<TabletToolCursor>()
+            .and_then(TabletToolCursor::location);
+        let cursor = match tool_location {
+            Some(tool_location) => cursor::draw_tablet_cursor(
+                renderer.as_gles2(),
+                seat,
+                state
+                    .shell
+                    .space_relative_output_geometry(tool_location, output),
+                &state.start_time,
+                !hardware_cursor,
+            ),
+            None => cursor::draw_cursor(
+                renderer.as_gles2(),
+                seat,
+                location,
+                &state.start_time,
+                !hardware_cursor,
+            ),
+        };
+        if let Some(cursor) = cursor {
             custom_elements.push(cursor)
         }
     }
 
+    let output_geo = Rectangle::from_loc_and_size((0, 0), mode.size);
+    let layer_map = layer_map_for_output(output);
+    let layers = layer_map
+        .layers_on(WlrLayer::Overlay)
+        .map(|layer_surface| {
+            let geo = layer_map.layer_geometry(layer_surface).unwrap();
+            (layer_surface.clone(), geo)
+        })
+        .collect::<Vec<_>>();
+
+    output
+        .user_data()
+        .insert_if_missing(|| RefCell::new(FullscreenDamage::default()));
+    let mut tracker = output
+        .user_data()
+        .get::<RefCell<FullscreenDamage>>()
+        .unwrap()
+        .borrow_mut();
+    let (damage, repaint) = if age == 0 {
+        // buffers without history (including offscreen captures) are repainted completely.
+        // Don't consume any surface damage here, it might be needed by the next frame of a
+        // proper swapchain, but older buffers can't rely on the history anymore.
+        tracker.history.clear();
+        (vec![output_geo], vec![output_geo])
+    } else {
+        let space = &state.shell.spaces[space_idx].space;
+        let mut elements = Vec::new();
+        elements.push((
+            ElementId::Window(window.clone()),
+            window.physical_bbox_with_popups((0.0, 0.0), scale),
+            window.accumulated_damage((0.0, 0.0), scale, Some((space, output))),
+        ));
+        for (layer_surface, geo) in &layers {
+            let location = geo.loc.to_f64().to_physical(scale);
+            elements.push((
+                ElementId::Layer(layer_surface.clone()),
+                Rectangle::from_loc_and_size(
+                    location.to_i32_round(),
+                    geo.size.to_physical_precise_round(scale),
+                ),
+                damage_from_surface_tree(
+                    layer_surface.wl_surface(),
+                    location,
+                    scale,
+                    Some((space, output)),
+                ),
+            ));
+        }
+        for elem in &custom_elements {
+            let geo = elem.geometry(scale);
+            let elem_damage = elem
+                .accumulated_damage(scale, Some(SpaceOutputTuple(space, output)))
+                .into_iter()
+                .map(|mut rect| {
+                    rect.loc += geo.loc;
+                    rect
+                })
+                .collect();
+            elements.push((
+                ElementId::Custom(std::mem::discriminant(elem), elem.id()),
+                geo,
+                elem_damage,
+            ));
+        }
+
+        let damage = tracker.frame_damage(elements, output_geo);
+        let repaint = tracker
+            .damage_for_age(age)
+            .unwrap_or_else(|| vec![output_geo]);
+        (damage, repaint)
+    };
+    std::mem::drop(tracker);
+
+    // damage is passed to the elements relative to their location
+    let relative_damage = |geo: Rectangle<i32, Physical>| {
+        repaint
+            .iter()
+            .filter_map(|rect| rect.intersection(geo))
+            .map(|mut rect| {
+                rect.loc -= geo.loc;
+                rect
+            })
+            .collect::<Vec<_>>()
+    };
+
     renderer
         .render(mode.size, transform, |renderer, frame| {
-            let mut damage = window.accumulated_damage((0.0, 0.0), scale, None);
-            frame.clear(
-                CLEAR_COLOR,
-                &[Rectangle::from_loc_and_size((0, 0), mode.size)],
-            )?;
-            draw_window(
-                renderer,
-                frame,
-                &window,
-                scale,
-                (0.0, 0.0),
-                &[Rectangle::from_loc_and_size((0, 0), mode.size)],
-                &slog_scope::logger(),
-            )?;
-            draw_window_popups(
-                renderer,
-                frame,
-                &window,
-                scale,
-                (0.0, 0.0),
-                &[Rectangle::from_loc_and_size((0, 0), mode.size)],
-                &slog_scope::logger(),
-            )?;
-            let layer_map = layer_map_for_output(output);
-            for layer_surface in layer_map.layers_on(WlrLayer::Overlay) {
-                let geo = layer_map.layer_geometry(&layer_surface).unwrap();
+            if repaint.is_empty() {
+                return Ok(Some(damage));
+            }
+
+            frame.clear(CLEAR_COLOR, &repaint)?;
+            let window_damage =
+                relative_damage(window.physical_bbox_with_popups((0.0, 0.0), scale));
+            if !window_damage.is_empty() {
+                draw_window(
+                    renderer,
+                    frame,
+                    &window,
+                    scale,
+                    (0.0, 0.0),
+                    &window_damage,
+                    &slog_scope::logger(),
+                )?;
+                draw_window_popups(
+                    renderer,
+                    frame,
+                    &window,
+                    scale,
+                    (0.0, 0.0),
+                    &window_damage,
+                    &slog_scope::logger(),
+                )?;
+            }
+            for (layer_surface, geo) in &layers {
+                let location = geo.loc.to_f64().to_physical(scale);
+                let layer_damage = relative_damage(Rectangle::from_loc_and_size(
+                    location.to_i32_round(),
+                    geo.size.to_physical_precise_round(scale),
+                ));
+                if layer_damage.is_empty() {
+                    continue;
+                }
                 draw_layer_surface(
                     renderer,
                     frame,
                     layer_surface,
                     scale,
-                    geo.loc.to_f64().to_physical(scale),
-                    &[Rectangle::from_loc_and_size(
-                        (0, 0),
-                        geo.size.to_physical_precise_round(scale),
-                    )],
+                    location,
+                    &layer_damage,
                     &slog_scope::logger(),
                 )?;
                 draw_layer_popups(
@@ -408,36 +739,25 @@ where
                     frame,
                     layer_surface,
                     scale,
-                    geo.loc.to_f64().to_physical(scale),
-                    &[Rectangle::from_loc_and_size(
-                        (0, 0),
-                        geo.size.to_physical_precise_round(scale),
-                    )],
+                    location,
+                    &layer_damage,
                     &slog_scope::logger(),
                 )?;
-                damage.extend(damage_from_surface_tree(
-                    layer_surface.wl_surface(),
-                    geo.loc.to_f64().to_physical(scale),
-                    scale,
-                    None,
-                ));
             }
             for elem in custom_elements {
                 let loc = elem.location(scale);
-                let geo = elem.geometry(scale);
-                let elem_damage = elem.accumulated_damage(scale, None);
+                let elem_damage = relative_damage(elem.geometry(scale));
+                if elem_damage.is_empty() {
+                    continue;
+                }
                 elem.draw(
                     renderer,
                     frame,
                     scale,
                     loc,
-                    &[Rectangle::from_loc_and_size((0, 0), geo.size)],
+                    &elem_damage,
                     &slog_scope::logger(),
                 )?;
-                damage.extend(elem_damage.into_iter().map(|mut rect| {
-                    rect.loc += geo.loc;
-                    rect
-                }))
             }
             Ok(Some(damage))
         })
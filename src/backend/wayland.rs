@@ -0,0 +1,662 @@
+// SPDX-License-Identifier: GPL-3.0-only
+
+use crate::{
+    backend::render,
+    config::OutputConfig,
+    input::{
+        Devices, VirtualDevice, VirtualInput, VirtualKeyboardKeyEvent, VirtualPointerAxisEvent,
+        VirtualPointerButtonEvent, VirtualPointerMotionAbsoluteEvent,
+    },
+    state::{BackendData, Common, Data},
+    utils::prelude::*,
+};
+use anyhow::{anyhow, Context, Result};
+use smithay::{
+    backend::{
+        egl::{
+            context::{GlAttributes, PixelFormatRequirements},
+            EGLContext, EGLDisplay, EGLSurface,
+        },
+        input::{AxisSource, ButtonState, DeviceCapability, InputEvent, KeyState},
+        renderer::{gles2::Gles2Renderer, Bind, ImportDma, ImportEgl},
+    },
+    desktop::layer_map_for_output,
+    reexports::{
+        calloop::{
+            ping,
+            timer::{TimeoutAction, Timer},
+            EventLoop, LoopHandle, RegistrationToken,
+        },
+        wayland_server::{
+            protocol::wl_output::{Subpixel, Transform},
+            DisplayHandle,
+        },
+        winit::{
+            dpi::LogicalSize,
+            event::{
+                ElementState, Event as WinitEvent, MouseButton, MouseScrollDelta, WindowEvent,
+            },
+            event_loop::{ControlFlow, EventLoop as WinitEventLoop},
+            platform::{
+                run_return::EventLoopExtRunReturn,
+                unix::{EventLoopExtUnix, WindowExtUnix},
+            },
+            window::{Window as WinitWindow, WindowBuilder, WindowId},
+        },
+    },
+    wayland::output::{Mode, Output, PhysicalProperties, Scale},
+};
+use std::{cell::RefCell, rc::Rc, sync::Arc, time::Duration};
+use wayland_egl::WlEglSurface;
+
+#[cfg(feature = "debug")]
+use crate::state::Fps;
+
+/// The host compositor gives us no presentation feedback, so we throttle rendering ourselves
+const FRAME_INTERVAL: Duration = Duration::from_micros(16_667);
+
+/// Number of windows (and therefore outputs) to open
+const WINDOWS_ENV: &str = "COSMIC_WAYLAND_WINDOWS";
+
+/// evdev codes of the mouse buttons
+const BTN_LEFT: u32 = 0x110;
+const BTN_RIGHT: u32 = 0x111;
+const BTN_MIDDLE: u32 = 0x112;
+
+pub struct WaylandState {
+    event_loop: WinitEventLoop<()>,
+    egl: EGLDisplay,
+    pub renderer: Gles2Renderer,
+    surfaces: Vec<Surface>,
+    keyboard: VirtualDevice,
+    pointer: VirtualDevice,
+    /// Keys currently held, the host compositor repeats them, but so do we.
+    pressed_keys: Vec<u32>,
+}
+
+impl WaylandState {
+    pub fn add_window(
+        &mut self,
+        handle: LoopHandle<'_, Data>,
+        position: (i32, i32),
+    ) -> Result<Output> {
+        let window = create_window(&self.event_loop)?;
+        self.add_surface(window, handle, position)
+    }
+
+    fn add_surface(
+        &mut self,
+        window: Arc<WinitWindow>,
+        handle: LoopHandle<'_, Data>,
+        position: (i32, i32),
+    ) -> Result<Output> {
+        let size = window.inner_size();
+        let wl_surface = window
+            .wayland_surface()
+            .with_context(|| "Window is not backed by a wayland surface")?;
+        let native = unsafe {
+            WlEglSurface::new_from_raw(wl_surface as *mut _, size.width as i32, size.height as i32)
+        }
+        .with_context(|| "Failed to create wayland egl surface")?;
+        let context = self.renderer.egl_context();
+        let egl_surface = EGLSurface::new(
+            &self.egl,
+            context.pixel_format().unwrap(),
+            context.config_id(),
+            native,
+            None,
+        )
+        .with_context(|| "Failed to create EGL surface")?;
+        // we draw our own cursor
+        window.set_cursor_visible(false);
+
+        let name = format!("WAYLAND-{}", self.surfaces.len());
+        let props = PhysicalProperties {
+            size: (0, 0).into(),
+            subpixel: Subpixel::Unknown,
+            make: "COSMIC".to_string(),
+            model: name.clone(),
+        };
+        let mode = Mode {
+            size: (size.width as i32, size.height as i32).into(),
+            refresh: 60_000,
+        };
+        let output = Output::new(name, props, None);
+        output.add_mode(mode);
+        output.set_preferred(mode);
+        output.change_current_state(
+            Some(mode),
+            Some(Transform::Flipped180),
+            Some(Scale::Integer(1)),
+            Some(position.into()),
+        );
+        output.user_data().insert_if_missing(|| {
+            RefCell::new(OutputConfig {
+                mode: ((size.width as i32, size.height as i32), None),
+                transform: Transform::Flipped180.into(),
+                position,
+                ..Default::default()
+            })
+        });
+
+        let output_ref = output.clone();
+        let (ping, source) =
+            ping::make_ping().with_context(|| "Failed to create output event loop source")?;
+        let render_token = handle
+            .insert_source(source, move |_, _, data| {
+                let wayland_state = data.state.backend.wayland();
+                if let Some(surface) = wayland_state
+                    .surfaces
+                    .iter_mut()
+                    .find(|s| s.output == output_ref)
+                {
+                    if let Err(err) =
+                        surface.render_output(&mut wayland_state.renderer, &mut data.state.common)
+                    {
+                        slog_scope::error!("Error rendering: {}", err);
+                    }
+                    surface.dirty = false;
+                    surface.pending = true;
+
+                    let output = output_ref.clone();
+                    if let Err(err) = data.state.common.event_loop_handle.insert_source(
+                        Timer::from_duration(FRAME_INTERVAL),
+                        move |_, _, data| {
+                            if let Some(surface) = data
+                                .state
+                                .backend
+                                .wayland()
+                                .surfaces
+                                .iter_mut()
+                                .find(|s| s.output == output)
+                            {
+                                if surface.dirty {
+                                    surface.render.ping();
+                                } else {
+                                    surface.pending = false;
+                                }
+                            }
+                            TimeoutAction::Drop
+                        },
+                    ) {
+                        slog_scope::warn!("Failed to schedule next frame: {}", err);
+                        surface.pending = false;
+                    }
+                }
+            })
+            .with_context(|| "Failed to add output to event loop")?;
+
+        self.surfaces.push(Surface {
+            window,
+            egl_surface: Rc::new(egl_surface),
+            output: output.clone(),
+            render: ping.clone(),
+            render_token,
+            dirty: false,
+            pending: true,
+            #[cfg(feature = "debug")]
+            fps: Fps::default(),
+        });
+
+        // schedule first render
+        ping.ping();
+        Ok(output)
+    }
+
+    pub fn schedule_render(&mut self, output: &Output) {
+        if let Some(surface) = self.surfaces.iter_mut().find(|s| s.output == *output) {
+            surface.dirty = true;
+            if !surface.pending {
+                surface.render.ping();
+            }
+        }
+    }
+
+    pub fn apply_config_for_output(
+        &mut self,
+        output: &Output,
+        test_only: bool,
+    ) -> Result<(), anyhow::Error> {
+        // TODO: don't ignore config.enabled
+        // reset size
+        let size = self
+            .surfaces
+            .iter()
+            .find(|s| s.output == *output)
+            .unwrap()
+            .window
+            .inner_size();
+        let mut config = output
+            .user_data()
+            .get::<RefCell<OutputConfig>>()
+            .unwrap()
+            .borrow_mut();
+        if config.mode.0 != (size.width as i32, size.height as i32) {
+            if !test_only {
+                config.mode = ((size.width as i32, size.height as i32), None);
+            }
+            Err(anyhow!("Cannot set window size"))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Reads all pending events of the host compositor
+    fn dispatch_events(&mut self) -> Vec<(WindowId, WindowEvent<'static>)> {
+        let mut events = Vec::new();
+        self.event_loop.run_return(|event, _, control_flow| {
+            *control_flow = ControlFlow::Exit;
+            if let WinitEvent::WindowEvent { window_id, event } = event {
+                // the only non-static event is `ScaleFactorChanged`, which we don't care about
+                if let Some(event) = event.to_static() {
+                    events.push((window_id, event));
+                }
+            }
+        });
+        events
+    }
+}
+
+pub struct Surface {
+    window: Arc<WinitWindow>,
+    egl_surface: Rc<EGLSurface>,
+    output: Output,
+    render: ping::Ping,
+    render_token: RegistrationToken,
+    dirty: bool,
+    pending: bool,
+    #[cfg(feature = "debug")]
+    fps: Fps,
+}
+
+impl Surface {
+    pub fn render_output(
+        &mut self,
+        renderer: &mut Gles2Renderer,
+        state: &mut Common,
+    ) -> Result<()> {
+        let age = if render::needs_buffer_reset(&self.output, state) {
+            0
+        } else {
+            self.egl_surface.buffer_age().unwrap_or(0)
+        };
+        renderer
+            .bind(self.egl_surface.clone())
+            .with_context(|| "Failed to bind surface")?;
+
+        match render::render_output(
+            None,
+            renderer,
+            age as u8,
+            state,
+            &self.output,
+            false,
+            #[cfg(feature = "debug")]
+            Some(&mut self.fps),
+        ) {
+            Ok(mut damage) => {
+                state
+                    .shell
+                    .active_space_mut(&self.output)
+                    .space
+                    .send_frames(state.start_time.elapsed().as_millis() as u32);
+                self.egl_surface
+                    .swap_buffers(damage.as_mut().map(|x| &mut **x))
+                    .with_context(|| "Failed to submit buffer for display")?;
+            }
+            Err(err) => {
+                anyhow::bail!("Rendering failed: {}", err);
+            }
+        };
+
+        Ok(())
+    }
+}
+
+fn create_window(event_loop: &WinitEventLoop<()>) -> Result<Arc<WinitWindow>> {
+    WindowBuilder::new()
+        .with_title("COSMIC")
+        .with_inner_size(LogicalSize::new(1280.0, 800.0))
+        .build(event_loop)
+        .map(Arc::new)
+        .with_context(|| "Failed to create window")
+}
+
+pub fn init_backend(
+    dh: &DisplayHandle,
+    event_loop: &mut EventLoop<Data>,
+    state: &mut State,
+) -> Result<()> {
+    let winit_event_loop = WinitEventLoop::<()>::new_wayland();
+    let window = create_window(&winit_event_loop)?;
+
+    let egl = EGLDisplay::new(&window, None).with_context(|| "Failed to create EGL display")?;
+    let context = EGLContext::new_with_config(
+        &egl,
+        GlAttributes {
+            version: (3, 0),
+            profile: None,
+            debug: cfg!(debug_assertions),
+            // rendering is throttled by us, never block on the host compositor
+            vsync: false,
+        },
+        PixelFormatRequirements::_8_bit(),
+        None,
+    )
+    .with_context(|| "Failed to create EGL context")?;
+    let mut renderer = unsafe { Gles2Renderer::new(context, None) }
+        .with_context(|| "Failed to initialize renderer")?;
+
+    init_egl_client_side(dh, state, &mut renderer)?;
+
+    let keyboard = VirtualDevice::new(DeviceCapability::Keyboard);
+    let pointer = VirtualDevice::new(DeviceCapability::Pointer);
+    state.backend = BackendData::Wayland(WaylandState {
+        event_loop: winit_event_loop,
+        egl,
+        renderer,
+        surfaces: Vec::new(),
+        keyboard: keyboard.clone(),
+        pointer: pointer.clone(),
+        pressed_keys: Vec::new(),
+    });
+
+    // every window is its own output, placed next to each other, left to right
+    let windows = match std::env::var(WINDOWS_ENV) {
+        Ok(windows) => windows
+            .parse::<usize>()
+            .ok()
+            .filter(|windows| *windows > 0)
+            .with_context(|| format!("Invalid {}: {}", WINDOWS_ENV, windows))?,
+        Err(_) => 1,
+    };
+    let mut outputs = vec![state
+        .backend
+        .wayland()
+        .add_surface(window, event_loop.handle(), (0, 0))
+        .with_context(|| "Failed to create wl_output")?];
+    for _ in 1..windows {
+        let x = outputs
+            .iter()
+            .map(|output| output.geometry())
+            .map(|geo| geo.loc.x + geo.size.w)
+            .max()
+            .unwrap_or(0);
+        let output = state
+            .backend
+            .wayland()
+            .add_window(event_loop.handle(), (x, 0))
+            .with_context(|| "Failed to create wl_output")?;
+        outputs.push(output);
+    }
+
+    state
+        .common
+        .output_configuration_state
+        .add_heads(outputs.iter());
+    state.common.output_configuration_state.update();
+    for output in &outputs {
+        state.common.shell.add_output(output);
+    }
+    state.common.config.read_outputs(
+        outputs.iter(),
+        &mut state.backend,
+        &mut state.common.shell,
+        &state.common.event_loop_handle,
+    );
+    state.common.shell.refresh_outputs();
+    state.common.config.write_outputs(outputs.iter());
+
+    let dh = dh.clone();
+    for device in [keyboard, pointer] {
+        state.process_input_event::<VirtualInput>(&dh, InputEvent::DeviceAdded { device });
+    }
+
+    // winit gives us no fd to poll, so keep dispatching like the winit backend does
+    let (event_ping, event_source) =
+        ping::make_ping().with_context(|| "Failed to init eventloop source for wayland")?;
+    let event_ping_handle = event_ping.clone();
+    event_loop
+        .handle()
+        .insert_source(event_source, move |_, _, data| {
+            let events = data.state.backend.wayland().dispatch_events();
+            let dh = data.display.handle();
+            for (window_id, event) in events {
+                data.state.process_wayland_event(&dh, window_id, event);
+            }
+            event_ping_handle.ping();
+        })
+        .map_err(|_| anyhow!("Failed to init eventloop source for wayland"))?;
+    event_ping.ping();
+
+    Ok(())
+}
+
+fn init_egl_client_side(
+    dh: &DisplayHandle,
+    state: &mut State,
+    renderer: &mut Gles2Renderer,
+) -> Result<()> {
+    let bind_result = renderer.bind_wl_display(dh);
+    match bind_result {
+        Ok(_) => {
+            slog_scope::info!("EGL hardware-acceleration enabled");
+            let dmabuf_formats = renderer.dmabuf_formats().cloned().collect::<Vec<_>>();
+            state
+                .common
+                .dmabuf_state
+                .create_global::<State, _>(dh, dmabuf_formats, None);
+        }
+        Err(err) => slog_scope::warn!("Unable to initialize bind display to EGL: {}", err),
+    };
+
+    Ok(())
+}
+
+impl State {
+    pub fn process_wayland_event(
+        &mut self,
+        dh: &DisplayHandle,
+        window_id: WindowId,
+        event: WindowEvent<'static>,
+    ) {
+        let wayland_state = self.backend.wayland();
+        let (output, window) = match wayland_state
+            .surfaces
+            .iter()
+            .find(|s| s.window.id() == window_id)
+        {
+            Some(surface) => (surface.output.clone(), surface.window.clone()),
+            None => return,
+        };
+        let keyboard = wayland_state.keyboard.clone();
+        let pointer = wayland_state.pointer.clone();
+        let time = self.common.start_time.elapsed().as_millis() as u32;
+
+        let event = match event {
+            WindowEvent::CloseRequested => {
+                let wayland_state = self.backend.wayland();
+                if let Some(idx) = wayland_state
+                    .surfaces
+                    .iter()
+                    .position(|s| s.window.id() == window_id)
+                {
+                    let surface = wayland_state.surfaces.remove(idx);
+                    self.common.event_loop_handle.remove(surface.render_token);
+                }
+                // like unplugging a monitor, but disabled outputs are not part of the shell
+                if self.common.shell.outputs().any(|o| *o == output)
+                    || self.common.shell.mirrored_output(&output).is_some()
+                {
+                    self.common.shell.remove_output(&output);
+                }
+                self.common
+                    .output_configuration_state
+                    .remove_heads(std::iter::once(&output));
+                self.common.output_configuration_state.update();
+
+                if self.backend.wayland().surfaces.is_empty() {
+                    slog_scope::info!("Last window closed");
+                    self.common.should_stop = true;
+                    return;
+                }
+                self.common.config.read_outputs(
+                    self.common.output_configuration_state.outputs(),
+                    &mut self.backend,
+                    &mut self.common.shell,
+                    &self.common.event_loop_handle,
+                );
+                self.common.shell.refresh_outputs();
+                self.common
+                    .config
+                    .write_outputs(self.common.output_configuration_state.outputs());
+                return;
+            }
+            WindowEvent::Resized(size) => {
+                let size = (size.width as i32, size.height as i32).into();
+                let mode = Mode {
+                    size,
+                    refresh: 60_000,
+                };
+                if let Some(surface) = self
+                    .backend
+                    .wayland()
+                    .surfaces
+                    .iter_mut()
+                    .find(|s| s.window.id() == window_id)
+                {
+                    surface.egl_surface.resize(size.w, size.h, 0, 0);
+                    surface.dirty = true;
+                    if !surface.pending {
+                        surface.render.ping();
+                    }
+                }
+                {
+                    let mut config = output
+                        .user_data()
+                        .get::<RefCell<OutputConfig>>()
+                        .unwrap()
+                        .borrow_mut();
+                    config.mode.0 = size.into();
+                }
+                output.delete_mode(output.current_mode().unwrap());
+                output.change_current_state(Some(mode), None, None, None);
+                output.set_preferred(mode);
+                layer_map_for_output(&output).arrange(dh);
+                self.common.output_configuration_state.update();
+                self.common.shell.refresh_outputs();
+                return;
+            }
+            WindowEvent::Focused(true) => {
+                for seat in self.common.seats.clone().iter() {
+                    let devices = seat.user_data().get::<Devices>().unwrap();
+                    if devices.has_device(&keyboard) {
+                        set_active_output(seat, &output);
+                        break;
+                    }
+                }
+                return;
+            }
+            WindowEvent::Focused(false) => {
+                // we won't see the release of any key held while the window loses focus
+                let pressed_keys = std::mem::take(&mut self.backend.wayland().pressed_keys);
+                for key_code in pressed_keys {
+                    self.process_input_event(
+                        dh,
+                        InputEvent::Keyboard {
+                            event: VirtualKeyboardKeyEvent {
+                                device: keyboard.clone(),
+                                time,
+                                key_code,
+                                state: KeyState::Released,
+                            },
+                        },
+                    );
+                }
+                return;
+            }
+            WindowEvent::KeyboardInput { input, .. } => {
+                let state = match input.state {
+                    ElementState::Pressed => KeyState::Pressed,
+                    ElementState::Released => KeyState::Released,
+                };
+                let pressed_keys = &mut self.backend.wayland().pressed_keys;
+                match state {
+                    KeyState::Pressed if pressed_keys.contains(&input.scancode) => return,
+                    KeyState::Pressed => pressed_keys.push(input.scancode),
+                    KeyState::Released => pressed_keys.retain(|key| *key != input.scancode),
+                }
+                InputEvent::Keyboard {
+                    event: VirtualKeyboardKeyEvent {
+                        device: keyboard,
+                        time,
+                        key_code: input.scancode,
+                        state,
+                    },
+                }
+            }
+            WindowEvent::CursorMoved { position, .. } => {
+                for seat in self.common.seats.clone().iter() {
+                    let devices = seat.user_data().get::<Devices>().unwrap();
+                    if devices.has_device(&pointer) {
+                        set_active_output(seat, &output);
+                        break;
+                    }
+                }
+                let size = window.inner_size();
+                InputEvent::PointerMotionAbsolute {
+                    event: VirtualPointerMotionAbsoluteEvent {
+                        device: pointer,
+                        time,
+                        x: position.x.max(0.0) as u32,
+                        y: position.y.max(0.0) as u32,
+                        x_extent: size.width,
+                        y_extent: size.height,
+                        output: Some(output.clone()),
+                    },
+                }
+            }
+            WindowEvent::MouseInput { state, button, .. } => InputEvent::PointerButton {
+                event: VirtualPointerButtonEvent {
+                    device: pointer,
+                    time,
+                    button: match button {
+                        MouseButton::Left => BTN_LEFT,
+                        MouseButton::Right => BTN_RIGHT,
+                        MouseButton::Middle => BTN_MIDDLE,
+                        MouseButton::Other(button) => button as u32,
+                    },
+                    state: match state {
+                        ElementState::Pressed => ButtonState::Pressed,
+                        ElementState::Released => ButtonState::Released,
+                    },
+                },
+            },
+            WindowEvent::MouseWheel { delta, .. } => {
+                // winit flips the axes of the host compositor, flip them back
+                let mut event = VirtualPointerAxisEvent::new(pointer);
+                event.time = time;
+                match delta {
+                    MouseScrollDelta::LineDelta(x, y) => {
+                        event.source = AxisSource::Wheel;
+                        event.horizontal_discrete = Some(-x as f64);
+                        event.vertical_discrete = Some(-y as f64);
+                        event.horizontal = Some(-x as f64 * 15.0);
+                        event.vertical = Some(-y as f64 * 15.0);
+                    }
+                    MouseScrollDelta::PixelDelta(delta) => {
+                        event.source = AxisSource::Finger;
+                        event.horizontal = Some(-delta.x);
+                        event.vertical = Some(-delta.y);
+                    }
+                }
+                InputEvent::PointerAxis { event }
+            }
+            _ => return,
+        };
+
+        self.process_input_event(dh, event);
+        self.backend
+            .schedule_render(&self.common.event_loop_handle, &output);
+    }
+}
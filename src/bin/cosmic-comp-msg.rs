@@ -0,0 +1,326 @@
+// SPDX-License-Identifier: GPL-3.0-only
+
+//! Command line client for the ipc socket of cosmic-comp.
+
+use anyhow::{anyhow, bail, Context, Result};
+use serde_json::Value;
+use std::{
+    io::{BufRead, BufReader, Write},
+    os::unix::net::UnixStream,
+    path::PathBuf,
+};
+
+// not every message is inspected by the client
+#[allow(dead_code)]
+#[path = "../ipc/protocol.rs"]
+mod protocol;
+use protocol::{parse_mode, Event, EventType, Request, Response, TreeNode, SOCKET_ENV};
+
+const USAGE: &str = "Usage: cosmic-comp-msg [OPTIONS] <COMMAND>
+
+Commands:
+  outputs              List outputs and their layout
+  workspaces           List workspaces
+  windows              List windows
+  tree                 Print the tiling tree of every workspace
+  action <ACTION>      Run an action, written like in config.ron, e.g. 'Workspace(2)'
+  create-output <MODE> [SCALE]
+                       Create a virtual output, e.g. '1920x1080@60 1.5'
+  destroy-output <NAME>
+                       Destroy a virtual output
+
+Options:
+  --json               Print the raw json messages of the compositor
+  --subscribe [TYPES]  Print events until interrupted, optionally only of the given types
+                       (window, workspace, output, config, shell_mode)
+  --socket <PATH>      Path of the ipc socket, defaults to $COSMIC_COMP_SOCK
+  -h, --help           Print this help";
+
+struct Args {
+    json: bool,
+    socket: Option<PathBuf>,
+    request: Request,
+}
+
+fn parse_args() -> Result<Option<Args>> {
+    let mut json = false;
+    let mut socket = None;
+    let mut subscribe = None;
+    let mut positional = Vec::new();
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-h" | "--help" => return Ok(None),
+            "--json" => json = true,
+            "--socket" => {
+                socket = Some(PathBuf::from(
+                    args.next()
+                        .ok_or_else(|| anyhow!("--socket requires a path"))?,
+                ))
+            }
+            "--subscribe" => subscribe = Some(Vec::new()),
+            _ if arg.starts_with("--") => bail!("Unknown option {}", arg),
+            _ => match subscribe.as_mut() {
+                Some(events) => events.push(arg),
+                None => positional.push(arg),
+            },
+        }
+    }
+
+    let request = if let Some(events) = subscribe.as_ref() {
+        if !positional.is_empty() {
+            bail!("--subscribe can't be combined with a command");
+        }
+        let events = events
+            .iter()
+            .map(|event| {
+                serde_json::from_value::<EventType>(Value::String(event.clone()))
+                    .with_context(|| format!("Unknown event type {}", event))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Request::Subscribe { events }
+    } else {
+        let mut positional = positional.into_iter();
+        let request = match positional.next().as_deref() {
+            Some("outputs") => Request::GetOutputs,
+            Some("workspaces") => Request::GetWorkspaces,
+            Some("windows") => Request::GetWindows,
+            Some("tree") => Request::GetTree,
+            Some("action") => {
+                // allow unquoted actions with spaces, e.g. `action Spawn("foot -e htop")`
+                let action = positional.by_ref().collect::<Vec<_>>().join(" ");
+                if action.is_empty() {
+                    bail!("action requires an argument, e.g. 'Workspace(2)'");
+                }
+                Request::RunAction { action }
+            }
+            Some("create-output") => {
+                let mode = positional
+                    .next()
+                    .ok_or_else(|| anyhow!("create-output requires a mode, e.g. '1920x1080@60'"))?;
+                let (size, refresh) = parse_mode(&mode)?;
+                let scale = match positional.next() {
+                    Some(scale) => scale
+                        .parse::<f64>()
+                        .with_context(|| format!("Invalid scale {}", scale))?,
+                    None => 1.0,
+                };
+                Request::CreateVirtualOutput {
+                    size,
+                    scale,
+                    refresh,
+                }
+            }
+            Some("destroy-output") => {
+                let name = positional
+                    .next()
+                    .ok_or_else(|| anyhow!("destroy-output requires the name of an output"))?;
+                Request::DestroyVirtualOutput { name }
+            }
+            Some(command) => bail!("Unknown command {}", command),
+            None => return Ok(None),
+        };
+        if positional.next().is_some() {
+            bail!("Too many arguments");
+        }
+        request
+    };
+
+    Ok(Some(Args {
+        json,
+        socket,
+        request,
+    }))
+}
+
+fn main() -> Result<()> {
+    let args = match parse_args()? {
+        Some(args) => args,
+        None => {
+            println!("{}", USAGE);
+            return Ok(());
+        }
+    };
+
+    let socket = match args.socket {
+        Some(path) => path,
+        None => std::env::var_os(SOCKET_ENV)
+            .map(PathBuf::from)
+            .ok_or_else(|| anyhow!("{} is not set, is cosmic-comp running?", SOCKET_ENV))?,
+    };
+    let mut stream = UnixStream::connect(&socket)
+        .with_context(|| format!("Failed to connect to {}", socket.display()))?;
+    let mut request = serde_json::to_vec(&args.request)?;
+    request.push(b'\n');
+    stream
+        .write_all(&request)
+        .with_context(|| "Failed to send request")?;
+
+    let mut lines = BufReader::new(stream).lines();
+    let response = lines
+        .next()
+        .ok_or_else(|| anyhow!("Compositor closed the connection"))?
+        .with_context(|| "Failed to read response")?;
+    let subscribe = matches!(args.request, Request::Subscribe { .. });
+    if args.json {
+        let response: Value =
+            serde_json::from_str(&response).with_context(|| "Invalid response from compositor")?;
+        if let Ok(Response::Error { message }) = serde_json::from_value(response.clone()) {
+            bail!("{}", message);
+        }
+        println!("{}", serde_json::to_string_pretty(&response)?);
+    } else {
+        let response: Response =
+            serde_json::from_str(&response).with_context(|| "Invalid response from compositor")?;
+        if let Response::Error { message } = response {
+            bail!("{}", message);
+        }
+        print_response(response);
+    }
+    if !subscribe {
+        return Ok(());
+    }
+
+    for line in lines {
+        let line = line.with_context(|| "Failed to read event")?;
+        if args.json {
+            println!("{}", line);
+        } else {
+            let event: Event =
+                serde_json::from_str(&line).with_context(|| "Invalid event from compositor")?;
+            print_event(&event);
+        }
+    }
+
+    Ok(())
+}
+
+fn print_response(response: Response) {
+    match response {
+        Response::Outputs { outputs } => {
+            for output in outputs {
+                println!("Output {} ({} {})", output.name, output.make, output.model);
+                if let Some(((w, h), refresh)) = output.mode {
+                    println!("  Mode: {}x{} @ {:.3} Hz", w, h, refresh as f64 / 1000.0);
+                }
+                println!(
+                    "  Position: {},{}, Size: {}x{}",
+                    output.position.0, output.position.1, output.size.0, output.size.1
+                );
+                println!(
+                    "  Scale: {}, Transform: {:?}",
+                    output.scale, output.transform
+                );
+                println!("  Active workspace: {}", output.active_workspace);
+            }
+        }
+        Response::Workspaces { workspaces } => {
+            for workspace in workspaces {
+                println!(
+                    "Workspace {}{}: {} window(s), on {}",
+                    workspace.number,
+                    if workspace.focused { " (focused)" } else { "" },
+                    workspace.windows,
+                    if workspace.outputs.is_empty() {
+                        String::from("no output")
+                    } else {
+                        workspace.outputs.join(", ")
+                    },
+                );
+            }
+        }
+        Response::Windows { windows } => {
+            for window in windows {
+                let mut flags = Vec::new();
+                if window.floating {
+                    flags.push("floating");
+                }
+                if window.fullscreen {
+                    flags.push("fullscreen");
+                }
+                if window.focused {
+                    flags.push("focused");
+                }
+                println!(
+                    "Window {}: \"{}\" ({}) on workspace {}{}",
+                    window.id,
+                    window.title,
+                    window.app_id,
+                    window.workspace,
+                    if flags.is_empty() {
+                        String::new()
+                    } else {
+                        format!(" [{}]", flags.join(", "))
+                    }
+                );
+            }
+        }
+        Response::VirtualOutput { name } => println!("{}", name),
+        Response::Tree { workspaces } => {
+            for workspace in workspaces {
+                println!("Workspace {}", workspace.number);
+                for output in workspace.outputs {
+                    println!("  Output {}", output.output);
+                    if let Some(node) = output.tiling.as_ref() {
+                        print_node(node, 2);
+                    }
+                }
+                if !workspace.floating.is_empty() {
+                    let floating = workspace
+                        .floating
+                        .iter()
+                        .map(|id| id.to_string())
+                        .collect::<Vec<_>>();
+                    println!("  Floating: {}", floating.join(", "));
+                }
+            }
+        }
+        Response::Success | Response::Subscribed | Response::Error { .. } => {}
+    }
+}
+
+fn print_node(node: &TreeNode, depth: usize) {
+    let indent = "  ".repeat(depth);
+    let children = match node {
+        TreeNode::Fork {
+            orientation,
+            ratio,
+            children,
+        } => {
+            println!("{}Fork {:?} ({:.2})", indent, orientation, ratio);
+            children.as_slice()
+        }
+        TreeNode::Stack { active, children } => {
+            println!("{}Stack (active {})", indent, active);
+            children.as_slice()
+        }
+        TreeNode::Window { id, title, app_id } => {
+            println!("{}Window {}: \"{}\" ({})", indent, id, title, app_id);
+            &[]
+        }
+    };
+    for child in children {
+        print_node(child, depth + 1);
+    }
+}
+
+fn print_event(event: &Event) {
+    // the json form of an event reads well enough, once the tag is pulled out
+    let mut value = serde_json::to_value(event).unwrap_or_default();
+    let name = value
+        .as_object_mut()
+        .and_then(|fields| fields.remove("event"))
+        .unwrap_or_default();
+    let fields = value
+        .as_object()
+        .into_iter()
+        .flatten()
+        .map(|(key, value)| format!("{}={}", key, value))
+        .collect::<Vec<_>>();
+    println!(
+        "{}: {}",
+        name.as_str().unwrap_or("unknown"),
+        fields.join(" ")
+    );
+}
@@ -3,6 +3,7 @@
 use crate::{
     shell::{focus::FocusDirection, Shell},
     state::{BackendData, Data},
+    wayland::protocols::security_context::SecurityContext,
 };
 use serde::{Deserialize, Serialize};
 pub use smithay::{
@@ -10,8 +11,8 @@ pub use smithay::{
     reexports::{
         calloop::LoopHandle,
         input::{
-            AccelProfile, ClickMethod, Device as InputDevice, ScrollMethod, SendEventsMode,
-            TapButtonMap,
+            AccelProfile, ClickMethod, Device as InputDevice, DeviceCapability as InputCapability,
+            ScrollMethod, SendEventsMode, TapButtonMap,
         },
     },
     utils::{Logical, Physical, Point, Size, Transform},
@@ -20,7 +21,12 @@ pub use smithay::{
         seat::{keysyms as KeySyms, Keysym, ModifiersState as KeyModifiers},
     },
 };
-use std::{cell::RefCell, collections::HashMap, fs::OpenOptions, path::PathBuf};
+use std::{
+    cell::RefCell,
+    collections::{HashMap, HashSet},
+    fs::OpenOptions,
+    path::{Path, PathBuf},
+};
 
 mod types;
 pub use self::types::*;
@@ -33,7 +39,61 @@ pub struct Config {
 #[derive(Debug, Deserialize)]
 pub struct StaticConfig {
     pub key_bindings: HashMap<KeyPattern, Action>,
+    #[serde(default)]
+    pub gesture_bindings: HashMap<GesturePattern, Action>,
+    /// Binding, that still lifts a keyboard shortcuts inhibitor, if a client requested one
+    #[serde(default = "default_shortcuts_inhibit_escape")]
+    pub shortcuts_inhibit_escape: KeyPattern,
     pub workspace_mode: WorkspaceMode,
+    /// Absolute paths of executables, whose clients may use privileged protocols,
+    /// like toplevel management or workspaces (e.g. panels or docks)
+    #[serde(default)]
+    pub privileged_clients: Vec<PathBuf>,
+    /// Lets every process of the same user change the state of the compositor over ipc,
+    /// instead of only the `privileged_clients`
+    #[serde(default)]
+    pub ipc_trust_same_user: bool,
+    /// Restricted globals granted to sandboxed clients, which can't see any of them otherwise
+    #[serde(default)]
+    pub sandbox_permissions: Vec<SandboxPermission>,
+}
+
+/// Grants globals to clients of the matching sandbox engine and app id, e.g.
+/// `(sandbox_engine: Some("org.flatpak"), app_id: Some("com.obsproject.*"), globals: ["zwlr_output_manager_v1"])`
+#[derive(Debug, Deserialize)]
+pub struct SandboxPermission {
+    /// Reverse-DNS name of the sandbox engine, matches every engine if unset
+    #[serde(default)]
+    sandbox_engine: Option<String>,
+    /// Glob pattern for the app id, supporting `*` and `?`, matches every app if unset
+    #[serde(default)]
+    app_id: Option<String>,
+    /// Interface names of the granted globals
+    globals: Vec<String>,
+}
+
+impl SandboxPermission {
+    fn matches(&self, context: &SecurityContext) -> bool {
+        self.sandbox_engine
+            .as_ref()
+            .map(|engine| Some(engine) == context.sandbox_engine.as_ref())
+            .unwrap_or(true)
+            && self
+                .app_id
+                .as_ref()
+                .map(|pattern| {
+                    context
+                        .app_id
+                        .as_ref()
+                        .map(|app_id| glob_match(pattern, app_id))
+                        .unwrap_or(false)
+                })
+                .unwrap_or(true)
+    }
+}
+
+fn default_shortcuts_inhibit_escape() -> KeyPattern {
+    KeyPattern::new(KeyModifier::Logo | KeyModifier::Alt, KeySyms::KEY_Escape)
 }
 
 #[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
@@ -45,6 +105,8 @@ pub enum WorkspaceMode {
 pub struct DynamicConfig {
     outputs: (Option<PathBuf>, OutputsConfig),
     inputs: (Option<PathBuf>, InputsConfig),
+    /// Merged configuration of every device, that matched any rule
+    resolved_devices: HashMap<String, InputConfig>,
 }
 
 #[derive(Debug, Deserialize, Serialize)]
@@ -52,13 +114,103 @@ pub struct OutputsConfig {
     pub config: HashMap<Vec<OutputInfo>, Vec<OutputConfig>>,
 }
 
+impl OutputsConfig {
+    /// Looks up the saved configs for `infos`, ordered like `infos`.
+    ///
+    /// Displays with a serial number are found regardless of the connector they are plugged into,
+    /// entries written before serial numbers were recorded still match by connector.
+    fn find(&self, infos: &[OutputInfo]) -> Option<Vec<OutputConfig>> {
+        if let Some(configs) = self.config.get(infos) {
+            return Some(configs.clone());
+        }
+
+        self.config
+            .iter()
+            .filter(|(saved, _)| saved.len() == infos.len())
+            .filter_map(|(saved, configs)| {
+                let (mapping, same_connectors) = match_outputs(saved, infos)?;
+                // mirrors refer to connectors, which might have changed as well
+                let rename = |name: &String| {
+                    let source = saved.iter().position(|info| &info.connector == name)?;
+                    let current = mapping.iter().position(|idx| *idx == source)?;
+                    Some(infos[current].connector.clone())
+                };
+                let configs = mapping
+                    .iter()
+                    .map(|idx| {
+                        let mut config = configs[*idx].clone();
+                        config.mirror_of =
+                            config.mirror_of.map(|name| rename(&name).unwrap_or(name));
+                        config
+                    })
+                    .collect::<Vec<_>>();
+                Some((same_connectors, configs))
+            })
+            // prefer entries, that need the fewest connectors swapped
+            .max_by_key(|(same_connectors, _)| *same_connectors)
+            .map(|(_, configs)| configs)
+    }
+}
+
+/// Assigns every output in `infos` to a distinct entry of `saved`,
+/// returning the index into `saved` for each output and how many kept their connector.
+fn match_outputs(saved: &[OutputInfo], infos: &[OutputInfo]) -> Option<(Vec<usize>, usize)> {
+    let mut mapping = vec![None; infos.len()];
+    let mut used = vec![false; saved.len()];
+    let mut same_connectors = 0;
+    // match on the same connector first, so identical displays without serial stay in place
+    for same_connector in [true, false] {
+        for (info, slot) in infos.iter().zip(mapping.iter_mut()) {
+            if slot.is_some() {
+                continue;
+            }
+            if let Some(idx) = saved
+                .iter()
+                .enumerate()
+                .position(|(idx, s)| !used[idx] && info.matches(s, same_connector))
+            {
+                used[idx] = true;
+                *slot = Some(idx);
+                if same_connector {
+                    same_connectors += 1;
+                }
+            }
+        }
+    }
+    let mapping = mapping.into_iter().collect::<Option<Vec<_>>>()?;
+    Some((mapping, same_connectors))
+}
+
 #[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct OutputInfo {
     pub connector: String,
     pub make: String,
     pub model: String,
+    /// Serial number of the display, if it has one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub serial: Option<String>,
 }
 
+impl OutputInfo {
+    /// Whether `saved` describes the same display, optionally also on the same connector.
+    ///
+    /// Without a serial number on both sides, displays can only be told apart by their connector.
+    fn matches(&self, saved: &OutputInfo, same_connector: bool) -> bool {
+        if self.make != saved.make || self.model != saved.model {
+            return false;
+        }
+        match (self.serial.as_ref(), saved.serial.as_ref()) {
+            (Some(serial), Some(saved_serial)) => {
+                serial == saved_serial && (!same_connector || self.connector == saved.connector)
+            }
+            _ => same_connector && self.connector == saved.connector,
+        }
+    }
+}
+
+/// Serial number read from the EDID of a display, stored in the user data of its `Output`
+pub struct OutputSerial(pub String);
+
 impl From<Output> for OutputInfo {
     fn from(o: Output) -> OutputInfo {
         let physical = o.physical_properties();
@@ -66,6 +218,10 @@ impl From<Output> for OutputInfo {
             connector: o.name(),
             make: physical.make,
             model: physical.model,
+            serial: o
+                .user_data()
+                .get::<OutputSerial>()
+                .map(|serial| serial.0.clone()),
         }
     }
 }
@@ -84,6 +240,9 @@ pub struct OutputConfig {
     pub position: (i32, i32),
     #[serde(default = "default_enabled")]
     pub enabled: bool,
+    /// Connector name of the output to show instead of an own workspace
+    #[serde(default)]
+    pub mirror_of: Option<String>,
 }
 
 impl Default for OutputConfig {
@@ -95,6 +254,7 @@ impl Default for OutputConfig {
             transform: Transform::Normal,
             position: (0, 0),
             enabled: true,
+            mirror_of: None,
         }
     }
 }
@@ -119,14 +279,176 @@ impl OutputConfig {
 #[derive(Debug, Deserialize, Serialize)]
 pub struct InputsConfig {
     xkb: XkbConfig,
+    #[serde(default)]
+    accessibility: AccessibilityConfig,
+    /// Configuration applied to every device matching a rule, merged in order
+    #[serde(default)]
+    rules: Vec<InputRule>,
     devices: HashMap<String, InputConfig>,
 }
 
+/// Settings for all devices matching `device`.
+///
+/// Like for entries in `devices` the `state` of the config is mandatory and always applied.
 #[derive(Debug, Deserialize, Serialize)]
+pub struct InputRule {
+    #[serde(default)]
+    device: DeviceMatch,
+    config: InputConfig,
+}
+
+/// Properties a device has to match, an empty match applies to every device
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct DeviceMatch {
+    /// Glob pattern for the device name, supporting `*` and `?`
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    vendor: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    product: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    device_type: Option<DeviceType>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
+pub enum DeviceType {
+    Keyboard,
+    Mouse,
+    Touchpad,
+    Touchscreen,
+    Tablet,
+}
+
+impl DeviceMatch {
+    fn matches(&self, device: &InputDevice) -> bool {
+        self.name
+            .as_ref()
+            .map(|pattern| glob_match(pattern, device.name()))
+            .unwrap_or(true)
+            && self
+                .vendor
+                .map(|vendor| vendor == device.id_vendor())
+                .unwrap_or(true)
+            && self
+                .product
+                .map(|product| product == device.id_product())
+                .unwrap_or(true)
+            && self
+                .device_type
+                .map(|device_type| device_type.matches(device))
+                .unwrap_or(true)
+    }
+}
+
+impl DeviceType {
+    fn matches(&self, device: &InputDevice) -> bool {
+        // libinput has no notion of touchpads, but only they support tapping
+        let touchpad = device.config_tap_finger_count() > 0;
+        match self {
+            DeviceType::Keyboard => device.has_capability(InputCapability::Keyboard),
+            DeviceType::Mouse => device.has_capability(InputCapability::Pointer) && !touchpad,
+            DeviceType::Touchpad => touchpad,
+            DeviceType::Touchscreen => device.has_capability(InputCapability::Touch),
+            DeviceType::Tablet => device.has_capability(InputCapability::TabletTool),
+        }
+    }
+}
+
+/// Sort key for connector names, so that e.g. `DP-2` comes before `DP-10`
+fn connector_order(name: &str) -> (String, u32) {
+    name.rsplit_once('-')
+        .and_then(|(kind, idx)| Some((kind.to_string(), idx.parse().ok()?)))
+        .unwrap_or_else(|| (name.to_string(), 0))
+}
+
+/// Guesses a scale from the physical size in millimeters as read from the edid,
+/// `None` if the size is unknown.
+///
+/// Content should appear at roughly the same size on every display, with laptop panels
+/// being looked at from a shorter distance than desktop monitors.
+fn default_scale((width, height): (i32, i32), mode_size: Size<i32, Physical>) -> Option<f64> {
+    // projectors and tvs often report nonsense like their aspect ratio in cm
+    if width < 100 || height < 100 {
+        return None;
+    }
+
+    let dpi = mode_size.w as f64 * 25.4 / width as f64;
+    let diagonal = ((width.pow(2) + height.pow(2)) as f64).sqrt() / 25.4;
+    let reference_dpi = if diagonal < 18.0 { 150.0 } else { 110.0 };
+    let scale = (dpi / reference_dpi * 4.0).round() / 4.0;
+    Some(scale.clamp(1.0, 3.0))
+}
+
+/// Matches `name` against a glob `pattern`, supporting `*` and `?` wildcards.
+fn glob_match(pattern: &str, name: &str) -> bool {
+    let pattern = pattern.chars().collect::<Vec<_>>();
+    let name = name.chars().collect::<Vec<_>>();
+    let (mut p, mut n) = (0, 0);
+    // position of the last `*` and the part of the name it currently covers
+    let mut backtrack = None;
+    while n < name.len() {
+        if p < pattern.len() && pattern[p] == '*' {
+            backtrack = Some((p, n));
+            p += 1;
+        } else if p < pattern.len() && (pattern[p] == '?' || pattern[p] == name[n]) {
+            p += 1;
+            n += 1;
+        } else if let Some((star, covered)) = backtrack {
+            p = star + 1;
+            n = covered + 1;
+            backtrack = Some((star, covered + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+/// Keyboard accessibility filters, applied before any key bindings
+#[derive(Debug, Deserialize, Serialize, Clone, Copy)]
+pub struct AccessibilityConfig {
+    /// Modifiers pressed on their own stay active for the next key, pressing them twice locks them
+    #[serde(default)]
+    pub sticky_keys: bool,
+    /// Key presses are only accepted, after the key was held for `slow_keys_delay` milliseconds
+    #[serde(default)]
+    pub slow_keys: bool,
+    #[serde(default = "default_slow_keys_delay")]
+    pub slow_keys_delay: u32,
+    /// Presses of a key released less than `bounce_keys_delay` milliseconds ago are ignored
+    #[serde(default)]
+    pub bounce_keys: bool,
+    #[serde(default = "default_bounce_keys_delay")]
+    pub bounce_keys_delay: u32,
+}
+
+fn default_slow_keys_delay() -> u32 {
+    300
+}
+
+fn default_bounce_keys_delay() -> u32 {
+    300
+}
+
+impl Default for AccessibilityConfig {
+    fn default() -> AccessibilityConfig {
+        AccessibilityConfig {
+            sticky_keys: false,
+            slow_keys: false,
+            slow_keys_delay: default_slow_keys_delay(),
+            bounce_keys: false,
+            bounce_keys_delay: default_bounce_keys_delay(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct InputConfig {
     state: DeviceState,
     #[serde(skip_serializing_if = "Option::is_none", default)]
     acceleration: Option<AccelConfig>,
+    /// Libinput calibration matrix, also maps tablets to a region of their output
     #[serde(skip_serializing_if = "Option::is_none", default)]
     calibration: Option<[f32; 6]>,
     #[serde(with = "ClickMethodDef")]
@@ -134,9 +456,15 @@ pub struct InputConfig {
     click_method: Option<ClickMethod>,
     #[serde(skip_serializing_if = "Option::is_none", default)]
     disable_while_typing: Option<bool>,
+    /// Evdev keycodes (see `linux/input-event-codes.h`) to replace, before they are
+    /// interpreted by xkb, e.g. `{ 58: 1 }` turns CapsLock into Escape.
+    #[serde(skip_serializing_if = "HashMap::is_empty", default)]
+    key_remap: HashMap<u32, u32>,
     #[serde(skip_serializing_if = "Option::is_none", default)]
     left_handed: Option<bool>,
     #[serde(skip_serializing_if = "Option::is_none", default)]
+    map_to_output: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
     middle_button_emulation: Option<bool>,
     #[serde(skip_serializing_if = "Option::is_none", default)]
     rotation_angle: Option<u32>,
@@ -146,14 +474,14 @@ pub struct InputConfig {
     tap_config: Option<TapConfig>,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct AccelConfig {
     #[serde(with = "AccelProfileDef")]
     profile: Option<AccelProfile>,
     speed: f64,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct ScrollConfig {
     #[serde(with = "ScrollMethodDef")]
     method: Option<ScrollMethod>,
@@ -161,14 +489,14 @@ pub struct ScrollConfig {
     scroll_button: Option<u32>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, Clone, Copy)]
 pub enum DeviceState {
     Enabled,
     Disabled,
     DisabledOnExternalMouse,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct TapConfig {
     enabled: bool,
     #[serde(with = "TapButtonMapDef")]
@@ -215,7 +543,12 @@ impl Config {
 
         StaticConfig {
             key_bindings: HashMap::new(),
+            gesture_bindings: HashMap::new(),
+            shortcuts_inhibit_escape: default_shortcuts_inhibit_escape(),
             workspace_mode: WorkspaceMode::Global,
+            privileged_clients: Vec::new(),
+            ipc_trust_same_user: false,
+            sandbox_permissions: Vec::new(),
         }
     }
 
@@ -230,6 +563,7 @@ impl Config {
         DynamicConfig {
             outputs: (output_path, outputs),
             inputs: (input_path, inputs),
+            resolved_devices: HashMap::new(),
         }
     }
 
@@ -270,6 +604,8 @@ impl Config {
 
         InputsConfig {
             xkb: XkbConfig::default(),
+            accessibility: AccessibilityConfig::default(),
+            rules: Vec::new(),
             devices: HashMap::new(),
         }
     }
@@ -288,7 +624,7 @@ impl Config {
             .map(Into::<crate::config::OutputInfo>::into)
             .collect::<Vec<_>>();
         infos.sort();
-        if let Some(configs) = self.dynamic_conf.outputs().config.get(&infos).cloned() {
+        if let Some(configs) = self.dynamic_conf.outputs().find(&infos) {
             let mut reset = false;
             let known_good_configs = outputs
                 .iter()
@@ -345,6 +681,44 @@ impl Config {
                     }
                 }
             }
+        } else {
+            // unknown set of outputs, place them left to right without any overlap
+            let mut outputs = outputs;
+            outputs.sort_by_key(|output| connector_order(&output.name()));
+            let mut x = 0;
+            for output in &outputs {
+                {
+                    let mut config = output
+                        .user_data()
+                        .get::<RefCell<OutputConfig>>()
+                        .unwrap()
+                        .borrow_mut();
+                    if !config.enabled || config.mirror_of.is_some() {
+                        continue;
+                    }
+                    let physical = output.physical_properties().size;
+                    if let Some(scale) = default_scale((physical.w, physical.h), config.mode_size())
+                    {
+                        config.scale = scale;
+                    }
+                    config.position = (x, 0);
+                    x += config
+                        .transform
+                        .transform_size(config.mode_size())
+                        .to_f64()
+                        .to_logical(config.scale)
+                        .to_i32_round::<i32>()
+                        .w;
+                }
+                if let Err(err) = backend.apply_config_for_output(output, false, shell, loop_handle)
+                {
+                    slog_scope::warn!(
+                        "Failed to set default config for output {}: {}",
+                        output.name(),
+                        err
+                    );
+                }
+            }
         }
     }
 
@@ -373,258 +747,356 @@ impl Config {
             .insert(infos, configs);
     }
 
+    /// Whether clients started from the given executable are granted privileged access.
+    pub fn is_privileged_client(&self, executable: &Path) -> bool {
+        self.static_conf
+            .privileged_clients
+            .iter()
+            .any(|path| path == executable)
+    }
+
+    /// Restricted globals a client of the given security context may see.
+    pub fn sandbox_globals(&self, context: &SecurityContext) -> HashSet<String> {
+        self.static_conf
+            .sandbox_permissions
+            .iter()
+            .filter(|permission| permission.matches(context))
+            .flat_map(|permission| permission.globals.iter().cloned())
+            .collect()
+    }
+
     pub fn xkb_config(&self) -> XkbConfig {
         self.dynamic_conf.inputs().xkb.clone()
     }
 
+    pub fn accessibility(&self) -> AccessibilityConfig {
+        self.dynamic_conf.inputs().accessibility
+    }
+
+    pub fn set_accessibility(&mut self, accessibility: AccessibilityConfig) {
+        self.dynamic_conf.inputs_mut().accessibility = accessibility;
+    }
+
+    /// Name of the output absolute input events of the given device
+    /// (e.g. touchscreens) should be mapped to, if any.
+    pub fn map_to_output(&self, device_name: &str) -> Option<String> {
+        self.dynamic_conf
+            .device_config(device_name)
+            .and_then(|config| config.map_to_output.clone())
+    }
+
+    /// Applies the key remapping configured for the given device to a keycode.
+    pub fn remap_key(&self, device_name: &str, keycode: u32) -> u32 {
+        self.dynamic_conf
+            .device_config(device_name)
+            .and_then(|config| config.key_remap.get(&keycode).copied())
+            .unwrap_or(keycode)
+    }
+
     pub fn read_device(&mut self, device: &mut InputDevice) {
-        use std::collections::hash_map::Entry;
-
-        let mut inputs = self.dynamic_conf.inputs_mut();
-        match inputs.devices.entry(device.name().into()) {
-            Entry::Occupied(entry) => {
-                let config = entry.get();
-                if let Err(err) = match config.state {
-                    DeviceState::Enabled => {
-                        device.config_send_events_set_mode(SendEventsMode::ENABLED)
-                    }
-                    DeviceState::Disabled => {
-                        device.config_send_events_set_mode(SendEventsMode::DISABLED)
-                    }
-                    DeviceState::DisabledOnExternalMouse => device
-                        .config_send_events_set_mode(SendEventsMode::DISABLED_ON_EXTERNAL_MOUSE),
-                } {
+        let name = device.name().to_string();
+        let inputs = self.dynamic_conf.inputs();
+
+        // matching rules are merged in order, the entry for the exact name takes precedence
+        let mut merged: Option<InputConfig> = None;
+        for config in inputs
+            .rules
+            .iter()
+            .filter(|rule| rule.device.matches(device))
+            .map(|rule| &rule.config)
+            .chain(inputs.devices.get(&name))
+        {
+            match merged.as_mut() {
+                Some(merged) => merged.merge(config),
+                None => merged = Some(config.clone()),
+            }
+        }
+
+        match merged {
+            Some(config) => {
+                config.apply(device);
+                self.dynamic_conf.resolved_devices.insert(name, config);
+            }
+            None => {
+                // only unknown devices change the config file
+                self.dynamic_conf
+                    .inputs_mut()
+                    .devices
+                    .insert(name, InputConfig::from_device(device));
+            }
+        }
+    }
+}
+
+impl InputConfig {
+    /// Overrides all settings, that are set in `other`.
+    fn merge(&mut self, other: &InputConfig) {
+        self.state = other.state;
+        macro_rules! merge {
+            ($($field:ident),*) => {
+                $(if other.$field.is_some() {
+                    self.$field = other.$field.clone();
+                })*
+            };
+        }
+        merge!(
+            acceleration,
+            calibration,
+            click_method,
+            disable_while_typing,
+            left_handed,
+            map_to_output,
+            middle_button_emulation,
+            rotation_angle,
+            scroll_config,
+            tap_config
+        );
+        self.key_remap
+            .extend(other.key_remap.iter().map(|(from, to)| (*from, *to)));
+    }
+
+    /// Applies this configuration to the given libinput device.
+    fn apply(&self, device: &mut InputDevice) {
+        if let Err(err) = match self.state {
+            DeviceState::Enabled => device.config_send_events_set_mode(SendEventsMode::ENABLED),
+            DeviceState::Disabled => device.config_send_events_set_mode(SendEventsMode::DISABLED),
+            DeviceState::DisabledOnExternalMouse => {
+                device.config_send_events_set_mode(SendEventsMode::DISABLED_ON_EXTERNAL_MOUSE)
+            }
+        } {
+            slog_scope::warn!(
+                "Failed to apply mode {:?} for device {:?}: {:?}",
+                self.state,
+                device.name(),
+                err
+            );
+        }
+        if let Some(accel) = self.acceleration.as_ref() {
+            if let Some(profile) = accel.profile {
+                if let Err(err) = device.config_accel_set_profile(profile) {
                     slog_scope::warn!(
-                        "Failed to apply mode {:?} for device {:?}: {:?}",
-                        config.state,
+                        "Failed to apply acceleration profile {:?} for device {:?}: {:?}",
+                        profile,
                         device.name(),
                         err
                     );
                 }
-                if let Some(accel) = config.acceleration.as_ref() {
-                    if let Some(profile) = accel.profile {
-                        if let Err(err) = device.config_accel_set_profile(profile) {
-                            slog_scope::warn!(
-                                "Failed to apply acceleration profile {:?} for device {:?}: {:?}",
-                                profile,
-                                device.name(),
-                                err
-                            );
-                        }
-                    }
-                    if let Err(err) = device.config_accel_set_speed(accel.speed) {
-                        slog_scope::warn!(
-                            "Failed to apply acceleration speed {:?} for device {:?}: {:?}",
-                            accel.speed,
-                            device.name(),
-                            err
-                        );
-                    }
-                }
-                if let Some(matrix) = config.calibration {
-                    if let Err(err) = device.config_calibration_set_matrix(matrix) {
-                        slog_scope::warn!(
-                            "Failed to apply calibration matrix {:?} for device {:?}: {:?}",
-                            matrix,
-                            device.name(),
-                            err
-                        );
-                    }
-                }
-                if let Some(method) = config.click_method {
-                    if let Err(err) = device.config_click_set_method(method) {
-                        slog_scope::warn!(
-                            "Failed to apply click method {:?} for device {:?}: {:?}",
-                            method,
-                            device.name(),
-                            err
-                        );
-                    }
-                }
-                if let Some(dwt) = config.disable_while_typing {
-                    if let Err(err) = device.config_dwt_set_enabled(dwt) {
-                        slog_scope::warn!(
-                            "Failed to apply disable-while-typing {:?} for device {:?}: {:?}",
-                            dwt,
-                            device.name(),
-                            err
-                        );
-                    }
-                }
-                if let Some(left) = config.left_handed {
-                    if let Err(err) = device.config_left_handed_set(left) {
-                        slog_scope::warn!(
-                            "Failed to apply left-handed {:?} for device {:?}: {:?}",
-                            left,
-                            device.name(),
-                            err
-                        );
-                    }
-                }
-                if let Some(middle) = config.middle_button_emulation {
-                    if let Err(err) = device.config_middle_emulation_set_enabled(middle) {
-                        slog_scope::warn!(
-                            "Failed to apply middle-button-emulation {:?} for device {:?}: {:?}",
-                            middle,
-                            device.name(),
-                            err
-                        );
-                    }
+            }
+            if let Err(err) = device.config_accel_set_speed(accel.speed) {
+                slog_scope::warn!(
+                    "Failed to apply acceleration speed {:?} for device {:?}: {:?}",
+                    accel.speed,
+                    device.name(),
+                    err
+                );
+            }
+        }
+        if let Some(matrix) = self.calibration {
+            if let Err(err) = device.config_calibration_set_matrix(matrix) {
+                slog_scope::warn!(
+                    "Failed to apply calibration matrix {:?} for device {:?}: {:?}",
+                    matrix,
+                    device.name(),
+                    err
+                );
+            }
+        }
+        if let Some(method) = self.click_method {
+            if let Err(err) = device.config_click_set_method(method) {
+                slog_scope::warn!(
+                    "Failed to apply click method {:?} for device {:?}: {:?}",
+                    method,
+                    device.name(),
+                    err
+                );
+            }
+        }
+        if let Some(dwt) = self.disable_while_typing {
+            if let Err(err) = device.config_dwt_set_enabled(dwt) {
+                slog_scope::warn!(
+                    "Failed to apply disable-while-typing {:?} for device {:?}: {:?}",
+                    dwt,
+                    device.name(),
+                    err
+                );
+            }
+        }
+        if let Some(left) = self.left_handed {
+            if let Err(err) = device.config_left_handed_set(left) {
+                slog_scope::warn!(
+                    "Failed to apply left-handed {:?} for device {:?}: {:?}",
+                    left,
+                    device.name(),
+                    err
+                );
+            }
+        }
+        if let Some(middle) = self.middle_button_emulation {
+            if let Err(err) = device.config_middle_emulation_set_enabled(middle) {
+                slog_scope::warn!(
+                    "Failed to apply middle-button-emulation {:?} for device {:?}: {:?}",
+                    middle,
+                    device.name(),
+                    err
+                );
+            }
+        }
+        if let Some(angle) = self.rotation_angle {
+            if let Err(err) = device.config_rotation_set_angle(angle) {
+                slog_scope::warn!(
+                    "Failed to apply rotation-angle {:?} for device {:?}: {:?}",
+                    angle,
+                    device.name(),
+                    err
+                );
+            }
+        }
+        if let Some(scroll) = self.scroll_config.as_ref() {
+            if let Some(method) = scroll.method {
+                if let Err(err) = device.config_scroll_set_method(method) {
+                    slog_scope::warn!(
+                        "Failed to apply scroll method {:?} for device {:?}: {:?}",
+                        method,
+                        device.name(),
+                        err
+                    );
                 }
-                if let Some(angle) = config.rotation_angle {
-                    if let Err(err) = device.config_rotation_set_angle(angle) {
-                        slog_scope::warn!(
-                            "Failed to apply rotation-angle {:?} for device {:?}: {:?}",
-                            angle,
-                            device.name(),
-                            err
-                        );
-                    }
+            }
+            if let Some(natural) = scroll.natural_scroll {
+                if let Err(err) = device.config_scroll_set_natural_scroll_enabled(natural) {
+                    slog_scope::warn!(
+                        "Failed to apply natural scrolling {:?} for device {:?}: {:?}",
+                        natural,
+                        device.name(),
+                        err
+                    );
                 }
-                if let Some(scroll) = config.scroll_config.as_ref() {
-                    if let Some(method) = scroll.method {
-                        if let Err(err) = device.config_scroll_set_method(method) {
-                            slog_scope::warn!(
-                                "Failed to apply scroll method {:?} for device {:?}: {:?}",
-                                method,
-                                device.name(),
-                                err
-                            );
-                        }
-                    }
-                    if let Some(natural) = scroll.natural_scroll {
-                        if let Err(err) = device.config_scroll_set_natural_scroll_enabled(natural) {
-                            slog_scope::warn!(
-                                "Failed to apply natural scrolling {:?} for device {:?}: {:?}",
-                                natural,
-                                device.name(),
-                                err
-                            );
-                        }
-                    }
-                    if let Some(button) = scroll.scroll_button {
-                        if let Err(err) = device.config_scroll_set_button(button) {
-                            slog_scope::warn!(
-                                "Failed to apply scroll button {:?} for device {:?}: {:?}",
-                                button,
-                                device.name(),
-                                err
-                            );
-                        }
-                    }
+            }
+            if let Some(button) = scroll.scroll_button {
+                if let Err(err) = device.config_scroll_set_button(button) {
+                    slog_scope::warn!(
+                        "Failed to apply scroll button {:?} for device {:?}: {:?}",
+                        button,
+                        device.name(),
+                        err
+                    );
                 }
-                if let Some(tap) = config.tap_config.as_ref() {
-                    if let Err(err) = device.config_tap_set_enabled(tap.enabled) {
-                        slog_scope::warn!(
-                            "Failed to apply tap-to-click {:?} for device {:?}: {:?}",
-                            tap.enabled,
-                            device.name(),
-                            err
-                        );
-                    }
-                    if let Some(button_map) = tap.button_map {
-                        if let Err(err) = device.config_tap_set_button_map(button_map) {
-                            slog_scope::warn!(
-                                "Failed to apply button map {:?} for device {:?}: {:?}",
-                                button_map,
-                                device.name(),
-                                err
-                            );
-                        }
-                    }
-                    if let Err(err) = device.config_tap_set_drag_enabled(tap.drag) {
-                        slog_scope::warn!(
-                            "Failed to apply tap-drag {:?} for device {:?}: {:?}",
-                            tap.drag,
-                            device.name(),
-                            err
-                        );
-                    }
-                    if let Err(err) = device.config_tap_set_drag_lock_enabled(tap.drag_lock) {
-                        slog_scope::warn!(
-                            "Failed to apply tap-drag-lock {:?} for device {:?}: {:?}",
-                            tap.drag_lock,
-                            device.name(),
-                            err
-                        );
-                    }
+            }
+        }
+        if let Some(tap) = self.tap_config.as_ref() {
+            if let Err(err) = device.config_tap_set_enabled(tap.enabled) {
+                slog_scope::warn!(
+                    "Failed to apply tap-to-click {:?} for device {:?}: {:?}",
+                    tap.enabled,
+                    device.name(),
+                    err
+                );
+            }
+            if let Some(button_map) = tap.button_map {
+                if let Err(err) = device.config_tap_set_button_map(button_map) {
+                    slog_scope::warn!(
+                        "Failed to apply button map {:?} for device {:?}: {:?}",
+                        button_map,
+                        device.name(),
+                        err
+                    );
                 }
             }
-            Entry::Vacant(entry) => {
-                entry.insert(InputConfig {
-                    state: match device.config_send_events_mode() {
-                        x if x.contains(SendEventsMode::ENABLED) => DeviceState::Enabled,
-                        x if x.contains(SendEventsMode::DISABLED_ON_EXTERNAL_MOUSE) => {
-                            DeviceState::DisabledOnExternalMouse
-                        }
-                        x if x.contains(SendEventsMode::DISABLED) => DeviceState::Disabled,
-                        _ => DeviceState::Disabled,
-                    },
-                    acceleration: if device.config_accel_is_available() {
-                        Some(AccelConfig {
-                            profile: device.config_accel_profile(),
-                            speed: device.config_accel_speed(),
-                        })
-                    } else {
-                        None
-                    },
-                    calibration: device.config_calibration_matrix(),
-                    click_method: device.config_click_method(),
-                    disable_while_typing: if device.config_dwt_is_available() {
-                        Some(device.config_dwt_enabled())
-                    } else {
-                        None
-                    },
-                    left_handed: if device.config_left_handed_is_available() {
-                        Some(device.config_left_handed())
-                    } else {
-                        None
-                    },
-                    middle_button_emulation: if device.config_middle_emulation_is_available() {
-                        Some(device.config_middle_emulation_enabled())
-                    } else {
-                        None
-                    },
-                    rotation_angle: if device.config_rotation_is_available() {
-                        Some(device.config_rotation_angle())
+            if let Err(err) = device.config_tap_set_drag_enabled(tap.drag) {
+                slog_scope::warn!(
+                    "Failed to apply tap-drag {:?} for device {:?}: {:?}",
+                    tap.drag,
+                    device.name(),
+                    err
+                );
+            }
+            if let Err(err) = device.config_tap_set_drag_lock_enabled(tap.drag_lock) {
+                slog_scope::warn!(
+                    "Failed to apply tap-drag-lock {:?} for device {:?}: {:?}",
+                    tap.drag_lock,
+                    device.name(),
+                    err
+                );
+            }
+        }
+    }
+
+    /// Reads the current configuration of the given libinput device.
+    fn from_device(device: &InputDevice) -> InputConfig {
+        InputConfig {
+            state: match device.config_send_events_mode() {
+                x if x.contains(SendEventsMode::ENABLED) => DeviceState::Enabled,
+                x if x.contains(SendEventsMode::DISABLED_ON_EXTERNAL_MOUSE) => {
+                    DeviceState::DisabledOnExternalMouse
+                }
+                x if x.contains(SendEventsMode::DISABLED) => DeviceState::Disabled,
+                _ => DeviceState::Disabled,
+            },
+            acceleration: if device.config_accel_is_available() {
+                Some(AccelConfig {
+                    profile: device.config_accel_profile(),
+                    speed: device.config_accel_speed(),
+                })
+            } else {
+                None
+            },
+            calibration: device.config_calibration_matrix(),
+            click_method: device.config_click_method(),
+            disable_while_typing: if device.config_dwt_is_available() {
+                Some(device.config_dwt_enabled())
+            } else {
+                None
+            },
+            key_remap: HashMap::new(),
+            left_handed: if device.config_left_handed_is_available() {
+                Some(device.config_left_handed())
+            } else {
+                None
+            },
+            map_to_output: None,
+            middle_button_emulation: if device.config_middle_emulation_is_available() {
+                Some(device.config_middle_emulation_enabled())
+            } else {
+                None
+            },
+            rotation_angle: if device.config_rotation_is_available() {
+                Some(device.config_rotation_angle())
+            } else {
+                None
+            },
+            scroll_config: if device
+                .config_scroll_methods()
+                .iter()
+                .any(|x| *x != ScrollMethod::NoScroll)
+            {
+                Some(ScrollConfig {
+                    method: device.config_scroll_method(),
+                    natural_scroll: if device.config_scroll_has_natural_scroll() {
+                        Some(device.config_scroll_natural_scroll_enabled())
                     } else {
                         None
                     },
-                    scroll_config: if device
-                        .config_scroll_methods()
-                        .iter()
-                        .any(|x| *x != ScrollMethod::NoScroll)
+                    scroll_button: if device.config_scroll_method()
+                        == Some(ScrollMethod::OnButtonDown)
                     {
-                        Some(ScrollConfig {
-                            method: device.config_scroll_method(),
-                            natural_scroll: if device.config_scroll_has_natural_scroll() {
-                                Some(device.config_scroll_natural_scroll_enabled())
-                            } else {
-                                None
-                            },
-                            scroll_button: if device.config_scroll_method()
-                                == Some(ScrollMethod::OnButtonDown)
-                            {
-                                Some(device.config_scroll_button())
-                            } else {
-                                None
-                            },
-                        })
-                    } else {
-                        None
-                    },
-                    tap_config: if device.config_tap_finger_count() > 0 {
-                        Some(TapConfig {
-                            enabled: device.config_tap_enabled(),
-                            button_map: device.config_tap_button_map(),
-                            drag: device.config_tap_drag_enabled(),
-                            drag_lock: device.config_tap_drag_lock_enabled(),
-                        })
+                        Some(device.config_scroll_button())
                     } else {
                         None
                     },
-                });
-            }
+                })
+            } else {
+                None
+            },
+            tap_config: if device.config_tap_finger_count() > 0 {
+                Some(TapConfig {
+                    enabled: device.config_tap_enabled(),
+                    button_map: device.config_tap_button_map(),
+                    drag: device.config_tap_drag_enabled(),
+                    drag_lock: device.config_tap_drag_lock_enabled(),
+                })
+            } else {
+                None
+            },
         }
     }
 }
@@ -682,6 +1154,13 @@ impl DynamicConfig {
     pub fn inputs_mut<'a>(&'a mut self) -> PersistenceGuard<'a, InputsConfig> {
         PersistenceGuard(self.inputs.0.clone(), &mut self.inputs.1)
     }
+
+    /// Configuration of the device with the given name, including any matching rules
+    pub fn device_config(&self, device_name: &str) -> Option<&InputConfig> {
+        self.resolved_devices
+            .get(device_name)
+            .or_else(|| self.inputs.1.devices.get(device_name))
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
@@ -754,6 +1233,44 @@ impl KeyPattern {
     }
 }
 
+/// Description of a touchpad gesture that might be
+/// handled by the compositor.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Hash)]
+#[serde(deny_unknown_fields)]
+pub struct GesturePattern {
+    /// How many fingers are expected to take part in the gesture
+    pub fingers: u32,
+    /// The kind of gesture, that was performed
+    pub gesture: Gesture,
+}
+
+impl GesturePattern {
+    pub fn new(fingers: u32, gesture: Gesture) -> GesturePattern {
+        GesturePattern { fingers, gesture }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Hash)]
+pub enum Gesture {
+    Swipe(SwipeDirection),
+    Pinch(PinchDirection),
+    Hold,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Hash)]
+pub enum SwipeDirection {
+    Left,
+    Right,
+    Up,
+    Down,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Hash)]
+pub enum PinchDirection {
+    In,
+    Out,
+}
+
 #[derive(Debug, Deserialize, Clone, PartialEq, Eq)]
 pub enum Action {
     Terminate,
@@ -761,11 +1278,265 @@ pub enum Action {
     Close,
     Workspace(u8),
     MoveToWorkspace(u8),
+    NextWorkspace,
+    PreviousWorkspace,
     Focus(FocusDirection),
     Orientation(crate::shell::layout::Orientation),
+    /// Switches the binding mode, which is announced to ipc subscribers
+    ShellMode(crate::shell::ShellMode),
     ToggleTiling,
     ToggleWindowFloating,
     Fullscreen,
     Screenshot,
+    ToggleStickyKeys,
+    ToggleSlowKeys,
+    ToggleBounceKeys,
     Spawn(String),
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(inputs: &str) -> Config {
+        Config {
+            static_conf: ron::from_str("(key_bindings: {}, workspace_mode: OutputBound)").unwrap(),
+            dynamic_conf: DynamicConfig {
+                outputs: (
+                    None,
+                    OutputsConfig {
+                        config: HashMap::new(),
+                    },
+                ),
+                inputs: (None, ron::from_str(inputs).unwrap()),
+                resolved_devices: HashMap::new(),
+            },
+        }
+    }
+
+    const INPUTS: &str = r#"(
+        xkb: (rules: "", model: "", layout: "us", variant: "", options: None),
+        devices: {
+            "Keyboard": (state: Enabled, key_remap: { 58: 1 }),
+        },
+    )"#;
+
+    #[test]
+    fn glob_match() {
+        assert!(super::glob_match("Logitech*", "Logitech MX Master 3"));
+        assert!(super::glob_match("*Touchpad", "SynPS/2 Synaptics Touchpad"));
+        assert!(super::glob_match("*Mouse*", "USB Optical Mouse Keyboard"));
+        assert!(super::glob_match("Key?oard", "Keyboard"));
+        assert!(super::glob_match("*", ""));
+        assert!(super::glob_match("a*b*c", "abbbc"));
+        assert!(!super::glob_match("Logitech*", "USB Logitech Mouse"));
+        assert!(!super::glob_match("Key?oard", "Keoard"));
+        assert!(!super::glob_match("a*b", "abc"));
+        assert!(!super::glob_match("", "Keyboard"));
+    }
+
+    fn info(connector: &str, model: &str, serial: Option<&str>) -> OutputInfo {
+        OutputInfo {
+            connector: connector.into(),
+            make: "Dell".into(),
+            model: model.into(),
+            serial: serial.map(Into::into),
+        }
+    }
+
+    fn placed(x: i32) -> OutputConfig {
+        OutputConfig {
+            position: (x, 0),
+            ..Default::default()
+        }
+    }
+
+    fn outputs(saved: Vec<OutputInfo>, configs: Vec<OutputConfig>) -> OutputsConfig {
+        OutputsConfig {
+            config: HashMap::from([(saved, configs)]),
+        }
+    }
+
+    #[test]
+    fn find_legacy_entry() {
+        let outputs = outputs(
+            vec![
+                info("DP-1", "U2720Q", None),
+                info("HDMI-A-1", "P2419H", None),
+            ],
+            vec![placed(0), placed(3840)],
+        );
+        let infos = [
+            info("HDMI-A-1", "P2419H", Some("2")),
+            info("DP-1", "U2720Q", Some("1")),
+        ];
+        assert_eq!(outputs.find(&infos), Some(vec![placed(3840), placed(0)]));
+
+        // without a serial there is no telling, if the displays were swapped
+        let infos = [
+            info("DP-1", "P2419H", Some("2")),
+            info("HDMI-A-1", "U2720Q", Some("1")),
+        ];
+        assert_eq!(outputs.find(&infos), None);
+    }
+
+    #[test]
+    fn find_moved_display() {
+        let outputs = outputs(
+            vec![
+                info("eDP-1", "Panel", None),
+                info("DP-1", "U2720Q", Some("1")),
+            ],
+            vec![placed(0), placed(1920)],
+        );
+        let infos = [
+            info("eDP-1", "Panel", None),
+            info("DP-3", "U2720Q", Some("1")),
+        ];
+        assert_eq!(outputs.find(&infos), Some(vec![placed(0), placed(1920)]));
+    }
+
+    #[test]
+    fn find_identical_displays() {
+        let outputs = outputs(
+            vec![
+                info("DP-1", "U2720Q", Some("1")),
+                info("DP-2", "U2720Q", Some("2")),
+            ],
+            vec![placed(0), placed(3840)],
+        );
+        let infos = [
+            info("DP-1", "U2720Q", Some("2")),
+            info("DP-2", "U2720Q", Some("1")),
+        ];
+        assert_eq!(outputs.find(&infos), Some(vec![placed(3840), placed(0)]));
+
+        let infos = [
+            info("DP-1", "U2720Q", Some("1")),
+            info("DP-2", "U2720Q", Some("3")),
+        ];
+        assert_eq!(outputs.find(&infos), None);
+    }
+
+    #[test]
+    fn find_renames_mirror() {
+        let mirror = OutputConfig {
+            mirror_of: Some("DP-1".into()),
+            ..Default::default()
+        };
+        let outputs = outputs(
+            vec![
+                info("DP-1", "U2720Q", Some("1")),
+                info("HDMI-A-1", "P2419H", Some("2")),
+            ],
+            vec![placed(0), mirror],
+        );
+        let infos = [
+            info("HDMI-A-1", "P2419H", Some("2")),
+            info("DP-2", "U2720Q", Some("1")),
+        ];
+        let configs = outputs.find(&infos).unwrap();
+        assert_eq!(configs[0].mirror_of.as_deref(), Some("DP-2"));
+        assert_eq!(configs[1], placed(0));
+    }
+
+    #[test]
+    fn sandbox_globals() {
+        let mut config = config(INPUTS);
+        config.static_conf.sandbox_permissions = ron::from_str(
+            r#"[
+                (globals: ["zwp_text_input_manager_v3"]),
+                (sandbox_engine: Some("org.flatpak"), app_id: Some("com.obsproject.*"), globals: ["zwlr_output_manager_v1"]),
+            ]"#,
+        )
+        .unwrap();
+        let context = |sandbox_engine: &str, app_id: Option<&str>| SecurityContext {
+            sandbox_engine: Some(sandbox_engine.into()),
+            app_id: app_id.map(Into::into),
+            instance_id: None,
+        };
+
+        let globals =
+            config.sandbox_globals(&context("org.flatpak", Some("com.obsproject.Studio")));
+        assert!(globals.contains("zwp_text_input_manager_v3"));
+        assert!(globals.contains("zwlr_output_manager_v1"));
+        let globals = config.sandbox_globals(&context("org.flatpak", Some("org.gnome.Maps")));
+        assert!(globals.contains("zwp_text_input_manager_v3"));
+        assert!(!globals.contains("zwlr_output_manager_v1"));
+        // without an app id, the app can't be told apart from any other
+        let globals = config.sandbox_globals(&context("org.flatpak", None));
+        assert!(!globals.contains("zwlr_output_manager_v1"));
+        let globals =
+            config.sandbox_globals(&context("io.snapcraft", Some("com.obsproject.Studio")));
+        assert!(!globals.contains("zwlr_output_manager_v1"));
+    }
+
+    #[test]
+    fn connector_order() {
+        let mut names = vec!["HDMI-A-1", "DP-10", "eDP-1", "DP-2", "Virtual"];
+        names.sort_by_key(|name| super::connector_order(name));
+        assert_eq!(names, ["DP-2", "DP-10", "HDMI-A-1", "Virtual", "eDP-1"]);
+    }
+
+    #[test]
+    fn default_scale() {
+        // 14" 4k laptop panel
+        assert_eq!(
+            super::default_scale((310, 174), (3840, 2160).into()),
+            Some(2.0)
+        );
+        // 27" 1440p monitor
+        assert_eq!(
+            super::default_scale((597, 336), (2560, 1440).into()),
+            Some(1.0)
+        );
+        // 27" 4k monitor
+        assert_eq!(
+            super::default_scale((597, 336), (3840, 2160).into()),
+            Some(1.5)
+        );
+        // no physical size or only the aspect ratio
+        assert_eq!(super::default_scale((0, 0), (1920, 1080).into()), None);
+        assert_eq!(super::default_scale((16, 9), (1920, 1080).into()), None);
+    }
+
+    #[test]
+    fn merge() {
+        let rule: InputConfig = ron::from_str(
+            "(state: Disabled, left_handed: Some(true), key_remap: { 58: 1, 1: 58 })",
+        )
+        .unwrap();
+        let device: InputConfig =
+            ron::from_str("(state: Enabled, rotation_angle: Some(90), key_remap: { 58: 29 })")
+                .unwrap();
+
+        let mut merged = rule.clone();
+        merged.merge(&device);
+        // state is mandatory and always overrides
+        assert!(matches!(merged.state, DeviceState::Enabled));
+        // unset options keep the value of earlier rules
+        assert_eq!(merged.left_handed, Some(true));
+        assert_eq!(merged.rotation_angle, Some(90));
+        // key_remap is extended, later entries win
+        assert_eq!(merged.key_remap.len(), 2);
+        assert_eq!(merged.key_remap[&58], 29);
+        assert_eq!(merged.key_remap[&1], 58);
+    }
+
+    #[test]
+    fn remap_key() {
+        let mut config = config(INPUTS);
+        assert_eq!(config.remap_key("Keyboard", 58), 1);
+        assert_eq!(config.remap_key("Keyboard", 30), 30);
+        assert_eq!(config.remap_key("Other Keyboard", 58), 58);
+
+        // merged rules take precedence over the plain device entry
+        let mut resolved = config.dynamic_conf.inputs().devices["Keyboard"].clone();
+        resolved.key_remap.insert(58, 29);
+        config
+            .dynamic_conf
+            .resolved_devices
+            .insert("Keyboard".into(), resolved);
+        assert_eq!(config.remap_key("Keyboard", 58), 29);
+    }
+}
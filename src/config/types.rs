@@ -35,6 +35,21 @@ impl Default for XkbConfig {
     }
 }
 
+impl XkbConfig {
+    /// Compiles the keymap described by this configuration, like the seat does.
+    pub fn compile_keymap(&self, context: &xkb::Context) -> Option<xkb::Keymap> {
+        xkb::Keymap::new_from_names(
+            context,
+            &self.rules,
+            &self.model,
+            &self.layout,
+            &self.variant,
+            self.options.clone(),
+            xkb::KEYMAP_COMPILE_NO_FLAGS,
+        )
+    }
+}
+
 impl<'a> Into<WlXkbConfig<'a>> for &'a XkbConfig {
     fn into(self) -> WlXkbConfig<'a> {
         WlXkbConfig {
@@ -54,6 +54,20 @@ pub fn fps_ui(
                     if let Some(hash) = std::option_env!("GIT_HASH").and_then(|x| x.get(0..8)) {
                         ui.label(hash);
                     }
+                    if let Some(surface) = state
+                        .shell
+                        .keyboard_shortcuts_inhibit_state
+                        .active_inhibitor()
+                    {
+                        ui.label(
+                            egui::RichText::new(format!(
+                                "Shortcuts inhibited by {}/{:?}",
+                                surface.id(),
+                                surface.client_id()
+                            ))
+                            .code(),
+                        );
+                    }
 
                     if !state.egui.active {
                         ui.label("Press Mod+Escape for debug menu");
@@ -0,0 +1,252 @@
+// SPDX-License-Identifier: GPL-3.0-only
+
+use crate::utils::prelude::*;
+use smithay::{
+    backend::input::KeyState,
+    reexports::{
+        calloop::{
+            timer::{TimeoutAction, Timer},
+            RegistrationToken,
+        },
+        wayland_server::DisplayHandle,
+    },
+    wayland::seat::Seat,
+};
+use std::{cell::RefCell, collections::HashMap, time::Duration};
+
+/// Evdev keycodes of the modifiers handled by sticky keys
+const MODIFIER_KEYS: [u32; 8] = [
+    29,  // KEY_LEFTCTRL
+    97,  // KEY_RIGHTCTRL
+    42,  // KEY_LEFTSHIFT
+    54,  // KEY_RIGHTSHIFT
+    56,  // KEY_LEFTALT
+    100, // KEY_RIGHTALT
+    125, // KEY_LEFTMETA
+    126, // KEY_RIGHTMETA
+];
+
+/// State of the keyboard accessibility filters of a seat
+#[derive(Debug, Default)]
+pub struct AccessibilityState(RefCell<FilterState>);
+
+#[derive(Debug, Default)]
+struct FilterState {
+    /// Modifiers currently tracked by sticky keys
+    sticky: HashMap<u32, StickyModifier>,
+    /// Presses waiting for the slow keys delay to pass
+    slow: HashMap<u32, RegistrationToken>,
+    /// Time of the last release of every key, used by bounce keys
+    released: HashMap<u32, u32>,
+    /// Keys, whose press was dropped, so their release has to be dropped as well
+    dropped: Vec<u32>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum StickyModifier {
+    /// Physically held, `used` once another key was pressed in the meantime
+    Held { used: bool },
+    /// Released on its own, stays active until the next key is released
+    Latched,
+    /// Pressed again while latched, becomes locked once released
+    Locking,
+    /// Stays active until it is pressed again
+    Locked,
+    /// Pressed again while locked, is released together with the key
+    Unlocking,
+}
+
+impl FilterState {
+    fn sticky_keys(
+        &mut self,
+        enabled: bool,
+        keycode: u32,
+        state: KeyState,
+    ) -> Vec<(u32, KeyState)> {
+        if !MODIFIER_KEYS.contains(&keycode) {
+            let mut events = vec![(keycode, state)];
+            match state {
+                KeyState::Pressed => {
+                    for modifier in self.sticky.values_mut() {
+                        if let StickyModifier::Held { used } = modifier {
+                            *used = true;
+                        }
+                    }
+                }
+                KeyState::Released => {
+                    let latched = self
+                        .sticky
+                        .iter()
+                        .filter(|(_, modifier)| **modifier == StickyModifier::Latched)
+                        .map(|(keycode, _)| *keycode)
+                        .collect::<Vec<_>>();
+                    for keycode in latched {
+                        self.sticky.remove(&keycode);
+                        events.push((keycode, KeyState::Released));
+                    }
+                }
+            }
+            return events;
+        }
+
+        let current = self.sticky.get(&keycode).copied();
+        if !enabled && current.is_none() {
+            return vec![(keycode, state)];
+        }
+
+        let (next, forward) = match (current, state) {
+            (None, KeyState::Pressed) => (Some(StickyModifier::Held { used: false }), true),
+            (Some(StickyModifier::Held { used: false }), KeyState::Released) => {
+                (Some(StickyModifier::Latched), false)
+            }
+            (Some(StickyModifier::Latched), KeyState::Pressed) => {
+                (Some(StickyModifier::Locking), false)
+            }
+            (Some(StickyModifier::Locking), KeyState::Released) => {
+                (Some(StickyModifier::Locked), false)
+            }
+            (Some(StickyModifier::Locked), KeyState::Pressed) => {
+                (Some(StickyModifier::Unlocking), false)
+            }
+            (_, KeyState::Released) => (None, true),
+            (current, KeyState::Pressed) => (current, false),
+        };
+        match next {
+            Some(modifier) => self.sticky.insert(keycode, modifier),
+            None => self.sticky.remove(&keycode),
+        };
+
+        if forward {
+            vec![(keycode, state)]
+        } else {
+            Vec::new()
+        }
+    }
+
+    /// Stops tracking all modifiers, returning those that need to be released
+    fn reset_sticky_keys(&mut self) -> Vec<u32> {
+        self.sticky
+            .drain()
+            // physically held keys are released as usual
+            .filter(|(_, modifier)| {
+                matches!(modifier, StickyModifier::Latched | StickyModifier::Locked)
+            })
+            .map(|(keycode, _)| keycode)
+            .collect()
+    }
+}
+
+impl State {
+    /// Runs a key event through the enabled accessibility filters.
+    ///
+    /// Returns the key events, that should be processed right away.
+    /// Presses held back by slow keys are processed once their delay has passed.
+    pub fn filter_accessibility(
+        &mut self,
+        seat: &Seat<State>,
+        keycode: u32,
+        state: KeyState,
+        time: u32,
+        from_input_method: bool,
+    ) -> Vec<(u32, KeyState)> {
+        let config = self.common.config.accessibility();
+        let mut filter = seat
+            .user_data()
+            .get::<AccessibilityState>()
+            .unwrap()
+            .0
+            .borrow_mut();
+
+        match state {
+            KeyState::Pressed => {
+                if config.bounce_keys {
+                    if let Some(released) = filter.released.get(&keycode) {
+                        if time.wrapping_sub(*released) < config.bounce_keys_delay {
+                            filter.dropped.push(keycode);
+                            return Vec::new();
+                        }
+                    }
+                }
+
+                if config.slow_keys && !filter.slow.contains_key(&keycode) {
+                    let delay = config.slow_keys_delay;
+                    let seat_clone = seat.clone();
+                    match self.common.event_loop_handle.insert_source(
+                        Timer::from_duration(Duration::from_millis(delay as u64)),
+                        move |_, _, data| {
+                            let seat = &seat_clone;
+                            let mut filter = seat
+                                .user_data()
+                                .get::<AccessibilityState>()
+                                .unwrap()
+                                .0
+                                .borrow_mut();
+                            // the key was released in the meantime
+                            if filter.slow.remove(&keycode).is_none() {
+                                return TimeoutAction::Drop;
+                            }
+                            let sticky_keys = data.state.common.config.accessibility().sticky_keys;
+                            let events =
+                                filter.sticky_keys(sticky_keys, keycode, KeyState::Pressed);
+                            std::mem::drop(filter);
+
+                            let dh = data.display.handle();
+                            let time = time.wrapping_add(delay);
+                            for (keycode, state) in events {
+                                data.state.handle_key(
+                                    &dh,
+                                    seat,
+                                    keycode,
+                                    state,
+                                    time,
+                                    from_input_method,
+                                );
+                            }
+                            TimeoutAction::Drop
+                        },
+                    ) {
+                        Ok(token) => {
+                            filter.slow.insert(keycode, token);
+                            return Vec::new();
+                        }
+                        Err(_) => {
+                            slog_scope::warn!("Failed to delay key press, accepting it right away")
+                        }
+                    }
+                }
+
+                filter.sticky_keys(config.sticky_keys, keycode, state)
+            }
+            KeyState::Released => {
+                if let Some(i) = filter.dropped.iter().position(|x| *x == keycode) {
+                    filter.dropped.remove(i);
+                    return Vec::new();
+                }
+                if let Some(token) = filter.slow.remove(&keycode) {
+                    self.common.event_loop_handle.remove(token);
+                    return Vec::new();
+                }
+
+                if config.bounce_keys {
+                    filter.released.insert(keycode, time);
+                }
+                filter.sticky_keys(config.sticky_keys, keycode, state)
+            }
+        }
+    }
+
+    /// Releases all modifiers latched or locked by sticky keys, e.g. after it was disabled.
+    pub fn release_sticky_keys(&mut self, dh: &DisplayHandle, seat: &Seat<State>) {
+        let keycodes = seat
+            .user_data()
+            .get::<AccessibilityState>()
+            .unwrap()
+            .0
+            .borrow_mut()
+            .reset_sticky_keys();
+        let time = self.common.start_time.elapsed().as_millis() as u32;
+        for keycode in keycodes {
+            self.handle_key(dh, seat, keycode, KeyState::Released, time, false);
+        }
+    }
+}
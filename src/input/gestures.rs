@@ -0,0 +1,310 @@
+// SPDX-License-Identifier: GPL-3.0-only
+
+use crate::{
+    config::{Action, Gesture, GesturePattern, PinchDirection, SwipeDirection},
+    utils::prelude::*,
+};
+use smithay::{
+    reexports::wayland_server::{protocol::wl_surface::WlSurface, DisplayHandle},
+    utils::{Logical, Point},
+    wayland::{seat::Seat, SERIAL_COUNTER},
+};
+use std::{cell::RefCell, collections::HashMap};
+
+/// Distance a swipe needs to cover to trigger a binding
+const SWIPE_THRESHOLD: f64 = 50.0;
+/// Change in scale a pinch needs to cover to trigger a binding
+const PINCH_THRESHOLD: f64 = 0.25;
+
+/// Gesture of a seat, that is currently consumed by the compositor
+/// or held back, until it is known, whether a binding matches it.
+#[derive(Debug, Default)]
+pub struct GestureState(pub RefCell<Option<TrackedGesture>>);
+
+#[derive(Debug, Clone, Copy)]
+pub struct TrackedGesture {
+    pub gesture: ActiveGesture,
+    /// Whether a binding matches the gesture, `false` while its direction is not known yet
+    pub bound: bool,
+    /// Time the gesture began, to replay it to clients, if it turns out to be unbound
+    pub time: u32,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum ActiveGesture {
+    Swipe {
+        fingers: u32,
+        delta: Point<f64, Logical>,
+    },
+    Pinch {
+        fingers: u32,
+        scale: f64,
+        rotation: f64,
+    },
+    Hold {
+        fingers: u32,
+    },
+}
+
+impl ActiveGesture {
+    fn fingers(&self) -> u32 {
+        match *self {
+            ActiveGesture::Swipe { fingers, .. }
+            | ActiveGesture::Pinch { fingers, .. }
+            | ActiveGesture::Hold { fingers } => fingers,
+        }
+    }
+
+    /// Whether any binding might match this gesture, once its direction is known.
+    fn might_be_bound(&self, bindings: &HashMap<GesturePattern, Action>) -> bool {
+        bindings.keys().any(|pattern| {
+            pattern.fingers == self.fingers()
+                && match (self, pattern.gesture) {
+                    (ActiveGesture::Swipe { .. }, Gesture::Swipe(_))
+                    | (ActiveGesture::Pinch { .. }, Gesture::Pinch(_))
+                    | (ActiveGesture::Hold { .. }, Gesture::Hold) => true,
+                    _ => false,
+                }
+        })
+    }
+
+    /// The pattern this gesture resolved to, if it passed the thresholds.
+    fn pattern(&self) -> Option<GesturePattern> {
+        let gesture = match *self {
+            ActiveGesture::Swipe { delta, .. } => {
+                if delta.x.abs().max(delta.y.abs()) < SWIPE_THRESHOLD {
+                    return None;
+                }
+                Gesture::Swipe(if delta.x.abs() >= delta.y.abs() {
+                    if delta.x < 0.0 {
+                        SwipeDirection::Left
+                    } else {
+                        SwipeDirection::Right
+                    }
+                } else if delta.y < 0.0 {
+                    SwipeDirection::Up
+                } else {
+                    SwipeDirection::Down
+                })
+            }
+            ActiveGesture::Pinch { scale, .. } => {
+                if (scale - 1.0).abs() < PINCH_THRESHOLD {
+                    return None;
+                }
+                Gesture::Pinch(if scale < 1.0 {
+                    PinchDirection::In
+                } else {
+                    PinchDirection::Out
+                })
+            }
+            ActiveGesture::Hold { .. } => Gesture::Hold,
+        };
+        Some(GesturePattern::new(self.fingers(), gesture))
+    }
+}
+
+impl State {
+    /// Starts a new gesture sequence on the given seat.
+    ///
+    /// If the compositor has bindings for gestures of this kind, the gesture is held back
+    /// in the seats `GestureState`, until its direction tells, if any of them matches.
+    /// Otherwise it is forwarded to the surface under the pointer through the pointer-gestures protocol.
+    pub fn gesture_begin(&mut self, seat: &Seat<State>, gesture: ActiveGesture, time: u32) {
+        if gesture.might_be_bound(&self.common.config.static_conf.gesture_bindings) {
+            *seat
+                .user_data()
+                .get::<GestureState>()
+                .unwrap()
+                .0
+                .borrow_mut() = Some(TrackedGesture {
+                gesture,
+                bound: false,
+                time,
+            });
+            self.gesture_resolve(seat, time);
+        } else {
+            self.forward_gesture_begin(seat, gesture, time);
+        }
+    }
+
+    pub fn gesture_swipe_update(
+        &mut self,
+        seat: &Seat<State>,
+        time: u32,
+        delta: Point<f64, Logical>,
+    ) {
+        let tracked = match seat
+            .user_data()
+            .get::<GestureState>()
+            .unwrap()
+            .0
+            .borrow_mut()
+            .as_mut()
+        {
+            Some(TrackedGesture {
+                gesture: ActiveGesture::Swipe { delta: total, .. },
+                ..
+            }) => {
+                *total += delta;
+                true
+            }
+            _ => false,
+        };
+        if tracked {
+            self.gesture_resolve(seat, time);
+        } else {
+            self.common.pointer_gestures_state.swipe_update(time, delta);
+        }
+    }
+
+    pub fn gesture_pinch_update(
+        &mut self,
+        seat: &Seat<State>,
+        time: u32,
+        delta: Point<f64, Logical>,
+        scale: f64,
+        rotation: f64,
+    ) {
+        let tracked = match seat
+            .user_data()
+            .get::<GestureState>()
+            .unwrap()
+            .0
+            .borrow_mut()
+            .as_mut()
+        {
+            Some(TrackedGesture {
+                gesture:
+                    ActiveGesture::Pinch {
+                        scale: total_scale,
+                        rotation: total_rotation,
+                        ..
+                    },
+                ..
+            }) => {
+                *total_scale = scale;
+                *total_rotation += rotation;
+                true
+            }
+            _ => false,
+        };
+        if tracked {
+            self.gesture_resolve(seat, time);
+        } else {
+            self.common
+                .pointer_gestures_state
+                .pinch_update(time, delta, scale, rotation);
+        }
+    }
+
+    /// Ends the gesture sequence on the given seat, triggering a matching binding.
+    ///
+    /// Returns `false`, if the gesture was forwarded to a client,
+    /// which then still needs to receive the end of the gesture.
+    pub fn gesture_end(
+        &mut self,
+        dh: &DisplayHandle,
+        seat: &Seat<State>,
+        time: u32,
+        cancelled: bool,
+    ) -> bool {
+        let tracked = seat
+            .user_data()
+            .get::<GestureState>()
+            .unwrap()
+            .0
+            .borrow_mut()
+            .take();
+        match tracked {
+            Some(tracked) if tracked.bound => {
+                if !cancelled {
+                    self.gesture_finished(dh, seat, tracked.gesture);
+                }
+                true
+            }
+            // never got far enough to match any binding
+            Some(tracked) => {
+                self.forward_gesture(seat, tracked, time);
+                false
+            }
+            None => false,
+        }
+    }
+
+    /// Decides, whether a held back gesture matches a binding, once its direction is known.
+    fn gesture_resolve(&mut self, seat: &Seat<State>, time: u32) {
+        let state = seat.user_data().get::<GestureState>().unwrap();
+        let mut tracked = state.0.borrow_mut();
+        let pattern = match tracked.as_ref() {
+            Some(gesture) if !gesture.bound => match gesture.gesture.pattern() {
+                Some(pattern) => pattern,
+                None => return,
+            },
+            _ => return,
+        };
+
+        if self
+            .common
+            .config
+            .static_conf
+            .gesture_bindings
+            .contains_key(&pattern)
+        {
+            tracked.as_mut().unwrap().bound = true;
+        } else {
+            let gesture = tracked.take().unwrap();
+            std::mem::drop(tracked);
+            self.forward_gesture(seat, gesture, time);
+        }
+    }
+
+    /// Replays a held back gesture to the surface under the pointer
+    fn forward_gesture(&mut self, seat: &Seat<State>, tracked: TrackedGesture, time: u32) {
+        self.forward_gesture_begin(seat, tracked.gesture, tracked.time);
+        let gestures = &mut self.common.pointer_gestures_state;
+        match tracked.gesture {
+            ActiveGesture::Swipe { delta, .. } => gestures.swipe_update(time, delta),
+            ActiveGesture::Pinch {
+                scale, rotation, ..
+            } => gestures.pinch_update(time, (0.0, 0.0).into(), scale, rotation),
+            ActiveGesture::Hold { .. } => {}
+        }
+    }
+
+    fn forward_gesture_begin(&mut self, seat: &Seat<State>, gesture: ActiveGesture, time: u32) {
+        if let Some(surface) = self.pointer_focus(seat) {
+            let serial = SERIAL_COUNTER.next_serial();
+            let gestures = &mut self.common.pointer_gestures_state;
+            match gesture {
+                ActiveGesture::Swipe { fingers, .. } => {
+                    gestures.swipe_begin(&surface, serial, time, fingers)
+                }
+                ActiveGesture::Pinch { fingers, .. } => {
+                    gestures.pinch_begin(&surface, serial, time, fingers)
+                }
+                ActiveGesture::Hold { fingers } => {
+                    gestures.hold_begin(&surface, serial, time, fingers)
+                }
+            }
+        }
+    }
+
+    /// Triggers the binding matching a finished, consumed gesture, if any.
+    fn gesture_finished(&mut self, dh: &DisplayHandle, seat: &Seat<State>, gesture: ActiveGesture) {
+        if let Some(action) = gesture.pattern().and_then(|pattern| {
+            self.common
+                .config
+                .static_conf
+                .gesture_bindings
+                .get(&pattern)
+                .cloned()
+        }) {
+            self.handle_action(dh, seat, action);
+        }
+    }
+
+    fn pointer_focus(&self, seat: &Seat<State>) -> Option<WlSurface> {
+        let position = seat.get_pointer()?.current_location();
+        self.surface_at(seat, position).map(|(surface, _)| surface)
+    }
+}
@@ -2,14 +2,18 @@
 
 use crate::{
     config::{Action, Config},
+    ipc::{self, ConfigSection},
     shell::{
-        Workspace,
+        Workspace, MAX_WORKSPACES,
         grabs::SeatMoveGrabState,
     },
     utils::prelude::*,
+    wayland::protocols::pointer_constraints::Constraint,
 };
 use smithay::{
-    backend::input::{Device, DeviceCapability, InputBackend, InputEvent, KeyState},
+    backend::input::{
+        Device, DeviceCapability, InputBackend, InputEvent, KeyState, TouchSlot, UnusedEvent,
+    },
     desktop::{layer_map_for_output, Kind, WindowSurfaceType},
     reexports::wayland_server::{protocol::wl_surface::WlSurface, DisplayHandle, Resource},
     utils::{Logical, Point, Rectangle},
@@ -18,15 +22,30 @@ use smithay::{
         output::Output,
         primary_selection::set_primary_focus,
         seat::{
-            keysyms, ButtonEvent, CursorImageStatus, FilterResult, KeysymHandle, MotionEvent, Seat,
+            keysyms, ButtonEvent, CursorImageStatus, DownEvent, FilterResult, KeysymHandle,
+            MotionEvent, Seat, TouchMotionEvent, UpEvent,
         },
         shell::wlr_layer::Layer as WlrLayer,
-        SERIAL_COUNTER,
+        tablet_manager::{TabletDescriptor, TabletSeatTrait},
+        Serial, SERIAL_COUNTER,
     },
 };
-use std::{cell::RefCell, collections::HashMap};
+use std::{
+    cell::{Cell, RefCell},
+    collections::HashMap,
+};
 use xkbcommon::xkb::KEY_XF86Switch_VT_12;
 
+mod accessibility;
+mod gestures;
+mod virtual_input;
+pub use self::accessibility::AccessibilityState;
+pub use self::gestures::{ActiveGesture, GestureState};
+pub use self::virtual_input::{
+    VirtualDevice, VirtualInput, VirtualKeyboardKeyEvent, VirtualPointerAxisEvent,
+    VirtualPointerButtonEvent, VirtualPointerMotionAbsoluteEvent, VirtualPointerMotionEvent,
+};
+
 crate::utils::id_gen!(next_seat_id, SEAT_ID, SEAT_IDS);
 
 #[repr(transparent)]
@@ -34,8 +53,63 @@ pub struct SeatId(pub usize);
 pub struct ActiveOutput(pub RefCell<Output>);
 #[derive(Default)]
 pub struct SupressedKeys(RefCell<Vec<u32>>);
+/// Keycodes held keys were remapped to, so releases match the press,
+/// even if the remapping changed in between
+#[derive(Default)]
+pub struct RemappedKeys(RefCell<HashMap<(String, u32), u32>>);
 #[derive(Default)]
 pub struct Devices(RefCell<HashMap<String, Vec<DeviceCapability>>>);
+#[derive(Default)]
+pub struct TouchPoints(RefCell<HashMap<TouchSlot, TouchPoint>>);
+/// Surface that received the last pointer motion of a seat and its location
+#[derive(Default)]
+pub struct PointerFocus(RefCell<Option<(WlSurface, Point<i32, Logical>)>>);
+
+#[derive(Debug, Clone)]
+pub struct TouchPoint {
+    pub serial: Serial,
+    pub location: Point<f64, Logical>,
+    pub focus: Option<WlSurface>,
+}
+
+/// Cursor of the tablet tool currently in proximity of one of the seats tablets
+pub struct TabletToolCursor {
+    location: Cell<Option<Point<f64, Logical>>>,
+    pub status: RefCell<CursorImageStatus>,
+}
+
+impl Default for TabletToolCursor {
+    fn default() -> TabletToolCursor {
+        TabletToolCursor {
+            location: Cell::new(None),
+            status: RefCell::new(CursorImageStatus::Default),
+        }
+    }
+}
+
+impl TabletToolCursor {
+    /// Location of the tool, if it is in proximity
+    pub fn location(&self) -> Option<Point<f64, Logical>> {
+        self.location.get()
+    }
+}
+
+/// Pointer motion events, that carry the microsecond timestamp needed for relative pointer events
+pub trait PointerMotionTime {
+    fn time_usec(&self) -> u64;
+}
+
+impl PointerMotionTime for smithay::reexports::input::event::pointer::PointerMotionEvent {
+    fn time_usec(&self) -> u64 {
+        smithay::reexports::input::event::pointer::PointerEventTrait::time_usec(self)
+    }
+}
+
+impl PointerMotionTime for UnusedEvent {
+    fn time_usec(&self) -> u64 {
+        match *self {}
+    }
+}
 
 impl Default for SeatId {
     fn default() -> SeatId {
@@ -65,12 +139,45 @@ impl SupressedKeys {
     }
 }
 
+impl RemappedKeys {
+    fn remap<D: Device>(&self, config: &Config, device: &D, keycode: u32, state: KeyState) -> u32 {
+        let key = (device.id(), keycode);
+        match state {
+            KeyState::Pressed => {
+                let remapped = config.remap_key(&device.name(), keycode);
+                self.0.borrow_mut().insert(key, remapped);
+                remapped
+            }
+            KeyState::Released => self
+                .0
+                .borrow_mut()
+                .remove(&key)
+                .unwrap_or_else(|| config.remap_key(&device.name(), keycode)),
+        }
+    }
+}
+
+impl TouchPoints {
+    /// Returns the slot and touch point, that started with the given serial
+    pub fn find_serial(&self, serial: Serial) -> Option<(TouchSlot, TouchPoint)> {
+        self.0
+            .borrow()
+            .iter()
+            .find(|(_, point)| point.serial == serial)
+            .map(|(slot, point)| (*slot, point.clone()))
+    }
+}
+
 impl Devices {
     fn add_device<D: Device>(&self, device: &D) -> Vec<DeviceCapability> {
         let id = device.id();
         let mut map = self.0.borrow_mut();
-        let caps = [DeviceCapability::Keyboard, DeviceCapability::Pointer]
-            .iter()
+        let caps = [
+            DeviceCapability::Keyboard,
+            DeviceCapability::Pointer,
+            DeviceCapability::Touch,
+        ]
+        .iter()
             .cloned()
             .filter(|c| device.has_capability(*c))
             .collect::<Vec<_>>();
@@ -104,6 +211,12 @@ pub fn add_seat(dh: &DisplayHandle, config: &Config, name: String) -> Seat<State
     userdata.insert_if_missing(SeatId::default);
     userdata.insert_if_missing(Devices::default);
     userdata.insert_if_missing(SupressedKeys::default);
+    userdata.insert_if_missing(RemappedKeys::default);
+    userdata.insert_if_missing(TouchPoints::default);
+    userdata.insert_if_missing(PointerFocus::default);
+    userdata.insert_if_missing(GestureState::default);
+    userdata.insert_if_missing(AccessibilityState::default);
+    userdata.insert_if_missing(TabletToolCursor::default);
     userdata.insert_if_missing(SeatMoveGrabState::default);
     userdata.insert_if_missing(|| RefCell::new(CursorImageStatus::Default));
 
@@ -142,28 +255,47 @@ pub fn add_seat(dh: &DisplayHandle, config: &Config, name: String) -> Seat<State
             .borrow_mut() = status;
     });
 
+    let owned_seat = seat.clone();
+    seat.tablet_seat().on_cursor_surface(move |_tool, status| {
+        *owned_seat
+            .user_data()
+            .get::<TabletToolCursor>()
+            .unwrap()
+            .status
+            .borrow_mut() = status;
+    });
+
     seat
 }
 
 impl State {
-    pub fn process_input_event<B: InputBackend>(
-        &mut self,
-        dh: &DisplayHandle,
-        event: InputEvent<B>,
-    ) {
+    pub fn process_input_event<B: InputBackend>(&mut self, dh: &DisplayHandle, event: InputEvent<B>)
+    where
+        B::PointerMotionEvent: PointerMotionTime,
+    {
         use smithay::backend::input::Event;
 
         match event {
             InputEvent::DeviceAdded { device } => {
                 let seat = &mut self.common.last_active_seat;
-                let userdata = seat.user_data();
-                let devices = userdata.get::<Devices>().unwrap();
-                for cap in devices.add_device(&device) {
+                let new_caps = {
+                    let userdata = seat.user_data();
+                    let devices = userdata.get::<Devices>().unwrap();
+                    devices.add_device(&device)
+                };
+                for cap in new_caps {
                     match cap {
-                        // TODO: Handle touch, tablet
+                        DeviceCapability::Touch => {
+                            seat.add_touch();
+                        }
                         _ => {}
                     }
                 }
+                // every tablet is announced on its own, not just the first one
+                if device.has_capability(DeviceCapability::TabletTool) {
+                    seat.tablet_seat()
+                        .add_tablet::<State>(dh, &TabletDescriptor::from(&device));
+                }
                 #[cfg(feature = "debug")]
                 {
                     self.common.egui.debug_state.handle_device_added(&device);
@@ -172,15 +304,36 @@ impl State {
             }
             InputEvent::DeviceRemoved { device } => {
                 for seat in &mut self.common.seats {
-                    let userdata = seat.user_data();
-                    let devices = userdata.get::<Devices>().unwrap();
-                    if devices.has_device(&device) {
-                        for cap in devices.remove_device(&device) {
+                    let removed_caps = {
+                        let userdata = seat.user_data();
+                        let devices = userdata.get::<Devices>().unwrap();
+                        if devices.has_device(&device) {
+                            Some(devices.remove_device(&device))
+                        } else {
+                            None
+                        }
+                    };
+                    if let Some(caps) = removed_caps {
+                        for cap in caps {
                             match cap {
-                                // TODO: Handle touch, tablet
+                                DeviceCapability::Touch => {
+                                    seat.remove_touch();
+                                }
                                 _ => {},
                             }
                         }
+                        if device.has_capability(DeviceCapability::TabletTool) {
+                            let tablet_seat = seat.tablet_seat();
+                            tablet_seat.remove_tablet(&TabletDescriptor::from(&device));
+                            if tablet_seat.count_tablets() == 0 {
+                                tablet_seat.clear_tools();
+                                seat.user_data()
+                                    .get::<TabletToolCursor>()
+                                    .unwrap()
+                                    .location
+                                    .set(None);
+                            }
+                        }
                         break;
                     }
                 }
@@ -198,225 +351,30 @@ impl State {
                     let userdata = seat.user_data();
                     let devices = userdata.get::<Devices>().unwrap();
                     if devices.has_device(&device) {
-                        let keycode = event.key_code();
+                        // remapping happens before any other processing, including xkb
                         let state = event.state();
+                        let keycode = userdata.get::<RemappedKeys>().unwrap().remap(
+                            &self.common.config,
+                            &device,
+                            event.key_code(),
+                            state,
+                        );
                         slog_scope::trace!("key"; "keycode" => keycode, "state" => format!("{:?}", state));
 
-                        let serial = SERIAL_COUNTER.next_serial();
                         let time = Event::time(&event);
-                        if let Some(action) = seat
-                            .get_keyboard()
-                            .unwrap()
-                            .input(dh, keycode, state, serial, time, |modifiers, handle| {
-                                if state == KeyState::Released
-                                    && userdata.get::<SupressedKeys>().unwrap().filter(&handle)
-                                {
-                                    return FilterResult::Intercept(None);
-                                }
-
-                                #[cfg(feature = "debug")]
-                                {
-                                    if self.common.seats.iter().position(|x| x == seat).unwrap()
-                                        == 0
-                                        && self.common.egui.active
-                                    {
-                                        if self.common.egui.debug_state.wants_keyboard() {
-                                            self.common.egui.debug_state.handle_keyboard(
-                                                &handle,
-                                                state == KeyState::Pressed,
-                                                modifiers.clone(),
-                                            );
-                                            userdata.get::<SupressedKeys>().unwrap().add(&handle);
-                                            return FilterResult::Intercept(None);
-                                        }
-                                        if self.common.egui.log_state.wants_keyboard() {
-                                            self.common.egui.log_state.handle_keyboard(
-                                                &handle,
-                                                state == KeyState::Pressed,
-                                                modifiers.clone(),
-                                            );
-                                            userdata.get::<SupressedKeys>().unwrap().add(&handle);
-                                            return FilterResult::Intercept(None);
-                                        }
-                                    }
-                                }
-
-                                if state == KeyState::Pressed
-                                    && (keysyms::KEY_XF86Switch_VT_1..=KEY_XF86Switch_VT_12)
-                                        .contains(&handle.modified_sym())
-                                {
-                                    if let Err(err) = self.backend.kms().switch_vt(
-                                        (handle.modified_sym() - keysyms::KEY_XF86Switch_VT_1 + 1)
-                                            as i32,
-                                    ) {
-                                        slog_scope::error!(
-                                            "Failed switching virtual terminal: {}",
-                                            err
-                                        );
-                                    }
-                                    userdata.get::<SupressedKeys>().unwrap().add(&handle);
-                                    return FilterResult::Intercept(None);
-                                }
-
-                                // here we can handle global shortcuts and the like
-                                for (binding, action) in
-                                    self.common.config.static_conf.key_bindings.iter()
-                                {
-                                    if state == KeyState::Pressed
-                                        && binding.modifiers == *modifiers
-                                        && handle.raw_syms().contains(&binding.key)
-                                    {
-                                        userdata.get::<SupressedKeys>().unwrap().add(&handle);
-                                        return FilterResult::Intercept(Some(action));
-                                    }
-                                }
-
-                                FilterResult::Forward
+                        // keys of the input methods own virtual keyboard skip its grab
+                        let from_input_method = self
+                            .common
+                            .virtual_keyboard_state
+                            .keyboard_object(&device.id())
+                            .map(|object| {
+                                self.common.shell.input_method_state.same_client_as(&object)
                             })
-                            .flatten()
+                            .unwrap_or(false);
+                        for (keycode, state) in
+                            self.filter_accessibility(seat, keycode, state, time, from_input_method)
                         {
-                            match action {
-                                Action::Terminate => {
-                                    self.common.should_stop = true;
-                                }
-                                #[cfg(feature = "debug")]
-                                Action::Debug => {
-                                    self.common.egui.active = !self.common.egui.active;
-                                }
-                                #[cfg(not(feature = "debug"))]
-                                Action::Debug => {
-                                    slog_scope::info!("Debug overlay not included in this version")
-                                }
-                                Action::Close => {
-                                    let current_output = active_output(seat, &self.common);
-                                    let workspace =
-                                        self.common.shell.active_space_mut(&current_output);
-                                    if let Some(window) = workspace.focus_stack(seat).last() {
-                                        #[allow(irrefutable_let_patterns)]
-                                        if let Kind::Xdg(xdg) = &window.toplevel() {
-                                            xdg.send_close();
-                                        }
-                                    }
-                                }
-                                Action::Workspace(key_num) => {
-                                    let current_output = active_output(seat, &self.common);
-                                    let workspace = match key_num {
-                                        0 => 9,
-                                        x => x - 1,
-                                    };
-                                    if let Some(motion_event) = self.common.shell.activate(
-                                        seat,
-                                        &current_output,
-                                        workspace as usize,
-                                    ) {
-                                        if let Some(ptr) = seat.get_pointer() {
-                                            ptr.motion(self, dh, &motion_event);
-                                        }
-                                    }
-                                }
-                                Action::MoveToWorkspace(key_num) => {
-                                    let current_output = active_output(seat, &self.common);
-                                    let workspace = match key_num {
-                                        0 => 9,
-                                        x => x - 1,
-                                    };
-                                    self.common.shell.move_current_window(
-                                        seat,
-                                        &current_output,
-                                        workspace as usize,
-                                    );
-                                }
-                                Action::Focus(focus) => {
-                                    let current_output = active_output(seat, &self.common);
-                                    let workspace =
-                                        self.common.shell.active_space_mut(&current_output);
-                                    let focus_stack = workspace.focus_stack(seat);
-                                    if let Some(window) = workspace.tiling_layer.move_focus(
-                                        *focus,
-                                        seat,
-                                        &mut workspace.space,
-                                        focus_stack.iter(),
-                                    ) {
-                                        std::mem::drop(focus_stack);
-                                        self.common.set_focus(
-                                            dh,
-                                            Some(window.toplevel().wl_surface()),
-                                            seat,
-                                            None,
-                                        );
-                                    }
-                                }
-                                Action::Fullscreen => {
-                                    let current_output = active_output(seat, &self.common);
-                                    let workspace =
-                                        self.common.shell.active_space_mut(&current_output);
-                                    let focused_window = workspace.focus_stack(seat).last();
-                                    if let Some(window) = focused_window {
-                                        workspace.fullscreen_toggle(&window, &current_output);
-                                    }
-                                }
-                                Action::Orientation(orientation) => {
-                                    let output = active_output(seat, &self.common);
-                                    let workspace = self.common.shell.active_space_mut(&output);
-                                    let focus_stack = workspace.focus_stack(seat);
-                                    workspace.tiling_layer.update_orientation(
-                                        *orientation,
-                                        &seat,
-                                        &mut workspace.space,
-                                        focus_stack.iter(),
-                                    );
-                                }
-                                Action::ToggleTiling => {
-                                    let output = active_output(seat, &self.common);
-                                    let workspace = self.common.shell.active_space_mut(&output);
-                                    workspace.toggle_tiling(seat);
-                                }
-                                Action::ToggleWindowFloating => {
-                                    let output = active_output(seat, &self.common);
-                                    let workspace = self.common.shell.active_space_mut(&output);
-                                    workspace.toggle_floating_window(seat);
-                                }
-                                Action::Spawn(command) => {
-                                    if let Err(err) = std::process::Command::new("/bin/sh")
-                                        .arg("-c")
-                                        .arg(command)
-                                        .env("WAYLAND_DISPLAY", &self.common.socket)
-                                        .env_remove("COSMIC_SESSION_SOCK")
-                                        .spawn()
-                                    {
-                                        slog_scope::warn!("Failed to spawn: {}", err);
-                                    }
-                                }
-                                Action::Screenshot => {
-                                    let home = match std::env::var("HOME") {
-                                        Ok(home) => home,
-                                        Err(err) => {
-                                            slog_scope::error!("$HOME is not set, can't save screenshots: {}", err);
-                                            break;
-                                        }
-                                    };
-                                    let timestamp = match std::time::SystemTime::UNIX_EPOCH.elapsed() {
-                                        Ok(duration) => duration.as_secs(),
-                                        Err(err) => {
-                                            slog_scope::error!("Unable to get timestamp, can't save screenshots: {}", err);
-                                            break;
-                                        }
-                                    };
-                                    for output in self.common.shell.outputs.clone().into_iter() {
-                                        match self.backend.offscreen_for_output(&output, &mut self.common) {
-                                            Ok(buffer) => {
-                                                let mut path = std::path::PathBuf::new();
-                                                path.push(&home);
-                                                path.push(format!("{}_{}.png", output.name(), timestamp));
-                                                if let Err(err) = buffer.save(&path) {
-                                                    slog_scope::error!("Unable to save screenshot at {}: {}", path.display(), err);
-                                                }
-                                            },
-                                            Err(err) => slog_scope::error!("Could not save screenshot for output {}: {}", output.name(), err),
-                                        }
-                                    }
-                                }
-                            }
+                            self.handle_key(dh, seat, keycode, state, time, from_input_method);
                         }
                         break;
                     }
@@ -431,9 +389,29 @@ impl State {
                     let devices = userdata.get::<Devices>().unwrap();
                     if devices.has_device(&device) {
                         let current_output = active_output(seat, &self.common);
+                        let pointer = seat.get_pointer().unwrap();
+                        let original_position = pointer.current_location();
+
+                        // relative motion is raw device motion and sent regardless of constraints
+                        let focus = userdata.get::<PointerFocus>().unwrap().0.borrow().clone();
+                        if let Some((surface, _)) = focus.as_ref() {
+                            self.common.relative_pointer_state.relative_motion(
+                                surface,
+                                event.time_usec(),
+                                event.delta(),
+                                event.delta_unaccel(),
+                            );
+                        }
 
-                        let mut position = seat.get_pointer().unwrap().current_location();
-                        position += event.delta();
+                        // a locked pointer does not move and therefore can't switch outputs either
+                        let mut position = match self.constrain_pointer(
+                            seat,
+                            original_position,
+                            original_position + event.delta(),
+                        ) {
+                            Some(position) => position,
+                            None => break,
+                        };
 
                         let output = self
                             .common
@@ -467,9 +445,9 @@ impl State {
                             output_geometry,
                             &workspace,
                         );
-                        seat.get_pointer().unwrap().motion(
-                            self,
+                        self.pointer_motion(
                             dh,
+                            seat,
                             &MotionEvent {
                                 location: position,
                                 focus: under,
@@ -503,8 +481,15 @@ impl State {
                     if devices.has_device(&device) {
                         let output = active_output(seat, &self.common);
                         let geometry = output.geometry();
-                        let position =
-                            geometry.loc.to_f64() + event.position_transformed(geometry.size);
+                        let original_position = seat.get_pointer().unwrap().current_location();
+                        let position = match self.constrain_pointer(
+                            seat,
+                            original_position,
+                            geometry.loc.to_f64() + event.position_transformed(geometry.size),
+                        ) {
+                            Some(position) => position,
+                            None => break,
+                        };
                         let relative_pos = self
                             .common
                             .shell
@@ -518,9 +503,9 @@ impl State {
                             geometry,
                             &workspace,
                         );
-                        seat.get_pointer().unwrap().motion(
-                            self,
+                        self.pointer_motion(
                             dh,
+                            seat,
                             &MotionEvent {
                                 location: position,
                                 focus: under,
@@ -586,89 +571,12 @@ impl State {
                         let state = match event.state() {
                             ButtonState::Pressed => {
                                 // change the keyboard focus unless the pointer or keyboard is grabbed
-                                // We test for any matching surface type here but always use the root
-                                // (in case of a window the toplevel) surface for the focus.
-                                // see: https://gitlab.freedesktop.org/wayland/wayland/-/issues/294
                                 if !seat.get_pointer().unwrap().is_grabbed()
                                     && !seat.get_keyboard().map(|k| k.is_grabbed()).unwrap_or(false)
                                 {
                                     let output = active_output(seat, &self.common);
                                     let pos = seat.get_pointer().unwrap().current_location();
-                                    let output_geo = output.geometry();
-                                    let relative_pos = self
-                                        .common
-                                        .shell
-                                        .space_relative_output_geometry(pos, &output);
-                                    let workspace = self.common.shell.active_space_mut(&output);
-                                    let layers = layer_map_for_output(&output);
-                                    let mut under = None;
-
-                                    if let Some(window) = workspace.get_fullscreen(&output) {
-                                        if let Some(layer) =
-                                            layers.layer_under(WlrLayer::Overlay, relative_pos)
-                                        {
-                                            if layer.can_receive_keyboard_focus() {
-                                                let layer_loc =
-                                                    layers.layer_geometry(layer).unwrap().loc;
-                                                under = layer
-                                                    .surface_under(
-                                                        pos - output_geo.loc.to_f64()
-                                                            - layer_loc.to_f64(),
-                                                        WindowSurfaceType::ALL,
-                                                    )
-                                                    .map(|(_, _)| layer.wl_surface().clone());
-                                            }
-                                        } else {
-                                            under = window
-                                                .surface_under(
-                                                    pos - output_geo.loc.to_f64(),
-                                                    WindowSurfaceType::ALL,
-                                                )
-                                                .map(|(_, _)| {
-                                                    window.toplevel().wl_surface().clone()
-                                                });
-                                        }
-                                    } else {
-                                        if let Some(layer) = layers
-                                            .layer_under(WlrLayer::Overlay, relative_pos)
-                                            .or_else(|| {
-                                                layers.layer_under(WlrLayer::Top, relative_pos)
-                                            })
-                                        {
-                                            if layer.can_receive_keyboard_focus() {
-                                                let layer_loc =
-                                                    layers.layer_geometry(layer).unwrap().loc;
-                                                under = layer
-                                                    .surface_under(
-                                                        pos - output_geo.loc.to_f64()
-                                                            - layer_loc.to_f64(),
-                                                        WindowSurfaceType::ALL,
-                                                    )
-                                                    .map(|(_, _)| layer.wl_surface().clone());
-                                            }
-                                        } else if let Some((window, _, _)) = workspace
-                                            .space
-                                            .surface_under(relative_pos, WindowSurfaceType::ALL)
-                                        {
-                                            under = Some(window.toplevel().wl_surface().clone());
-                                        } else if let Some(layer) =
-                                            layers.layer_under(WlrLayer::Bottom, pos).or_else(
-                                                || layers.layer_under(WlrLayer::Background, pos),
-                                            )
-                                        {
-                                            if layer.can_receive_keyboard_focus() {
-                                                let layer_loc =
-                                                    layers.layer_geometry(layer).unwrap().loc;
-                                                under = layer
-                                                    .surface_under(
-                                                        pos - output_geo.loc.to_f64()
-                                                            - layer_loc.to_f64(),
-                                                        WindowSurfaceType::ALL,
-                                                    )
-                                                    .map(|(_, _)| layer.wl_surface().clone());
-                                            }
-                                        };
-                                    }
+                                    let under = self.keyboard_focus_under(&output, pos);
 
                                     self.common
                                         .set_focus(dh, under.as_ref(), seat, Some(serial));
@@ -784,10 +692,959 @@ impl State {
                     }
                 }
             }
-            _ => { /* TODO e.g. tablet or touch events */ }
+            InputEvent::TouchDown { event, .. } => {
+                use smithay::backend::input::TouchDownEvent;
+
+                let device = event.device();
+                for seat in self.common.seats.clone().iter() {
+                    let userdata = seat.user_data();
+                    let devices = userdata.get::<Devices>().unwrap();
+                    if devices.has_device(&device) {
+                        let output = self.mapped_output(seat, &device);
+                        set_active_output(seat, &output);
+                        let geometry = output.geometry();
+                        let position =
+                            geometry.loc.to_f64() + event.position_transformed(geometry.size);
+                        let relative_pos = self
+                            .common
+                            .shell
+                            .space_relative_output_geometry(position, &output);
+                        let workspace = self.common.shell.active_space(&output);
+                        let under = State::surface_under(
+                            position,
+                            relative_pos,
+                            &output,
+                            geometry,
+                            workspace,
+                        );
+                        let serial = SERIAL_COUNTER.next_serial();
+
+                        // touching a surface focuses it, just like clicking on it would
+                        if !seat.get_keyboard().map(|k| k.is_grabbed()).unwrap_or(false) {
+                            let focus = self.keyboard_focus_under(&output, position);
+                            self.common.set_focus(dh, focus.as_ref(), seat, Some(serial));
+                        }
+
+                        userdata.get::<TouchPoints>().unwrap().0.borrow_mut().insert(
+                            event.slot(),
+                            TouchPoint {
+                                serial,
+                                location: position,
+                                focus: under.as_ref().map(|(surface, _)| surface.clone()),
+                            },
+                        );
+                        if let Some(touch) = seat.get_touch() {
+                            touch.down(
+                                self,
+                                dh,
+                                &DownEvent {
+                                    slot: event.slot(),
+                                    location: position,
+                                    focus: under,
+                                    serial,
+                                    time: event.time(),
+                                },
+                            );
+                        }
+                        break;
+                    }
+                }
+            }
+            InputEvent::TouchMotion { event, .. } => {
+                use smithay::backend::input::TouchMotionEvent as _;
+
+                let device = event.device();
+                for seat in self.common.seats.clone().iter() {
+                    let userdata = seat.user_data();
+                    let devices = userdata.get::<Devices>().unwrap();
+                    if devices.has_device(&device) {
+                        let output = self.mapped_output(seat, &device);
+                        let geometry = output.geometry();
+                        let position =
+                            geometry.loc.to_f64() + event.position_transformed(geometry.size);
+                        let slot = event.slot();
+
+                        if let Some(point) = userdata
+                            .get::<TouchPoints>()
+                            .unwrap()
+                            .0
+                            .borrow_mut()
+                            .get_mut(&slot)
+                        {
+                            point.location = position;
+                        }
+
+                        // a touch point driving a move grab is not forwarded to the client
+                        if userdata
+                            .get::<SeatMoveGrabState>()
+                            .unwrap()
+                            .borrow_mut()
+                            .as_mut()
+                            .map(|state| state.touch_motion(slot, position))
+                            .unwrap_or(false)
+                        {
+                            break;
+                        }
+
+                        if let Some(touch) = seat.get_touch() {
+                            touch.motion(
+                                self,
+                                dh,
+                                &TouchMotionEvent {
+                                    slot,
+                                    location: position,
+                                    time: event.time(),
+                                },
+                            );
+                        }
+                        break;
+                    }
+                }
+            }
+            InputEvent::TouchUp { event, .. } => {
+                use smithay::backend::input::TouchUpEvent;
+
+                let device = event.device();
+                for seat in self.common.seats.clone().iter() {
+                    let userdata = seat.user_data();
+                    let devices = userdata.get::<Devices>().unwrap();
+                    if devices.has_device(&device) {
+                        let slot = event.slot();
+                        let serial = SERIAL_COUNTER.next_serial();
+                        let point = userdata
+                            .get::<TouchPoints>()
+                            .unwrap()
+                            .0
+                            .borrow_mut()
+                            .remove(&slot);
+
+                        let ends_move = userdata
+                            .get::<SeatMoveGrabState>()
+                            .unwrap()
+                            .borrow()
+                            .as_ref()
+                            .map(|state| state.touch_slot() == Some(slot))
+                            .unwrap_or(false);
+                        if ends_move {
+                            let output = point
+                                .and_then(|point| {
+                                    self.common
+                                        .shell
+                                        .outputs()
+                                        .find(|o| o.geometry().to_f64().contains(point.location))
+                                        .cloned()
+                                })
+                                .unwrap_or_else(|| active_output(seat, &self.common));
+                            self.common.shell.drop_move(dh, seat, &output);
+                        }
+
+                        if let Some(touch) = seat.get_touch() {
+                            touch.up(
+                                self,
+                                dh,
+                                &UpEvent {
+                                    slot,
+                                    serial,
+                                    time: event.time(),
+                                },
+                            );
+                        }
+                        break;
+                    }
+                }
+            }
+            InputEvent::TouchCancel { event, .. } => {
+                let device = event.device();
+                for seat in self.common.seats.clone().iter() {
+                    let userdata = seat.user_data();
+                    let devices = userdata.get::<Devices>().unwrap();
+                    if devices.has_device(&device) {
+                        userdata.get::<TouchPoints>().unwrap().0.borrow_mut().clear();
+                        let ends_move = userdata
+                            .get::<SeatMoveGrabState>()
+                            .unwrap()
+                            .borrow()
+                            .as_ref()
+                            .map(|state| state.touch_slot().is_some())
+                            .unwrap_or(false);
+                        if ends_move {
+                            let output = active_output(seat, &self.common);
+                            self.common.shell.drop_move(dh, seat, &output);
+                        }
+
+                        if let Some(touch) = seat.get_touch() {
+                            touch.cancel(self, dh);
+                        }
+                        break;
+                    }
+                }
+            }
+            InputEvent::TouchFrame { event, .. } => {
+                let device = event.device();
+                for seat in self.common.seats.clone().iter() {
+                    let userdata = seat.user_data();
+                    let devices = userdata.get::<Devices>().unwrap();
+                    if devices.has_device(&device) {
+                        if let Some(touch) = seat.get_touch() {
+                            touch.frame(self, dh);
+                        }
+                        break;
+                    }
+                }
+            }
+            InputEvent::GestureSwipeBegin { event, .. } => {
+                use smithay::backend::input::GestureBeginEvent;
+
+                let device = event.device();
+                for seat in self.common.seats.clone().iter() {
+                    let userdata = seat.user_data();
+                    let devices = userdata.get::<Devices>().unwrap();
+                    if devices.has_device(&device) {
+                        self.gesture_begin(
+                            seat,
+                            ActiveGesture::Swipe {
+                                fingers: event.fingers(),
+                                delta: (0.0, 0.0).into(),
+                            },
+                            event.time(),
+                        );
+                        break;
+                    }
+                }
+            }
+            InputEvent::GestureSwipeUpdate { event, .. } => {
+                use smithay::backend::input::GestureSwipeUpdateEvent;
+
+                let device = event.device();
+                for seat in self.common.seats.clone().iter() {
+                    let userdata = seat.user_data();
+                    let devices = userdata.get::<Devices>().unwrap();
+                    if devices.has_device(&device) {
+                        self.gesture_swipe_update(
+                            seat,
+                            event.time(),
+                            (event.delta_x(), event.delta_y()).into(),
+                        );
+                        break;
+                    }
+                }
+            }
+            InputEvent::GestureSwipeEnd { event, .. } => {
+                use smithay::backend::input::GestureEndEvent;
+
+                let device = event.device();
+                for seat in self.common.seats.clone().iter() {
+                    let userdata = seat.user_data();
+                    let devices = userdata.get::<Devices>().unwrap();
+                    if devices.has_device(&device) {
+                        if !self.gesture_end(dh, seat, event.time(), event.cancelled()) {
+                            self.common.pointer_gestures_state.swipe_end(
+                                SERIAL_COUNTER.next_serial(),
+                                event.time(),
+                                event.cancelled(),
+                            );
+                        }
+                        break;
+                    }
+                }
+            }
+            InputEvent::GesturePinchBegin { event, .. } => {
+                use smithay::backend::input::GestureBeginEvent;
+
+                let device = event.device();
+                for seat in self.common.seats.clone().iter() {
+                    let userdata = seat.user_data();
+                    let devices = userdata.get::<Devices>().unwrap();
+                    if devices.has_device(&device) {
+                        self.gesture_begin(
+                            seat,
+                            ActiveGesture::Pinch {
+                                fingers: event.fingers(),
+                                scale: 1.0,
+                                rotation: 0.0,
+                            },
+                            event.time(),
+                        );
+                        break;
+                    }
+                }
+            }
+            InputEvent::GesturePinchUpdate { event, .. } => {
+                use smithay::backend::input::GesturePinchUpdateEvent;
+
+                let device = event.device();
+                for seat in self.common.seats.clone().iter() {
+                    let userdata = seat.user_data();
+                    let devices = userdata.get::<Devices>().unwrap();
+                    if devices.has_device(&device) {
+                        self.gesture_pinch_update(
+                            seat,
+                            event.time(),
+                            (event.delta_x(), event.delta_y()).into(),
+                            event.scale(),
+                            event.rotation(),
+                        );
+                        break;
+                    }
+                }
+            }
+            InputEvent::GesturePinchEnd { event, .. } => {
+                use smithay::backend::input::GestureEndEvent;
+
+                let device = event.device();
+                for seat in self.common.seats.clone().iter() {
+                    let userdata = seat.user_data();
+                    let devices = userdata.get::<Devices>().unwrap();
+                    if devices.has_device(&device) {
+                        if !self.gesture_end(dh, seat, event.time(), event.cancelled()) {
+                            self.common.pointer_gestures_state.pinch_end(
+                                SERIAL_COUNTER.next_serial(),
+                                event.time(),
+                                event.cancelled(),
+                            );
+                        }
+                        break;
+                    }
+                }
+            }
+            InputEvent::GestureHoldBegin { event, .. } => {
+                use smithay::backend::input::GestureBeginEvent;
+
+                let device = event.device();
+                for seat in self.common.seats.clone().iter() {
+                    let userdata = seat.user_data();
+                    let devices = userdata.get::<Devices>().unwrap();
+                    if devices.has_device(&device) {
+                        self.gesture_begin(
+                            seat,
+                            ActiveGesture::Hold {
+                                fingers: event.fingers(),
+                            },
+                            event.time(),
+                        );
+                        break;
+                    }
+                }
+            }
+            InputEvent::GestureHoldEnd { event, .. } => {
+                use smithay::backend::input::GestureEndEvent;
+
+                let device = event.device();
+                for seat in self.common.seats.clone().iter() {
+                    let userdata = seat.user_data();
+                    let devices = userdata.get::<Devices>().unwrap();
+                    if devices.has_device(&device) {
+                        if !self.gesture_end(dh, seat, event.time(), event.cancelled()) {
+                            self.common.pointer_gestures_state.hold_end(
+                                SERIAL_COUNTER.next_serial(),
+                                event.time(),
+                                event.cancelled(),
+                            );
+                        }
+                        break;
+                    }
+                }
+            }
+            InputEvent::TabletToolAxis { event, .. } => {
+                use smithay::backend::input::TabletToolEvent;
+
+                let device = event.device();
+                for seat in self.common.seats.clone().iter() {
+                    let userdata = seat.user_data();
+                    let devices = userdata.get::<Devices>().unwrap();
+                    if devices.has_device(&device) {
+                        // regions of the output are covered by the calibration matrix of the device
+                        let geometry = self.mapped_output(seat, &device).geometry();
+                        let position =
+                            geometry.loc.to_f64() + event.position_transformed(geometry.size);
+                        userdata
+                            .get::<TabletToolCursor>()
+                            .unwrap()
+                            .location
+                            .set(Some(position));
+                        let under = self.surface_at(seat, position);
+
+                        let tablet_seat = seat.tablet_seat();
+                        let tablet = tablet_seat.get_tablet(&TabletDescriptor::from(&device));
+                        let tool = tablet_seat.get_tool(&event.tool());
+                        if let (Some(tablet), Some(tool)) = (tablet, tool) {
+                            if event.pressure_has_changed() {
+                                tool.pressure(event.pressure());
+                            }
+                            if event.distance_has_changed() {
+                                tool.distance(event.distance());
+                            }
+                            if event.tilt_has_changed() {
+                                tool.tilt(event.tilt());
+                            }
+                            if event.slider_has_changed() {
+                                tool.slider_position(event.slider_position());
+                            }
+                            if event.rotation_has_changed() {
+                                tool.rotation(event.rotation());
+                            }
+                            if event.wheel_has_changed() {
+                                tool.wheel(event.wheel_delta(), event.wheel_delta_discrete());
+                            }
+                            tool.motion(
+                                position,
+                                under,
+                                &tablet,
+                                SERIAL_COUNTER.next_serial(),
+                                event.time(),
+                            );
+                        }
+                        break;
+                    }
+                }
+            }
+            InputEvent::TabletToolProximity { event, .. } => {
+                use smithay::backend::input::{
+                    ProximityState, TabletToolEvent, TabletToolProximityEvent,
+                };
+
+                let device = event.device();
+                for seat in self.common.seats.clone().iter() {
+                    let userdata = seat.user_data();
+                    let devices = userdata.get::<Devices>().unwrap();
+                    if devices.has_device(&device) {
+                        // regions of the output are covered by the calibration matrix of the device
+                        let geometry = self.mapped_output(seat, &device).geometry();
+                        let position =
+                            geometry.loc.to_f64() + event.position_transformed(geometry.size);
+                        let tool_cursor = userdata.get::<TabletToolCursor>().unwrap();
+
+                        let tablet_seat = seat.tablet_seat();
+                        tablet_seat.add_tool::<State>(dh, &event.tool());
+                        let tablet = tablet_seat.get_tablet(&TabletDescriptor::from(&device));
+                        let tool = tablet_seat.get_tool(&event.tool());
+
+                        match event.state() {
+                            ProximityState::In => {
+                                tool_cursor.location.set(Some(position));
+                                if let Some(output) = self
+                                    .common
+                                    .shell
+                                    .outputs()
+                                    .find(|output| output.geometry().to_f64().contains(position))
+                                    .cloned()
+                                {
+                                    set_active_output(seat, &output);
+                                }
+                                let under = self.surface_at(seat, position);
+                                if let (Some(under), Some(tablet), Some(tool)) =
+                                    (under, tablet, tool)
+                                {
+                                    tool.proximity_in(
+                                        position,
+                                        under,
+                                        &tablet,
+                                        SERIAL_COUNTER.next_serial(),
+                                        event.time(),
+                                    );
+                                }
+                            }
+                            ProximityState::Out => {
+                                tool_cursor.location.set(None);
+                                if let Some(tool) = tool {
+                                    tool.proximity_out(event.time());
+                                }
+                            }
+                        }
+                        break;
+                    }
+                }
+            }
+            InputEvent::TabletToolTip { event, .. } => {
+                use smithay::backend::input::{
+                    TabletToolEvent, TabletToolTipEvent, TabletToolTipState,
+                };
+
+                let device = event.device();
+                for seat in self.common.seats.clone().iter() {
+                    let userdata = seat.user_data();
+                    let devices = userdata.get::<Devices>().unwrap();
+                    if devices.has_device(&device) {
+                        if let Some(tool) = seat.tablet_seat().get_tool(&event.tool()) {
+                            let serial = SERIAL_COUNTER.next_serial();
+                            match event.tip_state() {
+                                TabletToolTipState::Down => {
+                                    // putting the pen down focuses a surface,
+                                    // just like clicking on it would
+                                    let position =
+                                        userdata.get::<TabletToolCursor>().unwrap().location();
+                                    let keyboard_grabbed = seat
+                                        .get_keyboard()
+                                        .map(|k| k.is_grabbed())
+                                        .unwrap_or(false);
+                                    if let Some(position) = position.filter(|_| !keyboard_grabbed) {
+                                        let output = active_output(seat, &self.common);
+                                        let focus = self.keyboard_focus_under(&output, position);
+                                        self.common
+                                            .set_focus(dh, focus.as_ref(), seat, Some(serial));
+                                    }
+                                    tool.tip_down(serial, event.time());
+                                }
+                                TabletToolTipState::Up => {
+                                    tool.tip_up(event.time());
+                                }
+                            }
+                        }
+                        break;
+                    }
+                }
+            }
+            InputEvent::TabletToolButton { event, .. } => {
+                use smithay::backend::input::{TabletToolButtonEvent, TabletToolEvent};
+
+                let device = event.device();
+                for seat in self.common.seats.clone().iter() {
+                    let userdata = seat.user_data();
+                    let devices = userdata.get::<Devices>().unwrap();
+                    if devices.has_device(&device) {
+                        if let Some(tool) = seat.tablet_seat().get_tool(&event.tool()) {
+                            tool.button(
+                                event.button(),
+                                event.button_state(),
+                                SERIAL_COUNTER.next_serial(),
+                                event.time(),
+                            );
+                        }
+                        break;
+                    }
+                }
+            }
+            // tablet pads need zwp_tablet_pad_v2, which the tablet seat of smithay doesn't announce yet
+            _ => {}
+        }
+    }
+
+    /// Processes a key event, after it passed the accessibility filters
+    pub fn handle_key(
+        &mut self,
+        dh: &DisplayHandle,
+        seat: &Seat<State>,
+        keycode: u32,
+        state: KeyState,
+        time: u32,
+        from_input_method: bool,
+    ) {
+        let userdata = seat.user_data();
+        let serial = SERIAL_COUNTER.next_serial();
+        if let Some(action) = seat
+            .get_keyboard()
+            .unwrap()
+            .input(dh, keycode, state, serial, time, |modifiers, handle| {
+                if state == KeyState::Released
+                    && userdata.get::<SupressedKeys>().unwrap().filter(&handle)
+                {
+                    return FilterResult::Intercept(None);
+                }
+
+                #[cfg(feature = "debug")]
+                {
+                    if self.common.seats.iter().position(|x| x == seat).unwrap() == 0
+                        && self.common.egui.active
+                    {
+                        if self.common.egui.debug_state.wants_keyboard() {
+                            self.common.egui.debug_state.handle_keyboard(
+                                &handle,
+                                state == KeyState::Pressed,
+                                modifiers.clone(),
+                            );
+                            userdata.get::<SupressedKeys>().unwrap().add(&handle);
+                            return FilterResult::Intercept(None);
+                        }
+                        if self.common.egui.log_state.wants_keyboard() {
+                            self.common.egui.log_state.handle_keyboard(
+                                &handle,
+                                state == KeyState::Pressed,
+                                modifiers.clone(),
+                            );
+                            userdata.get::<SupressedKeys>().unwrap().add(&handle);
+                            return FilterResult::Intercept(None);
+                        }
+                    }
+                }
+
+                if state == KeyState::Pressed
+                    && (keysyms::KEY_XF86Switch_VT_1..=KEY_XF86Switch_VT_12)
+                        .contains(&handle.modified_sym())
+                {
+                    if let Err(err) = self.backend.kms().switch_vt(
+                        (handle.modified_sym() - keysyms::KEY_XF86Switch_VT_1 + 1) as i32,
+                    ) {
+                        slog_scope::error!("Failed switching virtual terminal: {}", err);
+                    }
+                    userdata.get::<SupressedKeys>().unwrap().add(&handle);
+                    return FilterResult::Intercept(None);
+                }
+
+                // inhibiting clients get all keys, but the escape binding
+                let inhibit_state = &mut self.common.shell.keyboard_shortcuts_inhibit_state;
+                if inhibit_state.is_inhibited() {
+                    let escape = &self.common.config.static_conf.shortcuts_inhibit_escape;
+                    if state == KeyState::Pressed
+                        && escape.modifiers == *modifiers
+                        && handle.raw_syms().contains(&escape.key)
+                    {
+                        inhibit_state.deactivate();
+                        userdata.get::<SupressedKeys>().unwrap().add(&handle);
+                        return FilterResult::Intercept(None);
+                    }
+                } else {
+                    // here we can handle global shortcuts and the like
+                    for (binding, action) in self.common.config.static_conf.key_bindings.iter() {
+                        if state == KeyState::Pressed
+                            && binding.modifiers == *modifiers
+                            && handle.raw_syms().contains(&binding.key)
+                        {
+                            userdata.get::<SupressedKeys>().unwrap().add(&handle);
+                            return FilterResult::Intercept(Some(action));
+                        }
+                    }
+                }
+
+                // remaining keys go to the input method, if it grabbed the keyboard
+                if !from_input_method
+                    && self
+                        .common
+                        .shell
+                        .input_method_state
+                        .keyboard_grab_key(serial, time, keycode, state)
+                {
+                    return FilterResult::Intercept(None);
+                }
+
+                FilterResult::Forward
+            })
+            .flatten()
+            .cloned()
+        {
+            self.handle_action(dh, seat, action);
+        }
+    }
+
+    pub fn handle_action(&mut self, dh: &DisplayHandle, seat: &Seat<State>, action: Action) {
+        match action {
+            Action::Terminate => {
+                self.common.should_stop = true;
+            }
+            #[cfg(feature = "debug")]
+            Action::Debug => {
+                self.common.egui.active = !self.common.egui.active;
+            }
+            #[cfg(not(feature = "debug"))]
+            Action::Debug => {
+                slog_scope::info!("Debug overlay not included in this version")
+            }
+            Action::Close => {
+                let current_output = active_output(seat, &self.common);
+                let workspace = self.common.shell.active_space_mut(&current_output);
+                if let Some(window) = workspace.focus_stack(seat).last() {
+                    #[allow(irrefutable_let_patterns)]
+                    if let Kind::Xdg(xdg) = &window.toplevel() {
+                        xdg.send_close();
+                    }
+                }
+            }
+            Action::Workspace(key_num) => {
+                let current_output = active_output(seat, &self.common);
+                let workspace = match key_num {
+                    0 => 9,
+                    x => x - 1,
+                };
+                if let Some(motion_event) =
+                    self.common
+                        .shell
+                        .activate(seat, &current_output, workspace as usize)
+                {
+                    self.pointer_motion(dh, seat, &motion_event);
+                }
+            }
+            Action::NextWorkspace => {
+                let current_output = active_output(seat, &self.common);
+                let workspace = self.common.shell.active_space(&current_output).idx as usize + 1;
+                if workspace < MAX_WORKSPACES {
+                    if let Some(motion_event) =
+                        self.common.shell.activate(seat, &current_output, workspace)
+                    {
+                        self.pointer_motion(dh, seat, &motion_event);
+                    }
+                }
+            }
+            Action::PreviousWorkspace => {
+                let current_output = active_output(seat, &self.common);
+                let workspace = self.common.shell.active_space(&current_output).idx as usize;
+                if workspace > 0 {
+                    if let Some(motion_event) =
+                        self.common
+                            .shell
+                            .activate(seat, &current_output, workspace - 1)
+                    {
+                        self.pointer_motion(dh, seat, &motion_event);
+                    }
+                }
+            }
+            Action::MoveToWorkspace(key_num) => {
+                let current_output = active_output(seat, &self.common);
+                let workspace = match key_num {
+                    0 => 9,
+                    x => x - 1,
+                };
+                self.common
+                    .shell
+                    .move_current_window(seat, &current_output, workspace as usize);
+            }
+            Action::Focus(focus) => {
+                let current_output = active_output(seat, &self.common);
+                let workspace = self.common.shell.active_space_mut(&current_output);
+                let focus_stack = workspace.focus_stack(seat);
+                if let Some(window) = workspace.tiling_layer.move_focus(
+                    focus,
+                    seat,
+                    &mut workspace.space,
+                    focus_stack.iter(),
+                ) {
+                    std::mem::drop(focus_stack);
+                    self.common
+                        .set_focus(dh, Some(window.toplevel().wl_surface()), seat, None);
+                }
+            }
+            Action::Fullscreen => {
+                let current_output = active_output(seat, &self.common);
+                let workspace = self.common.shell.active_space_mut(&current_output);
+                let focused_window = workspace.focus_stack(seat).last();
+                if let Some(window) = focused_window {
+                    workspace.fullscreen_toggle(&window, &current_output);
+                }
+            }
+            Action::Orientation(orientation) => {
+                let output = active_output(seat, &self.common);
+                let workspace = self.common.shell.active_space_mut(&output);
+                let focus_stack = workspace.focus_stack(seat);
+                workspace.tiling_layer.update_orientation(
+                    orientation,
+                    &seat,
+                    &mut workspace.space,
+                    focus_stack.iter(),
+                );
+            }
+            Action::ShellMode(mode) => self.common.shell.set_shell_mode(mode),
+            Action::ToggleTiling => {
+                let output = active_output(seat, &self.common);
+                let workspace = self.common.shell.active_space_mut(&output);
+                workspace.toggle_tiling(seat);
+            }
+            Action::ToggleWindowFloating => {
+                let output = active_output(seat, &self.common);
+                let workspace = self.common.shell.active_space_mut(&output);
+                workspace.toggle_floating_window(seat);
+            }
+            Action::Spawn(command) => {
+                if let Err(err) = std::process::Command::new("/bin/sh")
+                    .arg("-c")
+                    .arg(command)
+                    .env("WAYLAND_DISPLAY", &self.common.socket)
+                    .env_remove("COSMIC_SESSION_SOCK")
+                    .spawn()
+                {
+                    slog_scope::warn!("Failed to spawn: {}", err);
+                }
+            }
+            Action::Screenshot => {
+                let home = match std::env::var("HOME") {
+                    Ok(home) => home,
+                    Err(err) => {
+                        slog_scope::error!("$HOME is not set, can't save screenshots: {}", err);
+                        return;
+                    }
+                };
+                let timestamp = match std::time::SystemTime::UNIX_EPOCH.elapsed() {
+                    Ok(duration) => duration.as_secs(),
+                    Err(err) => {
+                        slog_scope::error!(
+                            "Unable to get timestamp, can't save screenshots: {}",
+                            err
+                        );
+                        return;
+                    }
+                };
+                for output in self.common.shell.outputs.clone().into_iter() {
+                    match self.backend.offscreen_for_output(&output, &mut self.common) {
+                        Ok(buffer) => {
+                            let mut path = std::path::PathBuf::new();
+                            path.push(&home);
+                            path.push(format!("{}_{}.png", output.name(), timestamp));
+                            if let Err(err) = buffer.save(&path) {
+                                slog_scope::error!(
+                                    "Unable to save screenshot at {}: {}",
+                                    path.display(),
+                                    err
+                                );
+                            }
+                        }
+                        Err(err) => slog_scope::error!(
+                            "Could not save screenshot for output {}: {}",
+                            output.name(),
+                            err
+                        ),
+                    }
+                }
+            }
+            Action::ToggleStickyKeys => {
+                let mut accessibility = self.common.config.accessibility();
+                accessibility.sticky_keys = !accessibility.sticky_keys;
+                self.common.config.set_accessibility(accessibility);
+                self.common.shell.ipc_events.push(ipc::Event::ConfigChanged {
+                    section: ConfigSection::Inputs,
+                });
+                slog_scope::info!("Sticky keys enabled: {}", accessibility.sticky_keys);
+                if !accessibility.sticky_keys {
+                    for seat in self.common.seats.clone().iter() {
+                        self.release_sticky_keys(dh, seat);
+                    }
+                }
+            }
+            Action::ToggleSlowKeys => {
+                let mut accessibility = self.common.config.accessibility();
+                accessibility.slow_keys = !accessibility.slow_keys;
+                self.common.config.set_accessibility(accessibility);
+                self.common.shell.ipc_events.push(ipc::Event::ConfigChanged {
+                    section: ConfigSection::Inputs,
+                });
+                slog_scope::info!("Slow keys enabled: {}", accessibility.slow_keys);
+            }
+            Action::ToggleBounceKeys => {
+                let mut accessibility = self.common.config.accessibility();
+                accessibility.bounce_keys = !accessibility.bounce_keys;
+                self.common.config.set_accessibility(accessibility);
+                self.common.shell.ipc_events.push(ipc::Event::ConfigChanged {
+                    section: ConfigSection::Inputs,
+                });
+                slog_scope::info!("Bounce keys enabled: {}", accessibility.bounce_keys);
+            }
+        }
+    }
+
+    /// Returns the output absolute events of the given device are mapped to.
+    ///
+    /// Falls back to the active output of the seat, if the device is not
+    /// configured to be mapped to any (currently connected) output.
+    pub fn mapped_output<D: Device>(&self, seat: &Seat<State>, device: &D) -> Output {
+        self.common
+            .config
+            .map_to_output(&device.name())
+            .and_then(|name| {
+                self.common
+                    .shell
+                    .outputs()
+                    .find(|output| output.name() == name)
+                    .cloned()
+            })
+            .unwrap_or_else(|| active_output(seat, &self.common))
+    }
+
+    /// Moves the pointer of the seat and updates which surface has its focus.
+    fn pointer_motion(&mut self, dh: &DisplayHandle, seat: &Seat<State>, event: &MotionEvent) {
+        *seat
+            .user_data()
+            .get::<PointerFocus>()
+            .unwrap()
+            .0
+            .borrow_mut() = event.focus.clone();
+        seat.get_pointer().unwrap().motion(self, dh, event);
+        let location = event.location;
+        self.common.pointer_constraints_state.update_focus(
+            event
+                .focus
+                .as_ref()
+                .map(|(surface, loc)| (surface, location - loc.to_f64())),
+        );
+    }
+
+    /// Applies the pointer constraint of the focused surface to a pointer motion
+    /// from `original_position` to `position`.
+    ///
+    /// Returns `None`, if the pointer is locked and must not move at all.
+    fn constrain_pointer(
+        &self,
+        seat: &Seat<State>,
+        original_position: Point<f64, Logical>,
+        position: Point<f64, Logical>,
+    ) -> Option<Point<f64, Logical>> {
+        if seat.get_pointer().unwrap().is_grabbed() {
+            return Some(position);
+        }
+        let focus = seat
+            .user_data()
+            .get::<PointerFocus>()
+            .unwrap()
+            .0
+            .borrow()
+            .clone();
+        let (surface, surface_loc) = match focus {
+            Some(focus) => focus,
+            None => return Some(position),
+        };
+
+        match self
+            .common
+            .pointer_constraints_state
+            .active_constraint(&surface)
+        {
+            None => Some(position),
+            Some(Constraint::Locked) => None,
+            Some(Constraint::Confined(region)) => {
+                let confined = |position: Point<f64, Logical>| {
+                    self.surface_at(seat, position)
+                        .map(|(under, _)| under == surface)
+                        .unwrap_or(false)
+                        && region
+                            .as_ref()
+                            .map(|region| {
+                                region.contains((position - surface_loc.to_f64()).to_i32_round())
+                            })
+                            .unwrap_or(true)
+                };
+                if confined(position) {
+                    return Some(position);
+                }
+                // slide along the edge of the confinement, if possible
+                let horizontal = Point::from((position.x, original_position.y));
+                let vertical = Point::from((original_position.x, position.y));
+                Some(if confined(horizontal) {
+                    horizontal
+                } else if confined(vertical) {
+                    vertical
+                } else {
+                    original_position
+                })
+            }
         }
     }
 
+    /// Returns the surface under the given global position, if any.
+    pub fn surface_at(
+        &self,
+        seat: &Seat<State>,
+        position: Point<f64, Logical>,
+    ) -> Option<(WlSurface, Point<i32, Logical>)> {
+        let output = self
+            .common
+            .shell
+            .outputs()
+            .find(|output| output.geometry().to_f64().contains(position))
+            .cloned()
+            .unwrap_or_else(|| active_output(seat, &self.common));
+        let relative_pos = self
+            .common
+            .shell
+            .space_relative_output_geometry(position, &output);
+        let workspace = self.common.shell.active_space(&output);
+        State::surface_under(position, relative_pos, &output, output.geometry(), workspace)
+    }
+
     pub fn surface_under(
         global_pos: Point<f64, Logical>,
         relative_pos: Point<f64, Logical>,
@@ -846,4 +1703,75 @@ impl State {
             }
         }
     }
+
+    /// Returns the surface, that should receive keyboard focus,
+    /// if the user interacts with the given location.
+    ///
+    /// We test for any matching surface type here but always use the root
+    /// (in case of a window the toplevel) surface for the focus.
+    /// see: https://gitlab.freedesktop.org/wayland/wayland/-/issues/294
+    pub fn keyboard_focus_under(
+        &self,
+        output: &Output,
+        pos: Point<f64, Logical>,
+    ) -> Option<WlSurface> {
+        let output_geo = output.geometry();
+        let relative_pos = self.common.shell.space_relative_output_geometry(pos, output);
+        let workspace = self.common.shell.active_space(output);
+        let layers = layer_map_for_output(output);
+        let mut under = None;
+
+        if let Some(window) = workspace.get_fullscreen(output) {
+            if let Some(layer) = layers.layer_under(WlrLayer::Overlay, relative_pos) {
+                if layer.can_receive_keyboard_focus() {
+                    let layer_loc = layers.layer_geometry(layer).unwrap().loc;
+                    under = layer
+                        .surface_under(
+                            pos - output_geo.loc.to_f64() - layer_loc.to_f64(),
+                            WindowSurfaceType::ALL,
+                        )
+                        .map(|(_, _)| layer.wl_surface().clone());
+                }
+            } else {
+                under = window
+                    .surface_under(pos - output_geo.loc.to_f64(), WindowSurfaceType::ALL)
+                    .map(|(_, _)| window.toplevel().wl_surface().clone());
+            }
+        } else {
+            if let Some(layer) = layers
+                .layer_under(WlrLayer::Overlay, relative_pos)
+                .or_else(|| layers.layer_under(WlrLayer::Top, relative_pos))
+            {
+                if layer.can_receive_keyboard_focus() {
+                    let layer_loc = layers.layer_geometry(layer).unwrap().loc;
+                    under = layer
+                        .surface_under(
+                            pos - output_geo.loc.to_f64() - layer_loc.to_f64(),
+                            WindowSurfaceType::ALL,
+                        )
+                        .map(|(_, _)| layer.wl_surface().clone());
+                }
+            } else if let Some((window, _, _)) = workspace
+                .space
+                .surface_under(relative_pos, WindowSurfaceType::ALL)
+            {
+                under = Some(window.toplevel().wl_surface().clone());
+            } else if let Some(layer) = layers
+                .layer_under(WlrLayer::Bottom, pos)
+                .or_else(|| layers.layer_under(WlrLayer::Background, pos))
+            {
+                if layer.can_receive_keyboard_focus() {
+                    let layer_loc = layers.layer_geometry(layer).unwrap().loc;
+                    under = layer
+                        .surface_under(
+                            pos - output_geo.loc.to_f64() - layer_loc.to_f64(),
+                            WindowSurfaceType::ALL,
+                        )
+                        .map(|(_, _)| layer.wl_surface().clone());
+                }
+            };
+        }
+
+        under
+    }
 }
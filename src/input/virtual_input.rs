@@ -0,0 +1,302 @@
+// SPDX-License-Identifier: GPL-3.0-only
+
+use crate::input::PointerMotionTime;
+use smithay::{
+    backend::input::{
+        AbsolutePositionEvent, Axis, AxisSource, ButtonState, Device, DeviceCapability, Event,
+        InputBackend, KeyState, KeyboardKeyEvent, PointerAxisEvent, PointerButtonEvent,
+        PointerMotionAbsoluteEvent, PointerMotionEvent, UnusedEvent,
+    },
+    wayland::output::Output,
+};
+use std::path::PathBuf;
+
+crate::utils::id_gen!(next_device_id, DEVICE_ID, DEVICE_IDS);
+
+/// Input backend of devices created by clients, e.g. on-screen keyboards or test tooling.
+///
+/// Their events are fed into `State::process_input_event` like those of any physical device.
+#[derive(Debug)]
+pub struct VirtualInput;
+
+impl InputBackend for VirtualInput {
+    type Device = VirtualDevice;
+    type KeyboardKeyEvent = VirtualKeyboardKeyEvent;
+    type PointerAxisEvent = VirtualPointerAxisEvent;
+    type PointerButtonEvent = VirtualPointerButtonEvent;
+    type PointerMotionEvent = VirtualPointerMotionEvent;
+    type PointerMotionAbsoluteEvent = VirtualPointerMotionAbsoluteEvent;
+    type GestureSwipeBeginEvent = UnusedEvent;
+    type GestureSwipeUpdateEvent = UnusedEvent;
+    type GestureSwipeEndEvent = UnusedEvent;
+    type GesturePinchBeginEvent = UnusedEvent;
+    type GesturePinchUpdateEvent = UnusedEvent;
+    type GesturePinchEndEvent = UnusedEvent;
+    type GestureHoldBeginEvent = UnusedEvent;
+    type GestureHoldEndEvent = UnusedEvent;
+    type TouchDownEvent = UnusedEvent;
+    type TouchUpEvent = UnusedEvent;
+    type TouchMotionEvent = UnusedEvent;
+    type TouchCancelEvent = UnusedEvent;
+    type TouchFrameEvent = UnusedEvent;
+    type TabletToolAxisEvent = UnusedEvent;
+    type TabletToolProximityEvent = UnusedEvent;
+    type TabletToolTipEvent = UnusedEvent;
+    type TabletToolButtonEvent = UnusedEvent;
+    type SpecialEvent = UnusedEvent;
+}
+
+/// A keyboard or pointer created by a client
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct VirtualDevice {
+    id: usize,
+    capability: DeviceCapability,
+}
+
+impl VirtualDevice {
+    pub fn new(capability: DeviceCapability) -> VirtualDevice {
+        VirtualDevice {
+            id: next_device_id(),
+            capability,
+        }
+    }
+
+    /// Releases the id of the device, after it was removed from the seat
+    pub fn release(&self) {
+        DEVICE_IDS.lock().unwrap().remove(&self.id);
+    }
+}
+
+impl Device for VirtualDevice {
+    fn id(&self) -> String {
+        format!("virtual-{}", self.id)
+    }
+
+    fn name(&self) -> String {
+        match self.capability {
+            DeviceCapability::Keyboard => String::from("Virtual Keyboard"),
+            DeviceCapability::Pointer => String::from("Virtual Pointer"),
+            _ => String::from("Virtual Device"),
+        }
+    }
+
+    fn has_capability(&self, capability: DeviceCapability) -> bool {
+        self.capability == capability
+    }
+
+    fn usb_id(&self) -> Option<(u32, u32)> {
+        None
+    }
+
+    fn syspath(&self) -> Option<PathBuf> {
+        None
+    }
+}
+
+#[derive(Debug)]
+pub struct VirtualKeyboardKeyEvent {
+    pub device: VirtualDevice,
+    pub time: u32,
+    pub key_code: u32,
+    pub state: KeyState,
+}
+
+impl Event<VirtualInput> for VirtualKeyboardKeyEvent {
+    fn time(&self) -> u32 {
+        self.time
+    }
+
+    fn device(&self) -> VirtualDevice {
+        self.device.clone()
+    }
+}
+
+impl KeyboardKeyEvent<VirtualInput> for VirtualKeyboardKeyEvent {
+    fn key_code(&self) -> u32 {
+        self.key_code
+    }
+
+    fn state(&self) -> KeyState {
+        self.state
+    }
+
+    fn count(&self) -> u32 {
+        match self.state {
+            KeyState::Pressed => 1,
+            KeyState::Released => 0,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct VirtualPointerMotionEvent {
+    pub device: VirtualDevice,
+    pub time: u32,
+    pub dx: f64,
+    pub dy: f64,
+}
+
+impl Event<VirtualInput> for VirtualPointerMotionEvent {
+    fn time(&self) -> u32 {
+        self.time
+    }
+
+    fn device(&self) -> VirtualDevice {
+        self.device.clone()
+    }
+}
+
+impl PointerMotionTime for VirtualPointerMotionEvent {
+    fn time_usec(&self) -> u64 {
+        self.time as u64 * 1000
+    }
+}
+
+impl PointerMotionEvent<VirtualInput> for VirtualPointerMotionEvent {
+    fn delta_x(&self) -> f64 {
+        self.dx
+    }
+
+    fn delta_y(&self) -> f64 {
+        self.dy
+    }
+
+    // virtual pointers have no acceleration
+    fn delta_x_unaccel(&self) -> f64 {
+        self.dx
+    }
+
+    fn delta_y_unaccel(&self) -> f64 {
+        self.dy
+    }
+}
+
+#[derive(Debug)]
+pub struct VirtualPointerMotionAbsoluteEvent {
+    pub device: VirtualDevice,
+    pub time: u32,
+    pub x: u32,
+    pub y: u32,
+    pub x_extent: u32,
+    pub y_extent: u32,
+    /// Output the position is relative to, if the pointer was bound to one
+    pub output: Option<Output>,
+}
+
+impl Event<VirtualInput> for VirtualPointerMotionAbsoluteEvent {
+    fn time(&self) -> u32 {
+        self.time
+    }
+
+    fn device(&self) -> VirtualDevice {
+        self.device.clone()
+    }
+}
+
+impl AbsolutePositionEvent<VirtualInput> for VirtualPointerMotionAbsoluteEvent {
+    fn x(&self) -> f64 {
+        self.x as f64
+    }
+
+    fn y(&self) -> f64 {
+        self.y as f64
+    }
+
+    fn x_transformed(&self, width: i32) -> f64 {
+        self.x as f64 * width as f64 / self.x_extent.max(1) as f64
+    }
+
+    fn y_transformed(&self, height: i32) -> f64 {
+        self.y as f64 * height as f64 / self.y_extent.max(1) as f64
+    }
+}
+
+impl PointerMotionAbsoluteEvent<VirtualInput> for VirtualPointerMotionAbsoluteEvent {}
+
+#[derive(Debug)]
+pub struct VirtualPointerButtonEvent {
+    pub device: VirtualDevice,
+    pub time: u32,
+    pub button: u32,
+    pub state: ButtonState,
+}
+
+impl Event<VirtualInput> for VirtualPointerButtonEvent {
+    fn time(&self) -> u32 {
+        self.time
+    }
+
+    fn device(&self) -> VirtualDevice {
+        self.device.clone()
+    }
+}
+
+impl PointerButtonEvent<VirtualInput> for VirtualPointerButtonEvent {
+    fn button_code(&self) -> u32 {
+        self.button
+    }
+
+    fn state(&self) -> ButtonState {
+        self.state
+    }
+}
+
+/// Axis values accumulated until the client sends a frame
+#[derive(Debug)]
+pub struct VirtualPointerAxisEvent {
+    pub device: VirtualDevice,
+    pub time: u32,
+    pub source: AxisSource,
+    pub horizontal: Option<f64>,
+    pub vertical: Option<f64>,
+    pub horizontal_discrete: Option<f64>,
+    pub vertical_discrete: Option<f64>,
+}
+
+impl VirtualPointerAxisEvent {
+    pub fn new(device: VirtualDevice) -> VirtualPointerAxisEvent {
+        VirtualPointerAxisEvent {
+            device,
+            time: 0,
+            source: AxisSource::Wheel,
+            horizontal: None,
+            vertical: None,
+            horizontal_discrete: None,
+            vertical_discrete: None,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.horizontal.is_none() && self.vertical.is_none()
+    }
+}
+
+impl Event<VirtualInput> for VirtualPointerAxisEvent {
+    fn time(&self) -> u32 {
+        self.time
+    }
+
+    fn device(&self) -> VirtualDevice {
+        self.device.clone()
+    }
+}
+
+impl PointerAxisEvent<VirtualInput> for VirtualPointerAxisEvent {
+    fn amount(&self, axis: Axis) -> Option<f64> {
+        match axis {
+            Axis::Horizontal => self.horizontal,
+            Axis::Vertical => self.vertical,
+        }
+    }
+
+    fn amount_discrete(&self, axis: Axis) -> Option<f64> {
+        match axis {
+            Axis::Horizontal => self.horizontal_discrete,
+            Axis::Vertical => self.vertical_discrete,
+        }
+    }
+
+    fn source(&self) -> AxisSource {
+        self.source
+    }
+}
@@ -0,0 +1,714 @@
+// SPDX-License-Identifier: GPL-3.0-only
+
+use crate::{
+    config::{Action, Config},
+    shell::{layout::tiling::Data as TilingData, Workspace},
+    state::{Common, Data},
+    utils::{peer::Peer, prelude::*},
+};
+use anyhow::{Context, Result};
+use id_tree::{NodeId, Tree};
+use serde::Serialize;
+use smithay::{
+    desktop::Window,
+    reexports::{
+        calloop::{generic::Generic, Interest, LoopHandle, Mode, PostAction},
+        nix::unistd::getuid,
+        wayland_server::DisplayHandle,
+    },
+    utils::IsAlive,
+    wayland::{
+        compositor::with_states,
+        output::{Mode as OutputMode, Output},
+        shell::xdg::XdgToplevelSurfaceRoleAttributes,
+    },
+};
+use std::{
+    collections::HashMap,
+    io::{ErrorKind, Read, Write},
+    net::Shutdown,
+    os::unix::net::{UnixListener, UnixStream},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+};
+
+mod protocol;
+pub use self::protocol::*;
+
+/// Clients not reading their responses or events are disconnected,
+/// once this much output is pending
+const MAX_PENDING_SIZE: usize = 1024 * 1024;
+/// Upper bound for a single request, to not buffer garbage forever
+const MAX_REQUEST_SIZE: usize = 64 * 1024;
+
+static NEXT_WINDOW_ID: AtomicUsize = AtomicUsize::new(1);
+
+impl Request {
+    /// Whether the request changes the state of the compositor, instead of just querying it
+    fn mutates(&self) -> bool {
+        matches!(
+            self,
+            Request::RunAction { .. }
+                | Request::CreateVirtualOutput { .. }
+                | Request::DestroyVirtualOutput { .. }
+        )
+    }
+}
+
+impl From<crate::shell::ShellMode> for ShellMode {
+    fn from(mode: crate::shell::ShellMode) -> ShellMode {
+        match mode {
+            crate::shell::ShellMode::Normal => ShellMode::Normal,
+            crate::shell::ShellMode::Resize => ShellMode::Resize,
+            crate::shell::ShellMode::Adjust => ShellMode::Adjust,
+        }
+    }
+}
+
+impl From<crate::shell::layout::Orientation> for Orientation {
+    fn from(orientation: crate::shell::layout::Orientation) -> Orientation {
+        match orientation {
+            crate::shell::layout::Orientation::Horizontal => Orientation::Horizontal,
+            crate::shell::layout::Orientation::Vertical => Orientation::Vertical,
+        }
+    }
+}
+
+/// State of the ipc socket, removes the socket file once dropped.
+#[derive(Debug, Default)]
+pub struct IpcState {
+    path: Option<PathBuf>,
+    clients: HashMap<usize, IpcClient>,
+    next_client_id: usize,
+}
+
+#[derive(Debug)]
+struct IpcClient {
+    stream: UnixStream,
+    /// Encoded messages, that the socket did not accept yet
+    pending: Vec<u8>,
+    /// Whether a source waiting for the socket to become writable is registered
+    flushing: bool,
+    /// Event types the client subscribed to, if it did
+    subscription: Option<Vec<EventType>>,
+}
+
+impl IpcClient {
+    /// Writes as much of the pending output, as the socket accepts without blocking.
+    fn write_pending(&mut self) -> std::io::Result<()> {
+        while !self.pending.is_empty() {
+            match self.stream.write(&self.pending) {
+                Ok(0) => return Err(ErrorKind::WriteZero.into()),
+                Ok(written) => {
+                    self.pending.drain(..written);
+                }
+                Err(err) if err.kind() == ErrorKind::WouldBlock => break,
+                Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(())
+    }
+}
+
+impl IpcState {
+    pub fn socket_path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    fn add_client(&mut self, stream: &UnixStream) -> Result<usize> {
+        let stream = stream
+            .try_clone()
+            .with_context(|| "Failed to clone ipc client stream")?;
+        let id = self.next_client_id;
+        self.next_client_id += 1;
+        self.clients.insert(
+            id,
+            IpcClient {
+                stream,
+                pending: Vec::new(),
+                flushing: false,
+                subscription: None,
+            },
+        );
+        Ok(id)
+    }
+
+    fn remove_client(&mut self, id: usize) {
+        if let Some(client) = self.clients.remove(&id) {
+            // also ends the source reading from the client
+            let _ = client.stream.shutdown(Shutdown::Both);
+        }
+    }
+
+    fn subscribe(&mut self, id: usize, events: Vec<EventType>) -> Response {
+        match self.clients.get_mut(&id) {
+            Some(client) => {
+                client.subscription = Some(events);
+                Response::Subscribed
+            }
+            None => Response::Error {
+                message: String::from("Client is not connected"),
+            },
+        }
+    }
+
+    /// Queues a message for the given client and writes as much of it as possible right away.
+    fn send<T: Serialize>(&mut self, handle: &LoopHandle<'static, Data>, id: usize, message: &T) {
+        let client = match self.clients.get_mut(&id) {
+            Some(client) => client,
+            None => return,
+        };
+        match serde_json::to_vec(message) {
+            Ok(bytes) => {
+                client.pending.extend_from_slice(&bytes);
+                client.pending.push(b'\n');
+            }
+            Err(err) => {
+                slog_scope::warn!("Failed to encode ipc message into json: {}", err);
+                return;
+            }
+        }
+        self.flush(handle, id);
+    }
+
+    /// Writes the pending output of the given client and waits for its socket
+    /// to become writable, if it doesn't accept everything at once.
+    fn flush(&mut self, handle: &LoopHandle<'static, Data>, id: usize) {
+        if self.write_pending(id) != Some(false) {
+            return;
+        }
+        let client = self.clients.get_mut(&id).unwrap();
+        if client.flushing {
+            return;
+        }
+        let result = client
+            .stream
+            .try_clone()
+            .with_context(|| "Failed to clone ipc client stream")
+            .and_then(|stream| {
+                handle
+                    .insert_source(
+                        Generic::new(stream, Interest::WRITE, Mode::Level),
+                        move |_, _, data: &mut Data| {
+                            let ipc_state = &mut data.state.common.ipc_state;
+                            Ok(match ipc_state.write_pending(id) {
+                                Some(false) => PostAction::Continue,
+                                Some(true) => {
+                                    ipc_state.clients.get_mut(&id).unwrap().flushing = false;
+                                    PostAction::Remove
+                                }
+                                None => PostAction::Remove,
+                            })
+                        },
+                    )
+                    .with_context(|| "Failed to init the ipc client write source")
+            });
+        match result {
+            Ok(_) => client.flushing = true,
+            Err(err) => {
+                slog_scope::warn!("Dropping ipc client: {}", err);
+                self.remove_client(id);
+            }
+        }
+    }
+
+    /// Writes pending output of the given client without blocking, dropping the client
+    /// on errors or if it stopped reading.
+    ///
+    /// Returns whether all output was written, or `None`, if the client is gone.
+    fn write_pending(&mut self, id: usize) -> Option<bool> {
+        let client = self.clients.get_mut(&id)?;
+        let result = client.write_pending();
+        match result {
+            Ok(()) if client.pending.len() <= MAX_PENDING_SIZE => Some(client.pending.is_empty()),
+            Ok(()) => {
+                slog_scope::debug!("Dropping ipc client, that doesn't read its messages");
+                self.remove_client(id);
+                None
+            }
+            Err(err) => {
+                slog_scope::debug!("Dropping ipc client: {}", err);
+                self.remove_client(id);
+                None
+            }
+        }
+    }
+
+    /// Queues the given events for all interested subscribers.
+    pub fn send_events(
+        &mut self,
+        handle: &LoopHandle<'static, Data>,
+        events: impl Iterator<Item = Event>,
+    ) {
+        for event in events {
+            let event_type = event.event_type();
+            let subscribers = self
+                .clients
+                .iter()
+                .filter(|(_, client)| {
+                    client
+                        .subscription
+                        .as_ref()
+                        .map(|events| events.is_empty() || events.contains(&event_type))
+                        .unwrap_or(false)
+                })
+                .map(|(id, _)| *id)
+                .collect::<Vec<_>>();
+            for id in subscribers {
+                self.send(handle, id, &event);
+            }
+        }
+    }
+}
+
+impl Drop for IpcState {
+    fn drop(&mut self) {
+        if let Some(path) = self.path.take() {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+/// Events collected by the shell, until they are sent out by `IpcState::send_events`.
+#[derive(Debug, Default)]
+pub struct EventQueue {
+    events: Vec<Event>,
+    /// Mapped windows with their last known title and app id
+    windows: Vec<(Window, String, String)>,
+    focused: Option<usize>,
+}
+
+impl EventQueue {
+    pub fn push(&mut self, event: Event) {
+        self.events.push(event);
+    }
+
+    pub fn drain(&mut self) -> impl Iterator<Item = Event> + '_ {
+        self.events.drain(..)
+    }
+
+    pub fn window_opened(&mut self, window: &Window, workspace: u8) {
+        if self.windows.iter().any(|(w, _, _)| w == window) {
+            return;
+        }
+        let (title, app_id) = title_and_app_id(window);
+        self.events.push(Event::WindowOpened {
+            id: window_id(window),
+            title: title.clone(),
+            app_id: app_id.clone(),
+            workspace,
+        });
+        self.windows.push((window.clone(), title, app_id));
+    }
+
+    pub fn window_focused(&mut self, window: Option<&Window>) {
+        let id = window.map(window_id);
+        if id != self.focused {
+            self.focused = id;
+            self.events.push(Event::WindowFocused { id });
+        }
+    }
+
+    /// Checks the title and app id of the given window after a commit of its surface.
+    pub fn window_updated(&mut self, window: &Window) {
+        let (_, title, app_id) = match self.windows.iter_mut().find(|(w, _, _)| w == window) {
+            Some(known) => known,
+            None => return,
+        };
+        let (new_title, new_app_id) = title_and_app_id(window);
+        if *title != new_title || *app_id != new_app_id {
+            *title = new_title;
+            *app_id = new_app_id;
+            self.events.push(Event::WindowTitleChanged {
+                id: window_id(window),
+                title: title.clone(),
+                app_id: app_id.clone(),
+            });
+        }
+    }
+
+    /// Checks the known windows for closed ones.
+    pub fn refresh(&mut self) {
+        let events = &mut self.events;
+        self.windows.retain(|(window, _, _)| {
+            if !window.toplevel().alive() {
+                events.push(Event::WindowClosed {
+                    id: window_id(window),
+                });
+                return false;
+            }
+            true
+        });
+    }
+}
+
+struct WindowId(usize);
+
+fn window_id(window: &Window) -> usize {
+    window
+        .user_data()
+        .insert_if_missing(|| WindowId(NEXT_WINDOW_ID.fetch_add(1, Ordering::SeqCst)));
+    window.user_data().get::<WindowId>().unwrap().0
+}
+
+fn title_and_app_id(window: &Window) -> (String, String) {
+    with_states(window.toplevel().wl_surface(), |states| {
+        let attributes = states
+            .data_map
+            .get::<Mutex<XdgToplevelSurfaceRoleAttributes>>()
+            .unwrap()
+            .lock()
+            .unwrap();
+        (
+            attributes.title.clone().unwrap_or_default(),
+            attributes.app_id.clone().unwrap_or_default(),
+        )
+    })
+}
+
+pub fn setup_socket(handle: LoopHandle<'static, Data>, state: &mut State) -> Result<()> {
+    let dir = std::env::var_os("XDG_RUNTIME_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(std::env::temp_dir);
+    let path = dir.join(format!(
+        "cosmic-comp-{}.sock",
+        state.common.socket.to_string_lossy()
+    ));
+    // the wayland socket name is ours, so any existing file is a leftover of a crashed instance
+    if path.exists() {
+        std::fs::remove_file(&path)
+            .with_context(|| format!("Failed to remove stale ipc socket {}", path.display()))?;
+    }
+
+    let listener = UnixListener::bind(&path)
+        .with_context(|| format!("Failed to bind ipc socket {}", path.display()))?;
+    listener
+        .set_nonblocking(true)
+        .with_context(|| "Failed to set ipc socket non-blocking")?;
+    state.common.ipc_state.path = Some(path.clone());
+
+    handle
+        .insert_source(
+            Generic::new(listener, Interest::READ, Mode::Level),
+            |_, listener, data: &mut Data| {
+                loop {
+                    match listener.accept() {
+                        Ok((stream, _)) => {
+                            let trusted = peer_is_trusted(&stream, &data.state.common.config);
+                            if let Err(err) = insert_client(&mut data.state, stream, trusted) {
+                                slog_scope::warn!("Failed to add ipc client: {}", err);
+                            }
+                        }
+                        Err(err) if err.kind() == ErrorKind::WouldBlock => break,
+                        Err(err) => {
+                            slog_scope::warn!("Error accepting ipc client: {}", err);
+                            break;
+                        }
+                    }
+                }
+                Ok(PostAction::Continue)
+            },
+        )
+        .with_context(|| "Failed to init the ipc socket source")?;
+
+    // make the socket known to everything we spawn
+    std::env::set_var(SOCKET_ENV, &path);
+    slog_scope::info!("IPC listening on {}", path.display());
+
+    Ok(())
+}
+
+/// Whether the process on the other side of the socket may run requests changing the state
+/// of the compositor, which requires it to run as the same user and to be a privileged client,
+/// unless `ipc_trust_same_user` is set.
+fn peer_is_trusted(stream: &UnixStream, config: &Config) -> bool {
+    let peer = match Peer::from_stream(stream) {
+        Some(peer) => peer,
+        None => return false,
+    };
+    peer.uid == getuid().as_raw()
+        && (config.static_conf.ipc_trust_same_user
+            || peer
+                .executable
+                .map(|path| config.is_privileged_client(&path))
+                .unwrap_or(false))
+}
+
+fn insert_client(state: &mut State, stream: UnixStream, trusted: bool) -> Result<()> {
+    stream
+        .set_nonblocking(true)
+        .with_context(|| "Failed to set ipc client non-blocking")?;
+    let id = state.common.ipc_state.add_client(&stream)?;
+
+    let mut buffer = Vec::new();
+    let result = state.common.event_loop_handle.insert_source(
+        Generic::new(stream, Interest::READ, Mode::Level),
+        move |_, stream, data: &mut Data| {
+            let mut chunk = [0u8; 4096];
+            let read = match stream.read(&mut chunk) {
+                // client hung up
+                Ok(0) => {
+                    data.state.common.ipc_state.remove_client(id);
+                    return Ok(PostAction::Remove);
+                }
+                Ok(size) => size,
+                Err(err)
+                    if err.kind() == ErrorKind::Interrupted
+                        || err.kind() == ErrorKind::WouldBlock =>
+                {
+                    return Ok(PostAction::Continue)
+                }
+                Err(err) => {
+                    slog_scope::warn!("Error reading from ipc client: {}", err);
+                    data.state.common.ipc_state.remove_client(id);
+                    return Ok(PostAction::Remove);
+                }
+            };
+            buffer.extend_from_slice(&chunk[..read]);
+
+            let handle = data.state.common.event_loop_handle.clone();
+            while let Some(pos) = buffer.iter().position(|b| *b == b'\n') {
+                let line = buffer.drain(..=pos).collect::<Vec<_>>();
+                if line.iter().all(u8::is_ascii_whitespace) {
+                    continue;
+                }
+                let response = match serde_json::from_slice::<Request>(&line) {
+                    Ok(Request::Subscribe { events }) => {
+                        data.state.common.ipc_state.subscribe(id, events)
+                    }
+                    Ok(request) if request.mutates() && !trusted => Response::Error {
+                        message: String::from("Permission denied"),
+                    },
+                    Ok(request) => {
+                        let dh = data.display.handle();
+                        data.state.handle_ipc_request(&dh, request)
+                    }
+                    Err(err) => Response::Error {
+                        message: format!("Invalid request: {}", err),
+                    },
+                };
+                data.state.common.ipc_state.send(&handle, id, &response);
+            }
+            // the client might have been dropped for not reading its responses
+            if !data.state.common.ipc_state.clients.contains_key(&id) {
+                return Ok(PostAction::Remove);
+            }
+
+            if buffer.len() > MAX_REQUEST_SIZE {
+                slog_scope::warn!("Ipc request exceeds maximum size, disconnecting client");
+                data.state.common.ipc_state.remove_client(id);
+                return Ok(PostAction::Remove);
+            }
+            Ok(PostAction::Continue)
+        },
+    );
+    if let Err(err) = result {
+        state.common.ipc_state.remove_client(id);
+        anyhow::bail!("Failed to init the ipc client source: {}", err);
+    }
+
+    Ok(())
+}
+
+impl State {
+    fn handle_ipc_request(&mut self, dh: &DisplayHandle, request: Request) -> Response {
+        match request {
+            Request::GetOutputs => Response::Outputs {
+                outputs: self
+                    .common
+                    .shell
+                    .outputs()
+                    .map(|output| describe_output(&self.common, output))
+                    .collect(),
+            },
+            Request::GetWorkspaces => Response::Workspaces {
+                workspaces: self
+                    .common
+                    .shell
+                    .spaces
+                    .iter()
+                    .map(|workspace| describe_workspace(&self.common, workspace))
+                    .collect(),
+            },
+            Request::GetTree => Response::Tree {
+                workspaces: self.common.shell.spaces.iter().map(describe_tree).collect(),
+            },
+            Request::GetWindows => {
+                let focused = focused_window(&self.common);
+                Response::Windows {
+                    windows: self
+                        .common
+                        .shell
+                        .spaces
+                        .iter()
+                        .flat_map(|workspace| {
+                            let focused = focused.as_ref();
+                            workspace
+                                .space
+                                .windows()
+                                .map(move |window| describe_window(workspace, window, focused))
+                        })
+                        .collect(),
+                }
+            }
+            Request::RunAction { action } => match ron::de::from_str::<Action>(&action) {
+                Ok(action) => {
+                    let seat = self.common.last_active_seat.clone();
+                    self.handle_action(dh, &seat, action);
+                    Response::Success
+                }
+                Err(err) => Response::Error {
+                    message: format!("Invalid action {:?}: {}", action, err),
+                },
+            },
+            Request::CreateVirtualOutput {
+                size,
+                scale,
+                refresh,
+            } => {
+                if size.0 <= 0 || size.1 <= 0 || scale <= 0.0 || refresh <= 0 {
+                    return Response::Error {
+                        message: String::from("Invalid size, scale or refresh rate"),
+                    };
+                }
+                let mode = OutputMode {
+                    size: size.into(),
+                    refresh,
+                };
+                match self.create_virtual_output(mode, scale) {
+                    Ok(output) => Response::VirtualOutput {
+                        name: output.name(),
+                    },
+                    Err(err) => Response::Error {
+                        message: format!("Failed to create virtual output: {}", err),
+                    },
+                }
+            }
+            Request::DestroyVirtualOutput { name } => match self.destroy_virtual_output(&name) {
+                Ok(()) => Response::Success,
+                Err(err) => Response::Error {
+                    message: format!("Failed to destroy virtual output: {}", err),
+                },
+            },
+            Request::Subscribe { .. } => unreachable!("subscriptions are handled per connection"),
+        }
+    }
+}
+
+fn describe_output(common: &Common, output: &Output) -> OutputDescription {
+    let physical = output.physical_properties();
+    let geometry = output.geometry();
+    OutputDescription {
+        name: output.name(),
+        make: physical.make,
+        model: physical.model,
+        position: (geometry.loc.x, geometry.loc.y),
+        size: (geometry.size.w, geometry.size.h),
+        mode: output
+            .current_mode()
+            .map(|mode| ((mode.size.w, mode.size.h), mode.refresh)),
+        scale: output.current_scale().fractional_scale(),
+        transform: output.current_transform().into(),
+        active_workspace: common.shell.active_space(output).idx + 1,
+    }
+}
+
+fn focused_window(common: &Common) -> Option<Window> {
+    let seat = &common.last_active_seat;
+    let output = active_output(seat, common);
+    let window = common.shell.active_space(&output).focus_stack(seat).last();
+    window
+}
+
+fn describe_workspace(common: &Common, workspace: &Workspace) -> WorkspaceDescription {
+    let active_output = active_output(&common.last_active_seat, common);
+    WorkspaceDescription {
+        number: workspace.idx + 1,
+        outputs: workspace.space.outputs().map(|o| o.name()).collect(),
+        focused: workspace.space.outputs().any(|o| *o == active_output),
+        windows: workspace.space.windows().count(),
+    }
+}
+
+fn describe_window(
+    workspace: &Workspace,
+    window: &Window,
+    focused: Option<&Window>,
+) -> WindowDescription {
+    let (title, app_id) = title_and_app_id(window);
+    let size = window.geometry().size;
+    WindowDescription {
+        id: window_id(window),
+        title,
+        app_id,
+        workspace: workspace.idx + 1,
+        output: workspace
+            .space
+            .outputs_for_window(window)
+            .first()
+            .map(|o| o.name()),
+        position: workspace
+            .space
+            .window_location(window)
+            .map(|loc| (loc.x, loc.y)),
+        size: (size.w, size.h),
+        floating: workspace.floating_layer.windows.contains(window),
+        fullscreen: workspace.fullscreen.values().any(|w| w == window),
+        focused: focused == Some(window),
+    }
+}
+
+fn describe_tree(workspace: &Workspace) -> WorkspaceTree {
+    WorkspaceTree {
+        number: workspace.idx + 1,
+        outputs: workspace
+            .space
+            .outputs()
+            .map(|output| OutputTree {
+                output: output.name(),
+                tiling: workspace
+                    .tiling_layer
+                    .tree(&workspace.space, output)
+                    .and_then(|tree| Some(describe_node(tree, tree.root_node_id()?))),
+            })
+            .collect(),
+        floating: workspace
+            .floating_layer
+            .windows
+            .iter()
+            .map(window_id)
+            .collect(),
+    }
+}
+
+fn describe_node(tree: &Tree<TilingData>, node_id: &NodeId) -> TreeNode {
+    let children = || {
+        tree.children_ids(node_id)
+            .unwrap()
+            .map(|child| describe_node(tree, child))
+            .collect()
+    };
+    match tree.get(node_id).unwrap().data() {
+        TilingData::Fork { orientation, ratio } => TreeNode::Fork {
+            orientation: (*orientation).into(),
+            ratio: ratio.load(Ordering::SeqCst),
+            children: children(),
+        },
+        TilingData::Stack { active, .. } => TreeNode::Stack {
+            active: *active,
+            children: children(),
+        },
+        TilingData::Window(window) => {
+            let (title, app_id) = title_and_app_id(window);
+            TreeNode::Window {
+                id: window_id(window),
+                title,
+                app_id,
+            }
+        }
+    }
+}
@@ -0,0 +1,286 @@
+// SPDX-License-Identifier: GPL-3.0-only
+
+//! Messages of the ipc socket.
+//!
+//! This module only depends on external crates, as it is shared with `cosmic-comp-msg`.
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use smithay::utils::Transform;
+
+/// Environment variable holding the path of the ipc socket
+pub const SOCKET_ENV: &str = "COSMIC_COMP_SOCK";
+
+/// Requests accepted on the ipc socket, sent as one json object per line.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case", tag = "request")]
+pub enum Request {
+    GetOutputs,
+    GetWorkspaces,
+    GetTree,
+    GetWindows,
+    /// Runs an action written in the same syntax as the key bindings of `config.ron`,
+    /// e.g. `Workspace(2)` or `Spawn("alacritty")`.
+    RunAction {
+        action: String,
+    },
+    /// Creates an output without a physical connector, e.g. for remote desktop sessions.
+    /// Its contents are only accessible through screen capturing.
+    CreateVirtualOutput {
+        /// Size of the mode in pixels
+        size: (i32, i32),
+        #[serde(default = "default_scale")]
+        scale: f64,
+        /// Refresh rate in mHz
+        #[serde(default = "default_refresh")]
+        refresh: i32,
+    },
+    DestroyVirtualOutput {
+        name: String,
+    },
+    /// Turns the connection into an event stream, sending every `Event` of the given
+    /// types (or of all types, if empty) as one json object per line.
+    Subscribe {
+        #[serde(default)]
+        events: Vec<EventType>,
+    },
+}
+
+fn default_scale() -> f64 {
+    1.0
+}
+
+fn default_refresh() -> i32 {
+    60_000
+}
+
+/// Responses to `Request`s, sent as one json object per line.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case", tag = "response")]
+pub enum Response {
+    Outputs {
+        outputs: Vec<OutputDescription>,
+    },
+    Workspaces {
+        workspaces: Vec<WorkspaceDescription>,
+    },
+    Tree {
+        workspaces: Vec<WorkspaceTree>,
+    },
+    Windows {
+        windows: Vec<WindowDescription>,
+    },
+    VirtualOutput {
+        name: String,
+    },
+    Success,
+    Subscribed,
+    Error {
+        message: String,
+    },
+}
+
+/// Events sent to subscribed clients
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case", tag = "event")]
+pub enum Event {
+    WindowOpened {
+        id: usize,
+        title: String,
+        app_id: String,
+        workspace: u8,
+    },
+    WindowClosed {
+        id: usize,
+    },
+    /// The keyboard focus moved to another window, or away from any window
+    WindowFocused {
+        id: Option<usize>,
+    },
+    WindowTitleChanged {
+        id: usize,
+        title: String,
+        app_id: String,
+    },
+    WorkspaceActivated {
+        number: u8,
+        /// Output the workspace was activated on, unless workspaces span all outputs
+        output: Option<String>,
+    },
+    OutputAdded {
+        name: String,
+    },
+    OutputRemoved {
+        name: String,
+    },
+    ConfigChanged {
+        section: ConfigSection,
+    },
+    ShellModeChanged {
+        mode: ShellMode,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EventType {
+    Window,
+    Workspace,
+    Output,
+    Config,
+    ShellMode,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ConfigSection {
+    Outputs,
+    Inputs,
+}
+
+impl Event {
+    pub fn event_type(&self) -> EventType {
+        match self {
+            Event::WindowOpened { .. }
+            | Event::WindowClosed { .. }
+            | Event::WindowFocused { .. }
+            | Event::WindowTitleChanged { .. } => EventType::Window,
+            Event::WorkspaceActivated { .. } => EventType::Workspace,
+            Event::OutputAdded { .. } | Event::OutputRemoved { .. } => EventType::Output,
+            Event::ConfigChanged { .. } => EventType::Config,
+            Event::ShellModeChanged { .. } => EventType::ShellMode,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct OutputDescription {
+    pub name: String,
+    pub make: String,
+    pub model: String,
+    /// Position in the global compositor space
+    pub position: (i32, i32),
+    /// Logical size, after applying scale and transform
+    pub size: (i32, i32),
+    /// Physical size and refresh rate in mHz of the current mode
+    pub mode: Option<((i32, i32), i32)>,
+    pub scale: f64,
+    #[serde(with = "TransformDef")]
+    pub transform: Transform,
+    pub active_workspace: u8,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct WorkspaceDescription {
+    /// Number of the workspace, as used by the `Workspace` action
+    pub number: u8,
+    /// Outputs currently showing the workspace
+    pub outputs: Vec<String>,
+    /// Whether the workspace is shown on the output of the active seat
+    pub focused: bool,
+    pub windows: usize,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct WorkspaceTree {
+    pub number: u8,
+    pub outputs: Vec<OutputTree>,
+    /// Ids of the floating windows of the workspace
+    pub floating: Vec<usize>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct OutputTree {
+    pub output: String,
+    pub tiling: Option<TreeNode>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case", tag = "type")]
+pub enum TreeNode {
+    Fork {
+        orientation: Orientation,
+        ratio: f64,
+        children: Vec<TreeNode>,
+    },
+    Stack {
+        active: usize,
+        children: Vec<TreeNode>,
+    },
+    Window {
+        id: usize,
+        title: String,
+        app_id: String,
+    },
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct WindowDescription {
+    /// Id of the window, stable for as long as the window is mapped
+    pub id: usize,
+    pub title: String,
+    pub app_id: String,
+    pub workspace: u8,
+    pub output: Option<String>,
+    pub position: Option<(i32, i32)>,
+    pub size: (i32, i32),
+    pub floating: bool,
+    pub fullscreen: bool,
+    pub focused: bool,
+}
+
+// The following types mirror their counterparts of the compositor, which can't be shared
+// with `cosmic-comp-msg`. Changing them changes the wire format.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ShellMode {
+    Normal,
+    Resize,
+    Adjust,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Orientation {
+    Horizontal,
+    Vertical,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(remote = "Transform")]
+pub enum TransformDef {
+    Normal,
+    _90,
+    _180,
+    _270,
+    Flipped,
+    Flipped90,
+    Flipped180,
+    Flipped270,
+}
+
+/// Parses a mode like `1920x1080` or `1920x1080@60` into its size and refresh rate in mHz
+pub fn parse_mode(mode: &str) -> Result<((i32, i32), i32)> {
+    let (size, refresh) = match mode.split_once('@') {
+        Some((size, refresh)) => (
+            size,
+            refresh
+                .parse::<f64>()
+                .with_context(|| format!("Invalid refresh rate: {}", refresh))?,
+        ),
+        None => (mode, 60.0),
+    };
+    let (w, h) = size
+        .split_once('x')
+        .with_context(|| format!("Invalid size: {}, expected e.g. 1920x1080", size))?;
+    let w = w
+        .parse::<i32>()
+        .with_context(|| format!("Invalid width: {}", w))?;
+    let h = h
+        .parse::<i32>()
+        .with_context(|| format!("Invalid height: {}", h))?;
+    if w <= 0 || h <= 0 || refresh <= 0.0 {
+        anyhow::bail!("Invalid mode: {}", mode);
+    }
+
+    Ok(((w, h), (refresh * 1000.0).round() as i32))
+}
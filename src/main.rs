@@ -17,6 +17,7 @@ use std::{
 pub mod backend;
 pub mod config;
 pub mod input;
+pub mod ipc;
 mod logger;
 pub mod session;
 pub mod shell;
@@ -52,6 +53,10 @@ fn main() -> Result<()> {
     if let state::BackendData::Kms(_) = &state.backend {
         systemd::ready(&state);
     }
+    // init ipc socket, the compositor is still usable without it
+    if let Err(err) = ipc::setup_socket(event_loop.handle(), &mut state) {
+        slog_scope::warn!("Failed to set up the ipc socket: {:?}", err);
+    }
     // potentially tell the session we are setup now
     session::setup_socket(event_loop.handle(), &state)?;
 
@@ -69,6 +74,10 @@ fn main() -> Result<()> {
         // trigger routines
         data.state.common.shell.refresh(&data.display.handle());
         data.state.common.refresh_focus(&data.display.handle());
+        data.state.common.ipc_state.send_events(
+            &data.state.common.event_loop_handle,
+            data.state.common.shell.ipc_events.drain(),
+        );
 
         // do we need to trigger another render
         if data.state.common.dirty_flag.swap(false, Ordering::SeqCst) {
@@ -102,14 +111,12 @@ fn init_wayland_display(
     event_loop
         .handle()
         .insert_source(source, |client_stream, _, data| {
-            if let Err(err) = data.display.handle().insert_client(
-                client_stream,
-                Arc::new(if cfg!(debug_assertions) {
-                    data.state.new_privileged_client_state()
-                } else {
-                    data.state.new_client_state()
-                }),
-            ) {
+            let client_state = data.state.new_client_state_for_stream(&client_stream);
+            if let Err(err) = data
+                .display
+                .handle()
+                .insert_client(client_stream, Arc::new(client_state))
+            {
                 slog_scope::warn!("Error adding wayland client: {}", err);
             };
         })
@@ -77,6 +77,12 @@ pub fn setup_socket(handle: LoopHandle<Data>, state: &State) -> Result<()> {
                     .into_string()
                     .map_err(|_| anyhow!("wayland socket is no valid utf-8 string?"))?,
             );
+            if let Some(path) = state.common.ipc_state.socket_path() {
+                env.insert(
+                    String::from(crate::ipc::SOCKET_ENV),
+                    path.to_string_lossy().into_owned(),
+                );
+            }
             let message = serde_json::to_string(&Message::SetEnv { variables: env })
                 .with_context(|| "Failed to encode environment variables into json")?;
             let bytes = message.into_bytes();
@@ -2,7 +2,7 @@ use crate::{
     shell::{OutputBoundState, Shell, Workspace, WorkspaceMode},
     state::Common,
     utils::prelude::*,
-    wayland::handlers::xdg_shell::PopupGrabData,
+    wayland::{handlers::xdg_shell::PopupGrabData, protocols::text_input::TextInputUpdate},
 };
 use indexmap::IndexSet;
 use smithay::{
@@ -152,6 +152,26 @@ impl Shell {
                 surface,
                 serial.unwrap_or_else(|| SERIAL_COUNTER.next_serial()),
             );
+            self.update_keyboard_focus_state(surface);
+        }
+
+        let focused_window = surface.and_then(|surface| {
+            self.space_for_window(surface).and_then(|workspace| {
+                workspace
+                    .space
+                    .window_for_surface(surface, WindowSurfaceType::TOPLEVEL)
+                    .cloned()
+            })
+        });
+        self.ipc_events.window_focused(focused_window.as_ref());
+    }
+
+    /// Updates the protocol state following the keyboard focus.
+    fn update_keyboard_focus_state(&mut self, surface: Option<&WlSurface>) {
+        self.keyboard_shortcuts_inhibit_state.update_focus(surface);
+        if self.text_input_state.update_focus(surface) {
+            // the text-input lost focus, so it is implicitly disabled
+            self.input_method_state.text_input_updated(&TextInputUpdate::Disabled);
         }
     }
 
@@ -257,6 +277,7 @@ impl Common {
                     .map(|w| w.toplevel().wl_surface().clone());
                 if let Some(keyboard) = seat.get_keyboard() {
                     keyboard.set_focus(dh, surface.as_ref(), SERIAL_COUNTER.next_serial());
+                    self.shell.update_keyboard_focus_state(surface.as_ref());
                     ActiveFocus::set(seat, surface);
                 }
             }
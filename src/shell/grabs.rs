@@ -4,7 +4,10 @@ use crate::utils::prelude::*;
 use super::Shell;
 
 use smithay::{
-    backend::renderer::{Renderer, ImportAll},
+    backend::{
+        input::TouchSlot,
+        renderer::{Renderer, ImportAll},
+    },
     desktop::{Kind, Window, draw_window, space::{RenderElement, SpaceOutputTuple}},
     reexports::{
         wayland_protocols::xdg::shell::server::xdg_toplevel::State as XdgState,
@@ -32,77 +35,98 @@ impl Shell {
         serial: Serial,
         start_data: PointerGrabStartData,
     ) {
-        // TODO touch grab
         if let Some(pointer) = seat.get_pointer() {
-            let workspace = self.space_for_window_mut(window.toplevel().wl_surface()).unwrap();
-            if workspace.fullscreen.values().any(|w| w == window) {
-                return;
+            if let Some(state) = self.start_move(window, pointer.current_location(), None) {
+                let grab = MoveSurfaceGrab::new(start_data, window.clone(), seat);
+
+                *seat.user_data().get::<SeatMoveGrabState>().unwrap().borrow_mut() = Some(state);
+                pointer.set_grab(grab, serial, Focus::Clear);
             }
-            
-            let pos = pointer.current_location();
-            let output = workspace.space.outputs_for_window(&window)
-                .into_iter()
-                .find(|o| o.geometry().contains(pos.to_i32_round()))
-                .unwrap();
-            let mut initial_window_location = workspace.space.window_location(&window).unwrap();
-       
-            let output = match &window.toplevel() {
-                Kind::Xdg(surface) => {
-                    // If surface is maximized then unmaximize it
-                    let current_state = surface.current_state();
-                    if current_state.states.contains(XdgState::Maximized) {
-                        workspace.floating_layer.unmaximize_request(&mut workspace.space, window);
-                        let new_size = surface.with_pending_state(|state| state.size);
-                        let ratio = pos.x / output.geometry().size.w as f64;
-
-                        initial_window_location = new_size.map(|size| (
-                            pos.x - (size.w as f64 * ratio),
-                            pos.y,
-                        ).into()).unwrap_or_else(|| pos).to_i32_round();
-                    }
-
-                    output
+        }
+    }
+
+    pub fn touch_move_request(
+        &mut self,
+        window: &Window,
+        seat: &Seat<State>,
+        slot: TouchSlot,
+        location: Point<f64, Logical>,
+    ) {
+        // touch moves have no grab object, the touch events of the given slot
+        // are redirected by the input code as long as the state is set.
+        if let Some(state) = self.start_move(window, location, Some(slot)) {
+            *seat.user_data().get::<SeatMoveGrabState>().unwrap().borrow_mut() = Some(state);
+        }
+    }
+
+    fn start_move(
+        &mut self,
+        window: &Window,
+        pos: Point<f64, Logical>,
+        touch_slot: Option<TouchSlot>,
+    ) -> Option<MoveGrabState> {
+        let workspace = self.space_for_window_mut(window.toplevel().wl_surface()).unwrap();
+        if workspace.fullscreen.values().any(|w| w == window) {
+            return None;
+        }
+        
+        let output = workspace.space.outputs_for_window(&window)
+            .into_iter()
+            .find(|o| o.geometry().contains(pos.to_i32_round()))?;
+        let mut initial_window_location = workspace.space.window_location(&window).unwrap();
+   
+        let output = match &window.toplevel() {
+            Kind::Xdg(surface) => {
+                // If surface is maximized then unmaximize it
+                let current_state = surface.current_state();
+                if current_state.states.contains(XdgState::Maximized) {
+                    workspace.floating_layer.unmaximize_request(&mut workspace.space, window);
+                    let new_size = surface.with_pending_state(|state| state.size);
+                    let ratio = pos.x / output.geometry().size.w as f64;
+
+                    initial_window_location = new_size.map(|size| (
+                        pos.x - (size.w as f64 * ratio),
+                        pos.y,
+                    ).into()).unwrap_or_else(|| pos).to_i32_round();
                 }
-            };
 
-            let was_tiled = if workspace.tiling_layer.windows.contains(&window) {
-                workspace
-                .tiling_layer
-                .unmap_window(&mut workspace.space, &window);
-                true
-            } else {
-                workspace
-                    .floating_layer
-                    .unmap_window(&mut workspace.space, &window);
-                false
-            };
-            
-            let workspace_handle = workspace.handle;
-            let workspace_is_empty = workspace.space.windows().next().is_none();
-
-            if workspace_is_empty {
-                self.workspace_state.update().add_workspace_state(&workspace_handle, WState::Hidden);
+                output
             }
-            self.toplevel_info_state
-                .toplevel_leave_workspace(&window, &workspace_handle);
-            self.toplevel_info_state
-                .toplevel_leave_output(&window, &output);
-        
+        };
+
+        let was_tiled = if workspace.tiling_layer.windows.contains(&window) {
+            workspace
+            .tiling_layer
+            .unmap_window(&mut workspace.space, &window);
+            true
+        } else {
+            workspace
+                .floating_layer
+                .unmap_window(&mut workspace.space, &window);
+            false
+        };
         
-            let state = MoveGrabState {
-                window: window.clone(),
-                was_tiled,
-                initial_cursor_location: pointer.current_location(),
-                initial_window_location,
-            };
-            let grab = MoveSurfaceGrab::new(start_data, window.clone(), seat);
+        let workspace_handle = workspace.handle;
+        let workspace_is_empty = workspace.space.windows().next().is_none();
 
-            *seat.user_data().get::<SeatMoveGrabState>().unwrap().borrow_mut() = Some(state);
-            pointer.set_grab(grab, serial, Focus::Clear);
+        if workspace_is_empty {
+            self.workspace_state.update().add_workspace_state(&workspace_handle, WState::Hidden);
         }
+        self.toplevel_info_state
+            .toplevel_leave_workspace(&window, &workspace_handle);
+        self.toplevel_info_state
+            .toplevel_leave_output(&window, &output);
+    
+        Some(MoveGrabState {
+            window: window.clone(),
+            was_tiled,
+            initial_cursor_location: pos,
+            initial_window_location,
+            touch: touch_slot.map(|slot| (slot, pos)),
+        })
     }
 
-    fn drop_move(
+    pub fn drop_move(
         &mut self,
         dh: &DisplayHandle,
         seat: &Seat<State>,
@@ -110,11 +134,10 @@ impl Shell {
         output: &Output,
     ) {
         if let Some(move_state) = seat.user_data().get::<SeatMoveGrabState>().unwrap().borrow_mut().take() {
-            let pointer = seat.get_pointer().unwrap();
+            let delta = move_state.current_location(seat) - move_state.initial_cursor_location;
             let window = move_state.window;
            
             if window.alive() {
-                let delta = pointer.current_location() - move_state.initial_cursor_location;
                 let window_location = (move_state.initial_window_location.to_f64() + delta).to_i32_round();
                 let surface = window.toplevel().wl_surface().clone();
 
@@ -159,6 +182,7 @@ pub struct MoveGrabState {
     was_tiled: bool,
     initial_cursor_location: Point<f64, Logical>,
     initial_window_location: Point<i32, Logical>,
+    touch: Option<(TouchSlot, Point<f64, Logical>)>,
 }
 
 pub struct MoveGrabRenderElement {
@@ -216,11 +240,36 @@ where
 }
 
 impl MoveGrabState {
+    /// The touch slot driving this move, if it was started by a touch-point
+    pub fn touch_slot(&self) -> Option<TouchSlot> {
+        self.touch.map(|(slot, _)| slot)
+    }
+
+    /// Updates the location of a touch driven move.
+    ///
+    /// Returns `false`, if the given slot does not belong to this move.
+    pub fn touch_motion(&mut self, slot: TouchSlot, location: Point<f64, Logical>) -> bool {
+        match self.touch.as_mut() {
+            Some((touch_slot, touch_location)) if *touch_slot == slot => {
+                *touch_location = location;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn current_location(&self, seat: &Seat<State>) -> Point<f64, Logical> {
+        match self.touch {
+            Some((_, location)) => location,
+            None => seat.get_pointer().unwrap().current_location(),
+        }
+    }
+
     pub fn render<I>(&self, seat: &Seat<State>, output: &Output) -> Option<I>
     where
         I: From<MoveGrabRenderElement>
     {
-        let cursor_at = seat.get_pointer().unwrap().current_location();
+        let cursor_at = self.current_location(seat);
         let delta = cursor_at - self.initial_cursor_location;
         let mut window_geo = self.window.bbox();
         window_geo.loc += (self.initial_window_location.to_f64() + delta).to_i32_round();
@@ -14,7 +14,7 @@ use std::sync::Mutex;
 pub mod floating;
 pub mod tiling;
 
-#[derive(Debug, serde::Deserialize, Clone, Copy, PartialEq, Eq, Hash)]
+#[derive(Debug, serde::Deserialize, serde::Serialize, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum Orientation {
     Horizontal,
     Vertical,
@@ -14,6 +14,7 @@ use smithay::{
     },
     utils::{IsAlive, Rectangle},
     wayland::{
+        output::Output,
         seat::{Focus, PointerGrabStartData, Seat},
         Serial,
     },
@@ -70,6 +71,12 @@ impl TilingLayout {
             windows: HashSet::new(),
         }
     }
+
+    /// Layout tree of the given output, `None` if nothing was tiled on it yet
+    pub fn tree(&self, space: &Space, output: &Output) -> Option<&Tree<Data>> {
+        let idx = space.outputs().position(|o| o == output)?;
+        self.trees.get(idx)
+    }
 }
 
 impl TilingLayout {
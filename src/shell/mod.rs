@@ -22,9 +22,13 @@ use cosmic_protocols::workspace::v1::server::zcosmic_workspace_handle_v1::State
 
 use crate::{
     config::{Config, WorkspaceMode as ConfigMode},
-    //state::ClientState,
+    ipc::{Event, EventQueue},
+    state::{privileged_filter, sandbox_filter},
     utils::prelude::*,
     wayland::protocols::{
+        input_method::InputMethodState,
+        keyboard_shortcuts_inhibit::KeyboardShortcutsInhibitState,
+        text_input::TextInputState,
         toplevel_info::ToplevelInfoState,
         toplevel_management::{ToplevelManagementState, ManagementCapabilities},
         workspace::{
@@ -45,14 +49,23 @@ pub struct Shell {
     pub popups: PopupManager,
     pub spaces: [Workspace; MAX_WORKSPACES],
     pub outputs: Vec<Output>,
+    /// Outputs showing the contents of another output as `(mirror, source)`,
+    /// they are not part of the workspace layout
+    pub mirrors: Vec<(Output, Output)>,
     pub workspace_mode: WorkspaceMode,
     pub shell_mode: ShellMode,
 
     pub pending_windows: Vec<(Window, Seat<State>)>,
     pub pending_layers: Vec<(LayerSurface, Output, Seat<State>)>,
 
+    /// Events for ipc subscribers, sent out once per event loop iteration
+    pub ipc_events: EventQueue,
+
     // wayland_state
+    pub input_method_state: InputMethodState,
+    pub keyboard_shortcuts_inhibit_state: KeyboardShortcutsInhibitState,
     pub layer_shell_state: WlrLayerShellState,
+    pub text_input_state: TextInputState,
     pub toplevel_info_state: ToplevelInfoState<State>,
     pub toplevel_management_state: ToplevelManagementState,
     pub xdg_shell_state: XdgShellState,
@@ -68,6 +81,7 @@ pub enum WorkspaceMode {
     },
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
 pub enum ShellMode {
     Normal,
     Resize,
@@ -84,24 +98,30 @@ const UNINIT_SPACE: MaybeUninit<Workspace> = MaybeUninit::uninit();
 
 impl Shell {
     pub fn new(config: &Config, dh: &DisplayHandle) -> Self {
-        // TODO: Privileged protocols
+        let input_method_state = InputMethodState::new::<State, _>(
+            dh,
+            200,
+            25,
+            sandbox_filter("zwp_input_method_manager_v2"),
+        );
+        let keyboard_shortcuts_inhibit_state =
+            KeyboardShortcutsInhibitState::new::<State, _>(
+                dh,
+                sandbox_filter("zwp_keyboard_shortcuts_inhibit_manager_v1"),
+            );
         let layer_shell_state = WlrLayerShellState::new::<State, _>(dh, None);
+        let text_input_state =
+            TextInputState::new::<State, _>(dh, sandbox_filter("zwp_text_input_manager_v3"));
         let xdg_shell_state = XdgShellState::new::<State, _>(dh, None);
-        let toplevel_info_state = ToplevelInfoState::new(
-            dh,
-            //|client| client.get_data::<ClientState>().unwrap().privileged,
-            |_| true);
+        let toplevel_info_state =
+            ToplevelInfoState::new(dh, privileged_filter("zcosmic_toplevel_info_v1"));
         let toplevel_management_state = ToplevelManagementState::new::<State, _>(
             dh,
             vec![ManagementCapabilities::Close, ManagementCapabilities::Activate],
-            //|client| client.get_data::<ClientState>().unwrap().privileged,
-            |_| true,
-        );
-        let mut workspace_state = WorkspaceState::new(
-            dh,
-            //|client| client.get_data::<ClientState>().unwrap().privileged,
-            |_| true,
+            privileged_filter("zcosmic_toplevel_manager_v1"),
         );
+        let mut workspace_state =
+            WorkspaceState::new(dh, privileged_filter("zcosmic_workspace_manager_v1"));
 
         let mut spaces = unsafe {
             let mut spaces = [UNINIT_SPACE; MAX_WORKSPACES];
@@ -125,13 +145,19 @@ impl Shell {
             popups: PopupManager::new(None),
             spaces,
             outputs: Vec::new(),
+            mirrors: Vec::new(),
             workspace_mode: mode,
             shell_mode: ShellMode::Normal,
 
             pending_windows: Vec::new(),
             pending_layers: Vec::new(),
 
+            ipc_events: EventQueue::default(),
+
+            input_method_state,
+            keyboard_shortcuts_inhibit_state,
             layer_shell_state,
+            text_input_state,
             toplevel_info_state,
             toplevel_management_state,
             xdg_shell_state,
@@ -142,6 +168,9 @@ impl Shell {
     pub fn add_output(&mut self, output: &Output) {
         let was_empty = self.outputs.is_empty();
         self.outputs.push(output.clone());
+        self.ipc_events.push(Event::OutputAdded {
+            name: output.name(),
+        });
         let mut state = self.workspace_state.update();
 
         match self.workspace_mode {
@@ -204,8 +233,16 @@ impl Shell {
     }
 
     pub fn remove_output(&mut self, output: &Output) {
+        // mirrors are not part of the workspace layout
+        if self.stop_mirroring(output) {
+            return;
+        }
+
         let mut state = self.workspace_state.update();
         self.outputs.retain(|o| o != output);
+        self.ipc_events.push(Event::OutputRemoved {
+            name: output.name(),
+        });
 
         match self.workspace_mode {
             WorkspaceMode::OutputBound => {
@@ -252,6 +289,56 @@ impl Shell {
                 );
             }
         };
+        std::mem::drop(state);
+
+        // outputs mirroring this one become part of the layout again
+        let (orphans, mirrors) = std::mem::take(&mut self.mirrors)
+            .into_iter()
+            .partition::<Vec<_>, _>(|(_, source)| source == output);
+        self.mirrors = mirrors;
+        for (mirror, _) in orphans {
+            self.add_output(&mirror);
+        }
+    }
+
+    /// Makes `output` show the contents of `source` or puts it back into the workspace layout.
+    pub fn set_mirror(&mut self, output: &Output, source: Option<&Output>) {
+        if self.mirrored_output(output).as_ref() == source {
+            return;
+        }
+
+        let was_mirror = self.stop_mirroring(output);
+        match source {
+            Some(source) => {
+                if self.outputs.contains(output) {
+                    self.remove_output(output);
+                }
+                self.mirrors.push((output.clone(), source.clone()));
+            }
+            None if was_mirror => self.add_output(output),
+            None => {}
+        }
+    }
+
+    fn stop_mirroring(&mut self, output: &Output) -> bool {
+        let len = self.mirrors.len();
+        self.mirrors.retain(|(mirror, _)| mirror != output);
+        len != self.mirrors.len()
+    }
+
+    /// The output `output` is mirroring, if any
+    pub fn mirrored_output(&self, output: &Output) -> Option<Output> {
+        self.mirrors
+            .iter()
+            .find(|(mirror, _)| mirror == output)
+            .map(|(_, source)| source.clone())
+    }
+
+    pub fn mirrors_of<'a>(&'a self, source: &'a Output) -> impl Iterator<Item = &'a Output> + 'a {
+        self.mirrors
+            .iter()
+            .filter(move |(_, s)| s == source)
+            .map(|(mirror, _)| mirror)
     }
 
     pub fn refresh_outputs(&mut self) {
@@ -404,6 +491,10 @@ impl Shell {
                         Point::from((0, 0)),
                         &mut self.toplevel_info_state,
                     );
+                    self.ipc_events.push(Event::WorkspaceActivated {
+                        number: idx as u8 + 1,
+                        output: Some(output.name()),
+                    });
                 }
             }
             WorkspaceMode::Global { ref mut active, .. } => {
@@ -423,6 +514,12 @@ impl Shell {
                 }
                 state.remove_workspace_state(&self.spaces[old].handle, WState::Active);
                 state.add_workspace_state(&self.spaces[idx].handle, WState::Active);
+                if old != idx {
+                    self.ipc_events.push(Event::WorkspaceActivated {
+                        number: idx as u8 + 1,
+                        output: None,
+                    });
+                }
             }
         }
 
@@ -562,6 +659,15 @@ impl Shell {
         }
         self.toplevel_info_state
             .refresh(Some(&self.workspace_state));
+        self.ipc_events.refresh();
+    }
+
+    pub fn set_shell_mode(&mut self, mode: ShellMode) {
+        if self.shell_mode != mode {
+            self.shell_mode = mode;
+            self.ipc_events
+                .push(Event::ShellModeChanged { mode: mode.into() });
+        }
     }
 
     pub fn map_window(&mut self, window: &Window, output: &Output, dh: &DisplayHandle) {
@@ -592,6 +698,7 @@ impl Shell {
             .toplevel_enter_workspace(&window, &workspace.handle);
         self.toplevel_info_state
             .toplevel_enter_output(&window, &output);
+        self.ipc_events.window_opened(&window, workspace.idx + 1);
         if layout::should_be_floating(&window) {
             workspace
                 .floating_layer
@@ -1,25 +1,38 @@
 // SPDX-License-Identifier: GPL-3.0-only
 
 use crate::{
-    backend::{kms::KmsState, winit::WinitState, x11::X11State},
+    backend::{
+        headless::HeadlessState, kms::KmsState, wayland::WaylandState, winit::WinitState,
+        x11::X11State,
+    },
     config::{Config, OutputConfig},
+    ipc::IpcState,
     logger::LogState,
     shell::Shell,
-    wayland::protocols::{
-        drm::WlDrmState,
-        export_dmabuf::ExportDmabufState,
-        output_configuration::OutputConfigurationState,
-        workspace::WorkspaceClientState,
+    utils::{peer::Peer, prelude::*},
+    wayland::{
+        handlers::tablet_manager::create_tablet_manager_global,
+        protocols::{
+            drm::WlDrmState,
+            export_dmabuf::ExportDmabufState,
+            output_configuration::OutputConfigurationState,
+            pointer_constraints::PointerConstraintsState,
+            pointer_gestures::PointerGesturesState,
+            relative_pointer::RelativePointerState,
+            security_context::{SecurityContext, SecurityContextState},
+            virtual_keyboard::VirtualKeyboardState,
+            virtual_pointer::VirtualPointerState,
+            workspace::WorkspaceClientState,
+        },
     },
-    utils::prelude::*,
 };
 use smithay::{
     backend::drm::DrmNode,
     reexports::{
         calloop::{LoopHandle, LoopSignal},
         wayland_server::{
-            backend::{ClientData, ClientId, DisconnectReason},
-            Display, DisplayHandle,
+            backend::{ClientData, ClientId, DisconnectReason, GlobalId},
+            Client, Display, DisplayHandle,
         },
     },
     wayland::{
@@ -36,8 +49,11 @@ use smithay::{
 
 use std::{
     cell::RefCell,
+    collections::HashSet,
     ffi::OsString,
-    sync::{atomic::AtomicBool, Arc},
+    os::unix::net::UnixStream,
+    path::PathBuf,
+    sync::{atomic::AtomicBool, Arc, Mutex},
     time::Instant,
 };
 #[cfg(feature = "debug")]
@@ -47,12 +63,75 @@ pub struct ClientState {
     pub workspace_client_state: WorkspaceClientState,
     pub drm_node: Option<DrmNode>,
     pub privileged: bool,
+    /// Executable of the client process, if it could be determined
+    pub executable: Option<PathBuf>,
+    /// Set for clients connecting through a socket of a sandbox engine
+    pub security_context: Option<SecurityContext>,
+    /// Restricted globals granted to the sandboxed client by the `sandbox_permissions` config
+    pub sandbox_globals: HashSet<String>,
+    /// Globals already denied to the client, as filters run on every registry bind
+    denied_globals: Mutex<HashSet<&'static str>>,
 }
 impl ClientData for ClientState {
     fn initialized(&self, _client_id: ClientId) {}
     fn disconnected(&self, _client_id: ClientId, _reason: DisconnectReason) {}
 }
 
+impl ClientState {
+    /// Whether the client may use privileged protocols, which sandboxed clients never may.
+    pub fn is_privileged(&self) -> bool {
+        self.privileged && self.security_context.is_none()
+    }
+
+    fn log_denied(&self, global: &'static str) {
+        if !self.denied_globals.lock().unwrap().insert(global) {
+            return;
+        }
+        match self.security_context.as_ref() {
+            Some(context) => {
+                slog_scope::debug!("Denied {} to sandboxed client {}", global, context)
+            }
+            None => slog_scope::debug!(
+                "Denied {} to unprivileged client {}",
+                global,
+                self.executable
+                    .as_ref()
+                    .map(|path| path.display().to_string())
+                    .unwrap_or_else(|| String::from("<unknown executable>")),
+            ),
+        }
+    }
+}
+
+/// Client filter for globals, that only privileged clients may see.
+pub fn privileged_filter(
+    global: &'static str,
+) -> impl for<'a> Fn(&'a Client) -> bool + Send + Sync + 'static {
+    move |client: &Client| {
+        let client_state = client.get_data::<ClientState>().unwrap();
+        if !client_state.is_privileged() {
+            client_state.log_denied(global);
+        }
+        client_state.is_privileged()
+    }
+}
+
+/// Client filter for globals, that sandboxed clients may only see, if the `sandbox_permissions`
+/// matching their security context grant them.
+pub fn sandbox_filter(
+    global: &'static str,
+) -> impl for<'a> Fn(&'a Client) -> bool + Send + Sync + 'static {
+    move |client: &Client| {
+        let client_state = client.get_data::<ClientState>().unwrap();
+        let visible = client_state.security_context.is_none()
+            || client_state.sandbox_globals.contains(global);
+        if !visible {
+            client_state.log_denied(global);
+        }
+        visible
+    }
+}
+
 pub struct Data {
     pub display: Display<State>,
     pub state: State,
@@ -84,6 +163,8 @@ pub struct Common {
     #[cfg(feature = "debug")]
     pub egui: Egui,
 
+    pub ipc_state: IpcState,
+
     // wayland state
     pub compositor_state: CompositorState,
     pub data_device_state: DataDeviceState,
@@ -91,11 +172,18 @@ pub struct Common {
     pub export_dmabuf_state: ExportDmabufState,
     pub output_state: OutputManagerState,
     pub output_configuration_state: OutputConfigurationState<State>,
+    pub pointer_constraints_state: PointerConstraintsState,
+    pub pointer_gestures_state: PointerGesturesState,
     pub primary_selection_state: PrimarySelectionState,
+    pub relative_pointer_state: RelativePointerState,
     pub seat_state: SeatState<State>,
+    pub security_context_state: SecurityContextState,
     pub shm_state: ShmState,
+    pub tablet_manager_global: GlobalId,
     pub wl_drm_state: WlDrmState,
     pub viewporter_state: ViewporterState,
+    pub virtual_keyboard_state: VirtualKeyboardState,
+    pub virtual_pointer_state: VirtualPointerState,
 }
 
 #[cfg(feature = "debug")]
@@ -119,8 +207,8 @@ pub enum BackendData {
     X11(X11State),
     Winit(WinitState),
     Kms(KmsState),
-    // TODO
-    // Wayland(WaylandState),
+    Headless(HeadlessState),
+    Wayland(WaylandState),
     Unset,
 }
 
@@ -146,6 +234,20 @@ impl BackendData {
         }
     }
 
+    pub fn wayland(&mut self) -> &mut WaylandState {
+        match self {
+            BackendData::Wayland(ref mut wayland_state) => wayland_state,
+            _ => unreachable!("Called wayland in non wayland backend"),
+        }
+    }
+
+    pub fn headless(&mut self) -> &mut HeadlessState {
+        match self {
+            BackendData::Headless(ref mut headless_state) => headless_state,
+            _ => unreachable!("Called headless in non headless backend"),
+        }
+    }
+
     pub fn apply_config_for_output(
         &mut self,
         output: &Output,
@@ -153,12 +255,46 @@ impl BackendData {
         shell: &mut Shell,
         loop_handle: &LoopHandle<'_, Data>,
     ) -> Result<(), anyhow::Error> {
+        use anyhow::Context;
+
+        let source = {
+            let config = output
+                .user_data()
+                .get::<RefCell<OutputConfig>>()
+                .unwrap()
+                .borrow();
+            match config.mirror_of.as_ref() {
+                Some(name) => {
+                    if !matches!(self, BackendData::Kms(_)) {
+                        anyhow::bail!("Mirroring outputs is only supported on the kms backend");
+                    }
+                    // mirrors are not part of the layout, so we can't end up with chains of mirrors
+                    let source = shell
+                        .outputs()
+                        .find(|o| &o.name() == name && *o != output)
+                        .cloned()
+                        .with_context(|| format!("Unable to mirror unknown output {}", name))?;
+                    if !self.kms().can_mirror(&source) {
+                        anyhow::bail!("Unable to mirror {}, which is rendered in software", name);
+                    }
+                    Some(source)
+                }
+                None => None,
+            }
+        };
+
         let result = match self {
             BackendData::Kms(ref mut state) => {
                 state.apply_config_for_output(output, shell, test_only, loop_handle)
             }
             BackendData::Winit(ref mut state) => state.apply_config_for_output(output, test_only),
             BackendData::X11(ref mut state) => state.apply_config_for_output(output, test_only),
+            BackendData::Wayland(ref mut state) => {
+                state.apply_config_for_output(output, test_only)
+            }
+            BackendData::Headless(ref mut state) => {
+                state.apply_config_for_output(output, shell, test_only, loop_handle)
+            }
             _ => unreachable!("No backend set when applying output config"),
         };
 
@@ -184,6 +320,9 @@ impl BackendData {
             let location =
                 Some(final_config.position.into()).filter(|x| *x != output.current_location());
             output.change_current_state(mode, transform, scale.map(Scale::Fractional), location);
+            if !test_only && final_config.enabled {
+                shell.set_mirror(output, source.as_ref());
+            }
         }
 
         result
@@ -195,6 +334,8 @@ impl BackendData {
             // Winit has a very strict render-loop and skipping frames breaks atleast the wayland winit-backend.
             // Swapping with damage (which should be empty on these frames) is likely good enough anyway.
             BackendData::X11(ref mut state) => state.schedule_render(output),
+            BackendData::Wayland(ref mut state) => state.schedule_render(output),
+            BackendData::Headless(ref mut state) => state.schedule_render(output),
             BackendData::Kms(ref mut state) => {
                 if let Err(err) = state.schedule_render(loop_handle, output) {
                     slog_scope::crit!("Failed to schedule event, are we shutting down? {:?}", err);
@@ -211,12 +352,9 @@ impl BackendData {
     ) -> anyhow::Result<image::ImageBuffer<image::Rgba<u8>, Vec<u8>>> {
         use anyhow::Context;
         use smithay::{
-            backend::{
-                drm::NodeType,
-                renderer::{
-                    Bind, Offscreen, ExportMem,
-                    gles2::Gles2Renderbuffer,
-                },
+            backend::renderer::{
+                Bind, Offscreen, ExportMem,
+                gles2::Gles2Renderbuffer,
             },
             utils::Rectangle,
         };
@@ -266,12 +404,22 @@ impl BackendData {
         match self {
             BackendData::Winit(winit) => capture(None, winit.backend.renderer(), output, state),
             BackendData::X11(x11) => capture(None, &mut x11.renderer, output, state),
-            BackendData::Kms(kms) => {
-                let node = kms.target_node_for_output(output)
-                    .unwrap_or(kms.primary)
-                    .node_with_type(NodeType::Render)
-                    .with_context(|| "Unable to find node")??;
-                capture(Some(node), &mut kms.api.renderer::<Gles2Renderbuffer>(&node, &node)?, output, state)
+            BackendData::Wayland(wayland) => capture(None, &mut wayland.renderer, output, state),
+            BackendData::Headless(headless) => capture(
+                None,
+                headless.renderer.as_mut().with_context(|| "Headless backend runs without a renderer")?,
+                output,
+                state,
+            ),
+            BackendData::Kms(kms) => match kms.target_node_for_output(output).or(kms.primary) {
+                Some(node) => capture(Some(node), &mut kms.api.renderer::<Gles2Renderbuffer>(&node, &node)?, output, state),
+                // without any hardware-acceleration the output was rendered in software
+                None => capture(
+                    None,
+                    kms.software_renderer().with_context(|| "Software renderer not initialized")?,
+                    output,
+                    state,
+                ),
             },
             BackendData::Unset => unreachable!(),
         }
@@ -292,15 +440,40 @@ impl State {
         let dmabuf_state = DmabufState::new();
         let export_dmabuf_state = ExportDmabufState::new::<Self, _>(
             dh,
-            //|client| client.get_data::<ClientState>().unwrap().privileged,
-            |_| true,
+            privileged_filter("zcosmic_export_dmabuf_manager_v1"),
         );
         let output_state = OutputManagerState::new_with_xdg_output::<Self>(dh);
-        let output_configuration_state = OutputConfigurationState::new(dh, |_| true);
+        let output_configuration_state = OutputConfigurationState::new(
+            dh,
+            sandbox_filter("zwlr_output_manager_v1"),
+        );
+        let pointer_constraints_state = PointerConstraintsState::new::<Self, _>(
+            dh,
+            sandbox_filter("zwp_pointer_constraints_v1"),
+        );
+        let pointer_gestures_state = PointerGesturesState::new::<Self, _>(dh, |_| true);
         let primary_selection_state = PrimarySelectionState::new::<Self, _>(dh, None);
+        let relative_pointer_state = RelativePointerState::new::<Self, _>(
+            dh,
+            sandbox_filter("zwp_relative_pointer_manager_v1"),
+        );
         let shm_state = ShmState::new::<Self, _>(dh, vec![], None);
         let seat_state = SeatState::<Self>::new();
+        let security_context_state = SecurityContextState::new::<Self, _>(
+            dh,
+            sandbox_filter("wp_security_context_manager_v1"),
+        );
+        let tablet_manager_global =
+            create_tablet_manager_global(dh, sandbox_filter("zwp_tablet_manager_v2"));
         let viewporter_state = ViewporterState::new::<Self, _>(dh, None);
+        let virtual_keyboard_state = VirtualKeyboardState::new::<Self, _>(
+            dh,
+            privileged_filter("zwp_virtual_keyboard_manager_v1"),
+        );
+        let virtual_pointer_state = VirtualPointerState::new::<Self, _>(
+            dh,
+            privileged_filter("zwlr_virtual_pointer_manager_v1"),
+        );
         let wl_drm_state = WlDrmState;
 
         let shell = Shell::new(&config, dh);
@@ -342,16 +515,25 @@ impl State {
                     alpha: 1.0,
                 },
 
+                ipc_state: IpcState::default(),
+
                 compositor_state,
                 data_device_state,
                 dmabuf_state,
                 export_dmabuf_state,
                 shm_state,
                 seat_state,
+                security_context_state,
+                tablet_manager_global,
                 output_state,
                 output_configuration_state,
+                pointer_constraints_state,
+                pointer_gestures_state,
                 primary_selection_state,
+                relative_pointer_state,
                 viewporter_state,
+                virtual_keyboard_state,
+                virtual_pointer_state,
                 wl_drm_state,
             },
             backend: BackendData::Unset,
@@ -365,17 +547,46 @@ impl State {
                 BackendData::Kms(kms_state) => {
                     match std::env::var("COSMIC_RENDER_AUTO_ASSIGN").map(|val| val.to_lowercase()) {
                         Ok(val) if val == "y" || val == "yes" || val == "true" =>
-                            Some(
-                                kms_state.target_node_for_output(
-                                    &active_output(&self.common.last_active_seat, &self.common)
-                                ).unwrap_or(kms_state.primary)
-                            ),
-                        _ => Some(kms_state.primary),
+                            kms_state.target_node_for_output(
+                                &active_output(&self.common.last_active_seat, &self.common)
+                            ).or(kms_state.primary),
+                        _ => kms_state.primary,
                     }
                 },
                 _ => None,
             },
             privileged: false,
+            executable: None,
+            security_context: None,
+            sandbox_globals: HashSet::new(),
+            denied_globals: Mutex::new(HashSet::new()),
+        }
+    }
+
+    /// Creates the state of a client connecting through the wayland socket,
+    /// which is privileged, if its executable is part of the `privileged_clients` config.
+    ///
+    /// Clients in another mount namespace are never privileged, as they can make any file
+    /// show up under the path of a privileged executable.
+    pub fn new_client_state_for_stream(&self, stream: &UnixStream) -> ClientState {
+        let executable = Peer::from_stream(stream).and_then(|peer| peer.executable);
+        let mut client_state = match executable.as_deref() {
+            Some(path) if self.common.config.is_privileged_client(path) => {
+                slog_scope::info!("Granting privileged access to {}", path.display());
+                self.new_privileged_client_state()
+            }
+            _ => self.new_client_state(),
+        };
+        client_state.executable = executable;
+        client_state
+    }
+
+    /// Creates the state of a client connecting through the socket of a security context.
+    pub fn new_sandboxed_client_state(&self, context: SecurityContext) -> ClientState {
+        ClientState {
+            sandbox_globals: self.common.config.sandbox_globals(&context),
+            security_context: Some(context),
+            ..self.new_client_state()
         }
     }
 
@@ -384,6 +595,10 @@ impl State {
             workspace_client_state: WorkspaceClientState::default(),
             drm_node: Some(drm_node),
             privileged: false,
+            executable: None,
+            security_context: None,
+            sandbox_globals: HashSet::new(),
+            denied_globals: Mutex::new(HashSet::new()),
         }
     }
 
@@ -391,10 +606,14 @@ impl State {
         ClientState {
             workspace_client_state: WorkspaceClientState::default(),
             drm_node: match &self.backend {
-                BackendData::Kms(kms_state) => Some(kms_state.primary),
+                BackendData::Kms(kms_state) => kms_state.primary,
                 _ => None,
             },
             privileged: true,
+            executable: None,
+            security_context: None,
+            sandbox_globals: HashSet::new(),
+            denied_globals: Mutex::new(HashSet::new()),
         }
     }
 
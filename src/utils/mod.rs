@@ -3,4 +3,5 @@
 mod ids;
 pub(crate) use self::ids::id_gen;
 
+pub mod peer;
 pub mod prelude;
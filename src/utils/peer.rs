@@ -0,0 +1,85 @@
+// SPDX-License-Identifier: GPL-3.0-only
+
+use smithay::reexports::nix::{
+    libc,
+    sys::socket::{getsockopt, sockopt},
+};
+use std::{
+    fs::File,
+    os::unix::{
+        io::{AsRawFd, FromRawFd},
+        net::UnixStream,
+    },
+    path::PathBuf,
+};
+
+/// `SO_PEERPIDFD` (linux 6.5), which the libc crate we build against does not know yet
+const SO_PEERPIDFD: libc::c_int = 77;
+
+/// Process on the other side of a unix socket
+pub struct Peer {
+    pub uid: u32,
+    /// Executable of the process, `None` if it can't be trusted to mean what it says,
+    /// because the process runs in another mount namespace or its identity is unclear.
+    pub executable: Option<PathBuf>,
+}
+
+impl Peer {
+    pub fn from_stream(stream: &UnixStream) -> Option<Peer> {
+        let credentials = getsockopt(stream.as_raw_fd(), sockopt::PeerCredentials).ok()?;
+        Some(Peer {
+            uid: credentials.uid(),
+            executable: peer_executable(stream),
+        })
+    }
+}
+
+/// Looks up the executable through a pidfd of the peer, so a reused pid is never mistaken for it.
+fn peer_executable(stream: &UnixStream) -> Option<PathBuf> {
+    let pidfd = peer_pidfd(stream)?;
+    let pid = pidfd_pid(&pidfd)?;
+
+    // paths of other mount namespaces (e.g. containers) can point to any file they want
+    let namespace = std::fs::read_link(format!("/proc/{}/ns/mnt", pid)).ok()?;
+    if namespace != std::fs::read_link("/proc/self/ns/mnt").ok()? {
+        return None;
+    }
+    let executable = std::fs::read_link(format!("/proc/{}/exe", pid)).ok()?;
+
+    // only if the peer still exists, the pid was not handed to another process in the meantime
+    let alive = unsafe {
+        libc::syscall(
+            libc::SYS_pidfd_send_signal,
+            pidfd.as_raw_fd(),
+            0,
+            std::ptr::null::<libc::siginfo_t>(),
+            0,
+        )
+    } == 0;
+    alive.then(|| executable)
+}
+
+fn peer_pidfd(stream: &UnixStream) -> Option<File> {
+    let mut fd: libc::c_int = -1;
+    let mut len = std::mem::size_of::<libc::c_int>() as libc::socklen_t;
+    let result = unsafe {
+        libc::getsockopt(
+            stream.as_raw_fd(),
+            libc::SOL_SOCKET,
+            SO_PEERPIDFD,
+            &mut fd as *mut libc::c_int as *mut libc::c_void,
+            &mut len,
+        )
+    };
+    (result == 0 && fd >= 0).then(|| unsafe { File::from_raw_fd(fd) })
+}
+
+/// Pid of the process referred to by `pidfd`, `None` if it already exited.
+fn pidfd_pid(pidfd: &File) -> Option<u32> {
+    let fdinfo =
+        std::fs::read_to_string(format!("/proc/self/fdinfo/{}", pidfd.as_raw_fd())).ok()?;
+    fdinfo
+        .lines()
+        .find_map(|line| line.strip_prefix("Pid:"))
+        .and_then(|pid| pid.trim().parse().ok())
+}
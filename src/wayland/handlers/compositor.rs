@@ -105,8 +105,27 @@ impl CompositorHandler for State {
     }
 
     fn commit(&mut self, dh: &DisplayHandle, surface: &WlSurface) {
+        // direct scanout still reads from the current buffer, which smithay would release right away
+        let deferred = match &mut self.backend {
+            BackendData::Kms(kms_state) => kms_state.defer_buffer(surface),
+            _ => false,
+        };
         // first load the buffer for various smithay helper functions
-        on_commit_buffer_handler(surface);
+        if !deferred {
+            on_commit_buffer_handler(surface);
+        }
+        self.common.pointer_constraints_state.commit(surface);
+
+        // input method popups are placed relative to the text cursor, once their size is known
+        if self.common.shell.input_method_state.is_popup(surface) {
+            self.common.shell.update_input_popups();
+            if let Some(parent) = self.common.shell.text_input_state.focus().cloned() {
+                for output in self.common.shell.outputs_for_surface(&parent) {
+                    self.backend.schedule_render(&self.common.event_loop_handle, &output);
+                }
+            }
+            return;
+        }
 
         // then handle initial configure events and map windows if necessary
         if let Some((window, seat)) = self
@@ -186,6 +205,21 @@ impl CompositorHandler for State {
             }
         }
 
+        // clients follow changes of their title or app id with a commit
+        if let Some(window) = self
+            .common
+            .shell
+            .space_for_window(surface)
+            .and_then(|workspace| {
+                workspace
+                    .space
+                    .window_for_surface(surface, WindowSurfaceType::TOPLEVEL)
+                    .cloned()
+            })
+        {
+            self.common.shell.ipc_events.window_updated(&window);
+        }
+
         // We need to know every potential output for importing to the right gpu and scheduling a render,
         // so call this only after every potential surface map operation has been done.
         self.early_import_surface(dh, surface);
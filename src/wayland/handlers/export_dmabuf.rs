@@ -9,7 +9,7 @@ use std::{
 
 use smithay::{
     backend::{
-        drm::{DrmNode, NodeType},
+        drm::DrmNode,
         egl::EGLDevice,
         renderer::{
             Bind,
@@ -64,6 +64,9 @@ impl ExportDmabufHandler for State {
             },
             BackendData::Winit(ref mut winit) => winit.backend.renderer(),
             BackendData::X11(ref mut x11) => &mut x11.renderer,
+            BackendData::Wayland(ref mut wayland) => &mut wayland.renderer,
+            BackendData::Headless(ref mut headless) => headless.renderer.as_mut()
+                .ok_or(CaptureError::Permanent(anyhow!("Headless backend runs without a renderer").into()))?,
             _ => unreachable!(),
         };
         let device = device_from_renderer(renderer)
@@ -125,12 +128,25 @@ impl ExportDmabufHandler for State {
                         .map_err(|err| CaptureError::Permanent(err.into()))?;
                     capture_workspace(device, &mut x11.renderer, &output, workspace, &mut self.common)
                 },
+                BackendData::Wayland(ref mut wayland) => {
+                    let device = device_from_renderer(&wayland.renderer)
+                        .context("Failed to find DrmNode")
+                        .map_err(|err| CaptureError::Permanent(err.into()))?;
+                    capture_workspace(device, &mut wayland.renderer, &output, workspace, &mut self.common)
+                },
+                BackendData::Headless(ref mut headless) => {
+                    let renderer = headless.renderer.as_mut()
+                        .ok_or(CaptureError::Permanent(anyhow!("Headless backend runs without a renderer").into()))?;
+                    let device = device_from_renderer(renderer)
+                        .context("Failed to find DrmNode")
+                        .map_err(|err| CaptureError::Permanent(err.into()))?;
+                    capture_workspace(device, renderer, &output, workspace, &mut self.common)
+                },
                 BackendData::Kms(ref mut kms) => {
+                    // dmabufs can't be exported from the software renderer
                     let node = kms.target_node_for_output(&output)
-                        .unwrap_or(kms.primary)
-                        .node_with_type(NodeType::Render)
-                        .with_context(|| "Unable to find node")
-                        .map_err(|x| CaptureError::Permanent(x.into()))?
+                        .or(kms.primary)
+                        .with_context(|| "Exporting dmabufs requires hardware-acceleration")
                         .map_err(|x| CaptureError::Permanent(x.into()))?;
                     let mut renderer = kms.api.renderer::<Gles2Renderbuffer>(&node, &node)
                         .with_context(|| format!("Failed to optain renderer for {:?}", node))
@@ -198,6 +214,10 @@ impl ExportDmabufHandler for State {
         let device = match self.backend {
             BackendData::Winit(ref mut winit) => device_from_renderer(winit.backend.renderer()),
             BackendData::X11(ref x11) => device_from_renderer(&x11.renderer),
+            BackendData::Wayland(ref wayland) => device_from_renderer(&wayland.renderer),
+            BackendData::Headless(ref headless) => headless.renderer.as_ref()
+                .ok_or(anyhow!("Headless backend runs without a renderer"))
+                .and_then(device_from_renderer),
             BackendData::Kms(ref kms) => Ok(dh.get_client(window.toplevel().wl_surface().id())
                 .ok()
                 .with_context(|| "Unable to find matching wayland client")
@@ -206,7 +226,9 @@ impl ExportDmabufHandler for State {
                 .unwrap()
                 .drm_node
                 .clone()
-                .unwrap_or_else(|| kms.primary.clone())),
+                .or(kms.primary)
+                .with_context(|| "Exporting dmabufs requires hardware-acceleration")
+                .map_err(|x| CaptureError::Permanent(x.into()))?),
             _ => unreachable!(),
         }
             .context("Failed to find DrmNode")
@@ -229,6 +251,9 @@ impl ExportDmabufHandler for State {
         let renderer = match self.backend {
             BackendData::Winit(ref mut winit) => winit.backend.renderer(),
             BackendData::X11(ref mut x11) => &mut x11.renderer,
+            BackendData::Wayland(ref mut wayland) => &mut wayland.renderer,
+            // we found a device above, so there is a renderer
+            BackendData::Headless(ref mut headless) => headless.renderer.as_mut().unwrap(),
             BackendData::Kms(ref mut kms) => {
                 _tmp_multirenderer = Some(kms.api.renderer::<Gles2Renderbuffer>(&device, &device)
                     .with_context(|| format!("Failed to optain renderer for {:?}", device))
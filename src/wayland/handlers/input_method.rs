@@ -0,0 +1,30 @@
+// SPDX-License-Identifier: GPL-3.0-only
+
+use crate::{
+    config::XkbConfig,
+    state::State,
+    wayland::protocols::{
+        input_method::{delegate_input_method, InputMethodHandler, InputMethodState},
+        text_input::TextChanges,
+    },
+};
+
+impl InputMethodHandler for State {
+    fn input_method_state(&mut self) -> &mut InputMethodState {
+        &mut self.common.shell.input_method_state
+    }
+
+    fn input_method_xkb_config(&self) -> XkbConfig {
+        self.common.config.xkb_config()
+    }
+
+    fn input_method_commit(&mut self, changes: TextChanges) {
+        self.common.shell.text_input_state.apply_changes(&changes);
+    }
+
+    fn input_popups_changed(&mut self) {
+        self.common.shell.update_input_popups();
+    }
+}
+
+delegate_input_method!(State);
@@ -0,0 +1,17 @@
+// SPDX-License-Identifier: GPL-3.0-only
+
+use crate::{
+    state::State,
+    wayland::protocols::keyboard_shortcuts_inhibit::{
+        delegate_keyboard_shortcuts_inhibit, KeyboardShortcutsInhibitHandler,
+        KeyboardShortcutsInhibitState,
+    },
+};
+
+impl KeyboardShortcutsInhibitHandler for State {
+    fn keyboard_shortcuts_inhibit_state(&mut self) -> &mut KeyboardShortcutsInhibitState {
+        &mut self.common.shell.keyboard_shortcuts_inhibit_state
+    }
+}
+
+delegate_keyboard_shortcuts_inhibit!(State);
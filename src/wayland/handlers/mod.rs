@@ -5,15 +5,25 @@ pub mod compositor;
 pub mod data_device;
 pub mod dmabuf;
 pub mod export_dmabuf;
+pub mod input_method;
+pub mod keyboard_shortcuts_inhibit;
 pub mod layer_shell;
 pub mod output;
 pub mod output_configuration;
+pub mod pointer_constraints;
+pub mod pointer_gestures;
 pub mod primary_selection;
+pub mod relative_pointer;
 pub mod seat;
+pub mod security_context;
 pub mod shm;
+pub mod tablet_manager;
+pub mod text_input;
 pub mod toplevel_info;
 pub mod toplevel_management;
 pub mod viewporter;
+pub mod virtual_keyboard;
+pub mod virtual_pointer;
 pub mod wl_drm;
 pub mod workspace;
 pub mod xdg_shell;
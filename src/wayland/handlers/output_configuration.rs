@@ -4,6 +4,7 @@ use smithay::wayland::output::Output;
 
 use crate::{
     config::OutputConfig,
+    ipc::{ConfigSection, Event},
     state::State,
     wayland::protocols::output_configuration::{
         delegate_output_configuration, ModeConfiguration, OutputConfiguration,
@@ -137,6 +138,9 @@ impl State {
         self.common
             .config
             .write_outputs(self.common.output_configuration_state.outputs());
+        self.common.shell.ipc_events.push(Event::ConfigChanged {
+            section: ConfigSection::Outputs,
+        });
         self.common.event_loop_handle.insert_idle(move |data| {
             data.state.common.output_configuration_state.update();
         });
@@ -0,0 +1,16 @@
+// SPDX-License-Identifier: GPL-3.0-only
+
+use crate::{
+    state::State,
+    wayland::protocols::pointer_gestures::{
+        delegate_pointer_gestures, PointerGesturesHandler, PointerGesturesState,
+    },
+};
+
+impl PointerGesturesHandler for State {
+    fn pointer_gestures_state(&mut self) -> &mut PointerGesturesState {
+        &mut self.common.pointer_gestures_state
+    }
+}
+
+delegate_pointer_gestures!(State);
@@ -0,0 +1,16 @@
+// SPDX-License-Identifier: GPL-3.0-only
+
+use crate::{
+    state::State,
+    wayland::protocols::relative_pointer::{
+        delegate_relative_pointer, RelativePointerHandler, RelativePointerState,
+    },
+};
+
+impl RelativePointerHandler for State {
+    fn relative_pointer_state(&mut self) -> &mut RelativePointerState {
+        &mut self.common.relative_pointer_state
+    }
+}
+
+delegate_relative_pointer!(State);
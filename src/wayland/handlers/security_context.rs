@@ -0,0 +1,80 @@
+// SPDX-License-Identifier: GPL-3.0-only
+
+use crate::{
+    state::{Data, State},
+    wayland::protocols::security_context::{
+        delegate_security_context, SecurityContext, SecurityContextHandler,
+    },
+};
+use smithay::reexports::calloop::{generic::Generic, Interest, Mode, PostAction};
+use std::{fs::File, io::ErrorKind, os::unix::net::UnixListener, sync::Arc};
+
+impl SecurityContextHandler for State {
+    fn context_committed(
+        &mut self,
+        listener: UnixListener,
+        close_fd: File,
+        context: SecurityContext,
+    ) {
+        slog_scope::info!("Adding security context for {}", context);
+
+        let listener_context = context.clone();
+        let listener_token = match self.common.event_loop_handle.insert_source(
+            Generic::new(listener, Interest::READ, Mode::Level),
+            move |_, listener, data: &mut Data| {
+                loop {
+                    match listener.accept() {
+                        Ok((stream, _)) => {
+                            let client_state = data
+                                .state
+                                .new_sandboxed_client_state(listener_context.clone());
+                            if let Err(err) = data
+                                .display
+                                .handle()
+                                .insert_client(stream, Arc::new(client_state))
+                            {
+                                slog_scope::warn!(
+                                    "Error adding sandboxed wayland client ({}): {}",
+                                    listener_context,
+                                    err
+                                );
+                            }
+                        }
+                        Err(err) if err.kind() == ErrorKind::WouldBlock => break,
+                        Err(err) => {
+                            slog_scope::warn!(
+                                "Error accepting sandboxed wayland client ({}): {}",
+                                listener_context,
+                                err
+                            );
+                            break;
+                        }
+                    }
+                }
+                Ok(PostAction::Continue)
+            },
+        ) {
+            Ok(token) => token,
+            Err(err) => {
+                slog_scope::warn!("Failed to add security context socket: {}", err);
+                return;
+            }
+        };
+
+        // the sandbox engine closes its end once no new clients should be accepted,
+        // clients that are already connected stay around.
+        if let Err(err) = self.common.event_loop_handle.insert_source(
+            Generic::new(close_fd, Interest::READ, Mode::Level),
+            move |_, _, data: &mut Data| {
+                slog_scope::info!("Removing security context for {}", context);
+                data.state.common.event_loop_handle.remove(listener_token);
+                Ok(PostAction::Remove)
+            },
+        ) {
+            slog_scope::warn!("Failed to watch security context close fd: {}", err);
+            self.common.event_loop_handle.remove(listener_token);
+        }
+    }
+}
+
+delegate_security_context!(State);
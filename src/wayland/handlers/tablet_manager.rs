@@ -0,0 +1,48 @@
+// SPDX-License-Identifier: GPL-3.0-only
+
+use crate::state::State;
+use smithay::{
+    delegate_tablet_manager,
+    reexports::{
+        wayland_protocols::wp::tablet::zv2::server::zwp_tablet_manager_v2::ZwpTabletManagerV2,
+        wayland_server::{backend::GlobalId, Client, DataInit, DisplayHandle, GlobalDispatch, New},
+    },
+};
+
+pub struct TabletManagerGlobalData {
+    filter: Box<dyn for<'a> Fn(&'a Client) -> bool + Send + Sync>,
+}
+
+/// Creates the tablet manager global in place of smithays `TabletManagerState`,
+/// which has no way to hide it from clients. Everything else is still handled by smithay.
+pub fn create_tablet_manager_global<F>(dh: &DisplayHandle, client_filter: F) -> GlobalId
+where
+    F: for<'a> Fn(&'a Client) -> bool + Send + Sync + 'static,
+{
+    dh.create_global::<State, ZwpTabletManagerV2, _>(
+        1,
+        TabletManagerGlobalData {
+            filter: Box::new(client_filter),
+        },
+    )
+}
+
+impl GlobalDispatch<ZwpTabletManagerV2, TabletManagerGlobalData> for State {
+    fn bind(
+        _state: &mut State,
+        _dh: &DisplayHandle,
+        _client: &Client,
+        resource: New<ZwpTabletManagerV2>,
+        _global_data: &TabletManagerGlobalData,
+        data_init: &mut DataInit<'_, State>,
+    ) {
+        // the manager carries no user data in smithay, which dispatches its requests
+        data_init.init(resource, ());
+    }
+
+    fn can_view(client: Client, global_data: &TabletManagerGlobalData) -> bool {
+        (global_data.filter)(&client)
+    }
+}
+
+delegate_tablet_manager!(State);
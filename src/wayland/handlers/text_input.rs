@@ -0,0 +1,25 @@
+// SPDX-License-Identifier: GPL-3.0-only
+
+use crate::{
+    state::State,
+    wayland::protocols::text_input::{
+        delegate_text_input, TextInputHandler, TextInputState, TextInputUpdate,
+    },
+};
+
+impl TextInputHandler for State {
+    fn text_input_state(&mut self) -> &mut TextInputState {
+        &mut self.common.shell.text_input_state
+    }
+
+    fn text_input_updated(&mut self, update: TextInputUpdate) {
+        self.common
+            .shell
+            .input_method_state
+            .text_input_updated(&update);
+        // the text cursor might have moved
+        self.common.shell.update_input_popups();
+    }
+}
+
+delegate_text_input!(State);
@@ -0,0 +1,27 @@
+// SPDX-License-Identifier: GPL-3.0-only
+
+use crate::{
+    config::XkbConfig,
+    input::VirtualInput,
+    state::State,
+    wayland::protocols::virtual_keyboard::{
+        delegate_virtual_keyboard, VirtualKeyboardHandler, VirtualKeyboardState,
+    },
+};
+use smithay::{backend::input::InputEvent, reexports::wayland_server::DisplayHandle};
+
+impl VirtualKeyboardHandler for State {
+    fn virtual_keyboard_state(&mut self) -> &mut VirtualKeyboardState {
+        &mut self.common.virtual_keyboard_state
+    }
+
+    fn virtual_keyboard_xkb_config(&self) -> XkbConfig {
+        self.common.config.xkb_config()
+    }
+
+    fn virtual_keyboard_event(&mut self, dh: &DisplayHandle, event: InputEvent<VirtualInput>) {
+        self.process_input_event(dh, event);
+    }
+}
+
+delegate_virtual_keyboard!(State);
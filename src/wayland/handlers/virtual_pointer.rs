@@ -0,0 +1,38 @@
+// SPDX-License-Identifier: GPL-3.0-only
+
+use crate::{
+    input::{Devices, VirtualInput},
+    state::State,
+    utils::prelude::*,
+    wayland::protocols::virtual_pointer::{
+        delegate_virtual_pointer, VirtualPointerHandler, VirtualPointerState,
+    },
+};
+use smithay::{
+    backend::input::{Event, InputEvent},
+    reexports::wayland_server::DisplayHandle,
+};
+
+impl VirtualPointerHandler for State {
+    fn virtual_pointer_state(&mut self) -> &mut VirtualPointerState {
+        &mut self.common.virtual_pointer_state
+    }
+
+    fn virtual_pointer_event(&mut self, dh: &DisplayHandle, event: InputEvent<VirtualInput>) {
+        // absolute positions of pointers bound to an output are relative to that output
+        if let InputEvent::PointerMotionAbsolute { event } = &event {
+            if let Some(output) = event.output.as_ref() {
+                let device = event.device();
+                for seat in self.common.seats.iter() {
+                    let devices = seat.user_data().get::<Devices>().unwrap();
+                    if devices.has_device(&device) {
+                        set_active_output(seat, output);
+                    }
+                }
+            }
+        }
+        self.process_input_event(dh, event);
+    }
+}
+
+delegate_virtual_pointer!(State);
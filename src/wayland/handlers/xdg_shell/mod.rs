@@ -1,7 +1,12 @@
 // SPDX-License-Identifier: GPL-3.0-only
 
-use crate::utils::prelude::*;
+use crate::{
+    input::{TouchPoint, TouchPoints},
+    shell::grabs::SeatMoveGrabState,
+    utils::prelude::*,
+};
 use smithay::{
+    backend::input::TouchSlot,
     delegate_xdg_shell,
     desktop::{
         Kind, PopupGrab, PopupKeyboardGrab, PopupKind, PopupPointerGrab, PopupUngrabStrategy,
@@ -182,6 +187,23 @@ impl XdgShellHandler for State {
                 .clone();
 
             self.common.shell.move_request(&window, &seat, serial, start_data);
+        } else if let Some((slot, point)) =
+            check_touch_grab_preconditions(&seat, surface.wl_surface(), serial)
+        {
+            let workspace = self
+                .common
+                .shell
+                .space_for_window_mut(surface.wl_surface())
+                .unwrap();
+            let window = workspace
+                .space
+                .window_for_surface(surface.wl_surface(), WindowSurfaceType::TOPLEVEL)
+                .unwrap()
+                .clone();
+
+            self.common
+                .shell
+                .touch_move_request(&window, &seat, slot, point.location);
         }
     }
 
@@ -309,4 +331,40 @@ fn check_grab_preconditions(
     Some(start_data)
 }
 
+fn check_touch_grab_preconditions(
+    seat: &Seat<State>,
+    surface: &WlSurface,
+    serial: Serial,
+) -> Option<(TouchSlot, TouchPoint)> {
+    use smithay::reexports::wayland_server::Resource;
+
+    // Check that this surface has an active touch-point started with the given serial.
+    let (slot, point) = seat
+        .user_data()
+        .get::<TouchPoints>()
+        .and_then(|points| points.find_serial(serial))?;
+
+    // If the focus was for a different surface, ignore the request.
+    if !point
+        .focus
+        .as_ref()
+        .map(|focus| focus.id().same_client_as(&surface.id()))
+        .unwrap_or(false)
+    {
+        return None;
+    }
+
+    // Only one move at a time
+    if seat
+        .user_data()
+        .get::<SeatMoveGrabState>()
+        .map(|state| state.borrow().is_some())
+        .unwrap_or(false)
+    {
+        return None;
+    }
+
+    Some((slot, point))
+}
+
 delegate_xdg_shell!(State);
@@ -3,8 +3,8 @@
 use crate::{shell::Shell, utils::prelude::*};
 use smithay::{
     desktop::{
-        layer_map_for_output, LayerSurface, PopupKind, PopupManager, Space, Window,
-        WindowSurfaceType,
+        layer_map_for_output, utils::bbox_from_surface_tree, LayerSurface, PopupKind,
+        PopupManager, Space, Window, WindowSurfaceType,
     },
     reexports::{
         wayland_protocols::xdg::shell::server::xdg_positioner::{
@@ -12,7 +12,7 @@ use smithay::{
         },
         wayland_server::protocol::wl_surface::WlSurface,
     },
-    utils::{Logical, Point, Rectangle},
+    utils::{Logical, Point, Rectangle, Size},
     wayland::{
         compositor::{get_role, with_states},
         output::Output,
@@ -48,6 +48,53 @@ impl Shell {
             update_reactive_popups(&workspace.space, window);
         }
     }
+
+    /// Positions the input method popups next to the text cursor of the focused text-input.
+    pub fn update_input_popups(&mut self) {
+        let parent = match self.text_input_state.focus() {
+            Some(parent) => parent.clone(),
+            None => return,
+        };
+        let cursor = self
+            .text_input_state
+            .cursor_rectangle()
+            .unwrap_or_else(|| Rectangle::from_loc_and_size((0, 0), (0, 0)));
+
+        // the output containing the cursor represented relative to the parents surface
+        let bounds = if let Some(workspace) = self.space_for_window(&parent) {
+            let window = workspace
+                .space
+                .window_for_surface(&parent, WindowSurfaceType::ALL)
+                .unwrap();
+            let origin = workspace.space.window_location(window).unwrap() - window.geometry().loc;
+            workspace
+                .space
+                .outputs_for_window(window)
+                .into_iter()
+                .filter_map(|o| workspace.space.output_geometry(&o))
+                .find(|geo| geo.contains(origin + cursor.loc))
+                .map(|mut relative| {
+                    relative.loc -= origin;
+                    relative
+                })
+        } else {
+            self.outputs().find_map(|o| {
+                let map = layer_map_for_output(o);
+                let layer_surface = map.layer_for_surface(&parent, WindowSurfaceType::ALL)?;
+                let mut relative = Rectangle::from_loc_and_size((0, 0), o.geometry().size);
+                relative.loc -= map.layer_geometry(layer_surface)?.loc;
+                Some(relative)
+            })
+        };
+
+        if let Some(bounds) = bounds {
+            for popup in self.input_method_state.popups_mut() {
+                let size = bbox_from_surface_tree(popup.surface(), (0, 0)).size;
+                let location = unconstrain_input_popup(cursor, size, bounds);
+                popup.set_location(location, cursor);
+            }
+        }
+    }
 }
 
 pub fn update_reactive_popups(space: &Space, window: &Window) {
@@ -130,6 +177,34 @@ fn unconstrain_layer_popup(
     }
 }
 
+/// Places an input method popup below the text cursor, flipping it above
+/// or sliding it along the output edge, if it would be constrained otherwise.
+fn unconstrain_input_popup(
+    cursor: Rectangle<i32, Logical>,
+    size: Size<i32, Logical>,
+    toplevel_box: Rectangle<i32, Logical>,
+) -> Point<i32, Logical> {
+    let mut positioner = PositionerState {
+        rect_size: size,
+        anchor_rect: cursor,
+        anchor_edges: Anchor::BottomLeft,
+        gravity: Gravity::BottomRight,
+        ..Default::default()
+    };
+
+    let mut geometry = positioner.get_geometry();
+    if constrained_offset(geometry, toplevel_box).y != 0 {
+        positioner.anchor_edges = invert_anchor_y(positioner.anchor_edges);
+        positioner.gravity = invert_gravity_y(positioner.gravity);
+        let flipped = positioner.get_geometry();
+        if constrained_offset(flipped, toplevel_box).y == 0 {
+            geometry = flipped;
+        }
+    }
+
+    geometry.loc + constrained_offset(geometry, toplevel_box)
+}
+
 fn unconstrain_flip(
     popup: &PopupSurface,
     positioner: &PositionerState,
@@ -268,7 +343,13 @@ fn check_constrained(
         geometry.loc + get_popup_toplevel_coords(popup),
         geometry.size,
     );
+    constrained_offset(relative_coords, toplevel_box)
+}
 
+fn constrained_offset(
+    relative_coords: Rectangle<i32, Logical>,
+    toplevel_box: Rectangle<i32, Logical>,
+) -> Point<i32, Logical> {
     let mut offset = (0, 0).into();
     if toplevel_box.contains_rect(relative_coords) {
         return offset;
@@ -0,0 +1,599 @@
+// SPDX-License-Identifier: GPL-3.0-only
+
+// Re-export only the actual code, and then only use this re-export
+// The `generated` module below is just some boilerplate to properly isolate stuff
+// and avoid exposing internal details.
+pub use generated::{
+    zwp_input_method_keyboard_grab_v2, zwp_input_method_manager_v2, zwp_input_method_v2,
+    zwp_input_popup_surface_v2,
+};
+
+mod generated {
+    use smithay::reexports::{
+        wayland_protocols::wp::text_input::zv3::server::*,
+        wayland_server::{self, protocol::*},
+    };
+
+    pub mod __interfaces {
+        use smithay::reexports::wayland_server::protocol::__interfaces::*;
+        use wayland_backend;
+        wayland_scanner::generate_interfaces!("resources/protocols/input-method-unstable-v2.xml");
+    }
+    use self::__interfaces::*;
+
+    wayland_scanner::generate_server_code!("resources/protocols/input-method-unstable-v2.xml");
+}
+
+use self::{
+    zwp_input_method_keyboard_grab_v2::ZwpInputMethodKeyboardGrabV2,
+    zwp_input_method_manager_v2::ZwpInputMethodManagerV2, zwp_input_method_v2::ZwpInputMethodV2,
+    zwp_input_popup_surface_v2::ZwpInputPopupSurfaceV2,
+};
+use crate::{
+    config::XkbConfig,
+    wayland::protocols::text_input::{TextChanges, TextInputUpdate},
+};
+use smithay::{
+    backend::input::KeyState,
+    reexports::wayland_server::{
+        backend::{ClientId, GlobalId, ObjectId},
+        protocol::{
+            wl_keyboard::{KeyState as WlKeyState, KeymapFormat},
+            wl_surface::WlSurface,
+        },
+        Client, DataInit, Dispatch, DisplayHandle, GlobalDispatch, New, Resource,
+    },
+    utils::{Logical, Point, Rectangle},
+    wayland::{compositor::give_role, Serial},
+};
+use std::{fs::File, io::Write, os::unix::io::AsRawFd};
+use xkbcommon::xkb;
+
+pub const INPUT_POPUP_ROLE: &str = "zwp_input_popup_surface_v2";
+
+/// State of the input-method global.
+///
+/// As we only ever expose a single seat, there is at most one usable input method.
+pub struct InputMethodState {
+    input_method: Option<InputMethod>,
+    repeat_delay: i32,
+    repeat_rate: i32,
+    global: GlobalId,
+}
+
+pub trait InputMethodHandler {
+    fn input_method_state(&mut self) -> &mut InputMethodState;
+    /// Current keyboard configuration, new keyboard grabs compile their keymap from it.
+    fn input_method_xkb_config(&self) -> XkbConfig;
+    /// The input method committed text changes for the focused text-input.
+    fn input_method_commit(&mut self, changes: TextChanges);
+    /// The popups of the input method need to be (re-)positioned.
+    fn input_popups_changed(&mut self);
+}
+
+pub struct InputMethodGlobalData {
+    filter: Box<dyn for<'a> Fn(&'a Client) -> bool + Send + Sync>,
+}
+
+#[derive(Debug)]
+struct InputMethod {
+    object: ZwpInputMethodV2,
+    active: bool,
+    pending: TextChanges,
+    keyboard_grab: Option<KeyboardGrab>,
+    popups: Vec<InputPopup>,
+}
+
+struct KeyboardGrab {
+    object: ZwpInputMethodKeyboardGrabV2,
+    state: xkb::State,
+    // the client might map the keymap at any time
+    _keymap_file: File,
+}
+
+impl std::fmt::Debug for KeyboardGrab {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("KeyboardGrab")
+            .field("object", &self.object)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Popup surface of the input method, shown next to the text cursor
+#[derive(Debug)]
+pub struct InputPopup {
+    object: ZwpInputPopupSurfaceV2,
+    surface: WlSurface,
+    location: Point<i32, Logical>,
+    cursor: Option<Rectangle<i32, Logical>>,
+}
+
+impl InputPopup {
+    pub fn surface(&self) -> &WlSurface {
+        &self.surface
+    }
+
+    /// Location of the popup relative to the focused text-input surface
+    pub fn location(&self) -> Point<i32, Logical> {
+        self.location
+    }
+
+    /// Moves the popup and tells the input method, where the text cursor is relative to it.
+    pub fn set_location(&mut self, location: Point<i32, Logical>, cursor: Rectangle<i32, Logical>) {
+        // don't bother the client, if nothing changed
+        if self.location == location && self.cursor == Some(cursor) {
+            return;
+        }
+        self.location = location;
+        self.cursor = Some(cursor);
+        self.object.text_input_rectangle(
+            cursor.loc.x - location.x,
+            cursor.loc.y - location.y,
+            cursor.size.w,
+            cursor.size.h,
+        );
+    }
+}
+
+impl InputMethodState {
+    pub fn new<D, F>(
+        dh: &DisplayHandle,
+        repeat_delay: i32,
+        repeat_rate: i32,
+        client_filter: F,
+    ) -> InputMethodState
+    where
+        D: GlobalDispatch<ZwpInputMethodManagerV2, InputMethodGlobalData>
+            + Dispatch<ZwpInputMethodManagerV2, ()>
+            + Dispatch<ZwpInputMethodV2, ()>
+            + Dispatch<ZwpInputMethodKeyboardGrabV2, ()>
+            + Dispatch<ZwpInputPopupSurfaceV2, ()>
+            + InputMethodHandler
+            + 'static,
+        F: for<'a> Fn(&'a Client) -> bool + Send + Sync + 'static,
+    {
+        let global = dh.create_global::<D, ZwpInputMethodManagerV2, _>(
+            1,
+            InputMethodGlobalData {
+                filter: Box::new(client_filter),
+            },
+        );
+        InputMethodState {
+            input_method: None,
+            repeat_delay,
+            repeat_rate,
+            global,
+        }
+    }
+
+    pub fn global_id(&self) -> GlobalId {
+        self.global.clone()
+    }
+
+    /// Forwards state changes of the focused text-input to the input method.
+    pub fn text_input_updated(&mut self, update: &TextInputUpdate) {
+        let input_method = match self.input_method.as_mut() {
+            Some(input_method) => input_method,
+            None => return,
+        };
+
+        let content = match update {
+            TextInputUpdate::Enabled(content) => {
+                input_method.object.activate();
+                input_method.active = true;
+                content
+            }
+            TextInputUpdate::Changed(content) => content,
+            TextInputUpdate::Disabled => {
+                input_method.object.deactivate();
+                input_method.active = false;
+                input_method.object.done();
+                return;
+            }
+        };
+
+        if let Some((text, cursor, anchor)) = content.surrounding_text.clone() {
+            input_method.object.surrounding_text(text, cursor, anchor);
+        }
+        if let Some(cause) = content.change_cause {
+            input_method.object.text_change_cause(cause);
+        }
+        if let Some((hint, purpose)) = content.content_type {
+            input_method.object.content_type(hint, purpose);
+        }
+        input_method.object.done();
+    }
+
+    /// Popups of the input method, that should currently be shown.
+    pub fn popups(&self) -> impl Iterator<Item = &InputPopup> {
+        self.input_method
+            .iter()
+            .filter(|input_method| input_method.active)
+            .flat_map(|input_method| input_method.popups.iter())
+    }
+
+    pub fn popups_mut(&mut self) -> impl Iterator<Item = &mut InputPopup> {
+        self.input_method
+            .iter_mut()
+            .flat_map(|input_method| input_method.popups.iter_mut())
+    }
+
+    pub fn is_popup(&self, surface: &WlSurface) -> bool {
+        self.input_method
+            .iter()
+            .flat_map(|input_method| input_method.popups.iter())
+            .any(|popup| &popup.surface == surface)
+    }
+
+    /// Whether the given object belongs to the client of the input method.
+    pub fn same_client_as(&self, id: &ObjectId) -> bool {
+        self.input_method
+            .as_ref()
+            .map(|input_method| input_method.object.id().same_client_as(id))
+            .unwrap_or(false)
+    }
+
+    /// Forwards a key event to the input method, if it grabbed the keyboard.
+    ///
+    /// Returns `true` if the key was consumed by the grab.
+    pub fn keyboard_grab_key(
+        &mut self,
+        serial: Serial,
+        time: u32,
+        keycode: u32,
+        state: KeyState,
+    ) -> bool {
+        let grab = match self
+            .input_method
+            .as_mut()
+            .and_then(|input_method| input_method.keyboard_grab.as_mut())
+        {
+            Some(grab) => grab,
+            None => return false,
+        };
+
+        let (direction, wl_state) = match state {
+            KeyState::Pressed => (xkb::KeyDirection::Down, WlKeyState::Pressed),
+            KeyState::Released => (xkb::KeyDirection::Up, WlKeyState::Released),
+        };
+        let serial = u32::from(serial);
+        grab.object.key(serial, time, keycode, wl_state);
+        // xkb keycodes are offset by 8 from evdev ones
+        if grab.state.update_key(keycode + 8, direction) != 0 {
+            grab.object.modifiers(
+                serial,
+                grab.state.serialize_mods(xkb::STATE_MODS_DEPRESSED),
+                grab.state.serialize_mods(xkb::STATE_MODS_LATCHED),
+                grab.state.serialize_mods(xkb::STATE_MODS_LOCKED),
+                grab.state.serialize_layout(xkb::STATE_LAYOUT_EFFECTIVE),
+            );
+        }
+        true
+    }
+
+    fn keyboard_grab(
+        &self,
+        object: ZwpInputMethodKeyboardGrabV2,
+        xkb_config: &XkbConfig,
+    ) -> std::io::Result<KeyboardGrab> {
+        // compiled for every grab, so changes of the configuration reach the next grab
+        let context = xkb::Context::new(xkb::CONTEXT_NO_FLAGS);
+        let keymap = xkb_config.compile_keymap(&context).ok_or_else(|| {
+            std::io::Error::new(std::io::ErrorKind::InvalidData, "Invalid keymap")
+        })?;
+        let keymap_string = keymap.get_as_string(xkb::KEYMAP_FORMAT_TEXT_V1);
+        let file = keymap_file(&keymap_string)?;
+
+        object.keymap(
+            KeymapFormat::XkbV1,
+            file.as_raw_fd(),
+            keymap_string.len() as u32 + 1,
+        );
+        object.repeat_info(self.repeat_rate, self.repeat_delay);
+
+        Ok(KeyboardGrab {
+            object,
+            state: xkb::State::new(&keymap),
+            _keymap_file: file,
+        })
+    }
+}
+
+/// Writes the keymap into an anonymous file, that can be shared with clients.
+fn keymap_file(keymap: &str) -> std::io::Result<File> {
+    let mut file = tempfile::tempfile()?;
+    file.write_all(keymap.as_bytes())?;
+    file.write_all(&[0])?;
+    file.flush()?;
+    Ok(file)
+}
+
+impl<D> GlobalDispatch<ZwpInputMethodManagerV2, InputMethodGlobalData, D> for InputMethodState
+where
+    D: GlobalDispatch<ZwpInputMethodManagerV2, InputMethodGlobalData>
+        + Dispatch<ZwpInputMethodManagerV2, ()>
+        + Dispatch<ZwpInputMethodV2, ()>
+        + Dispatch<ZwpInputMethodKeyboardGrabV2, ()>
+        + Dispatch<ZwpInputPopupSurfaceV2, ()>
+        + InputMethodHandler
+        + 'static,
+{
+    fn bind(
+        _state: &mut D,
+        _dh: &DisplayHandle,
+        _client: &Client,
+        resource: New<ZwpInputMethodManagerV2>,
+        _global_data: &InputMethodGlobalData,
+        data_init: &mut DataInit<'_, D>,
+    ) {
+        data_init.init(resource, ());
+    }
+
+    fn can_view(client: Client, global_data: &InputMethodGlobalData) -> bool {
+        (global_data.filter)(&client)
+    }
+}
+
+impl<D> Dispatch<ZwpInputMethodManagerV2, (), D> for InputMethodState
+where
+    D: GlobalDispatch<ZwpInputMethodManagerV2, InputMethodGlobalData>
+        + Dispatch<ZwpInputMethodManagerV2, ()>
+        + Dispatch<ZwpInputMethodV2, ()>
+        + Dispatch<ZwpInputMethodKeyboardGrabV2, ()>
+        + Dispatch<ZwpInputPopupSurfaceV2, ()>
+        + InputMethodHandler
+        + 'static,
+{
+    fn request(
+        state: &mut D,
+        _client: &Client,
+        _obj: &ZwpInputMethodManagerV2,
+        request: zwp_input_method_manager_v2::Request,
+        _data: &(),
+        _dh: &DisplayHandle,
+        data_init: &mut DataInit<'_, D>,
+    ) {
+        match request {
+            zwp_input_method_manager_v2::Request::GetInputMethod { input_method, .. } => {
+                let input_method = data_init.init(input_method, ());
+                let input_method_state = state.input_method_state();
+                if input_method_state.input_method.is_some() {
+                    input_method.unavailable();
+                    return;
+                }
+                input_method_state.input_method = Some(InputMethod {
+                    object: input_method,
+                    active: false,
+                    pending: TextChanges::default(),
+                    keyboard_grab: None,
+                    popups: Vec::new(),
+                });
+            }
+            zwp_input_method_manager_v2::Request::Destroy => {}
+            _ => unreachable!(),
+        }
+    }
+}
+
+impl<D> Dispatch<ZwpInputMethodV2, (), D> for InputMethodState
+where
+    D: GlobalDispatch<ZwpInputMethodManagerV2, InputMethodGlobalData>
+        + Dispatch<ZwpInputMethodManagerV2, ()>
+        + Dispatch<ZwpInputMethodV2, ()>
+        + Dispatch<ZwpInputMethodKeyboardGrabV2, ()>
+        + Dispatch<ZwpInputPopupSurfaceV2, ()>
+        + InputMethodHandler
+        + 'static,
+{
+    fn request(
+        state: &mut D,
+        _client: &Client,
+        obj: &ZwpInputMethodV2,
+        request: zwp_input_method_v2::Request,
+        _data: &(),
+        _dh: &DisplayHandle,
+        data_init: &mut DataInit<'_, D>,
+    ) {
+        let xkb_config = match request {
+            zwp_input_method_v2::Request::GrabKeyboard { .. } => {
+                Some(state.input_method_xkb_config())
+            }
+            _ => None,
+        };
+        let input_method_state = state.input_method_state();
+        let is_current = input_method_state
+            .input_method
+            .as_ref()
+            .map(|input_method| &input_method.object == obj)
+            .unwrap_or(false);
+
+        match request {
+            zwp_input_method_v2::Request::GetInputPopupSurface { id, surface } => {
+                let popup = data_init.init(id, ());
+                if give_role(&surface, INPUT_POPUP_ROLE).is_err() {
+                    obj.post_error(0u32, "Surface already has a role");
+                    return;
+                }
+                if let Some(input_method) = input_method_state
+                    .input_method
+                    .as_mut()
+                    .filter(|_| is_current)
+                {
+                    input_method.popups.push(InputPopup {
+                        object: popup,
+                        surface,
+                        location: (0, 0).into(),
+                        cursor: None,
+                    });
+                    state.input_popups_changed();
+                }
+            }
+            zwp_input_method_v2::Request::GrabKeyboard { keyboard } => {
+                let keyboard = data_init.init(keyboard, ());
+                if !is_current {
+                    return;
+                }
+                match input_method_state.keyboard_grab(keyboard, xkb_config.as_ref().unwrap()) {
+                    Ok(grab) => {
+                        input_method_state
+                            .input_method
+                            .as_mut()
+                            .unwrap()
+                            .keyboard_grab = Some(grab)
+                    }
+                    Err(err) => {
+                        slog_scope::warn!("Failed to grab keyboard for input method: {}", err)
+                    }
+                }
+            }
+            // inert input methods are ignored
+            _ if !is_current => {}
+            zwp_input_method_v2::Request::CommitString { text } => {
+                input_method_state
+                    .input_method
+                    .as_mut()
+                    .unwrap()
+                    .pending
+                    .commit_string = Some(text);
+            }
+            zwp_input_method_v2::Request::SetPreeditString {
+                text,
+                cursor_begin,
+                cursor_end,
+            } => {
+                input_method_state
+                    .input_method
+                    .as_mut()
+                    .unwrap()
+                    .pending
+                    .preedit = Some((text, cursor_begin, cursor_end));
+            }
+            zwp_input_method_v2::Request::DeleteSurroundingText {
+                before_length,
+                after_length,
+            } => {
+                input_method_state
+                    .input_method
+                    .as_mut()
+                    .unwrap()
+                    .pending
+                    .delete_surrounding_text = Some((before_length, after_length));
+            }
+            zwp_input_method_v2::Request::Commit { .. } => {
+                let changes =
+                    std::mem::take(&mut input_method_state.input_method.as_mut().unwrap().pending);
+                state.input_method_commit(changes);
+            }
+            zwp_input_method_v2::Request::Destroy => {}
+            _ => unreachable!(),
+        }
+    }
+
+    fn destroyed(state: &mut D, _client: ClientId, resource: ObjectId, _data: &()) {
+        let input_method_state = state.input_method_state();
+        if input_method_state
+            .input_method
+            .as_ref()
+            .map(|input_method| input_method.object.id() == resource)
+            .unwrap_or(false)
+        {
+            input_method_state.input_method = None;
+            state.input_popups_changed();
+        }
+    }
+}
+
+impl<D> Dispatch<ZwpInputMethodKeyboardGrabV2, (), D> for InputMethodState
+where
+    D: GlobalDispatch<ZwpInputMethodManagerV2, InputMethodGlobalData>
+        + Dispatch<ZwpInputMethodManagerV2, ()>
+        + Dispatch<ZwpInputMethodV2, ()>
+        + Dispatch<ZwpInputMethodKeyboardGrabV2, ()>
+        + Dispatch<ZwpInputPopupSurfaceV2, ()>
+        + InputMethodHandler
+        + 'static,
+{
+    fn request(
+        _state: &mut D,
+        _client: &Client,
+        _obj: &ZwpInputMethodKeyboardGrabV2,
+        request: zwp_input_method_keyboard_grab_v2::Request,
+        _data: &(),
+        _dh: &DisplayHandle,
+        _data_init: &mut DataInit<'_, D>,
+    ) {
+        match request {
+            zwp_input_method_keyboard_grab_v2::Request::Release => {}
+            _ => unreachable!(),
+        }
+    }
+
+    fn destroyed(state: &mut D, _client: ClientId, resource: ObjectId, _data: &()) {
+        if let Some(input_method) = state.input_method_state().input_method.as_mut() {
+            if input_method
+                .keyboard_grab
+                .as_ref()
+                .map(|grab| grab.object.id() == resource)
+                .unwrap_or(false)
+            {
+                input_method.keyboard_grab = None;
+            }
+        }
+    }
+}
+
+impl<D> Dispatch<ZwpInputPopupSurfaceV2, (), D> for InputMethodState
+where
+    D: GlobalDispatch<ZwpInputMethodManagerV2, InputMethodGlobalData>
+        + Dispatch<ZwpInputMethodManagerV2, ()>
+        + Dispatch<ZwpInputMethodV2, ()>
+        + Dispatch<ZwpInputMethodKeyboardGrabV2, ()>
+        + Dispatch<ZwpInputPopupSurfaceV2, ()>
+        + InputMethodHandler
+        + 'static,
+{
+    fn request(
+        _state: &mut D,
+        _client: &Client,
+        _obj: &ZwpInputPopupSurfaceV2,
+        request: zwp_input_popup_surface_v2::Request,
+        _data: &(),
+        _dh: &DisplayHandle,
+        _data_init: &mut DataInit<'_, D>,
+    ) {
+        match request {
+            zwp_input_popup_surface_v2::Request::Destroy => {}
+            _ => unreachable!(),
+        }
+    }
+
+    fn destroyed(state: &mut D, _client: ClientId, resource: ObjectId, _data: &()) {
+        if let Some(input_method) = state.input_method_state().input_method.as_mut() {
+            input_method
+                .popups
+                .retain(|popup| popup.object.id() != resource);
+        }
+        state.input_popups_changed();
+    }
+}
+
+macro_rules! delegate_input_method {
+    ($(@<$( $lt:tt $( : $clt:tt $(+ $dlt:tt )* )? ),+>)? $ty: ty) => {
+        smithay::reexports::wayland_server::delegate_global_dispatch!($(@< $( $lt $( : $clt $(+ $dlt )* )? ),+ >)? $ty: [
+            $crate::wayland::protocols::input_method::zwp_input_method_manager_v2::ZwpInputMethodManagerV2: $crate::wayland::protocols::input_method::InputMethodGlobalData
+        ] => $crate::wayland::protocols::input_method::InputMethodState);
+        smithay::reexports::wayland_server::delegate_dispatch!($(@< $( $lt $( : $clt $(+ $dlt )* )? ),+ >)? $ty: [
+            $crate::wayland::protocols::input_method::zwp_input_method_manager_v2::ZwpInputMethodManagerV2: ()
+        ] => $crate::wayland::protocols::input_method::InputMethodState);
+        smithay::reexports::wayland_server::delegate_dispatch!($(@< $( $lt $( : $clt $(+ $dlt )* )? ),+ >)? $ty: [
+            $crate::wayland::protocols::input_method::zwp_input_method_v2::ZwpInputMethodV2: ()
+        ] => $crate::wayland::protocols::input_method::InputMethodState);
+        smithay::reexports::wayland_server::delegate_dispatch!($(@< $( $lt $( : $clt $(+ $dlt )* )? ),+ >)? $ty: [
+            $crate::wayland::protocols::input_method::zwp_input_method_keyboard_grab_v2::ZwpInputMethodKeyboardGrabV2: ()
+        ] => $crate::wayland::protocols::input_method::InputMethodState);
+        smithay::reexports::wayland_server::delegate_dispatch!($(@< $( $lt $( : $clt $(+ $dlt )* )? ),+ >)? $ty: [
+            $crate::wayland::protocols::input_method::zwp_input_popup_surface_v2::ZwpInputPopupSurfaceV2: ()
+        ] => $crate::wayland::protocols::input_method::InputMethodState);
+    };
+}
+pub(crate) use delegate_input_method;
@@ -0,0 +1,243 @@
+// SPDX-License-Identifier: GPL-3.0-only
+
+use smithay::reexports::{
+    wayland_protocols::wp::keyboard_shortcuts_inhibit::zv1::server::{
+        zwp_keyboard_shortcuts_inhibit_manager_v1::{self, ZwpKeyboardShortcutsInhibitManagerV1},
+        zwp_keyboard_shortcuts_inhibitor_v1::{self, ZwpKeyboardShortcutsInhibitorV1},
+    },
+    wayland_server::{
+        backend::{ClientId, GlobalId, ObjectId},
+        protocol::wl_surface::WlSurface,
+        Client, DataInit, Dispatch, DisplayHandle, GlobalDispatch, New, Resource,
+    },
+};
+
+/// State of the keyboard-shortcuts-inhibit global.
+///
+/// Inhibitors are tracked per surface, as we only ever expose a single seat.
+pub struct KeyboardShortcutsInhibitState {
+    inhibitors: Vec<Inhibitor>,
+    focus: Option<WlSurface>,
+    global: GlobalId,
+}
+
+pub trait KeyboardShortcutsInhibitHandler {
+    fn keyboard_shortcuts_inhibit_state(&mut self) -> &mut KeyboardShortcutsInhibitState;
+}
+
+pub struct KeyboardShortcutsInhibitGlobalData {
+    filter: Box<dyn for<'a> Fn(&'a Client) -> bool + Send + Sync>,
+}
+
+#[derive(Debug)]
+struct Inhibitor {
+    surface: WlSurface,
+    object: ZwpKeyboardShortcutsInhibitorV1,
+    active: bool,
+}
+
+impl Inhibitor {
+    fn activate(&mut self) {
+        self.object.active();
+        self.active = true;
+    }
+
+    fn deactivate(&mut self) {
+        self.object.inactive();
+        self.active = false;
+    }
+}
+
+impl KeyboardShortcutsInhibitState {
+    pub fn new<D, F>(dh: &DisplayHandle, client_filter: F) -> KeyboardShortcutsInhibitState
+    where
+        D: GlobalDispatch<
+                ZwpKeyboardShortcutsInhibitManagerV1,
+                KeyboardShortcutsInhibitGlobalData,
+            > + Dispatch<ZwpKeyboardShortcutsInhibitManagerV1, ()>
+            + Dispatch<ZwpKeyboardShortcutsInhibitorV1, ()>
+            + KeyboardShortcutsInhibitHandler
+            + 'static,
+        F: for<'a> Fn(&'a Client) -> bool + Send + Sync + 'static,
+    {
+        let global = dh.create_global::<D, ZwpKeyboardShortcutsInhibitManagerV1, _>(
+            1,
+            KeyboardShortcutsInhibitGlobalData {
+                filter: Box::new(client_filter),
+            },
+        );
+        KeyboardShortcutsInhibitState {
+            inhibitors: Vec::new(),
+            focus: None,
+            global,
+        }
+    }
+
+    pub fn global_id(&self) -> GlobalId {
+        self.global.clone()
+    }
+
+    /// Whether compositor shortcuts are currently inhibited for the focused surface.
+    pub fn is_inhibited(&self) -> bool {
+        self.inhibitors.iter().any(|inhibitor| inhibitor.active)
+    }
+
+    /// Returns the surface of the inhibitor currently in effect, if any.
+    pub fn active_inhibitor(&self) -> Option<&WlSurface> {
+        self.inhibitors
+            .iter()
+            .find(|inhibitor| inhibitor.active)
+            .map(|inhibitor| &inhibitor.surface)
+    }
+
+    /// Updates which inhibitors are active after the keyboard focus changed.
+    ///
+    /// Inhibitors only take effect, while their surface has keyboard focus.
+    pub fn update_focus(&mut self, focus: Option<&WlSurface>) {
+        if self.focus.as_ref() == focus {
+            return;
+        }
+
+        for inhibitor in self.inhibitors.iter_mut() {
+            let focused = focus.map(|s| s == &inhibitor.surface).unwrap_or(false);
+            if inhibitor.active && !focused {
+                inhibitor.deactivate();
+            } else if !inhibitor.active && focused {
+                inhibitor.activate();
+            }
+        }
+        self.focus = focus.cloned();
+    }
+
+    /// Temporarily lifts the inhibitor of the focused surface, e.g. on an escape binding.
+    ///
+    /// The inhibitor becomes active again the next time the surface gains keyboard focus.
+    pub fn deactivate(&mut self) {
+        for inhibitor in self.inhibitors.iter_mut().filter(|inhibitor| inhibitor.active) {
+            inhibitor.deactivate();
+        }
+    }
+}
+
+impl<D> GlobalDispatch<ZwpKeyboardShortcutsInhibitManagerV1, KeyboardShortcutsInhibitGlobalData, D>
+    for KeyboardShortcutsInhibitState
+where
+    D: GlobalDispatch<ZwpKeyboardShortcutsInhibitManagerV1, KeyboardShortcutsInhibitGlobalData>
+        + Dispatch<ZwpKeyboardShortcutsInhibitManagerV1, ()>
+        + Dispatch<ZwpKeyboardShortcutsInhibitorV1, ()>
+        + KeyboardShortcutsInhibitHandler
+        + 'static,
+{
+    fn bind(
+        _state: &mut D,
+        _dh: &DisplayHandle,
+        _client: &Client,
+        resource: New<ZwpKeyboardShortcutsInhibitManagerV1>,
+        _global_data: &KeyboardShortcutsInhibitGlobalData,
+        data_init: &mut DataInit<'_, D>,
+    ) {
+        data_init.init(resource, ());
+    }
+
+    fn can_view(client: Client, global_data: &KeyboardShortcutsInhibitGlobalData) -> bool {
+        (global_data.filter)(&client)
+    }
+}
+
+impl<D> Dispatch<ZwpKeyboardShortcutsInhibitManagerV1, (), D> for KeyboardShortcutsInhibitState
+where
+    D: GlobalDispatch<ZwpKeyboardShortcutsInhibitManagerV1, KeyboardShortcutsInhibitGlobalData>
+        + Dispatch<ZwpKeyboardShortcutsInhibitManagerV1, ()>
+        + Dispatch<ZwpKeyboardShortcutsInhibitorV1, ()>
+        + KeyboardShortcutsInhibitHandler
+        + 'static,
+{
+    fn request(
+        state: &mut D,
+        _client: &Client,
+        obj: &ZwpKeyboardShortcutsInhibitManagerV1,
+        request: zwp_keyboard_shortcuts_inhibit_manager_v1::Request,
+        _data: &(),
+        _dh: &DisplayHandle,
+        data_init: &mut DataInit<'_, D>,
+    ) {
+        match request {
+            zwp_keyboard_shortcuts_inhibit_manager_v1::Request::InhibitShortcuts {
+                id,
+                surface,
+                ..
+            } => {
+                let inhibit_state = state.keyboard_shortcuts_inhibit_state();
+                if inhibit_state
+                    .inhibitors
+                    .iter()
+                    .any(|inhibitor| inhibitor.surface == surface)
+                {
+                    obj.post_error(
+                        zwp_keyboard_shortcuts_inhibit_manager_v1::Error::AlreadyInhibited,
+                        "Surface already has a shortcuts inhibitor",
+                    );
+                    return;
+                }
+
+                let mut inhibitor = Inhibitor {
+                    object: data_init.init(id, ()),
+                    surface,
+                    active: false,
+                };
+                if inhibit_state.focus.as_ref() == Some(&inhibitor.surface) {
+                    inhibitor.activate();
+                }
+                inhibit_state.inhibitors.push(inhibitor);
+            }
+            zwp_keyboard_shortcuts_inhibit_manager_v1::Request::Destroy => {}
+            _ => unreachable!(),
+        }
+    }
+}
+
+impl<D> Dispatch<ZwpKeyboardShortcutsInhibitorV1, (), D> for KeyboardShortcutsInhibitState
+where
+    D: GlobalDispatch<ZwpKeyboardShortcutsInhibitManagerV1, KeyboardShortcutsInhibitGlobalData>
+        + Dispatch<ZwpKeyboardShortcutsInhibitManagerV1, ()>
+        + Dispatch<ZwpKeyboardShortcutsInhibitorV1, ()>
+        + KeyboardShortcutsInhibitHandler
+        + 'static,
+{
+    fn request(
+        _state: &mut D,
+        _client: &Client,
+        _obj: &ZwpKeyboardShortcutsInhibitorV1,
+        request: zwp_keyboard_shortcuts_inhibitor_v1::Request,
+        _data: &(),
+        _dh: &DisplayHandle,
+        _data_init: &mut DataInit<'_, D>,
+    ) {
+        match request {
+            zwp_keyboard_shortcuts_inhibitor_v1::Request::Destroy => {}
+            _ => unreachable!(),
+        }
+    }
+
+    fn destroyed(state: &mut D, _client: ClientId, resource: ObjectId, _data: &()) {
+        state
+            .keyboard_shortcuts_inhibit_state()
+            .inhibitors
+            .retain(|inhibitor| inhibitor.object.id() != resource);
+    }
+}
+
+macro_rules! delegate_keyboard_shortcuts_inhibit {
+    ($(@<$( $lt:tt $( : $clt:tt $(+ $dlt:tt )* )? ),+>)? $ty: ty) => {
+        smithay::reexports::wayland_server::delegate_global_dispatch!($(@< $( $lt $( : $clt $(+ $dlt )* )? ),+ >)? $ty: [
+            smithay::reexports::wayland_protocols::wp::keyboard_shortcuts_inhibit::zv1::server::zwp_keyboard_shortcuts_inhibit_manager_v1::ZwpKeyboardShortcutsInhibitManagerV1: $crate::wayland::protocols::keyboard_shortcuts_inhibit::KeyboardShortcutsInhibitGlobalData
+        ] => $crate::wayland::protocols::keyboard_shortcuts_inhibit::KeyboardShortcutsInhibitState);
+        smithay::reexports::wayland_server::delegate_dispatch!($(@< $( $lt $( : $clt $(+ $dlt )* )? ),+ >)? $ty: [
+            smithay::reexports::wayland_protocols::wp::keyboard_shortcuts_inhibit::zv1::server::zwp_keyboard_shortcuts_inhibit_manager_v1::ZwpKeyboardShortcutsInhibitManagerV1: ()
+        ] => $crate::wayland::protocols::keyboard_shortcuts_inhibit::KeyboardShortcutsInhibitState);
+        smithay::reexports::wayland_server::delegate_dispatch!($(@< $( $lt $( : $clt $(+ $dlt )* )? ),+ >)? $ty: [
+            smithay::reexports::wayland_protocols::wp::keyboard_shortcuts_inhibit::zv1::server::zwp_keyboard_shortcuts_inhibitor_v1::ZwpKeyboardShortcutsInhibitorV1: ()
+        ] => $crate::wayland::protocols::keyboard_shortcuts_inhibit::KeyboardShortcutsInhibitState);
+    };
+}
+pub(crate) use delegate_keyboard_shortcuts_inhibit;
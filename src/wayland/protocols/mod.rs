@@ -2,7 +2,16 @@
 
 pub mod drm;
 pub mod export_dmabuf;
+pub mod input_method;
+pub mod keyboard_shortcuts_inhibit;
 pub mod output_configuration;
+pub mod pointer_constraints;
+pub mod pointer_gestures;
+pub mod relative_pointer;
+pub mod security_context;
+pub mod text_input;
 pub mod toplevel_info;
 pub mod toplevel_management;
+pub mod virtual_keyboard;
+pub mod virtual_pointer;
 pub mod workspace;
@@ -0,0 +1,378 @@
+// SPDX-License-Identifier: GPL-3.0-only
+
+use smithay::{
+    reexports::{
+        wayland_protocols::wp::pointer_constraints::zv1::server::{
+            zwp_confined_pointer_v1::{self, ZwpConfinedPointerV1},
+            zwp_locked_pointer_v1::{self, ZwpLockedPointerV1},
+            zwp_pointer_constraints_v1::{self, Lifetime, ZwpPointerConstraintsV1},
+        },
+        wayland_server::{
+            backend::{ClientId, GlobalId, ObjectId},
+            protocol::{wl_region::WlRegion, wl_surface::WlSurface},
+            Client, DataInit, Dispatch, DisplayHandle, GlobalDispatch, New, Resource, WEnum,
+        },
+    },
+    utils::{Logical, Point},
+    wayland::compositor::{get_region_attributes, RegionAttributes},
+};
+
+/// State of the pointer-constraints global.
+///
+/// Constraints are tracked per surface, as we only ever expose a single seat.
+pub struct PointerConstraintsState {
+    constraints: Vec<PointerConstraint>,
+    global: GlobalId,
+}
+
+pub trait PointerConstraintsHandler {
+    fn pointer_constraints_state(&mut self) -> &mut PointerConstraintsState;
+}
+
+pub struct PointerConstraintsGlobalData {
+    filter: Box<dyn for<'a> Fn(&'a Client) -> bool + Send + Sync>,
+}
+
+/// Constraint currently in effect for a pointer focusing a given surface
+#[derive(Debug, Clone)]
+pub enum Constraint {
+    /// The pointer may not move at all
+    Locked,
+    /// The pointer may not leave the surface, or the given region of it
+    Confined(Option<RegionAttributes>),
+}
+
+#[derive(Debug)]
+enum ConstraintObject {
+    Locked(ZwpLockedPointerV1),
+    Confined(ZwpConfinedPointerV1),
+}
+
+impl ConstraintObject {
+    fn id(&self) -> ObjectId {
+        match self {
+            ConstraintObject::Locked(locked) => locked.id(),
+            ConstraintObject::Confined(confined) => confined.id(),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct PointerConstraint {
+    surface: WlSurface,
+    object: ConstraintObject,
+    persistent: bool,
+    active: bool,
+    defunct: bool,
+    region: Option<RegionAttributes>,
+    pending_region: Option<Option<RegionAttributes>>,
+}
+
+impl PointerConstraint {
+    fn activate(&mut self) {
+        match &self.object {
+            ConstraintObject::Locked(locked) => locked.locked(),
+            ConstraintObject::Confined(confined) => confined.confined(),
+        }
+        self.active = true;
+    }
+
+    fn deactivate(&mut self) {
+        match &self.object {
+            ConstraintObject::Locked(locked) => locked.unlocked(),
+            ConstraintObject::Confined(confined) => confined.unconfined(),
+        }
+        self.active = false;
+    }
+}
+
+impl PointerConstraintsState {
+    pub fn new<D, F>(dh: &DisplayHandle, client_filter: F) -> PointerConstraintsState
+    where
+        D: GlobalDispatch<ZwpPointerConstraintsV1, PointerConstraintsGlobalData>
+            + Dispatch<ZwpPointerConstraintsV1, ()>
+            + Dispatch<ZwpLockedPointerV1, ()>
+            + Dispatch<ZwpConfinedPointerV1, ()>
+            + PointerConstraintsHandler
+            + 'static,
+        F: for<'a> Fn(&'a Client) -> bool + Send + Sync + 'static,
+    {
+        let global = dh.create_global::<D, ZwpPointerConstraintsV1, _>(
+            1,
+            PointerConstraintsGlobalData {
+                filter: Box::new(client_filter),
+            },
+        );
+        PointerConstraintsState {
+            constraints: Vec::new(),
+            global,
+        }
+    }
+
+    pub fn global_id(&self) -> GlobalId {
+        self.global.clone()
+    }
+
+    /// Returns the constraint in effect for the given surface, if any.
+    pub fn active_constraint(&self, surface: &WlSurface) -> Option<Constraint> {
+        self.constraints
+            .iter()
+            .find(|constraint| constraint.active && &constraint.surface == surface)
+            .map(|constraint| match constraint.object {
+                ConstraintObject::Locked(_) => Constraint::Locked,
+                ConstraintObject::Confined(_) => Constraint::Confined(constraint.region.clone()),
+            })
+    }
+
+    /// Updates which constraints are active after the pointer focus changed or moved.
+    ///
+    /// `focus` is the surface under the pointer and the pointer location relative to it.
+    /// A constraint only activates, once the pointer entered its region.
+    pub fn update_focus(&mut self, focus: Option<(&WlSurface, Point<f64, Logical>)>) {
+        for constraint in self.constraints.iter_mut() {
+            let focused = focus
+                .map(|(surface, _)| surface == &constraint.surface)
+                .unwrap_or(false);
+
+            if constraint.active && !focused {
+                constraint.deactivate();
+                // oneshot constraints are done, once they have been deactivated,
+                // but still count for the surface until the client destroys them
+                constraint.defunct = !constraint.persistent;
+            } else if !constraint.active && !constraint.defunct && focused {
+                let location = focus.unwrap().1.to_i32_round();
+                if constraint
+                    .region
+                    .as_ref()
+                    .map(|region| region.contains(location))
+                    .unwrap_or(true)
+                {
+                    constraint.activate();
+                }
+            }
+        }
+    }
+
+    /// Applies double-buffered constraint state on commit of the given surface.
+    pub fn commit(&mut self, surface: &WlSurface) {
+        for constraint in self
+            .constraints
+            .iter_mut()
+            .filter(|constraint| &constraint.surface == surface)
+        {
+            if let Some(region) = constraint.pending_region.take() {
+                constraint.region = region;
+            }
+        }
+    }
+
+    fn new_constraint(
+        &mut self,
+        manager: &ZwpPointerConstraintsV1,
+        surface: WlSurface,
+        object: ConstraintObject,
+        region: Option<WlRegion>,
+        lifetime: WEnum<Lifetime>,
+    ) {
+        if self.constraints.iter().any(|c| c.surface == surface) {
+            manager.post_error(
+                zwp_pointer_constraints_v1::Error::AlreadyConstrained,
+                "Surface already has a pointer constraint",
+            );
+            return;
+        }
+        self.constraints.push(PointerConstraint {
+            surface,
+            object,
+            persistent: matches!(lifetime, WEnum::Value(Lifetime::Persistent)),
+            active: false,
+            defunct: false,
+            region: region.as_ref().map(get_region_attributes),
+            pending_region: None,
+        });
+    }
+
+    fn constraint_mut(&mut self, id: &ObjectId) -> Option<&mut PointerConstraint> {
+        self.constraints
+            .iter_mut()
+            .find(|constraint| &constraint.object.id() == id)
+    }
+}
+
+impl<D> GlobalDispatch<ZwpPointerConstraintsV1, PointerConstraintsGlobalData, D>
+    for PointerConstraintsState
+where
+    D: GlobalDispatch<ZwpPointerConstraintsV1, PointerConstraintsGlobalData>
+        + Dispatch<ZwpPointerConstraintsV1, ()>
+        + Dispatch<ZwpLockedPointerV1, ()>
+        + Dispatch<ZwpConfinedPointerV1, ()>
+        + PointerConstraintsHandler
+        + 'static,
+{
+    fn bind(
+        _state: &mut D,
+        _dh: &DisplayHandle,
+        _client: &Client,
+        resource: New<ZwpPointerConstraintsV1>,
+        _global_data: &PointerConstraintsGlobalData,
+        data_init: &mut DataInit<'_, D>,
+    ) {
+        data_init.init(resource, ());
+    }
+
+    fn can_view(client: Client, global_data: &PointerConstraintsGlobalData) -> bool {
+        (global_data.filter)(&client)
+    }
+}
+
+impl<D> Dispatch<ZwpPointerConstraintsV1, (), D> for PointerConstraintsState
+where
+    D: GlobalDispatch<ZwpPointerConstraintsV1, PointerConstraintsGlobalData>
+        + Dispatch<ZwpPointerConstraintsV1, ()>
+        + Dispatch<ZwpLockedPointerV1, ()>
+        + Dispatch<ZwpConfinedPointerV1, ()>
+        + PointerConstraintsHandler
+        + 'static,
+{
+    fn request(
+        state: &mut D,
+        _client: &Client,
+        obj: &ZwpPointerConstraintsV1,
+        request: zwp_pointer_constraints_v1::Request,
+        _data: &(),
+        _dh: &DisplayHandle,
+        data_init: &mut DataInit<'_, D>,
+    ) {
+        match request {
+            zwp_pointer_constraints_v1::Request::LockPointer {
+                id,
+                surface,
+                region,
+                lifetime,
+                ..
+            } => {
+                let locked = data_init.init(id, ());
+                state.pointer_constraints_state().new_constraint(
+                    obj,
+                    surface,
+                    ConstraintObject::Locked(locked),
+                    region,
+                    lifetime,
+                );
+            }
+            zwp_pointer_constraints_v1::Request::ConfinePointer {
+                id,
+                surface,
+                region,
+                lifetime,
+                ..
+            } => {
+                let confined = data_init.init(id, ());
+                state.pointer_constraints_state().new_constraint(
+                    obj,
+                    surface,
+                    ConstraintObject::Confined(confined),
+                    region,
+                    lifetime,
+                );
+            }
+            zwp_pointer_constraints_v1::Request::Destroy => {}
+            _ => unreachable!(),
+        }
+    }
+}
+
+impl<D> Dispatch<ZwpLockedPointerV1, (), D> for PointerConstraintsState
+where
+    D: GlobalDispatch<ZwpPointerConstraintsV1, PointerConstraintsGlobalData>
+        + Dispatch<ZwpPointerConstraintsV1, ()>
+        + Dispatch<ZwpLockedPointerV1, ()>
+        + Dispatch<ZwpConfinedPointerV1, ()>
+        + PointerConstraintsHandler
+        + 'static,
+{
+    fn request(
+        state: &mut D,
+        _client: &Client,
+        obj: &ZwpLockedPointerV1,
+        request: zwp_locked_pointer_v1::Request,
+        _data: &(),
+        _dh: &DisplayHandle,
+        _data_init: &mut DataInit<'_, D>,
+    ) {
+        if let Some(constraint) = state.pointer_constraints_state().constraint_mut(&obj.id()) {
+            match request {
+                zwp_locked_pointer_v1::Request::SetCursorPositionHint { .. } => {
+                    // we never warp the pointer on unlock, so the hint is of no use to us
+                }
+                zwp_locked_pointer_v1::Request::SetRegion { region } => {
+                    constraint.pending_region = Some(region.as_ref().map(get_region_attributes));
+                }
+                zwp_locked_pointer_v1::Request::Destroy => {}
+                _ => unreachable!(),
+            }
+        }
+    }
+
+    fn destroyed(state: &mut D, _client: ClientId, resource: ObjectId, _data: &()) {
+        state
+            .pointer_constraints_state()
+            .constraints
+            .retain(|constraint| constraint.object.id() != resource);
+    }
+}
+
+impl<D> Dispatch<ZwpConfinedPointerV1, (), D> for PointerConstraintsState
+where
+    D: GlobalDispatch<ZwpPointerConstraintsV1, PointerConstraintsGlobalData>
+        + Dispatch<ZwpPointerConstraintsV1, ()>
+        + Dispatch<ZwpLockedPointerV1, ()>
+        + Dispatch<ZwpConfinedPointerV1, ()>
+        + PointerConstraintsHandler
+        + 'static,
+{
+    fn request(
+        state: &mut D,
+        _client: &Client,
+        obj: &ZwpConfinedPointerV1,
+        request: zwp_confined_pointer_v1::Request,
+        _data: &(),
+        _dh: &DisplayHandle,
+        _data_init: &mut DataInit<'_, D>,
+    ) {
+        if let Some(constraint) = state.pointer_constraints_state().constraint_mut(&obj.id()) {
+            match request {
+                zwp_confined_pointer_v1::Request::SetRegion { region } => {
+                    constraint.pending_region = Some(region.as_ref().map(get_region_attributes));
+                }
+                zwp_confined_pointer_v1::Request::Destroy => {}
+                _ => unreachable!(),
+            }
+        }
+    }
+
+    fn destroyed(state: &mut D, _client: ClientId, resource: ObjectId, _data: &()) {
+        state
+            .pointer_constraints_state()
+            .constraints
+            .retain(|constraint| constraint.object.id() != resource);
+    }
+}
+
+macro_rules! delegate_pointer_constraints {
+    ($(@<$( $lt:tt $( : $clt:tt $(+ $dlt:tt )* )? ),+>)? $ty: ty) => {
+        smithay::reexports::wayland_server::delegate_global_dispatch!($(@< $( $lt $( : $clt $(+ $dlt )* )? ),+ >)? $ty: [
+            smithay::reexports::wayland_protocols::wp::pointer_constraints::zv1::server::zwp_pointer_constraints_v1::ZwpPointerConstraintsV1: $crate::wayland::protocols::pointer_constraints::PointerConstraintsGlobalData
+        ] => $crate::wayland::protocols::pointer_constraints::PointerConstraintsState);
+        smithay::reexports::wayland_server::delegate_dispatch!($(@< $( $lt $( : $clt $(+ $dlt )* )? ),+ >)? $ty: [
+            smithay::reexports::wayland_protocols::wp::pointer_constraints::zv1::server::zwp_pointer_constraints_v1::ZwpPointerConstraintsV1: ()
+        ] => $crate::wayland::protocols::pointer_constraints::PointerConstraintsState);
+        smithay::reexports::wayland_server::delegate_dispatch!($(@< $( $lt $( : $clt $(+ $dlt )* )? ),+ >)? $ty: [
+            smithay::reexports::wayland_protocols::wp::pointer_constraints::zv1::server::zwp_locked_pointer_v1::ZwpLockedPointerV1: ()
+        ] => $crate::wayland::protocols::pointer_constraints::PointerConstraintsState);
+        smithay::reexports::wayland_server::delegate_dispatch!($(@< $( $lt $( : $clt $(+ $dlt )* )? ),+ >)? $ty: [
+            smithay::reexports::wayland_protocols::wp::pointer_constraints::zv1::server::zwp_confined_pointer_v1::ZwpConfinedPointerV1: ()
+        ] => $crate::wayland::protocols::pointer_constraints::PointerConstraintsState);
+    };
+}
+pub(crate) use delegate_pointer_constraints;
@@ -0,0 +1,337 @@
+// SPDX-License-Identifier: GPL-3.0-only
+
+use smithay::{
+    reexports::{
+        wayland_protocols::wp::pointer_gestures::zv1::server::{
+            zwp_pointer_gesture_hold_v1::{self, ZwpPointerGestureHoldV1},
+            zwp_pointer_gesture_pinch_v1::{self, ZwpPointerGesturePinchV1},
+            zwp_pointer_gesture_swipe_v1::{self, ZwpPointerGestureSwipeV1},
+            zwp_pointer_gestures_v1::{self, ZwpPointerGesturesV1},
+        },
+        wayland_server::{
+            backend::{ClientId, GlobalId, ObjectId},
+            protocol::wl_surface::WlSurface,
+            Client, DataInit, Dispatch, DisplayHandle, GlobalDispatch, New, Resource,
+        },
+    },
+    utils::{Logical, Point},
+    wayland::Serial,
+};
+
+/// State of the pointer-gestures global.
+///
+/// We only ever expose a single seat to clients, so gesture objects are not
+/// tracked per pointer, but per client. A gesture sequence is always sent to
+/// the client owning the surface the sequence started on.
+pub struct PointerGesturesState {
+    swipes: Vec<ZwpPointerGestureSwipeV1>,
+    pinches: Vec<ZwpPointerGesturePinchV1>,
+    holds: Vec<ZwpPointerGestureHoldV1>,
+    swipe_focus: Option<WlSurface>,
+    pinch_focus: Option<WlSurface>,
+    hold_focus: Option<WlSurface>,
+    global: GlobalId,
+}
+
+pub trait PointerGesturesHandler {
+    fn pointer_gestures_state(&mut self) -> &mut PointerGesturesState;
+}
+
+pub struct PointerGesturesGlobalData {
+    filter: Box<dyn for<'a> Fn(&'a Client) -> bool + Send + Sync>,
+}
+
+impl PointerGesturesState {
+    pub fn new<D, F>(dh: &DisplayHandle, client_filter: F) -> PointerGesturesState
+    where
+        D: GlobalDispatch<ZwpPointerGesturesV1, PointerGesturesGlobalData>
+            + Dispatch<ZwpPointerGesturesV1, ()>
+            + Dispatch<ZwpPointerGestureSwipeV1, ()>
+            + Dispatch<ZwpPointerGesturePinchV1, ()>
+            + Dispatch<ZwpPointerGestureHoldV1, ()>
+            + PointerGesturesHandler
+            + 'static,
+        F: for<'a> Fn(&'a Client) -> bool + Send + Sync + 'static,
+    {
+        let global = dh.create_global::<D, ZwpPointerGesturesV1, _>(
+            3,
+            PointerGesturesGlobalData {
+                filter: Box::new(client_filter),
+            },
+        );
+        PointerGesturesState {
+            swipes: Vec::new(),
+            pinches: Vec::new(),
+            holds: Vec::new(),
+            swipe_focus: None,
+            pinch_focus: None,
+            hold_focus: None,
+            global,
+        }
+    }
+
+    pub fn global_id(&self) -> GlobalId {
+        self.global.clone()
+    }
+
+    pub fn swipe_begin(&mut self, surface: &WlSurface, serial: Serial, time: u32, fingers: u32) {
+        for swipe in gestures_for(&self.swipes, surface) {
+            swipe.begin(serial.into(), time, surface, fingers);
+        }
+        self.swipe_focus = Some(surface.clone());
+    }
+
+    pub fn swipe_update(&mut self, time: u32, delta: Point<f64, Logical>) {
+        if let Some(surface) = self.swipe_focus.as_ref() {
+            for swipe in gestures_for(&self.swipes, surface) {
+                swipe.update(time, delta.x, delta.y);
+            }
+        }
+    }
+
+    pub fn swipe_end(&mut self, serial: Serial, time: u32, cancelled: bool) {
+        if let Some(surface) = self.swipe_focus.take() {
+            for swipe in gestures_for(&self.swipes, &surface) {
+                swipe.end(serial.into(), time, cancelled as i32);
+            }
+        }
+    }
+
+    pub fn pinch_begin(&mut self, surface: &WlSurface, serial: Serial, time: u32, fingers: u32) {
+        for pinch in gestures_for(&self.pinches, surface) {
+            pinch.begin(serial.into(), time, surface, fingers);
+        }
+        self.pinch_focus = Some(surface.clone());
+    }
+
+    pub fn pinch_update(
+        &mut self,
+        time: u32,
+        delta: Point<f64, Logical>,
+        scale: f64,
+        rotation: f64,
+    ) {
+        if let Some(surface) = self.pinch_focus.as_ref() {
+            for pinch in gestures_for(&self.pinches, surface) {
+                pinch.update(time, delta.x, delta.y, scale, rotation);
+            }
+        }
+    }
+
+    pub fn pinch_end(&mut self, serial: Serial, time: u32, cancelled: bool) {
+        if let Some(surface) = self.pinch_focus.take() {
+            for pinch in gestures_for(&self.pinches, &surface) {
+                pinch.end(serial.into(), time, cancelled as i32);
+            }
+        }
+    }
+
+    pub fn hold_begin(&mut self, surface: &WlSurface, serial: Serial, time: u32, fingers: u32) {
+        for hold in gestures_for(&self.holds, surface) {
+            hold.begin(serial.into(), time, surface, fingers);
+        }
+        self.hold_focus = Some(surface.clone());
+    }
+
+    pub fn hold_end(&mut self, serial: Serial, time: u32, cancelled: bool) {
+        if let Some(surface) = self.hold_focus.take() {
+            for hold in gestures_for(&self.holds, &surface) {
+                hold.end(serial.into(), time, cancelled as i32);
+            }
+        }
+    }
+}
+
+fn gestures_for<'a, R: Resource>(
+    gestures: &'a [R],
+    surface: &'a WlSurface,
+) -> impl Iterator<Item = &'a R> + 'a {
+    gestures
+        .iter()
+        .filter(move |gesture| gesture.id().same_client_as(&surface.id()))
+}
+
+impl<D> GlobalDispatch<ZwpPointerGesturesV1, PointerGesturesGlobalData, D>
+    for PointerGesturesState
+where
+    D: GlobalDispatch<ZwpPointerGesturesV1, PointerGesturesGlobalData>
+        + Dispatch<ZwpPointerGesturesV1, ()>
+        + Dispatch<ZwpPointerGestureSwipeV1, ()>
+        + Dispatch<ZwpPointerGesturePinchV1, ()>
+        + Dispatch<ZwpPointerGestureHoldV1, ()>
+        + PointerGesturesHandler
+        + 'static,
+{
+    fn bind(
+        _state: &mut D,
+        _dh: &DisplayHandle,
+        _client: &Client,
+        resource: New<ZwpPointerGesturesV1>,
+        _global_data: &PointerGesturesGlobalData,
+        data_init: &mut DataInit<'_, D>,
+    ) {
+        data_init.init(resource, ());
+    }
+
+    fn can_view(client: Client, global_data: &PointerGesturesGlobalData) -> bool {
+        (global_data.filter)(&client)
+    }
+}
+
+impl<D> Dispatch<ZwpPointerGesturesV1, (), D> for PointerGesturesState
+where
+    D: GlobalDispatch<ZwpPointerGesturesV1, PointerGesturesGlobalData>
+        + Dispatch<ZwpPointerGesturesV1, ()>
+        + Dispatch<ZwpPointerGestureSwipeV1, ()>
+        + Dispatch<ZwpPointerGesturePinchV1, ()>
+        + Dispatch<ZwpPointerGestureHoldV1, ()>
+        + PointerGesturesHandler
+        + 'static,
+{
+    fn request(
+        state: &mut D,
+        _client: &Client,
+        _obj: &ZwpPointerGesturesV1,
+        request: zwp_pointer_gestures_v1::Request,
+        _data: &(),
+        _dh: &DisplayHandle,
+        data_init: &mut DataInit<'_, D>,
+    ) {
+        match request {
+            zwp_pointer_gestures_v1::Request::GetSwipeGesture { id, .. } => {
+                let swipe = data_init.init(id, ());
+                state.pointer_gestures_state().swipes.push(swipe);
+            }
+            zwp_pointer_gestures_v1::Request::GetPinchGesture { id, .. } => {
+                let pinch = data_init.init(id, ());
+                state.pointer_gestures_state().pinches.push(pinch);
+            }
+            zwp_pointer_gestures_v1::Request::GetHoldGesture { id, .. } => {
+                let hold = data_init.init(id, ());
+                state.pointer_gestures_state().holds.push(hold);
+            }
+            zwp_pointer_gestures_v1::Request::Release => {}
+            _ => unreachable!(),
+        }
+    }
+}
+
+impl<D> Dispatch<ZwpPointerGestureSwipeV1, (), D> for PointerGesturesState
+where
+    D: GlobalDispatch<ZwpPointerGesturesV1, PointerGesturesGlobalData>
+        + Dispatch<ZwpPointerGesturesV1, ()>
+        + Dispatch<ZwpPointerGestureSwipeV1, ()>
+        + Dispatch<ZwpPointerGesturePinchV1, ()>
+        + Dispatch<ZwpPointerGestureHoldV1, ()>
+        + PointerGesturesHandler
+        + 'static,
+{
+    fn request(
+        _state: &mut D,
+        _client: &Client,
+        _obj: &ZwpPointerGestureSwipeV1,
+        request: zwp_pointer_gesture_swipe_v1::Request,
+        _data: &(),
+        _dh: &DisplayHandle,
+        _data_init: &mut DataInit<'_, D>,
+    ) {
+        match request {
+            zwp_pointer_gesture_swipe_v1::Request::Destroy => {}
+            _ => unreachable!(),
+        }
+    }
+
+    fn destroyed(state: &mut D, _client: ClientId, resource: ObjectId, _data: &()) {
+        state
+            .pointer_gestures_state()
+            .swipes
+            .retain(|swipe| swipe.id() != resource);
+    }
+}
+
+impl<D> Dispatch<ZwpPointerGesturePinchV1, (), D> for PointerGesturesState
+where
+    D: GlobalDispatch<ZwpPointerGesturesV1, PointerGesturesGlobalData>
+        + Dispatch<ZwpPointerGesturesV1, ()>
+        + Dispatch<ZwpPointerGestureSwipeV1, ()>
+        + Dispatch<ZwpPointerGesturePinchV1, ()>
+        + Dispatch<ZwpPointerGestureHoldV1, ()>
+        + PointerGesturesHandler
+        + 'static,
+{
+    fn request(
+        _state: &mut D,
+        _client: &Client,
+        _obj: &ZwpPointerGesturePinchV1,
+        request: zwp_pointer_gesture_pinch_v1::Request,
+        _data: &(),
+        _dh: &DisplayHandle,
+        _data_init: &mut DataInit<'_, D>,
+    ) {
+        match request {
+            zwp_pointer_gesture_pinch_v1::Request::Destroy => {}
+            _ => unreachable!(),
+        }
+    }
+
+    fn destroyed(state: &mut D, _client: ClientId, resource: ObjectId, _data: &()) {
+        state
+            .pointer_gestures_state()
+            .pinches
+            .retain(|pinch| pinch.id() != resource);
+    }
+}
+
+impl<D> Dispatch<ZwpPointerGestureHoldV1, (), D> for PointerGesturesState
+where
+    D: GlobalDispatch<ZwpPointerGesturesV1, PointerGesturesGlobalData>
+        + Dispatch<ZwpPointerGesturesV1, ()>
+        + Dispatch<ZwpPointerGestureSwipeV1, ()>
+        + Dispatch<ZwpPointerGesturePinchV1, ()>
+        + Dispatch<ZwpPointerGestureHoldV1, ()>
+        + PointerGesturesHandler
+        + 'static,
+{
+    fn request(
+        _state: &mut D,
+        _client: &Client,
+        _obj: &ZwpPointerGestureHoldV1,
+        request: zwp_pointer_gesture_hold_v1::Request,
+        _data: &(),
+        _dh: &DisplayHandle,
+        _data_init: &mut DataInit<'_, D>,
+    ) {
+        match request {
+            zwp_pointer_gesture_hold_v1::Request::Destroy => {}
+            _ => unreachable!(),
+        }
+    }
+
+    fn destroyed(state: &mut D, _client: ClientId, resource: ObjectId, _data: &()) {
+        state
+            .pointer_gestures_state()
+            .holds
+            .retain(|hold| hold.id() != resource);
+    }
+}
+
+macro_rules! delegate_pointer_gestures {
+    ($(@<$( $lt:tt $( : $clt:tt $(+ $dlt:tt )* )? ),+>)? $ty: ty) => {
+        smithay::reexports::wayland_server::delegate_global_dispatch!($(@< $( $lt $( : $clt $(+ $dlt )* )? ),+ >)? $ty: [
+            smithay::reexports::wayland_protocols::wp::pointer_gestures::zv1::server::zwp_pointer_gestures_v1::ZwpPointerGesturesV1: $crate::wayland::protocols::pointer_gestures::PointerGesturesGlobalData
+        ] => $crate::wayland::protocols::pointer_gestures::PointerGesturesState);
+        smithay::reexports::wayland_server::delegate_dispatch!($(@< $( $lt $( : $clt $(+ $dlt )* )? ),+ >)? $ty: [
+            smithay::reexports::wayland_protocols::wp::pointer_gestures::zv1::server::zwp_pointer_gestures_v1::ZwpPointerGesturesV1: ()
+        ] => $crate::wayland::protocols::pointer_gestures::PointerGesturesState);
+        smithay::reexports::wayland_server::delegate_dispatch!($(@< $( $lt $( : $clt $(+ $dlt )* )? ),+ >)? $ty: [
+            smithay::reexports::wayland_protocols::wp::pointer_gestures::zv1::server::zwp_pointer_gesture_swipe_v1::ZwpPointerGestureSwipeV1: ()
+        ] => $crate::wayland::protocols::pointer_gestures::PointerGesturesState);
+        smithay::reexports::wayland_server::delegate_dispatch!($(@< $( $lt $( : $clt $(+ $dlt )* )? ),+ >)? $ty: [
+            smithay::reexports::wayland_protocols::wp::pointer_gestures::zv1::server::zwp_pointer_gesture_pinch_v1::ZwpPointerGesturePinchV1: ()
+        ] => $crate::wayland::protocols::pointer_gestures::PointerGesturesState);
+        smithay::reexports::wayland_server::delegate_dispatch!($(@< $( $lt $( : $clt $(+ $dlt )* )? ),+ >)? $ty: [
+            smithay::reexports::wayland_protocols::wp::pointer_gestures::zv1::server::zwp_pointer_gesture_hold_v1::ZwpPointerGestureHoldV1: ()
+        ] => $crate::wayland::protocols::pointer_gestures::PointerGesturesState);
+    };
+}
+pub(crate) use delegate_pointer_gestures;
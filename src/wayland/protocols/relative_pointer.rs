@@ -0,0 +1,185 @@
+// SPDX-License-Identifier: GPL-3.0-only
+
+use smithay::{
+    reexports::{
+        wayland_protocols::wp::relative_pointer::zv1::server::{
+            zwp_relative_pointer_manager_v1::{self, ZwpRelativePointerManagerV1},
+            zwp_relative_pointer_v1::{self, ZwpRelativePointerV1},
+        },
+        wayland_server::{
+            backend::{ClientId, GlobalId, ObjectId},
+            protocol::wl_surface::WlSurface,
+            Client, DataInit, Dispatch, DisplayHandle, GlobalDispatch, New, Resource,
+        },
+    },
+    utils::{Logical, Point},
+};
+
+/// State of the relative-pointer global.
+///
+/// Like for pointer gestures relative pointers are tracked per client,
+/// as we only ever expose a single seat.
+pub struct RelativePointerState {
+    pointers: Vec<ZwpRelativePointerV1>,
+    global: GlobalId,
+}
+
+pub trait RelativePointerHandler {
+    fn relative_pointer_state(&mut self) -> &mut RelativePointerState;
+}
+
+pub struct RelativePointerGlobalData {
+    filter: Box<dyn for<'a> Fn(&'a Client) -> bool + Send + Sync>,
+}
+
+impl RelativePointerState {
+    pub fn new<D, F>(dh: &DisplayHandle, client_filter: F) -> RelativePointerState
+    where
+        D: GlobalDispatch<ZwpRelativePointerManagerV1, RelativePointerGlobalData>
+            + Dispatch<ZwpRelativePointerManagerV1, ()>
+            + Dispatch<ZwpRelativePointerV1, ()>
+            + RelativePointerHandler
+            + 'static,
+        F: for<'a> Fn(&'a Client) -> bool + Send + Sync + 'static,
+    {
+        let global = dh.create_global::<D, ZwpRelativePointerManagerV1, _>(
+            1,
+            RelativePointerGlobalData {
+                filter: Box::new(client_filter),
+            },
+        );
+        RelativePointerState {
+            pointers: Vec::new(),
+            global,
+        }
+    }
+
+    pub fn global_id(&self) -> GlobalId {
+        self.global.clone()
+    }
+
+    /// Sends relative motion to the client owning the given pointer focus.
+    ///
+    /// `utime` is a timestamp in microseconds.
+    pub fn relative_motion(
+        &self,
+        focus: &WlSurface,
+        utime: u64,
+        delta: Point<f64, Logical>,
+        delta_unaccel: Point<f64, Logical>,
+    ) {
+        for pointer in self
+            .pointers
+            .iter()
+            .filter(|pointer| pointer.id().same_client_as(&focus.id()))
+        {
+            pointer.relative_motion(
+                (utime >> 32) as u32,
+                (utime & 0xFFFF_FFFF) as u32,
+                delta.x,
+                delta.y,
+                delta_unaccel.x,
+                delta_unaccel.y,
+            );
+        }
+    }
+}
+
+impl<D> GlobalDispatch<ZwpRelativePointerManagerV1, RelativePointerGlobalData, D>
+    for RelativePointerState
+where
+    D: GlobalDispatch<ZwpRelativePointerManagerV1, RelativePointerGlobalData>
+        + Dispatch<ZwpRelativePointerManagerV1, ()>
+        + Dispatch<ZwpRelativePointerV1, ()>
+        + RelativePointerHandler
+        + 'static,
+{
+    fn bind(
+        _state: &mut D,
+        _dh: &DisplayHandle,
+        _client: &Client,
+        resource: New<ZwpRelativePointerManagerV1>,
+        _global_data: &RelativePointerGlobalData,
+        data_init: &mut DataInit<'_, D>,
+    ) {
+        data_init.init(resource, ());
+    }
+
+    fn can_view(client: Client, global_data: &RelativePointerGlobalData) -> bool {
+        (global_data.filter)(&client)
+    }
+}
+
+impl<D> Dispatch<ZwpRelativePointerManagerV1, (), D> for RelativePointerState
+where
+    D: GlobalDispatch<ZwpRelativePointerManagerV1, RelativePointerGlobalData>
+        + Dispatch<ZwpRelativePointerManagerV1, ()>
+        + Dispatch<ZwpRelativePointerV1, ()>
+        + RelativePointerHandler
+        + 'static,
+{
+    fn request(
+        state: &mut D,
+        _client: &Client,
+        _obj: &ZwpRelativePointerManagerV1,
+        request: zwp_relative_pointer_manager_v1::Request,
+        _data: &(),
+        _dh: &DisplayHandle,
+        data_init: &mut DataInit<'_, D>,
+    ) {
+        match request {
+            zwp_relative_pointer_manager_v1::Request::GetRelativePointer { id, .. } => {
+                let pointer = data_init.init(id, ());
+                state.relative_pointer_state().pointers.push(pointer);
+            }
+            zwp_relative_pointer_manager_v1::Request::Destroy => {}
+            _ => unreachable!(),
+        }
+    }
+}
+
+impl<D> Dispatch<ZwpRelativePointerV1, (), D> for RelativePointerState
+where
+    D: GlobalDispatch<ZwpRelativePointerManagerV1, RelativePointerGlobalData>
+        + Dispatch<ZwpRelativePointerManagerV1, ()>
+        + Dispatch<ZwpRelativePointerV1, ()>
+        + RelativePointerHandler
+        + 'static,
+{
+    fn request(
+        _state: &mut D,
+        _client: &Client,
+        _obj: &ZwpRelativePointerV1,
+        request: zwp_relative_pointer_v1::Request,
+        _data: &(),
+        _dh: &DisplayHandle,
+        _data_init: &mut DataInit<'_, D>,
+    ) {
+        match request {
+            zwp_relative_pointer_v1::Request::Destroy => {}
+            _ => unreachable!(),
+        }
+    }
+
+    fn destroyed(state: &mut D, _client: ClientId, resource: ObjectId, _data: &()) {
+        state
+            .relative_pointer_state()
+            .pointers
+            .retain(|pointer| pointer.id() != resource);
+    }
+}
+
+macro_rules! delegate_relative_pointer {
+    ($(@<$( $lt:tt $( : $clt:tt $(+ $dlt:tt )* )? ),+>)? $ty: ty) => {
+        smithay::reexports::wayland_server::delegate_global_dispatch!($(@< $( $lt $( : $clt $(+ $dlt )* )? ),+ >)? $ty: [
+            smithay::reexports::wayland_protocols::wp::relative_pointer::zv1::server::zwp_relative_pointer_manager_v1::ZwpRelativePointerManagerV1: $crate::wayland::protocols::relative_pointer::RelativePointerGlobalData
+        ] => $crate::wayland::protocols::relative_pointer::RelativePointerState);
+        smithay::reexports::wayland_server::delegate_dispatch!($(@< $( $lt $( : $clt $(+ $dlt )* )? ),+ >)? $ty: [
+            smithay::reexports::wayland_protocols::wp::relative_pointer::zv1::server::zwp_relative_pointer_manager_v1::ZwpRelativePointerManagerV1: ()
+        ] => $crate::wayland::protocols::relative_pointer::RelativePointerState);
+        smithay::reexports::wayland_server::delegate_dispatch!($(@< $( $lt $( : $clt $(+ $dlt )* )? ),+ >)? $ty: [
+            smithay::reexports::wayland_protocols::wp::relative_pointer::zv1::server::zwp_relative_pointer_v1::ZwpRelativePointerV1: ()
+        ] => $crate::wayland::protocols::relative_pointer::RelativePointerState);
+    };
+}
+pub(crate) use delegate_relative_pointer;
@@ -0,0 +1,281 @@
+// SPDX-License-Identifier: GPL-3.0-only
+
+// Re-export only the actual code, and then only use this re-export
+// The `generated` module below is just some boilerplate to properly isolate stuff
+// and avoid exposing internal details.
+pub use generated::{wp_security_context_manager_v1, wp_security_context_v1};
+
+mod generated {
+    use smithay::reexports::wayland_server::{self, protocol::*};
+
+    pub mod __interfaces {
+        use smithay::reexports::wayland_server::protocol::__interfaces::*;
+        use wayland_backend;
+        wayland_scanner::generate_interfaces!("resources/protocols/security-context-v1.xml");
+    }
+    use self::__interfaces::*;
+
+    wayland_scanner::generate_server_code!("resources/protocols/security-context-v1.xml");
+}
+
+use self::{
+    wp_security_context_manager_v1::WpSecurityContextManagerV1,
+    wp_security_context_v1::WpSecurityContextV1,
+};
+use smithay::reexports::{
+    nix::sys::socket::{getsockopt, sockopt},
+    wayland_server::{
+        backend::GlobalId, Client, DataInit, Dispatch, DisplayHandle, GlobalDispatch, New, Resource,
+    },
+};
+use std::{
+    fmt,
+    fs::File,
+    os::unix::{
+        io::{AsRawFd, FromRawFd},
+        net::UnixListener,
+    },
+    sync::Mutex,
+};
+
+/// State of the security-context global.
+///
+/// Nesting is prevented by the client filter, which should hide the global from clients
+/// that already belong to a security context.
+pub struct SecurityContextState {
+    global: GlobalId,
+}
+
+pub trait SecurityContextHandler {
+    /// A security context was committed.
+    ///
+    /// Clients connecting through `listener` belong to `context`,
+    /// until `close_fd` becomes readable (because the sandbox engine closed its end).
+    fn context_committed(
+        &mut self,
+        listener: UnixListener,
+        close_fd: File,
+        context: SecurityContext,
+    );
+}
+
+pub struct SecurityContextGlobalData {
+    filter: Box<dyn for<'a> Fn(&'a Client) -> bool + Send + Sync>,
+}
+
+/// Metadata attached to clients by a sandbox engine, e.g. flatpak
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SecurityContext {
+    /// Reverse-DNS name of the sandbox engine, e.g. `org.flatpak`
+    pub sandbox_engine: Option<String>,
+    pub app_id: Option<String>,
+    pub instance_id: Option<String>,
+}
+
+impl fmt::Display for SecurityContext {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} ({}",
+            self.app_id.as_deref().unwrap_or("<unknown app>"),
+            self.sandbox_engine
+                .as_deref()
+                .unwrap_or("<unknown sandbox>")
+        )?;
+        if let Some(instance_id) = self.instance_id.as_ref() {
+            write!(f, ", instance {}", instance_id)?;
+        }
+        write!(f, ")")
+    }
+}
+
+#[derive(Debug)]
+pub struct SecurityContextData(Mutex<PendingContext>);
+
+#[derive(Debug)]
+struct PendingContext {
+    /// Taken once committed
+    fds: Option<(UnixListener, File)>,
+    context: SecurityContext,
+}
+
+impl SecurityContextState {
+    pub fn new<D, F>(dh: &DisplayHandle, client_filter: F) -> SecurityContextState
+    where
+        D: GlobalDispatch<WpSecurityContextManagerV1, SecurityContextGlobalData>
+            + Dispatch<WpSecurityContextManagerV1, ()>
+            + Dispatch<WpSecurityContextV1, SecurityContextData>
+            + SecurityContextHandler
+            + 'static,
+        F: for<'a> Fn(&'a Client) -> bool + Send + Sync + 'static,
+    {
+        let global = dh.create_global::<D, WpSecurityContextManagerV1, _>(
+            1,
+            SecurityContextGlobalData {
+                filter: Box::new(client_filter),
+            },
+        );
+        SecurityContextState { global }
+    }
+
+    pub fn global_id(&self) -> GlobalId {
+        self.global.clone()
+    }
+}
+
+impl<D> GlobalDispatch<WpSecurityContextManagerV1, SecurityContextGlobalData, D>
+    for SecurityContextState
+where
+    D: GlobalDispatch<WpSecurityContextManagerV1, SecurityContextGlobalData>
+        + Dispatch<WpSecurityContextManagerV1, ()>
+        + Dispatch<WpSecurityContextV1, SecurityContextData>
+        + SecurityContextHandler
+        + 'static,
+{
+    fn bind(
+        _state: &mut D,
+        _dh: &DisplayHandle,
+        _client: &Client,
+        resource: New<WpSecurityContextManagerV1>,
+        _global_data: &SecurityContextGlobalData,
+        data_init: &mut DataInit<'_, D>,
+    ) {
+        data_init.init(resource, ());
+    }
+
+    fn can_view(client: Client, global_data: &SecurityContextGlobalData) -> bool {
+        (global_data.filter)(&client)
+    }
+}
+
+impl<D> Dispatch<WpSecurityContextManagerV1, (), D> for SecurityContextState
+where
+    D: GlobalDispatch<WpSecurityContextManagerV1, SecurityContextGlobalData>
+        + Dispatch<WpSecurityContextManagerV1, ()>
+        + Dispatch<WpSecurityContextV1, SecurityContextData>
+        + SecurityContextHandler
+        + 'static,
+{
+    fn request(
+        _state: &mut D,
+        _client: &Client,
+        obj: &WpSecurityContextManagerV1,
+        request: wp_security_context_manager_v1::Request,
+        _data: &(),
+        _dh: &DisplayHandle,
+        data_init: &mut DataInit<'_, D>,
+    ) {
+        match request {
+            wp_security_context_manager_v1::Request::CreateListener {
+                id,
+                listen_fd,
+                close_fd,
+            } => {
+                // the fds are ours now, make sure they get closed
+                let listener = unsafe { UnixListener::from_raw_fd(listen_fd) };
+                let close_fd = unsafe { File::from_raw_fd(close_fd) };
+
+                if !getsockopt(listener.as_raw_fd(), sockopt::AcceptConn).unwrap_or(false) {
+                    obj.post_error(
+                        wp_security_context_manager_v1::Error::InvalidListenFd,
+                        "listen_fd is no listening socket",
+                    );
+                    return;
+                }
+                if let Err(err) = listener.set_nonblocking(true) {
+                    slog_scope::warn!(
+                        "Failed to set security context socket non-blocking: {}",
+                        err
+                    );
+                    obj.post_error(
+                        wp_security_context_manager_v1::Error::InvalidListenFd,
+                        "listen_fd can't be used",
+                    );
+                    return;
+                }
+
+                data_init.init(
+                    id,
+                    SecurityContextData(Mutex::new(PendingContext {
+                        fds: Some((listener, close_fd)),
+                        context: SecurityContext::default(),
+                    })),
+                );
+            }
+            wp_security_context_manager_v1::Request::Destroy => {}
+            _ => unreachable!(),
+        }
+    }
+}
+
+impl<D> Dispatch<WpSecurityContextV1, SecurityContextData, D> for SecurityContextState
+where
+    D: GlobalDispatch<WpSecurityContextManagerV1, SecurityContextGlobalData>
+        + Dispatch<WpSecurityContextManagerV1, ()>
+        + Dispatch<WpSecurityContextV1, SecurityContextData>
+        + SecurityContextHandler
+        + 'static,
+{
+    fn request(
+        state: &mut D,
+        _client: &Client,
+        obj: &WpSecurityContextV1,
+        request: wp_security_context_v1::Request,
+        data: &SecurityContextData,
+        _dh: &DisplayHandle,
+        _data_init: &mut DataInit<'_, D>,
+    ) {
+        let mut pending = data.0.lock().unwrap();
+        if pending.fds.is_none() && !matches!(request, wp_security_context_v1::Request::Destroy) {
+            obj.post_error(
+                wp_security_context_v1::Error::AlreadyUsed,
+                "Security context was already committed",
+            );
+            return;
+        }
+
+        let (field, value) = match request {
+            wp_security_context_v1::Request::SetSandboxEngine { name } => {
+                (&mut pending.context.sandbox_engine, name)
+            }
+            wp_security_context_v1::Request::SetAppId { app_id } => {
+                (&mut pending.context.app_id, app_id)
+            }
+            wp_security_context_v1::Request::SetInstanceId { instance_id } => {
+                (&mut pending.context.instance_id, instance_id)
+            }
+            wp_security_context_v1::Request::Commit => {
+                let (listener, close_fd) = pending.fds.take().unwrap();
+                let context = pending.context.clone();
+                std::mem::drop(pending);
+                state.context_committed(listener, close_fd, context);
+                return;
+            }
+            wp_security_context_v1::Request::Destroy => return,
+            _ => unreachable!(),
+        };
+        if field.is_some() {
+            obj.post_error(
+                wp_security_context_v1::Error::AlreadySet,
+                "Metadata was already set",
+            );
+            return;
+        }
+        *field = Some(value);
+    }
+}
+
+macro_rules! delegate_security_context {
+    ($(@<$( $lt:tt $( : $clt:tt $(+ $dlt:tt )* )? ),+>)? $ty: ty) => {
+        smithay::reexports::wayland_server::delegate_global_dispatch!($(@< $( $lt $( : $clt $(+ $dlt )* )? ),+ >)? $ty: [
+            $crate::wayland::protocols::security_context::wp_security_context_manager_v1::WpSecurityContextManagerV1: $crate::wayland::protocols::security_context::SecurityContextGlobalData
+        ] => $crate::wayland::protocols::security_context::SecurityContextState);
+        smithay::reexports::wayland_server::delegate_dispatch!($(@< $( $lt $( : $clt $(+ $dlt )* )? ),+ >)? $ty: [
+            $crate::wayland::protocols::security_context::wp_security_context_manager_v1::WpSecurityContextManagerV1: ()
+        ] => $crate::wayland::protocols::security_context::SecurityContextState);
+        smithay::reexports::wayland_server::delegate_dispatch!($(@< $( $lt $( : $clt $(+ $dlt )* )? ),+ >)? $ty: [
+            $crate::wayland::protocols::security_context::wp_security_context_v1::WpSecurityContextV1: $crate::wayland::protocols::security_context::SecurityContextData
+        ] => $crate::wayland::protocols::security_context::SecurityContextState);
+    };
+}
+pub(crate) use delegate_security_context;
@@ -0,0 +1,373 @@
+// SPDX-License-Identifier: GPL-3.0-only
+
+use smithay::{
+    reexports::{
+        wayland_protocols::wp::text_input::zv3::server::{
+            zwp_text_input_manager_v3::{self, ZwpTextInputManagerV3},
+            zwp_text_input_v3::{self, ChangeCause, ContentHint, ContentPurpose, ZwpTextInputV3},
+        },
+        wayland_server::{
+            backend::{ClientId, GlobalId, ObjectId},
+            protocol::wl_surface::WlSurface,
+            Client, DataInit, Dispatch, DisplayHandle, GlobalDispatch, New, Resource,
+        },
+    },
+    utils::{IsAlive, Logical, Rectangle},
+};
+
+/// State of the text-input global.
+///
+/// Text inputs are tracked per client, as we only ever expose a single seat.
+pub struct TextInputState {
+    text_inputs: Vec<TextInput>,
+    focus: Option<WlSurface>,
+    global: GlobalId,
+}
+
+pub trait TextInputHandler {
+    fn text_input_state(&mut self) -> &mut TextInputState;
+    /// A text-input of the focused client committed new state.
+    fn text_input_updated(&mut self, update: TextInputUpdate);
+}
+
+pub struct TextInputGlobalData {
+    filter: Box<dyn for<'a> Fn(&'a Client) -> bool + Send + Sync>,
+}
+
+/// Description of the text field, a text-input is currently editing
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TextInputContent {
+    /// Text around the cursor, with the cursor and anchor position in bytes
+    pub surrounding_text: Option<(String, u32, u32)>,
+    pub change_cause: Option<ChangeCause>,
+    pub content_type: Option<(ContentHint, ContentPurpose)>,
+    /// Position of the text cursor in surface-local coordinates
+    pub cursor_rectangle: Option<Rectangle<i32, Logical>>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TextInputUpdate {
+    Enabled(TextInputContent),
+    Changed(TextInputContent),
+    Disabled,
+}
+
+/// Text changes requested by an input method for the focused text-input
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TextChanges {
+    /// Text to show at the cursor, with the cursor begin and end in bytes
+    pub preedit: Option<(String, i32, i32)>,
+    pub commit_string: Option<String>,
+    /// Bytes to delete before and after the cursor
+    pub delete_surrounding_text: Option<(u32, u32)>,
+}
+
+#[derive(Debug)]
+struct TextInput {
+    object: ZwpTextInputV3,
+    enabled: bool,
+    pending_enabled: Option<bool>,
+    pending: TextInputContent,
+    current: TextInputContent,
+    commits: u32,
+}
+
+impl TextInputState {
+    pub fn new<D, F>(dh: &DisplayHandle, client_filter: F) -> TextInputState
+    where
+        D: GlobalDispatch<ZwpTextInputManagerV3, TextInputGlobalData>
+            + Dispatch<ZwpTextInputManagerV3, ()>
+            + Dispatch<ZwpTextInputV3, ()>
+            + TextInputHandler
+            + 'static,
+        F: for<'a> Fn(&'a Client) -> bool + Send + Sync + 'static,
+    {
+        let global = dh.create_global::<D, ZwpTextInputManagerV3, _>(
+            1,
+            TextInputGlobalData {
+                filter: Box::new(client_filter),
+            },
+        );
+        TextInputState {
+            text_inputs: Vec::new(),
+            focus: None,
+            global,
+        }
+    }
+
+    pub fn global_id(&self) -> GlobalId {
+        self.global.clone()
+    }
+
+    /// Returns the surface text-inputs are currently focused on, if any.
+    pub fn focus(&self) -> Option<&WlSurface> {
+        self.focus.as_ref()
+    }
+
+    /// Returns the cursor rectangle of the enabled text-input of the focused client, if any.
+    pub fn cursor_rectangle(&self) -> Option<Rectangle<i32, Logical>> {
+        self.active()
+            .and_then(|text_input| text_input.current.cursor_rectangle)
+    }
+
+    /// Sends enter and leave events following the keyboard focus.
+    ///
+    /// Returns `true` if a previously enabled text-input lost focus.
+    pub fn update_focus(&mut self, focus: Option<&WlSurface>) -> bool {
+        if self.focus.as_ref() == focus {
+            return false;
+        }
+
+        let mut was_enabled = false;
+        if let Some(old_focus) = self.focus.take() {
+            for text_input in self
+                .text_inputs
+                .iter_mut()
+                .filter(|text_input| text_input.object.id().same_client_as(&old_focus.id()))
+            {
+                if old_focus.alive() {
+                    text_input.object.leave(&old_focus);
+                }
+                was_enabled |= text_input.enabled;
+                text_input.enabled = false;
+            }
+        }
+        if let Some(focus) = focus {
+            for text_input in self
+                .text_inputs
+                .iter()
+                .filter(|text_input| text_input.object.id().same_client_as(&focus.id()))
+            {
+                text_input.object.enter(focus);
+            }
+        }
+        self.focus = focus.cloned();
+
+        was_enabled
+    }
+
+    /// Forwards text changes of the input method to the enabled text-input.
+    pub fn apply_changes(&self, changes: &TextChanges) {
+        if let Some(text_input) = self.active() {
+            if let Some((before, after)) = changes.delete_surrounding_text {
+                text_input.object.delete_surrounding_text(before, after);
+            }
+            if let Some(text) = changes.commit_string.clone() {
+                text_input.object.commit_string(Some(text));
+            }
+            match changes.preedit.clone() {
+                Some((text, begin, end)) => {
+                    text_input.object.preedit_string(Some(text), begin, end)
+                }
+                None => text_input.object.preedit_string(None, 0, 0),
+            }
+            text_input.object.done(text_input.commits);
+        }
+    }
+
+    fn active(&self) -> Option<&TextInput> {
+        let focus = self.focus.as_ref()?;
+        self.text_inputs.iter().find(|text_input| {
+            text_input.enabled && text_input.object.id().same_client_as(&focus.id())
+        })
+    }
+}
+
+impl<D> GlobalDispatch<ZwpTextInputManagerV3, TextInputGlobalData, D> for TextInputState
+where
+    D: GlobalDispatch<ZwpTextInputManagerV3, TextInputGlobalData>
+        + Dispatch<ZwpTextInputManagerV3, ()>
+        + Dispatch<ZwpTextInputV3, ()>
+        + TextInputHandler
+        + 'static,
+{
+    fn bind(
+        _state: &mut D,
+        _dh: &DisplayHandle,
+        _client: &Client,
+        resource: New<ZwpTextInputManagerV3>,
+        _global_data: &TextInputGlobalData,
+        data_init: &mut DataInit<'_, D>,
+    ) {
+        data_init.init(resource, ());
+    }
+
+    fn can_view(client: Client, global_data: &TextInputGlobalData) -> bool {
+        (global_data.filter)(&client)
+    }
+}
+
+impl<D> Dispatch<ZwpTextInputManagerV3, (), D> for TextInputState
+where
+    D: GlobalDispatch<ZwpTextInputManagerV3, TextInputGlobalData>
+        + Dispatch<ZwpTextInputManagerV3, ()>
+        + Dispatch<ZwpTextInputV3, ()>
+        + TextInputHandler
+        + 'static,
+{
+    fn request(
+        state: &mut D,
+        _client: &Client,
+        _obj: &ZwpTextInputManagerV3,
+        request: zwp_text_input_manager_v3::Request,
+        _data: &(),
+        _dh: &DisplayHandle,
+        data_init: &mut DataInit<'_, D>,
+    ) {
+        match request {
+            zwp_text_input_manager_v3::Request::GetTextInput { id, .. } => {
+                let text_input = data_init.init(id, ());
+                let text_input_state = state.text_input_state();
+                if let Some(focus) = text_input_state.focus.as_ref() {
+                    if text_input.id().same_client_as(&focus.id()) {
+                        text_input.enter(focus);
+                    }
+                }
+                text_input_state.text_inputs.push(TextInput {
+                    object: text_input,
+                    enabled: false,
+                    pending_enabled: None,
+                    pending: TextInputContent::default(),
+                    current: TextInputContent::default(),
+                    commits: 0,
+                });
+            }
+            zwp_text_input_manager_v3::Request::Destroy => {}
+            _ => unreachable!(),
+        }
+    }
+}
+
+impl<D> Dispatch<ZwpTextInputV3, (), D> for TextInputState
+where
+    D: GlobalDispatch<ZwpTextInputManagerV3, TextInputGlobalData>
+        + Dispatch<ZwpTextInputManagerV3, ()>
+        + Dispatch<ZwpTextInputV3, ()>
+        + TextInputHandler
+        + 'static,
+{
+    fn request(
+        state: &mut D,
+        _client: &Client,
+        obj: &ZwpTextInputV3,
+        request: zwp_text_input_v3::Request,
+        _data: &(),
+        _dh: &DisplayHandle,
+        _data_init: &mut DataInit<'_, D>,
+    ) {
+        let text_input_state = state.text_input_state();
+        let focused = text_input_state
+            .focus
+            .as_ref()
+            .map(|focus| obj.id().same_client_as(&focus.id()))
+            .unwrap_or(false);
+        let text_input = match text_input_state
+            .text_inputs
+            .iter_mut()
+            .find(|text_input| &text_input.object == obj)
+        {
+            Some(text_input) => text_input,
+            None => return,
+        };
+
+        match request {
+            zwp_text_input_v3::Request::Enable => {
+                // enabling resets all state
+                text_input.pending_enabled = Some(true);
+                text_input.pending = TextInputContent::default();
+            }
+            zwp_text_input_v3::Request::Disable => {
+                text_input.pending_enabled = Some(false);
+            }
+            zwp_text_input_v3::Request::SetSurroundingText {
+                text,
+                cursor,
+                anchor,
+            } => {
+                text_input.pending.surrounding_text =
+                    Some((text, cursor.max(0) as u32, anchor.max(0) as u32));
+            }
+            zwp_text_input_v3::Request::SetTextChangeCause { cause } => {
+                text_input.pending.change_cause = cause.into_result().ok();
+            }
+            zwp_text_input_v3::Request::SetContentType { hint, purpose } => {
+                text_input.pending.content_type = Some((
+                    hint.into_result().unwrap_or_else(|_| ContentHint::empty()),
+                    purpose.into_result().unwrap_or(ContentPurpose::Normal),
+                ));
+            }
+            zwp_text_input_v3::Request::SetCursorRectangle {
+                x,
+                y,
+                width,
+                height,
+            } => {
+                text_input.pending.cursor_rectangle =
+                    Some(Rectangle::from_loc_and_size((x, y), (width, height)));
+            }
+            zwp_text_input_v3::Request::Commit => {
+                text_input.commits = text_input.commits.wrapping_add(1);
+
+                let was_enabled = text_input.enabled;
+                if let Some(enabled) = text_input.pending_enabled.take() {
+                    text_input.enabled = enabled;
+                }
+                // everything but the change cause stays valid until the next enable
+                let current = text_input.pending.clone();
+                text_input.pending.change_cause = None;
+                text_input.current = current.clone();
+
+                // only the focused client may talk to the input method
+                if !focused {
+                    return;
+                }
+                let update = match (was_enabled, text_input.enabled) {
+                    (false, true) => TextInputUpdate::Enabled(current),
+                    (true, true) => TextInputUpdate::Changed(current),
+                    (true, false) => TextInputUpdate::Disabled,
+                    (false, false) => return,
+                };
+                state.text_input_updated(update);
+            }
+            zwp_text_input_v3::Request::Destroy => {}
+            _ => unreachable!(),
+        }
+    }
+
+    fn destroyed(state: &mut D, _client: ClientId, resource: ObjectId, _data: &()) {
+        let text_input_state = state.text_input_state();
+        let was_enabled = text_input_state
+            .text_inputs
+            .iter()
+            .find(|text_input| text_input.object.id() == resource)
+            .map(|text_input| text_input.enabled)
+            .unwrap_or(false);
+        let focused = text_input_state
+            .focus
+            .as_ref()
+            .map(|focus| resource.same_client_as(&focus.id()))
+            .unwrap_or(false);
+        text_input_state
+            .text_inputs
+            .retain(|text_input| text_input.object.id() != resource);
+
+        if was_enabled && focused {
+            state.text_input_updated(TextInputUpdate::Disabled);
+        }
+    }
+}
+
+macro_rules! delegate_text_input {
+    ($(@<$( $lt:tt $( : $clt:tt $(+ $dlt:tt )* )? ),+>)? $ty: ty) => {
+        smithay::reexports::wayland_server::delegate_global_dispatch!($(@< $( $lt $( : $clt $(+ $dlt )* )? ),+ >)? $ty: [
+            smithay::reexports::wayland_protocols::wp::text_input::zv3::server::zwp_text_input_manager_v3::ZwpTextInputManagerV3: $crate::wayland::protocols::text_input::TextInputGlobalData
+        ] => $crate::wayland::protocols::text_input::TextInputState);
+        smithay::reexports::wayland_server::delegate_dispatch!($(@< $( $lt $( : $clt $(+ $dlt )* )? ),+ >)? $ty: [
+            smithay::reexports::wayland_protocols::wp::text_input::zv3::server::zwp_text_input_manager_v3::ZwpTextInputManagerV3: ()
+        ] => $crate::wayland::protocols::text_input::TextInputState);
+        smithay::reexports::wayland_server::delegate_dispatch!($(@< $( $lt $( : $clt $(+ $dlt )* )? ),+ >)? $ty: [
+            smithay::reexports::wayland_protocols::wp::text_input::zv3::server::zwp_text_input_v3::ZwpTextInputV3: ()
+        ] => $crate::wayland::protocols::text_input::TextInputState);
+    };
+}
+pub(crate) use delegate_text_input;
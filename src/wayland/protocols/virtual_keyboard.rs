@@ -0,0 +1,479 @@
+// SPDX-License-Identifier: GPL-3.0-only
+
+// Re-export only the actual code, and then only use this re-export
+// The `generated` module below is just some boilerplate to properly isolate stuff
+// and avoid exposing internal details.
+pub use generated::{zwp_virtual_keyboard_manager_v1, zwp_virtual_keyboard_v1};
+
+mod generated {
+    use smithay::reexports::wayland_server::{self, protocol::*};
+
+    pub mod __interfaces {
+        use smithay::reexports::wayland_server::protocol::__interfaces::*;
+        use wayland_backend;
+        wayland_scanner::generate_interfaces!(
+            "resources/protocols/virtual-keyboard-unstable-v1.xml"
+        );
+    }
+    use self::__interfaces::*;
+
+    wayland_scanner::generate_server_code!("resources/protocols/virtual-keyboard-unstable-v1.xml");
+}
+
+use self::{
+    zwp_virtual_keyboard_manager_v1::ZwpVirtualKeyboardManagerV1,
+    zwp_virtual_keyboard_v1::ZwpVirtualKeyboardV1,
+};
+use crate::{
+    config::XkbConfig,
+    input::{VirtualDevice, VirtualInput, VirtualKeyboardKeyEvent},
+};
+use smithay::{
+    backend::input::{DeviceCapability, InputEvent, KeyState},
+    reexports::wayland_server::{
+        backend::{ClientId, GlobalId, ObjectId},
+        protocol::wl_keyboard::KeymapFormat,
+        Client, DataInit, Dispatch, DisplayHandle, GlobalDispatch, New, Resource,
+    },
+};
+use std::{
+    collections::HashMap,
+    fs::File,
+    os::unix::{fs::FileExt, io::FromRawFd},
+};
+use xkbcommon::xkb;
+
+/// State of the virtual-keyboard global.
+///
+/// The seat only has a single keymap, so keys of a virtual keyboard are translated
+/// to the keys of the seat producing the same keysyms under the keymap of the client.
+pub struct VirtualKeyboardState {
+    keyboards: Vec<VirtualKeyboard>,
+    dh: DisplayHandle,
+    global: GlobalId,
+}
+
+pub trait VirtualKeyboardHandler {
+    fn virtual_keyboard_state(&mut self) -> &mut VirtualKeyboardState;
+    /// Keyboard configuration of the seat, keys are translated into its keymap.
+    fn virtual_keyboard_xkb_config(&self) -> XkbConfig;
+    /// A virtual keyboard was added, removed or produced a key event.
+    fn virtual_keyboard_event(&mut self, dh: &DisplayHandle, event: InputEvent<VirtualInput>);
+}
+
+pub struct VirtualKeyboardGlobalData {
+    filter: Box<dyn for<'a> Fn(&'a Client) -> bool + Send + Sync>,
+}
+
+struct VirtualKeyboard {
+    object: ZwpVirtualKeyboardV1,
+    device: VirtualDevice,
+    has_keymap: bool,
+    /// `None` if the keymap of the client could not be compiled, keys are sent untranslated then
+    keymap: Option<ClientKeymap>,
+    /// Pressed keys of the client and the keys of the seat they were sent as
+    pressed: Vec<(u32, u32)>,
+}
+
+/// Keymap uploaded by a client, used to translate its keys into the keymap of the seat
+struct ClientKeymap {
+    state: xkb::State,
+    /// Keys of the seat producing a given keysym, preferring lower shift levels
+    seat_keys: HashMap<xkb::Keysym, u32>,
+    /// Modifiers of the client and the modifier keys of the seat setting them
+    modifier_keys: Vec<(&'static str, u32)>,
+    caps_lock_key: Option<u32>,
+    /// Modifier keys of the seat held on behalf of the `modifiers` request
+    held_modifiers: Vec<u32>,
+    caps_locked: bool,
+}
+
+const MODIFIER_KEYSYMS: [(&str, xkb::Keysym); 5] = [
+    (xkb::MOD_NAME_SHIFT, xkb::KEY_Shift_L),
+    (xkb::MOD_NAME_CTRL, xkb::KEY_Control_L),
+    (xkb::MOD_NAME_ALT, xkb::KEY_Alt_L),
+    (xkb::MOD_NAME_LOGO, xkb::KEY_Super_L),
+    ("Mod5", xkb::KEY_ISO_Level3_Shift),
+];
+
+impl ClientKeymap {
+    fn new(file: &File, size: u32, seat_config: &XkbConfig) -> std::io::Result<ClientKeymap> {
+        let invalid = |msg| std::io::Error::new(std::io::ErrorKind::InvalidData, msg);
+
+        // the client might have written the file without rewinding it
+        let mut keymap = vec![0; size as usize];
+        file.read_exact_at(&mut keymap, 0)?;
+        if let Some(end) = keymap.iter().position(|byte| *byte == 0) {
+            keymap.truncate(end);
+        }
+        let keymap = String::from_utf8(keymap).map_err(|_| invalid("Keymap is not utf-8"))?;
+
+        let context = xkb::Context::new(xkb::CONTEXT_NO_FLAGS);
+        let keymap = xkb::Keymap::new_from_string(
+            &context,
+            keymap,
+            xkb::KEYMAP_FORMAT_TEXT_V1,
+            xkb::KEYMAP_COMPILE_NO_FLAGS,
+        )
+        .ok_or_else(|| invalid("Invalid keymap"))?;
+        let seat_keymap = seat_config
+            .compile_keymap(&context)
+            .ok_or_else(|| invalid("Invalid keymap of the seat"))?;
+
+        let mut seat_keys = HashMap::new();
+        for level in 0..8 {
+            for keycode in seat_keymap.min_keycode().max(8)..=seat_keymap.max_keycode() {
+                for keysym in seat_keymap.key_get_syms_by_level(keycode, 0, level) {
+                    // xkb keycodes are offset by 8 from evdev ones
+                    seat_keys.entry(*keysym).or_insert(keycode - 8);
+                }
+            }
+        }
+        let modifier_keys = MODIFIER_KEYSYMS
+            .iter()
+            .filter(|(name, _)| keymap.mod_get_index(name) != xkb::MOD_INVALID)
+            .filter_map(|(name, keysym)| Some((*name, *seat_keys.get(keysym)?)))
+            .collect();
+        let caps_lock_key = seat_keys.get(&xkb::KEY_Caps_Lock).copied();
+
+        Ok(ClientKeymap {
+            state: xkb::State::new(&keymap),
+            seat_keys,
+            modifier_keys,
+            caps_lock_key,
+            held_modifiers: Vec::new(),
+            caps_locked: false,
+        })
+    }
+
+    /// Returns the key of the seat producing the same keysym as the given key of the client.
+    fn update_key(&mut self, key: u32, state: KeyState) -> u32 {
+        let keysym = self.state.key_get_one_sym(key + 8);
+        let direction = match state {
+            KeyState::Pressed => xkb::KeyDirection::Down,
+            KeyState::Released => xkb::KeyDirection::Up,
+        };
+        self.state.update_key(key + 8, direction);
+        self.seat_keys.get(&keysym).copied().unwrap_or(key)
+    }
+
+    /// Presses and releases modifier keys of the seat, until they match the modifiers of the client.
+    fn update_modifiers(
+        &mut self,
+        depressed: u32,
+        latched: u32,
+        locked: u32,
+        group: u32,
+    ) -> Vec<(u32, KeyState)> {
+        self.state
+            .update_mask(depressed, latched, locked, 0, 0, group);
+
+        let mut keys = Vec::new();
+        for (name, key) in self.modifier_keys.iter() {
+            let active = self
+                .state
+                .mod_name_is_active(name, xkb::STATE_MODS_DEPRESSED | xkb::STATE_MODS_LATCHED);
+            let held = self.held_modifiers.contains(key);
+            if active && !held {
+                self.held_modifiers.push(*key);
+                keys.push((*key, KeyState::Pressed));
+            } else if !active && held {
+                self.held_modifiers.retain(|held| held != key);
+                keys.push((*key, KeyState::Released));
+            }
+        }
+
+        let caps_locked = self
+            .state
+            .mod_name_is_active(xkb::MOD_NAME_CAPS, xkb::STATE_MODS_LOCKED);
+        if caps_locked != self.caps_locked {
+            if let Some(key) = self.caps_lock_key {
+                keys.push((key, KeyState::Pressed));
+                keys.push((key, KeyState::Released));
+            }
+            self.caps_locked = caps_locked;
+        }
+        keys
+    }
+}
+
+impl VirtualKeyboardState {
+    pub fn new<D, F>(dh: &DisplayHandle, client_filter: F) -> VirtualKeyboardState
+    where
+        D: GlobalDispatch<ZwpVirtualKeyboardManagerV1, VirtualKeyboardGlobalData>
+            + Dispatch<ZwpVirtualKeyboardManagerV1, ()>
+            + Dispatch<ZwpVirtualKeyboardV1, ()>
+            + VirtualKeyboardHandler
+            + 'static,
+        F: for<'a> Fn(&'a Client) -> bool + Send + Sync + 'static,
+    {
+        let global = dh.create_global::<D, ZwpVirtualKeyboardManagerV1, _>(
+            1,
+            VirtualKeyboardGlobalData {
+                filter: Box::new(client_filter),
+            },
+        );
+        VirtualKeyboardState {
+            keyboards: Vec::new(),
+            dh: dh.clone(),
+            global,
+        }
+    }
+
+    pub fn global_id(&self) -> GlobalId {
+        self.global.clone()
+    }
+
+    /// Returns the protocol object backing the given virtual device, if any.
+    pub fn keyboard_object(&self, device_id: &str) -> Option<ObjectId> {
+        self.keyboards
+            .iter()
+            .find(|keyboard| keyboard.device.id() == device_id)
+            .map(|keyboard| keyboard.object.id())
+    }
+}
+
+impl<D> GlobalDispatch<ZwpVirtualKeyboardManagerV1, VirtualKeyboardGlobalData, D>
+    for VirtualKeyboardState
+where
+    D: GlobalDispatch<ZwpVirtualKeyboardManagerV1, VirtualKeyboardGlobalData>
+        + Dispatch<ZwpVirtualKeyboardManagerV1, ()>
+        + Dispatch<ZwpVirtualKeyboardV1, ()>
+        + VirtualKeyboardHandler
+        + 'static,
+{
+    fn bind(
+        _state: &mut D,
+        _dh: &DisplayHandle,
+        _client: &Client,
+        resource: New<ZwpVirtualKeyboardManagerV1>,
+        _global_data: &VirtualKeyboardGlobalData,
+        data_init: &mut DataInit<'_, D>,
+    ) {
+        data_init.init(resource, ());
+    }
+
+    fn can_view(client: Client, global_data: &VirtualKeyboardGlobalData) -> bool {
+        (global_data.filter)(&client)
+    }
+}
+
+impl<D> Dispatch<ZwpVirtualKeyboardManagerV1, (), D> for VirtualKeyboardState
+where
+    D: GlobalDispatch<ZwpVirtualKeyboardManagerV1, VirtualKeyboardGlobalData>
+        + Dispatch<ZwpVirtualKeyboardManagerV1, ()>
+        + Dispatch<ZwpVirtualKeyboardV1, ()>
+        + VirtualKeyboardHandler
+        + 'static,
+{
+    fn request(
+        state: &mut D,
+        _client: &Client,
+        _obj: &ZwpVirtualKeyboardManagerV1,
+        request: zwp_virtual_keyboard_manager_v1::Request,
+        _data: &(),
+        dh: &DisplayHandle,
+        data_init: &mut DataInit<'_, D>,
+    ) {
+        match request {
+            zwp_virtual_keyboard_manager_v1::Request::CreateVirtualKeyboard { id, .. } => {
+                let device = VirtualDevice::new(DeviceCapability::Keyboard);
+                state
+                    .virtual_keyboard_state()
+                    .keyboards
+                    .push(VirtualKeyboard {
+                        object: data_init.init(id, ()),
+                        device: device.clone(),
+                        has_keymap: false,
+                        keymap: None,
+                        pressed: Vec::new(),
+                    });
+                state.virtual_keyboard_event(dh, InputEvent::DeviceAdded { device });
+            }
+            _ => unreachable!(),
+        }
+    }
+}
+
+impl<D> Dispatch<ZwpVirtualKeyboardV1, (), D> for VirtualKeyboardState
+where
+    D: GlobalDispatch<ZwpVirtualKeyboardManagerV1, VirtualKeyboardGlobalData>
+        + Dispatch<ZwpVirtualKeyboardManagerV1, ()>
+        + Dispatch<ZwpVirtualKeyboardV1, ()>
+        + VirtualKeyboardHandler
+        + 'static,
+{
+    fn request(
+        state: &mut D,
+        _client: &Client,
+        obj: &ZwpVirtualKeyboardV1,
+        request: zwp_virtual_keyboard_v1::Request,
+        _data: &(),
+        dh: &DisplayHandle,
+        _data_init: &mut DataInit<'_, D>,
+    ) {
+        let xkb_config = match request {
+            zwp_virtual_keyboard_v1::Request::Keymap { .. } => {
+                Some(state.virtual_keyboard_xkb_config())
+            }
+            _ => None,
+        };
+        let keyboard = match state
+            .virtual_keyboard_state()
+            .keyboards
+            .iter_mut()
+            .find(|keyboard| &keyboard.object == obj)
+        {
+            Some(keyboard) => keyboard,
+            None => return,
+        };
+
+        let (time, keys) = match request {
+            zwp_virtual_keyboard_v1::Request::Keymap { format, fd, size } => {
+                // the fd is ours to close
+                let file = unsafe { File::from_raw_fd(fd) };
+                keyboard.keymap = None;
+                if format == KeymapFormat::XkbV1 as u32 {
+                    match ClientKeymap::new(&file, size, xkb_config.as_ref().unwrap()) {
+                        Ok(keymap) => keyboard.keymap = Some(keymap),
+                        Err(err) => {
+                            slog_scope::warn!("Failed to load keymap of virtual keyboard: {}", err)
+                        }
+                    }
+                }
+                keyboard.has_keymap = true;
+                return;
+            }
+            zwp_virtual_keyboard_v1::Request::Key {
+                time,
+                key,
+                state: key_state,
+            } => {
+                if !keyboard.has_keymap {
+                    obj.post_error(
+                        zwp_virtual_keyboard_v1::Error::NoKeymap,
+                        "No keymap was set",
+                    );
+                    return;
+                }
+
+                let position = keyboard
+                    .pressed
+                    .iter()
+                    .position(|(pressed, _)| *pressed == key);
+                let (key_code, key_state) = match (key_state, position) {
+                    (0, Some(position)) => {
+                        // release the key of the seat, that was pressed, even if the keymap changed
+                        let (_, key_code) = keyboard.pressed.remove(position);
+                        if let Some(keymap) = keyboard.keymap.as_mut() {
+                            keymap.update_key(key, KeyState::Released);
+                        }
+                        (key_code, KeyState::Released)
+                    }
+                    (0, None) | (_, Some(_)) => return,
+                    (_, None) => {
+                        let key_code = keyboard
+                            .keymap
+                            .as_mut()
+                            .map(|keymap| keymap.update_key(key, KeyState::Pressed))
+                            .unwrap_or(key);
+                        keyboard.pressed.push((key, key_code));
+                        (key_code, KeyState::Pressed)
+                    }
+                };
+                (time, vec![(key_code, key_state)])
+            }
+            zwp_virtual_keyboard_v1::Request::Modifiers {
+                mods_depressed,
+                mods_latched,
+                mods_locked,
+                group,
+            } => {
+                if !keyboard.has_keymap {
+                    obj.post_error(
+                        zwp_virtual_keyboard_v1::Error::NoKeymap,
+                        "No keymap was set",
+                    );
+                    return;
+                }
+                // the seat keyboard only knows about keys, so hold its modifier keys instead
+                let keys = match keyboard.keymap.as_mut() {
+                    Some(keymap) => {
+                        keymap.update_modifiers(mods_depressed, mods_latched, mods_locked, group)
+                    }
+                    None => return,
+                };
+                (0, keys)
+            }
+            zwp_virtual_keyboard_v1::Request::Destroy => return,
+            _ => unreachable!(),
+        };
+
+        let device = keyboard.device.clone();
+        for (key_code, key_state) in keys {
+            let event = VirtualKeyboardKeyEvent {
+                device: device.clone(),
+                time,
+                key_code,
+                state: key_state,
+            };
+            state.virtual_keyboard_event(dh, InputEvent::Keyboard { event });
+        }
+    }
+
+    fn destroyed(state: &mut D, _client: ClientId, resource: ObjectId, _data: &()) {
+        let virtual_keyboard_state = state.virtual_keyboard_state();
+        let dh = virtual_keyboard_state.dh.clone();
+        let position = match virtual_keyboard_state
+            .keyboards
+            .iter()
+            .position(|keyboard| keyboard.object.id() == resource)
+        {
+            Some(position) => position,
+            None => return,
+        };
+        let keyboard = virtual_keyboard_state.keyboards.remove(position);
+
+        // don't leave any keys stuck
+        let held_modifiers = keyboard
+            .keymap
+            .map(|keymap| keymap.held_modifiers)
+            .unwrap_or_default();
+        for key in keyboard
+            .pressed
+            .into_iter()
+            .map(|(_, key)| key)
+            .chain(held_modifiers)
+        {
+            let event = VirtualKeyboardKeyEvent {
+                device: keyboard.device.clone(),
+                time: 0,
+                key_code: key,
+                state: KeyState::Released,
+            };
+            state.virtual_keyboard_event(&dh, InputEvent::Keyboard { event });
+        }
+        state.virtual_keyboard_event(
+            &dh,
+            InputEvent::DeviceRemoved {
+                device: keyboard.device.clone(),
+            },
+        );
+        keyboard.device.release();
+    }
+}
+
+macro_rules! delegate_virtual_keyboard {
+    ($(@<$( $lt:tt $( : $clt:tt $(+ $dlt:tt )* )? ),+>)? $ty: ty) => {
+        smithay::reexports::wayland_server::delegate_global_dispatch!($(@< $( $lt $( : $clt $(+ $dlt )* )? ),+ >)? $ty: [
+            $crate::wayland::protocols::virtual_keyboard::zwp_virtual_keyboard_manager_v1::ZwpVirtualKeyboardManagerV1: $crate::wayland::protocols::virtual_keyboard::VirtualKeyboardGlobalData
+        ] => $crate::wayland::protocols::virtual_keyboard::VirtualKeyboardState);
+        smithay::reexports::wayland_server::delegate_dispatch!($(@< $( $lt $( : $clt $(+ $dlt )* )? ),+ >)? $ty: [
+            $crate::wayland::protocols::virtual_keyboard::zwp_virtual_keyboard_manager_v1::ZwpVirtualKeyboardManagerV1: ()
+        ] => $crate::wayland::protocols::virtual_keyboard::VirtualKeyboardState);
+        smithay::reexports::wayland_server::delegate_dispatch!($(@< $( $lt $( : $clt $(+ $dlt )* )? ),+ >)? $ty: [
+            $crate::wayland::protocols::virtual_keyboard::zwp_virtual_keyboard_v1::ZwpVirtualKeyboardV1: ()
+        ] => $crate::wayland::protocols::virtual_keyboard::VirtualKeyboardState);
+    };
+}
+pub(crate) use delegate_virtual_keyboard;
@@ -0,0 +1,364 @@
+// SPDX-License-Identifier: GPL-3.0-only
+
+use crate::input::{
+    VirtualDevice, VirtualInput, VirtualPointerAxisEvent, VirtualPointerButtonEvent,
+    VirtualPointerMotionAbsoluteEvent, VirtualPointerMotionEvent,
+};
+use smithay::{
+    backend::input::{AxisSource, ButtonState, DeviceCapability, InputEvent},
+    reexports::{
+        wayland_protocols_wlr::virtual_pointer::v1::server::{
+            zwlr_virtual_pointer_manager_v1::{self, ZwlrVirtualPointerManagerV1},
+            zwlr_virtual_pointer_v1::{self, ZwlrVirtualPointerV1},
+        },
+        wayland_server::{
+            backend::{ClientId, GlobalId, ObjectId},
+            protocol::wl_pointer,
+            Client, DataInit, Dispatch, DisplayHandle, GlobalDispatch, New, Resource, WEnum,
+        },
+    },
+    wayland::output::Output,
+};
+
+/// State of the wlr-virtual-pointer global.
+pub struct VirtualPointerState {
+    pointers: Vec<VirtualPointer>,
+    dh: DisplayHandle,
+    global: GlobalId,
+}
+
+pub trait VirtualPointerHandler {
+    fn virtual_pointer_state(&mut self) -> &mut VirtualPointerState;
+    /// A virtual pointer was added, removed or produced a pointer event.
+    fn virtual_pointer_event(&mut self, dh: &DisplayHandle, event: InputEvent<VirtualInput>);
+}
+
+pub struct VirtualPointerGlobalData {
+    filter: Box<dyn for<'a> Fn(&'a Client) -> bool + Send + Sync>,
+}
+
+#[derive(Debug)]
+struct VirtualPointer {
+    object: ZwlrVirtualPointerV1,
+    device: VirtualDevice,
+    output: Option<Output>,
+    pressed: Vec<u32>,
+    // axis events are accumulated until the next frame
+    axis: VirtualPointerAxisEvent,
+}
+
+impl VirtualPointerState {
+    pub fn new<D, F>(dh: &DisplayHandle, client_filter: F) -> VirtualPointerState
+    where
+        D: GlobalDispatch<ZwlrVirtualPointerManagerV1, VirtualPointerGlobalData>
+            + Dispatch<ZwlrVirtualPointerManagerV1, ()>
+            + Dispatch<ZwlrVirtualPointerV1, ()>
+            + VirtualPointerHandler
+            + 'static,
+        F: for<'a> Fn(&'a Client) -> bool + Send + Sync + 'static,
+    {
+        let global = dh.create_global::<D, ZwlrVirtualPointerManagerV1, _>(
+            2,
+            VirtualPointerGlobalData {
+                filter: Box::new(client_filter),
+            },
+        );
+        VirtualPointerState {
+            pointers: Vec::new(),
+            dh: dh.clone(),
+            global,
+        }
+    }
+
+    pub fn global_id(&self) -> GlobalId {
+        self.global.clone()
+    }
+}
+
+fn axis_mut(
+    event: &mut VirtualPointerAxisEvent,
+    axis: WEnum<wl_pointer::Axis>,
+) -> Option<(&mut Option<f64>, &mut Option<f64>)> {
+    match axis {
+        WEnum::Value(wl_pointer::Axis::HorizontalScroll) => {
+            Some((&mut event.horizontal, &mut event.horizontal_discrete))
+        }
+        WEnum::Value(wl_pointer::Axis::VerticalScroll) => {
+            Some((&mut event.vertical, &mut event.vertical_discrete))
+        }
+        _ => None,
+    }
+}
+
+impl<D> GlobalDispatch<ZwlrVirtualPointerManagerV1, VirtualPointerGlobalData, D>
+    for VirtualPointerState
+where
+    D: GlobalDispatch<ZwlrVirtualPointerManagerV1, VirtualPointerGlobalData>
+        + Dispatch<ZwlrVirtualPointerManagerV1, ()>
+        + Dispatch<ZwlrVirtualPointerV1, ()>
+        + VirtualPointerHandler
+        + 'static,
+{
+    fn bind(
+        _state: &mut D,
+        _dh: &DisplayHandle,
+        _client: &Client,
+        resource: New<ZwlrVirtualPointerManagerV1>,
+        _global_data: &VirtualPointerGlobalData,
+        data_init: &mut DataInit<'_, D>,
+    ) {
+        data_init.init(resource, ());
+    }
+
+    fn can_view(client: Client, global_data: &VirtualPointerGlobalData) -> bool {
+        (global_data.filter)(&client)
+    }
+}
+
+impl<D> Dispatch<ZwlrVirtualPointerManagerV1, (), D> for VirtualPointerState
+where
+    D: GlobalDispatch<ZwlrVirtualPointerManagerV1, VirtualPointerGlobalData>
+        + Dispatch<ZwlrVirtualPointerManagerV1, ()>
+        + Dispatch<ZwlrVirtualPointerV1, ()>
+        + VirtualPointerHandler
+        + 'static,
+{
+    fn request(
+        state: &mut D,
+        _client: &Client,
+        _obj: &ZwlrVirtualPointerManagerV1,
+        request: zwlr_virtual_pointer_manager_v1::Request,
+        _data: &(),
+        dh: &DisplayHandle,
+        data_init: &mut DataInit<'_, D>,
+    ) {
+        let (id, output) = match request {
+            zwlr_virtual_pointer_manager_v1::Request::CreateVirtualPointer { id, .. } => (id, None),
+            zwlr_virtual_pointer_manager_v1::Request::CreateVirtualPointerWithOutput {
+                id,
+                output,
+                ..
+            } => (id, output.as_ref().and_then(Output::from_resource)),
+            zwlr_virtual_pointer_manager_v1::Request::Destroy => return,
+            _ => unreachable!(),
+        };
+
+        let device = VirtualDevice::new(DeviceCapability::Pointer);
+        state.virtual_pointer_state().pointers.push(VirtualPointer {
+            object: data_init.init(id, ()),
+            device: device.clone(),
+            output,
+            pressed: Vec::new(),
+            axis: VirtualPointerAxisEvent::new(device.clone()),
+        });
+        state.virtual_pointer_event(dh, InputEvent::DeviceAdded { device });
+    }
+}
+
+impl<D> Dispatch<ZwlrVirtualPointerV1, (), D> for VirtualPointerState
+where
+    D: GlobalDispatch<ZwlrVirtualPointerManagerV1, VirtualPointerGlobalData>
+        + Dispatch<ZwlrVirtualPointerManagerV1, ()>
+        + Dispatch<ZwlrVirtualPointerV1, ()>
+        + VirtualPointerHandler
+        + 'static,
+{
+    fn request(
+        state: &mut D,
+        _client: &Client,
+        obj: &ZwlrVirtualPointerV1,
+        request: zwlr_virtual_pointer_v1::Request,
+        _data: &(),
+        dh: &DisplayHandle,
+        _data_init: &mut DataInit<'_, D>,
+    ) {
+        let pointer = match state
+            .virtual_pointer_state()
+            .pointers
+            .iter_mut()
+            .find(|pointer| &pointer.object == obj)
+        {
+            Some(pointer) => pointer,
+            None => return,
+        };
+
+        let event = match request {
+            zwlr_virtual_pointer_v1::Request::Motion { time, dx, dy } => {
+                InputEvent::PointerMotion {
+                    event: VirtualPointerMotionEvent {
+                        device: pointer.device.clone(),
+                        time,
+                        dx,
+                        dy,
+                    },
+                }
+            }
+            zwlr_virtual_pointer_v1::Request::MotionAbsolute {
+                time,
+                x,
+                y,
+                x_extent,
+                y_extent,
+            } => {
+                if x_extent == 0 || y_extent == 0 {
+                    return;
+                }
+                InputEvent::PointerMotionAbsolute {
+                    event: VirtualPointerMotionAbsoluteEvent {
+                        device: pointer.device.clone(),
+                        time,
+                        x,
+                        y,
+                        x_extent,
+                        y_extent,
+                        output: pointer.output.clone(),
+                    },
+                }
+            }
+            zwlr_virtual_pointer_v1::Request::Button {
+                time,
+                button,
+                state: button_state,
+            } => {
+                let button_state = match button_state {
+                    WEnum::Value(wl_pointer::ButtonState::Pressed) => {
+                        if pointer.pressed.contains(&button) {
+                            return;
+                        }
+                        pointer.pressed.push(button);
+                        ButtonState::Pressed
+                    }
+                    _ => {
+                        if !pointer.pressed.contains(&button) {
+                            return;
+                        }
+                        pointer.pressed.retain(|pressed| *pressed != button);
+                        ButtonState::Released
+                    }
+                };
+                InputEvent::PointerButton {
+                    event: VirtualPointerButtonEvent {
+                        device: pointer.device.clone(),
+                        time,
+                        button,
+                        state: button_state,
+                    },
+                }
+            }
+            zwlr_virtual_pointer_v1::Request::Axis { time, axis, value } => {
+                pointer.axis.time = time;
+                match axis_mut(&mut pointer.axis, axis) {
+                    Some((amount, _)) => *amount = Some(amount.unwrap_or(0.0) + value),
+                    None => {
+                        obj.post_error(zwlr_virtual_pointer_v1::Error::InvalidAxis, "Invalid axis")
+                    }
+                }
+                return;
+            }
+            zwlr_virtual_pointer_v1::Request::AxisDiscrete {
+                time,
+                axis,
+                value,
+                discrete,
+            } => {
+                pointer.axis.time = time;
+                match axis_mut(&mut pointer.axis, axis) {
+                    Some((amount, amount_discrete)) => {
+                        *amount = Some(amount.unwrap_or(0.0) + value);
+                        *amount_discrete = Some(amount_discrete.unwrap_or(0.0) + discrete as f64);
+                    }
+                    None => {
+                        obj.post_error(zwlr_virtual_pointer_v1::Error::InvalidAxis, "Invalid axis")
+                    }
+                }
+                return;
+            }
+            zwlr_virtual_pointer_v1::Request::AxisStop { time, axis } => {
+                pointer.axis.time = time;
+                match axis_mut(&mut pointer.axis, axis) {
+                    Some((amount, _)) => *amount = Some(0.0),
+                    None => {
+                        obj.post_error(zwlr_virtual_pointer_v1::Error::InvalidAxis, "Invalid axis")
+                    }
+                }
+                return;
+            }
+            zwlr_virtual_pointer_v1::Request::AxisSource { axis_source } => {
+                pointer.axis.source = match axis_source {
+                    WEnum::Value(wl_pointer::AxisSource::Wheel) => AxisSource::Wheel,
+                    WEnum::Value(wl_pointer::AxisSource::Finger) => AxisSource::Finger,
+                    WEnum::Value(wl_pointer::AxisSource::Continuous) => AxisSource::Continuous,
+                    WEnum::Value(wl_pointer::AxisSource::WheelTilt) => AxisSource::WheelTilt,
+                    _ => {
+                        obj.post_error(
+                            zwlr_virtual_pointer_v1::Error::InvalidAxisSource,
+                            "Invalid axis source",
+                        );
+                        return;
+                    }
+                };
+                return;
+            }
+            zwlr_virtual_pointer_v1::Request::Frame => {
+                if pointer.axis.is_empty() {
+                    return;
+                }
+                let event = std::mem::replace(
+                    &mut pointer.axis,
+                    VirtualPointerAxisEvent::new(pointer.device.clone()),
+                );
+                InputEvent::PointerAxis { event }
+            }
+            zwlr_virtual_pointer_v1::Request::Destroy => return,
+            _ => unreachable!(),
+        };
+        state.virtual_pointer_event(dh, event);
+    }
+
+    fn destroyed(state: &mut D, _client: ClientId, resource: ObjectId, _data: &()) {
+        let virtual_pointer_state = state.virtual_pointer_state();
+        let dh = virtual_pointer_state.dh.clone();
+        let position = match virtual_pointer_state
+            .pointers
+            .iter()
+            .position(|pointer| pointer.object.id() == resource)
+        {
+            Some(position) => position,
+            None => return,
+        };
+        let pointer = virtual_pointer_state.pointers.remove(position);
+
+        // don't leave any buttons stuck
+        for button in pointer.pressed {
+            let event = VirtualPointerButtonEvent {
+                device: pointer.device.clone(),
+                time: 0,
+                button,
+                state: ButtonState::Released,
+            };
+            state.virtual_pointer_event(&dh, InputEvent::PointerButton { event });
+        }
+        state.virtual_pointer_event(
+            &dh,
+            InputEvent::DeviceRemoved {
+                device: pointer.device.clone(),
+            },
+        );
+        pointer.device.release();
+    }
+}
+
+macro_rules! delegate_virtual_pointer {
+    ($(@<$( $lt:tt $( : $clt:tt $(+ $dlt:tt )* )? ),+>)? $ty: ty) => {
+        smithay::reexports::wayland_server::delegate_global_dispatch!($(@< $( $lt $( : $clt $(+ $dlt )* )? ),+ >)? $ty: [
+            smithay::reexports::wayland_protocols_wlr::virtual_pointer::v1::server::zwlr_virtual_pointer_manager_v1::ZwlrVirtualPointerManagerV1: $crate::wayland::protocols::virtual_pointer::VirtualPointerGlobalData
+        ] => $crate::wayland::protocols::virtual_pointer::VirtualPointerState);
+        smithay::reexports::wayland_server::delegate_dispatch!($(@< $( $lt $( : $clt $(+ $dlt )* )? ),+ >)? $ty: [
+            smithay::reexports::wayland_protocols_wlr::virtual_pointer::v1::server::zwlr_virtual_pointer_manager_v1::ZwlrVirtualPointerManagerV1: ()
+        ] => $crate::wayland::protocols::virtual_pointer::VirtualPointerState);
+        smithay::reexports::wayland_server::delegate_dispatch!($(@< $( $lt $( : $clt $(+ $dlt )* )? ),+ >)? $ty: [
+            smithay::reexports::wayland_protocols_wlr::virtual_pointer::v1::server::zwlr_virtual_pointer_v1::ZwlrVirtualPointerV1: ()
+        ] => $crate::wayland::protocols::virtual_pointer::VirtualPointerState);
+    };
+}
+pub(crate) use delegate_virtual_pointer;